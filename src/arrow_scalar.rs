@@ -0,0 +1,129 @@
+//! Arrow Scalar Functions over LIST and ARRAY Arguments
+//!
+//! Shared by bins via `mod arrow_scalar;`. duckdb-rs 1.4 converts the input
+//! chunk of a `VArrowScalar` with `data_chunk_to_arrow`, which hits `todo!()`
+//! for LIST and ARRAY columns. That panic happens inside DuckDB's C callback,
+//! so instead of failing the query it aborts the process.
+//!
+//! `Listed<T>` registers any `VArrowScalar` as a plain `VScalar` and builds
+//! the `RecordBatch` itself:
+//! - `DOUBLE[]` becomes a `ListArray` of `Float64`. Each row's entry is
+//!   copied out, since DuckDB list entries need not be contiguous
+//! - `DOUBLE[n]` becomes a `FixedSizeListArray` of `Float64`
+//! - everything else goes through duckdb-rs's `flat_vector_to_arrow_array`
+//!
+//!   conn.register_scalar_function::<Listed<MyScalar>>("my_scalar")?;
+//!
+//! `doubles(&batch)` downcasts the leading columns for the many UDFs whose
+//! arguments are all DOUBLE.
+
+#![allow(dead_code)]
+
+use duckdb::arrow::array::{Array, FixedSizeListArray, Float64Array, ListArray};
+use duckdb::arrow::buffer::{NullBuffer, OffsetBuffer};
+use duckdb::arrow::datatypes::{DataType, Field};
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::core::{DataChunkHandle, FlatVector, LogicalTypeId};
+use duckdb::vscalar::{ScalarFunctionSignature, VArrowScalar, VScalar};
+use duckdb::vtab::arrow::{flat_vector_to_arrow_array, write_arrow_array_to_vector, WritableVector};
+use std::error::Error;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The first `N` columns of `input`, which must all be DOUBLE.
+pub fn doubles<const N: usize>(input: &RecordBatch) -> Result<[&Float64Array; N], Box<dyn Error>> {
+    let mut columns = Vec::with_capacity(N);
+    for i in 0..N {
+        let column = input.columns().get(i).ok_or_else(|| format!("missing argument {}", i))?;
+        columns.push(column.as_any().downcast_ref::<Float64Array>().ok_or_else(|| format!("argument {} is not DOUBLE", i))?);
+    }
+    Ok(columns.try_into().expect("N columns collected"))
+}
+
+/// `T` with LIST/ARRAY-capable input conversion.
+pub struct Listed<T>(PhantomData<T>);
+
+impl<T: VArrowScalar> VScalar for Listed<T> {
+    type State = T::State;
+
+    unsafe fn invoke(
+        state: &Self::State,
+        input: &mut DataChunkHandle,
+        out: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        let array = T::invoke(state, chunk_to_batch(input)?)?;
+        write_arrow_array_to_vector(&array, out)
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        <T as VScalar>::signatures()
+    }
+}
+
+/// `None` when every row is valid.
+fn nulls(len: usize, is_null: impl Fn(usize) -> bool) -> Option<NullBuffer> {
+    (0..len).any(&is_null).then(|| NullBuffer::from_iter((0..len).map(|i| !is_null(i))))
+}
+
+fn item_field() -> Arc<Field> {
+    Arc::new(Field::new("item", DataType::Float64, true))
+}
+
+fn require_double(child: &FlatVector, column: usize) -> Result<(), Box<dyn Error>> {
+    match child.logical_type().id() {
+        LogicalTypeId::Double => Ok(()),
+        other => Err(format!("argument {}: only DOUBLE lists are supported, got {:?} elements", column, other).into()),
+    }
+}
+
+/// `data_chunk_to_arrow`, plus `DOUBLE[]` and `DOUBLE[n]` columns.
+pub fn chunk_to_batch(chunk: &DataChunkHandle) -> Result<RecordBatch, Box<dyn Error>> {
+    let len = chunk.len();
+    let mut columns: Vec<(String, Arc<dyn Array>)> = Vec::with_capacity(chunk.num_columns());
+    for i in 0..chunk.num_columns() {
+        let mut vector = chunk.flat_vector(i);
+        let row_nulls = nulls(len, |r| vector.row_is_null(r as u64));
+        let array: Arc<dyn Array> = match vector.logical_type().id() {
+            LogicalTypeId::List => {
+                let list = chunk.list_vector(i);
+                let total = list.len();
+                let child = list.child(total);
+                require_double(&child, i)?;
+                let source = child.as_slice_with_len::<f64>(total);
+                let mut values = Vec::with_capacity(total);
+                let mut value_nulls = Vec::with_capacity(total);
+                let mut lengths = Vec::with_capacity(len);
+                for row in 0..len {
+                    let (offset, n) = if vector.row_is_null(row as u64) { (0, 0) } else { list.get_entry(row) };
+                    values.extend_from_slice(&source[offset..offset + n]);
+                    value_nulls.extend((offset..offset + n).map(|k| !child.row_is_null(k as u64)));
+                    lengths.push(n);
+                }
+                let value_nulls = value_nulls.contains(&false).then(|| NullBuffer::from(value_nulls));
+                Arc::new(ListArray::try_new(
+                    item_field(),
+                    OffsetBuffer::from_lengths(lengths),
+                    Arc::new(Float64Array::new(values.into(), value_nulls)),
+                    row_nulls,
+                )?)
+            }
+            LogicalTypeId::Array => {
+                let array = chunk.array_vector(i);
+                let size = array.get_array_size() as usize;
+                let child = array.child(len * size);
+                require_double(&child, i)?;
+                let values = child.as_slice_with_len::<f64>(len * size).to_vec();
+                let value_nulls = nulls(len * size, |k| child.row_is_null(k as u64));
+                Arc::new(FixedSizeListArray::try_new(
+                    item_field(),
+                    size as i32,
+                    Arc::new(Float64Array::new(values.into(), value_nulls)),
+                    row_nulls,
+                )?)
+            }
+            _ => flat_vector_to_arrow_array(&mut vector, len)?,
+        };
+        columns.push((i.to_string(), array));
+    }
+    Ok(RecordBatch::try_from_iter(columns)?)
+}
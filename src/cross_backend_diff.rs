@@ -0,0 +1,264 @@
+//! Proving a Ported System Matches: Checksums and Diffs Across Backends
+//!
+//! When a system moves from SQL to Polars or to a hand-written Arrow/Lua
+//! kernel there was no way to show the results agree. Using `world_diff.rs`:
+//! 1. One tick of movement + regen in DuckDB SQL, Polars and a Rust kernel
+//!    over Arrow batches — checksums must be identical
+//! 2. Row order doesn't affect the checksum
+//! 3. A port that keeps positions in f32: checksum differs, diff within a
+//!    1e-4 tolerance is clean, with an exact tolerance every position differs
+//! 4. A port with an off-by-one regen cap: diff pinpoints ids and the column
+//! 5. Missing rows and extra columns
+//! 6. Cost of checksum/diff per source
+
+mod world_diff;
+
+use duckdb::arrow::array::{Array, ArrayRef, Float64Array, Int32Array};
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::{params, Connection};
+use polars::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+use std::time::Instant;
+use world_diff::{diff, TableData};
+
+const ROWS: usize = 500_000;
+const DT: f64 = 1.0 / 60.0;
+
+fn make_units() -> PolarsResult<DataFrame> {
+    let mut rng = StdRng::seed_from_u64(39);
+    let id: Vec<i64> = (0..ROWS as i64).collect();
+    let x: Vec<f64> = (0..ROWS).map(|_| rng.gen_range(0.0..1000.0)).collect();
+    let y: Vec<f64> = (0..ROWS).map(|_| rng.gen_range(0.0..1000.0)).collect();
+    let vx: Vec<f64> = (0..ROWS).map(|_| rng.gen_range(-5.0..5.0)).collect();
+    let vy: Vec<f64> = (0..ROWS).map(|_| rng.gen_range(-5.0..5.0)).collect();
+    let hp: Vec<i32> = (0..ROWS as i32).map(|i| 50 + i % 51).collect();
+    let max_hp = vec![100i32; ROWS];
+    df!["id" => id, "x" => x, "y" => y, "vx" => vx, "vy" => vy, "hp" => hp, "max_hp" => max_hp]
+}
+
+fn load_duckdb(conn: &Connection, units: &DataFrame) -> Result<(), Box<dyn std::error::Error>> {
+    conn.execute_batch(
+        "CREATE OR REPLACE TABLE units (id BIGINT, x DOUBLE, y DOUBLE, vx DOUBLE, vy DOUBLE, hp INTEGER, max_hp INTEGER);",
+    )?;
+    let f = |name: &str| -> PolarsResult<Vec<f64>> { Ok(units.column(name)?.f64()?.into_no_null_iter().collect()) };
+    let i = |name: &str| -> PolarsResult<Vec<i32>> { Ok(units.column(name)?.i32()?.into_no_null_iter().collect()) };
+    let id: Vec<i64> = units.column("id")?.i64()?.into_no_null_iter().collect();
+    let (x, y, vx, vy) = (f("x")?, f("y")?, f("vx")?, f("vy")?);
+    let (hp, max_hp) = (i("hp")?, i("max_hp")?);
+    let mut appender = conn.appender("units")?;
+    for r in 0..id.len() {
+        appender.append_row(params![id[r], x[r], y[r], vx[r], vy[r], hp[r], max_hp[r]])?;
+    }
+    appender.flush()?;
+    Ok(())
+}
+
+// ============================================================================
+// The system, three ways
+// ============================================================================
+
+fn tick_sql(conn: &Connection) -> duckdb::Result<()> {
+    conn.execute(
+        "UPDATE units SET x = x + vx * ?, y = y + vy * ?, hp = least(hp + 1, max_hp)",
+        params![DT, DT],
+    )?;
+    Ok(())
+}
+
+fn tick_polars(units: &DataFrame) -> PolarsResult<DataFrame> {
+    units
+        .clone()
+        .lazy()
+        .with_columns([
+            (col("x") + col("vx") * lit(DT)).alias("x"),
+            (col("y") + col("vy") * lit(DT)).alias("y"),
+            when((col("hp") + lit(1)).lt(col("max_hp")))
+                .then(col("hp") + lit(1))
+                .otherwise(col("max_hp"))
+                .alias("hp"),
+        ])
+        .collect()
+}
+
+fn tick_arrow(batches: &[RecordBatch]) -> Result<Vec<RecordBatch>, Box<dyn std::error::Error>> {
+    let mut out = Vec::with_capacity(batches.len());
+    for batch in batches {
+        let f64_col = |name: &str| {
+            batch.column_by_name(name).and_then(|c| c.as_any().downcast_ref::<Float64Array>()).ok_or(name.to_string())
+        };
+        let i32_col = |name: &str| {
+            batch.column_by_name(name).and_then(|c| c.as_any().downcast_ref::<Int32Array>()).ok_or(name.to_string())
+        };
+        let (x, y, vx, vy) = (f64_col("x")?, f64_col("y")?, f64_col("vx")?, f64_col("vy")?);
+        let (hp, max_hp) = (i32_col("hp")?, i32_col("max_hp")?);
+
+        let n = batch.num_rows();
+        let new_x: Float64Array = (0..n).map(|i| x.value(i) + vx.value(i) * DT).collect::<Vec<_>>().into();
+        let new_y: Float64Array = (0..n).map(|i| y.value(i) + vy.value(i) * DT).collect::<Vec<_>>().into();
+        let new_hp: Int32Array = (0..n).map(|i| (hp.value(i) + 1).min(max_hp.value(i))).collect::<Vec<_>>().into();
+
+        let columns: Vec<ArrayRef> = batch
+            .schema()
+            .fields()
+            .iter()
+            .zip(batch.columns())
+            .map(|(field, column)| match field.name().as_str() {
+                "x" => Arc::new(new_x.clone()) as ArrayRef,
+                "y" => Arc::new(new_y.clone()) as ArrayRef,
+                "hp" => Arc::new(new_hp.clone()) as ArrayRef,
+                _ => column.clone(),
+            })
+            .collect();
+        out.push(RecordBatch::try_new(batch.schema(), columns)?);
+    }
+    Ok(out)
+}
+
+// Buggy ports
+
+fn tick_polars_f32(units: &DataFrame) -> PolarsResult<DataFrame> {
+    tick_polars(units)?
+        .lazy()
+        .with_columns([col("x").cast(DataType::Float32), col("y").cast(DataType::Float32)])
+        .collect()
+}
+
+fn tick_polars_regen_off_by_one(units: &DataFrame) -> PolarsResult<DataFrame> {
+    tick_polars(units)?
+        .lazy()
+        .with_column(
+            when(col("hp").gt_eq(col("max_hp")))
+                .then(col("max_hp") - lit(1))
+                .otherwise(col("hp"))
+                .alias("hp"),
+        )
+        .collect()
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("=== Cross-Backend Checksums & Diffs ===\n");
+    println!("Rows: {}\n", ROWS);
+    let mut failures = 0;
+
+    let units = make_units()?;
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch("SET threads TO 8;")?;
+    load_duckdb(&conn, &units)?;
+
+    // --------------------------------------------------------------------
+    // 1. Same system, three implementations
+    // --------------------------------------------------------------------
+    println!("--- 1. Movement + regen: SQL vs Polars vs Arrow kernel ---");
+    let before: Vec<RecordBatch> = conn.prepare("SELECT * FROM units")?.query_arrow([])?.collect();
+    tick_sql(&conn)?;
+
+    let sql = TableData::from_duckdb(&conn, "units", "id")?;
+    let polars_df = tick_polars(&units)?;
+    let polars = TableData::from_polars(&polars_df, "id")?;
+    let arrow = TableData::from_arrow(&tick_arrow(&before)?, "id")?;
+
+    for (name, t) in [("DuckDB SQL", &sql), ("Polars", &polars), ("Arrow kernel", &arrow)] {
+        println!("  {:<14} {:016x}  ({} rows)", name, t.checksum(), t.rows());
+    }
+    let agree = sql.checksum() == polars.checksum() && sql.checksum() == arrow.checksum();
+    // A checksum match is probabilistic; an exact diff is the proof
+    let exact = diff(&sql, &polars, 0.0).is_empty() && diff(&sql, &arrow, 0.0).is_empty();
+    println!("  All three checksums agree: {}, exact diff clean: {}\n", agree, exact);
+    if !agree || !exact {
+        failures += 1;
+        diff(&sql, &polars, 0.0).print(5);
+        diff(&sql, &arrow, 0.0).print(5);
+    }
+
+    // --------------------------------------------------------------------
+    // 2. Row order
+    // --------------------------------------------------------------------
+    println!("--- 2. Row order ---");
+    let shuffled = polars_df.sort(["x"], SortMultipleOptions::default().with_order_descending(true))?;
+    let same = TableData::from_polars(&shuffled, "id")?.checksum() == polars.checksum();
+    println!("  Sorted by x desc, checksum unchanged: {}\n", same);
+    if !same {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 3. f32 positions
+    // --------------------------------------------------------------------
+    println!("--- 3. Port with f32 positions ---");
+    let f32_port = TableData::from_polars(&tick_polars_f32(&units)?, "id")?;
+    println!("  Checksum matches: {}", f32_port.checksum() == sql.checksum());
+    let loose = diff(&sql, &f32_port, 1e-4);
+    println!("  diff(tolerance 1e-4):");
+    loose.print(3);
+    let exact = diff(&sql, &f32_port, 0.0);
+    println!("  diff(tolerance 0):");
+    exact.print(3);
+    println!();
+    if !loose.is_empty() || exact.is_empty() {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 4. Off-by-one regen cap
+    // --------------------------------------------------------------------
+    println!("--- 4. Port with off-by-one regen cap ---");
+    let buggy = TableData::from_polars(&tick_polars_regen_off_by_one(&units)?, "id")?;
+    let d = diff(&sql, &buggy, 1e-9);
+    d.print(5);
+    let only_hp = d.by_column().keys().eq(["hp"].iter());
+    println!("  Only `hp` differs: {}\n", only_hp);
+    if !only_hp {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 5. Missing rows / extra columns
+    // --------------------------------------------------------------------
+    println!("--- 5. Missing rows and extra columns ---");
+    let trimmed = polars_df
+        .clone()
+        .lazy()
+        .filter((col("id") % lit(100_000i64)).neq(lit(0i64)))
+        .with_column((col("vx") * col("vx") + col("vy") * col("vy")).sqrt().alias("speed"))
+        .collect()?;
+    let d = diff(&sql, &TableData::from_polars(&trimmed, "id")?, 1e-9);
+    d.print(10);
+    if d.only_left.len() != ROWS / 100_000 || d.columns_only_right != ["speed"] || !d.mismatches.is_empty() {
+        failures += 1;
+    }
+    println!();
+
+    // --------------------------------------------------------------------
+    // 6. Cost
+    // --------------------------------------------------------------------
+    println!("--- 6. Cost ({} rows) ---", ROWS);
+    let start = Instant::now();
+    let t = TableData::from_duckdb(&conn, "units", "id")?;
+    let load_duck = start.elapsed();
+    let start = Instant::now();
+    let t_polars = TableData::from_polars(&polars_df, "id")?;
+    let load_polars = start.elapsed();
+    let start = Instant::now();
+    let _ = t.checksum();
+    let checksum_time = start.elapsed();
+    let start = Instant::now();
+    let _ = diff(&t, &t_polars, 1e-9);
+    let diff_time = start.elapsed();
+    println!("  Normalize from DuckDB: {:?}", load_duck);
+    println!("  Normalize from Polars: {:?}", load_polars);
+    println!("  checksum():            {:?}", checksum_time);
+    println!("  diff():                {:?}", diff_time);
+
+    println!("\n=== Summary ===\n");
+    println!("  • SQL, Polars and an Arrow kernel produce the same checksum (diff confirms cell by cell)");
+    println!("  • Checksums ignore row order; diffs match rows by entity id");
+    println!("  • Tolerance separates precision loss (f32) from logic bugs (off-by-one)");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
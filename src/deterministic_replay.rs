@@ -0,0 +1,428 @@
+//! Deterministic Replay: Record Once, Replay on Either Backend
+//!
+//! Polars and DuckDB results can't be compared today because the worlds come
+//! from `random()` and `rand::thread_rng()` (see `add_xoshiro_on_df` in
+//! `main.rs`). This records a session instead:
+//! 1. Initial world generated from a single seed (`StdRng`), not `random()`
+//! 2. Per-tick input events (impulses, damage) captured as they're applied
+//! 3. Per-tick world checksum (`world_diff.rs`): order-independent, over the
+//!    f64 bits with -0.0 and NaN canonicalized. A matching checksum is strong
+//!    evidence, not proof, of identical worlds; a mismatch is always real
+//! 4. Replay on DuckDB or Polars, stopping at the first tick whose checksum
+//!    differs from the recording
+//!
+//! Recordings round-trip through a small text file (floats stored as bits),
+//! so a session recorded on one backend can be replayed later on the other.
+
+mod world_diff;
+
+use duckdb::{params, Connection};
+use polars::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+use world_diff::TableData;
+
+const ENTITIES: usize = 20_000;
+const TICKS: usize = 600;
+const SEED: u64 = 0x5EED_2024;
+/// Power of two so `vx * DT` is exact and can't round differently anywhere.
+const DT: f64 = 1.0 / 16.0;
+const INPUTS_PER_TICK: usize = 50;
+
+// ============================================================================
+// Recording
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Input {
+    Impulse { id: i64, dvx: f64, dvy: f64 },
+    Damage { id: i64, amount: i32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Recording {
+    seed: u64,
+    entities: usize,
+    /// Inputs applied on each tick, and the checksum after that tick.
+    ticks: Vec<(Vec<Input>, u64)>,
+}
+
+impl Recording {
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut out = format!("seed {}\nentities {}\n", self.seed, self.entities);
+        for (tick, (inputs, checksum)) in self.ticks.iter().enumerate() {
+            out.push_str(&format!("tick {} {:016x}\n", tick + 1, checksum));
+            for input in inputs {
+                match input {
+                    Input::Impulse { id, dvx, dvy } => {
+                        out.push_str(&format!("impulse {} {:016x} {:016x}\n", id, dvx.to_bits(), dvy.to_bits()))
+                    }
+                    Input::Damage { id, amount } => out.push_str(&format!("damage {} {}\n", id, amount)),
+                }
+            }
+        }
+        fs::write(path, out)
+    }
+
+    fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let mut rec = Recording { seed: 0, entities: 0, ticks: Vec::new() };
+        for line in text.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let bits = |s: &str| -> Result<u64, Box<dyn Error>> { Ok(u64::from_str_radix(s, 16)?) };
+            match parts.as_slice() {
+                ["seed", seed] => rec.seed = seed.parse()?,
+                ["entities", n] => rec.entities = n.parse()?,
+                ["tick", _, checksum] => rec.ticks.push((Vec::new(), bits(checksum)?)),
+                ["impulse", id, dvx, dvy] => current_tick(&mut rec, line)?.push(Input::Impulse {
+                    id: id.parse()?,
+                    dvx: f64::from_bits(bits(dvx)?),
+                    dvy: f64::from_bits(bits(dvy)?),
+                }),
+                ["damage", id, amount] => current_tick(&mut rec, line)?.push(Input::Damage {
+                    id: id.parse()?,
+                    amount: amount.parse()?,
+                }),
+                _ => return Err(format!("bad recording line: {:?}", line).into()),
+            }
+        }
+        Ok(rec)
+    }
+}
+
+fn current_tick<'a>(rec: &'a mut Recording, line: &str) -> Result<&'a mut Vec<Input>, Box<dyn Error>> {
+    rec.ticks
+        .last_mut()
+        .map(|(inputs, _)| inputs)
+        .ok_or_else(|| format!("input before first tick: {:?}", line).into())
+}
+
+/// Stand-in for player input: at most one impulse and one damage event per
+/// entity per tick, so no backend has to pick an order to apply them in.
+fn generate_inputs(rng: &mut StdRng, entities: usize) -> Vec<Input> {
+    let mut inputs = Vec::with_capacity(INPUTS_PER_TICK);
+    let mut pushed = HashSet::new();
+    let mut hit = HashSet::new();
+    for _ in 0..INPUTS_PER_TICK {
+        let id = rng.gen_range(0..entities as i64);
+        if rng.gen_bool(0.5) {
+            if pushed.insert(id) {
+                inputs.push(Input::Impulse { id, dvx: rng.gen_range(-2.0..2.0), dvy: rng.gen_range(-2.0..2.0) });
+            }
+        } else if hit.insert(id) {
+            inputs.push(Input::Damage { id, amount: rng.gen_range(20..80) });
+        }
+    }
+    inputs
+}
+
+// ============================================================================
+// Initial world (shared by both backends)
+// ============================================================================
+
+struct InitialWorld {
+    id: Vec<i64>,
+    x: Vec<f64>,
+    y: Vec<f64>,
+    vx: Vec<f64>,
+    vy: Vec<f64>,
+    hp: Vec<i32>,
+}
+
+fn initial_world(seed: u64, entities: usize) -> InitialWorld {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut w = InitialWorld { id: vec![], x: vec![], y: vec![], vx: vec![], vy: vec![], hp: vec![] };
+    for i in 0..entities {
+        w.id.push(i as i64);
+        w.x.push(rng.gen_range(0.0..1000.0));
+        w.y.push(rng.gen_range(0.0..1000.0));
+        w.vx.push(rng.gen_range(-1.0..1.0));
+        w.vy.push(rng.gen_range(-1.0..1.0));
+        w.hp.push(100);
+    }
+    w
+}
+
+// ============================================================================
+// Backends
+// ============================================================================
+
+/// The same simulation rules on each backend:
+///   impulse: vx += dvx, vy += dvy
+///   damage:  hp = max(hp - amount, 0)
+///   then:    x += vx * DT, y += vy * DT
+trait ReplayBackend {
+    fn name(&self) -> &'static str;
+    fn reset(&mut self, world: &InitialWorld) -> Result<(), Box<dyn Error>>;
+    fn step(&mut self, inputs: &[Input]) -> Result<(), Box<dyn Error>>;
+    fn checksum(&mut self) -> Result<u64, Box<dyn Error>>;
+}
+
+struct DuckDbBackend {
+    conn: Connection,
+}
+
+impl DuckDbBackend {
+    fn new() -> duckdb::Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(
+            "SET threads TO 8;
+             CREATE TABLE tick_impulses (id BIGINT, dvx DOUBLE, dvy DOUBLE);
+             CREATE TABLE tick_damage (id BIGINT, amount INTEGER);",
+        )?;
+        Ok(DuckDbBackend { conn })
+    }
+}
+
+impl ReplayBackend for DuckDbBackend {
+    fn name(&self) -> &'static str {
+        "DuckDB"
+    }
+
+    fn reset(&mut self, w: &InitialWorld) -> Result<(), Box<dyn Error>> {
+        self.conn.execute_batch(
+            "CREATE OR REPLACE TABLE units (id BIGINT, x DOUBLE, y DOUBLE, vx DOUBLE, vy DOUBLE, hp INTEGER);",
+        )?;
+        let mut appender = self.conn.appender("units")?;
+        for i in 0..w.id.len() {
+            appender.append_row(params![w.id[i], w.x[i], w.y[i], w.vx[i], w.vy[i], w.hp[i]])?;
+        }
+        appender.flush()?;
+        Ok(())
+    }
+
+    fn step(&mut self, inputs: &[Input]) -> Result<(), Box<dyn Error>> {
+        self.conn.execute_batch("DELETE FROM tick_impulses; DELETE FROM tick_damage;")?;
+        {
+            let mut impulses = self.conn.appender("tick_impulses")?;
+            let mut damage = self.conn.appender("tick_damage")?;
+            for input in inputs {
+                match *input {
+                    Input::Impulse { id, dvx, dvy } => impulses.append_row(params![id, dvx, dvy])?,
+                    Input::Damage { id, amount } => damage.append_row(params![id, amount])?,
+                }
+            }
+            impulses.flush()?;
+            damage.flush()?;
+        }
+        // The two input joins are re-planned every tick: a cached plan kept
+        // the id range of the tick it was prepared on and silently skipped
+        // later ids outside it. The integration step has no join and is cached.
+        self.conn.execute(
+            "UPDATE units SET vx = units.vx + i.dvx, vy = units.vy + i.dvy
+             FROM tick_impulses i WHERE units.id = i.id",
+            [],
+        )?;
+        self.conn.execute(
+            "UPDATE units SET hp = greatest(units.hp - d.amount, 0)
+             FROM tick_damage d WHERE units.id = d.id",
+            [],
+        )?;
+        let mut stmt = self.conn.prepare_cached("UPDATE units SET x = x + vx * ?, y = y + vy * ?")?;
+        stmt.execute(params![DT, DT])?;
+        Ok(())
+    }
+
+    fn checksum(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(TableData::from_duckdb(&self.conn, "units", "id")?.checksum())
+    }
+}
+
+struct PolarsBackend {
+    units: DataFrame,
+    /// Deliberate bug for the divergence demo: forget to clamp hp at 0.
+    clamp_hp: bool,
+}
+
+impl PolarsBackend {
+    fn new(clamp_hp: bool) -> Self {
+        PolarsBackend { units: DataFrame::empty(), clamp_hp }
+    }
+}
+
+impl ReplayBackend for PolarsBackend {
+    fn name(&self) -> &'static str {
+        if self.clamp_hp { "Polars" } else { "Polars (no hp clamp)" }
+    }
+
+    fn reset(&mut self, w: &InitialWorld) -> Result<(), Box<dyn Error>> {
+        self.units = df![
+            "id" => &w.id,
+            "x" => &w.x,
+            "y" => &w.y,
+            "vx" => &w.vx,
+            "vy" => &w.vy,
+            "hp" => &w.hp,
+        ]?;
+        Ok(())
+    }
+
+    fn step(&mut self, inputs: &[Input]) -> Result<(), Box<dyn Error>> {
+        let (mut imp_id, mut imp_dvx, mut imp_dvy) = (vec![], vec![], vec![]);
+        let (mut dmg_id, mut dmg_amount) = (vec![], vec![]);
+        for input in inputs {
+            match *input {
+                Input::Impulse { id, dvx, dvy } => {
+                    imp_id.push(id);
+                    imp_dvx.push(dvx);
+                    imp_dvy.push(dvy);
+                }
+                Input::Damage { id, amount } => {
+                    dmg_id.push(id);
+                    dmg_amount.push(amount);
+                }
+            }
+        }
+        let impulses = df!["id" => imp_id, "dvx" => imp_dvx, "dvy" => imp_dvy]?;
+        let damage = df!["id" => dmg_id, "amount" => dmg_amount]?;
+
+        let damaged = col("hp") - col("amount");
+        let damaged = if self.clamp_hp {
+            when(damaged.clone().lt(lit(0))).then(lit(0)).otherwise(damaged)
+        } else {
+            damaged
+        };
+        // when/then rather than fill_null(0): adding 0.0 would turn -0.0 into
+        // 0.0 and change the bits for untouched rows.
+        self.units = std::mem::replace(&mut self.units, DataFrame::empty())
+            .lazy()
+            .join(impulses.lazy(), [col("id")], [col("id")], JoinArgs::new(JoinType::Left))
+            .join(damage.lazy(), [col("id")], [col("id")], JoinArgs::new(JoinType::Left))
+            .with_columns([
+                when(col("dvx").is_null()).then(col("vx")).otherwise(col("vx") + col("dvx")).alias("vx"),
+                when(col("dvy").is_null()).then(col("vy")).otherwise(col("vy") + col("dvy")).alias("vy"),
+                when(col("amount").is_null()).then(col("hp")).otherwise(damaged).alias("hp"),
+            ])
+            .with_columns([
+                (col("x") + col("vx") * lit(DT)).alias("x"),
+                (col("y") + col("vy") * lit(DT)).alias("y"),
+            ])
+            .select([col("id"), col("x"), col("y"), col("vx"), col("vy"), col("hp")])
+            .collect()?;
+        Ok(())
+    }
+
+    fn checksum(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(TableData::from_polars(&self.units, "id")?.checksum())
+    }
+}
+
+// ============================================================================
+// Record / replay
+// ============================================================================
+
+fn record(backend: &mut dyn ReplayBackend, seed: u64, entities: usize, ticks: usize) -> Result<Recording, Box<dyn Error>> {
+    backend.reset(&initial_world(seed, entities))?;
+    // Inputs get their own stream so the initial world doesn't shift them.
+    let mut input_rng = StdRng::seed_from_u64(seed.wrapping_add(1));
+    let mut rec = Recording { seed, entities, ticks: Vec::with_capacity(ticks) };
+    for _ in 0..ticks {
+        let inputs = generate_inputs(&mut input_rng, entities);
+        backend.step(&inputs)?;
+        rec.ticks.push((inputs, backend.checksum()?));
+    }
+    Ok(rec)
+}
+
+#[derive(Debug)]
+struct Divergence {
+    tick: usize,
+    expected: u64,
+    actual: u64,
+}
+
+/// Replays `rec` and returns the first tick whose checksum differs, if any.
+fn replay(backend: &mut dyn ReplayBackend, rec: &Recording) -> Result<Option<Divergence>, Box<dyn Error>> {
+    backend.reset(&initial_world(rec.seed, rec.entities))?;
+    for (i, (inputs, expected)) in rec.ticks.iter().enumerate() {
+        backend.step(inputs)?;
+        let actual = backend.checksum()?;
+        if actual != *expected {
+            return Ok(Some(Divergence { tick: i + 1, expected: *expected, actual }));
+        }
+    }
+    Ok(None)
+}
+
+fn report(backend: &mut dyn ReplayBackend, rec: &Recording) -> Result<Option<Divergence>, Box<dyn Error>> {
+    let start = Instant::now();
+    let result = replay(backend, rec)?;
+    let elapsed = start.elapsed();
+    match &result {
+        None => println!("  {:<22} all {} ticks match ({:?})", backend.name(), rec.ticks.len(), elapsed),
+        Some(d) => println!("  {:<22} diverged at tick {}: expected {:016x}, got {:016x}",
+                            backend.name(), d.tick, d.expected, d.actual),
+    }
+    Ok(result)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== Deterministic Replay ===\n");
+    println!("Entities: {}, ticks: {}, seed: {:#x}\n", ENTITIES, TICKS, SEED);
+    let mut failures = 0;
+
+    // --------------------------------------------------------------------
+    // 1. Record on DuckDB, save, load
+    // --------------------------------------------------------------------
+    println!("--- 1. Record on DuckDB ---");
+    let start = Instant::now();
+    let rec = record(&mut DuckDbBackend::new()?, SEED, ENTITIES, TICKS)?;
+    println!("  Recorded in {:?}", start.elapsed());
+
+    let path = std::env::temp_dir().join("polars_ecs_replay.txt");
+    rec.save(&path)?;
+    let loaded = Recording::load(&path)?;
+    let size = fs::metadata(&path)?.len();
+    let _ = fs::remove_file(&path);
+    let inputs: usize = rec.ticks.iter().map(|(i, _)| i.len()).sum();
+    println!("  {} inputs, {:.1} KB on disk, round-trips exactly: {}\n",
+             inputs, size as f64 / 1024.0, loaded == rec);
+    if loaded != rec {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 2. Replay on both backends
+    // --------------------------------------------------------------------
+    println!("--- 2. Replay ---");
+    if report(&mut DuckDbBackend::new()?, &loaded)?.is_some() {
+        failures += 1;
+    }
+    if report(&mut PolarsBackend::new(true), &loaded)?.is_some() {
+        failures += 1;
+    }
+
+    let polars_rec = record(&mut PolarsBackend::new(true), SEED, ENTITIES, TICKS)?;
+    let same = polars_rec == rec;
+    println!("  Recording made on Polars is identical to DuckDB's: {}\n", same);
+    if !same {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 3. A ported system with a bug
+    // --------------------------------------------------------------------
+    println!("--- 3. Divergence detection ---");
+    let divergence = report(&mut PolarsBackend::new(false), &loaded)?;
+    if let Some(d) = &divergence {
+        println!("  Caught at tick {} of {}: the first tick where some hp would go below 0", d.tick, TICKS);
+    } else {
+        failures += 1;
+    }
+
+    println!("\n=== Summary ===\n");
+    println!("  • Seeded world + recorded inputs replay on DuckDB and Polars with every per-tick checksum matching");
+    println!("  • Checksums are order-independent, so row order doesn't matter");
+    println!("  • A ported system that differs is caught at the first divergent tick");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,370 @@
+//! Archetype-Style Table Partitioning for the DuckDB ECS
+//!
+//! Every other benchmark keeps all entities in one wide `entities` table, so
+//! adding a component is an ALTER TABLE (~37 ms per change in
+//! `duckdb_bench.rs::bench_component_operations`) that touches every row.
+//!
+//! Archetype mode instead:
+//! 1. Entities with the same component set live in one table (`arch_position_velocity`)
+//! 2. Adding/removing a component moves the matching rows between archetype
+//!    tables in bulk (INSERT ... SELECT + DELETE in one transaction)
+//! 3. Queries fan out over every archetype that has the required components
+//!    with UNION ALL; updates run once per matching archetype table
+
+use duckdb::{Connection, Result};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+const SIZE: usize = 1_000_000; // 1M entities
+
+// ============================================================================
+// Component registry
+// ============================================================================
+
+/// A component is a named group of columns; `default` is the SQL expression
+/// used to initialize the column when the component is added.
+struct ComponentDef {
+    name: &'static str,
+    columns: &'static [Column],
+}
+
+struct Column {
+    name: &'static str,
+    sql_type: &'static str,
+    default: &'static str,
+}
+
+const POSITION: ComponentDef = ComponentDef {
+    name: "position",
+    columns: &[
+        Column { name: "x", sql_type: "FLOAT", default: "(random() * 1000)::FLOAT" },
+        Column { name: "y", sql_type: "FLOAT", default: "(random() * 1000)::FLOAT" },
+    ],
+};
+
+const VELOCITY: ComponentDef = ComponentDef {
+    name: "velocity",
+    columns: &[
+        Column { name: "vx", sql_type: "FLOAT", default: "1.0::FLOAT" },
+        Column { name: "vy", sql_type: "FLOAT", default: "1.0::FLOAT" },
+    ],
+};
+
+const HEALTH: ComponentDef = ComponentDef {
+    name: "health",
+    columns: &[
+        Column { name: "hp", sql_type: "INTEGER", default: "100" },
+        Column { name: "max_hp", sql_type: "INTEGER", default: "100" },
+    ],
+};
+
+const BURNING: ComponentDef = ComponentDef {
+    name: "burning",
+    columns: &[
+        Column { name: "burn_dps", sql_type: "FLOAT", default: "5.0::FLOAT" },
+        Column { name: "burn_ticks", sql_type: "INTEGER", default: "60" },
+        Column { name: "burn_source", sql_type: "BIGINT", default: "-1" },
+    ],
+};
+
+/// Bitset of component ids.
+type Mask = u32;
+
+// ============================================================================
+// Archetype world
+// ============================================================================
+
+struct ArchetypeWorld<'c> {
+    conn: &'c Connection,
+    components: Vec<&'static ComponentDef>,
+    /// Component set -> table name, for every archetype created so far.
+    archetypes: BTreeMap<Mask, String>,
+}
+
+impl<'c> ArchetypeWorld<'c> {
+    fn new(conn: &'c Connection) -> Self {
+        Self { conn, components: Vec::new(), archetypes: BTreeMap::new() }
+    }
+
+    fn register(&mut self, def: &'static ComponentDef) -> Mask {
+        assert!(self.components.len() < Mask::BITS as usize, "too many components");
+        self.components.push(def);
+        1 << (self.components.len() - 1)
+    }
+
+    /// Components of `mask` in registration order.
+    fn components_in(&self, mask: Mask) -> Vec<&'static ComponentDef> {
+        self.components
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| mask & (1 << bit) != 0)
+            .map(|(_, c)| *c)
+            .collect()
+    }
+
+    /// Table for a component set, created on first use.
+    fn archetype_table(&mut self, mask: Mask) -> Result<String> {
+        if let Some(table) = self.archetypes.get(&mask) {
+            return Ok(table.clone());
+        }
+        let comps = self.components_in(mask);
+        let table = if comps.is_empty() {
+            "arch_empty".to_string()
+        } else {
+            let names: Vec<&str> = comps.iter().map(|c| c.name).collect();
+            format!("arch_{}", names.join("_"))
+        };
+        let mut cols = vec!["id BIGINT".to_string()];
+        for c in &comps {
+            cols.extend(c.columns.iter().map(|col| format!("{} {}", col.name, col.sql_type)));
+        }
+        self.conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} ({});",
+            table,
+            cols.join(", ")
+        ))?;
+        self.archetypes.insert(mask, table.clone());
+        Ok(table)
+    }
+
+    /// Run `f` in one transaction; a failure rolls everything it did back,
+    /// including archetype tables it created.
+    fn atomically<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let archetypes = self.archetypes.clone();
+        self.conn.execute_batch("BEGIN TRANSACTION;")?;
+        match f(self).and_then(|v| self.conn.execute_batch("COMMIT;").map(|_| v)) {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                let _ = self.conn.execute_batch("ROLLBACK;");
+                self.archetypes = archetypes;
+                Err(e)
+            }
+        }
+    }
+
+    /// Spawn `count` entities with ids `first_id..` and default component values.
+    fn spawn_batch(&mut self, mask: Mask, first_id: usize, count: usize) -> Result<()> {
+        let table = self.archetype_table(mask)?;
+        let mut exprs = vec!["i".to_string()];
+        for c in self.components_in(mask) {
+            exprs.extend(c.columns.iter().map(|col| col.default.to_string()));
+        }
+        self.conn.execute_batch(&format!(
+            "INSERT INTO {} SELECT {} FROM range({}, {}) AS t(i);",
+            table,
+            exprs.join(", "),
+            first_id,
+            first_id + count
+        ))
+    }
+
+    /// Move rows matching `predicate` from archetype `from` to archetype `to`.
+    /// Columns present in both are copied, new columns get their defaults and
+    /// dropped columns are discarded. Returns the number of rows moved.
+    /// Runs inside the caller's transaction.
+    fn move_rows(&mut self, from: Mask, to: Mask, predicate: &str) -> Result<usize> {
+        let src = self.archetype_table(from)?;
+        let dst = self.archetype_table(to)?;
+
+        let mut dst_cols = vec!["id".to_string()];
+        let mut src_exprs = vec!["id".to_string()];
+        for c in self.components_in(to) {
+            let carried = self.components_in(from).iter().any(|f| f.name == c.name);
+            for col in c.columns {
+                dst_cols.push(col.name.to_string());
+                src_exprs.push(if carried { col.name.to_string() } else { col.default.to_string() });
+            }
+        }
+
+        // Materialize the selection first so a non-deterministic predicate
+        // can't select different rows for the INSERT and the DELETE.
+        self.conn.execute_batch(&format!(
+            "CREATE OR REPLACE TEMP TABLE _moving AS SELECT id FROM {src} WHERE {predicate};
+             INSERT INTO {dst} ({}) SELECT {} FROM {src} WHERE id IN (SELECT id FROM _moving);
+             DELETE FROM {src} WHERE id IN (SELECT id FROM _moving);",
+            dst_cols.join(", "),
+            src_exprs.join(", ")
+        ))?;
+        let moved: i64 = self.conn.query_row("SELECT COUNT(*) FROM _moving", [], |r| r.get(0))?;
+        Ok(moved as usize)
+    }
+
+    /// Add `component` to every entity (in any archetype lacking it) matching `predicate`.
+    fn add_component(&mut self, component: Mask, predicate: &str) -> Result<usize> {
+        let sources: Vec<Mask> = self.archetypes.keys().copied().filter(|m| m & component == 0).collect();
+        self.atomically(|w| {
+            let mut moved = 0;
+            for from in sources {
+                moved += w.move_rows(from, from | component, predicate)?;
+            }
+            Ok(moved)
+        })
+    }
+
+    /// Remove `component` from every entity that has it and matches `predicate`.
+    fn remove_component(&mut self, component: Mask, predicate: &str) -> Result<usize> {
+        let sources: Vec<Mask> = self.archetypes.keys().copied().filter(|m| m & component != 0).collect();
+        self.atomically(|w| {
+            let mut moved = 0;
+            for from in sources {
+                moved += w.move_rows(from, from & !component, predicate)?;
+            }
+            Ok(moved)
+        })
+    }
+
+    /// Archetype tables containing every component in `required`.
+    fn matching_tables(&self, required: Mask) -> Vec<&str> {
+        self.archetypes
+            .iter()
+            .filter(|(mask, _)| *mask & required == required)
+            .map(|(_, table)| table.as_str())
+            .collect()
+    }
+
+    /// UNION ALL over matching archetypes; `columns` must belong to `required`.
+    fn query_sql(&self, required: Mask, columns: &[&str], filter: Option<&str>) -> String {
+        let where_clause = filter.map(|f| format!(" WHERE {}", f)).unwrap_or_default();
+        let branches: Vec<String> = self
+            .matching_tables(required)
+            .iter()
+            .map(|t| format!("SELECT {} FROM {}{}", columns.join(", "), t, where_clause))
+            .collect();
+        if branches.is_empty() {
+            // Keep the result shape valid when no archetype matches yet.
+            return format!("SELECT {} WHERE false", columns.iter().map(|_| "NULL").collect::<Vec<_>>().join(", "));
+        }
+        branches.join("\nUNION ALL\n")
+    }
+
+    /// System-style UPDATE, run once per matching archetype table.
+    fn update(&self, required: Mask, set_clause: &str, filter: Option<&str>) -> Result<()> {
+        let where_clause = filter.map(|f| format!(" WHERE {}", f)).unwrap_or_default();
+        let sql: Vec<String> = self
+            .matching_tables(required)
+            .iter()
+            .map(|t| format!("UPDATE {} SET {}{};", t, set_clause, where_clause))
+            .collect();
+        self.conn.execute_batch(&sql.join("\n"))
+    }
+
+    fn table_sizes(&self) -> Result<Vec<(String, i64)>> {
+        let mut sizes = Vec::new();
+        for table in self.archetypes.values() {
+            let n: i64 = self.conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |r| r.get(0))?;
+            sizes.push((table.clone(), n));
+        }
+        Ok(sizes)
+    }
+}
+
+// ============================================================================
+// Benchmarks
+// ============================================================================
+
+fn main() -> Result<()> {
+    println!("=== DuckDB Archetype Partitioning ===\n");
+    println!("Entity count: {}\n", SIZE);
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch("SET threads TO 8;")?;
+
+    // --------------------------------------------------------------------
+    // Baseline: one wide table, component add = ALTER TABLE
+    // --------------------------------------------------------------------
+    println!("--- Baseline: Wide Table + ALTER TABLE ---");
+    conn.execute_batch(&format!(
+        "CREATE TABLE entities AS
+         SELECT i AS id,
+                (random() * 1000)::FLOAT AS x, (random() * 1000)::FLOAT AS y,
+                1.0::FLOAT AS vx, 1.0::FLOAT AS vy,
+                100 AS hp, 100 AS max_hp
+         FROM range(0, {SIZE}) AS t(i);"
+    ))?;
+
+    let start = Instant::now();
+    conn.execute_batch(
+        "ALTER TABLE entities ADD COLUMN burn_dps FLOAT DEFAULT NULL;
+         ALTER TABLE entities ADD COLUMN burn_ticks INTEGER DEFAULT NULL;
+         ALTER TABLE entities ADD COLUMN burn_source BIGINT DEFAULT NULL;
+         UPDATE entities SET burn_dps = 5.0, burn_ticks = 60, burn_source = -1 WHERE id % 10 = 0;",
+    )?;
+    let wide_add = start.elapsed();
+    println!("  Add 'burning' to 10%: {:?}", wide_add);
+
+    let start = Instant::now();
+    let wide_burning: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM entities WHERE burn_ticks IS NOT NULL",
+        [],
+        |r| r.get(0),
+    )?;
+    println!("  Query burning entities: {:?} ({} rows)\n", start.elapsed(), wide_burning);
+
+    // --------------------------------------------------------------------
+    // Archetype world
+    // --------------------------------------------------------------------
+    println!("--- Archetype Mode ---");
+    let mut world = ArchetypeWorld::new(&conn);
+    let position = world.register(&POSITION);
+    let velocity = world.register(&VELOCITY);
+    let health = world.register(&HEALTH);
+    let burning = world.register(&BURNING);
+
+    // 80% movers with health, 20% static props with just a position
+    let movers = SIZE * 8 / 10;
+    let start = Instant::now();
+    world.spawn_batch(position | velocity | health, 0, movers)?;
+    world.spawn_batch(position, movers, SIZE - movers)?;
+    println!("  Spawn {} entities into 2 archetypes: {:?}", SIZE, start.elapsed());
+
+    // Add component: rows move between archetype tables in bulk
+    let start = Instant::now();
+    let moved = world.add_component(burning, "id % 10 = 0")?;
+    let arch_add = start.elapsed();
+    println!("  Add 'burning' to 10%: {:?} ({} rows moved)  {:.1}× vs ALTER TABLE",
+             arch_add, moved, wide_add.as_secs_f64() / arch_add.as_secs_f64());
+
+    // Query: fan out over matching archetypes
+    let burning_sql = world.query_sql(burning, &["id", "burn_ticks"], None);
+    let start = Instant::now();
+    let arch_burning: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM ({})", burning_sql),
+        [],
+        |r| r.get(0),
+    )?;
+    println!("  Query burning entities: {:?} ({} rows, {} archetypes)",
+             start.elapsed(), arch_burning, world.matching_tables(burning).len());
+
+    // System: movement touches only archetypes with position + velocity
+    let iterations = 60;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        world.update(position | velocity, "x = x + vx * 0.016667, y = y + vy * 0.016667", None)?;
+    }
+    println!("  Movement system: {:.2} ms/tick over {} archetypes",
+             start.elapsed().as_secs_f64() * 1000.0 / iterations as f64,
+             world.matching_tables(position | velocity).len());
+
+    // Burn system: tick down, then drop the component once it expires
+    world.update(burning | health, "hp = hp - 1, burn_ticks = burn_ticks - 60", None)?;
+    let start = Instant::now();
+    let expired = world.remove_component(burning, "burn_ticks <= 0")?;
+    println!("  Remove expired 'burning': {:?} ({} rows moved)\n", start.elapsed(), expired);
+
+    println!("  Archetype tables:");
+    for (table, rows) in world.table_sizes()? {
+        println!("    {:<40} {:>8} rows", table, rows);
+    }
+
+    let positions = world.query_sql(position, &["id", "x", "y"], None);
+    let total: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM ({})", positions), [], |r| r.get(0))?;
+    println!("\n  Entities with position: {} (expected {})", total, SIZE);
+    println!("  Burning counts match: {}", arch_burning == wide_burning);
+
+    println!("\n=== Summary ===\n");
+    println!("  • Component add/remove touches only the moved rows, not the whole world");
+    println!("  • Queries pay one UNION ALL branch per matching archetype");
+    println!("  • Systems run one UPDATE per archetype table they match");
+
+    Ok(())
+}
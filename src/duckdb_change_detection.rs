@@ -0,0 +1,298 @@
+//! Change Detection for the DuckDB ECS
+//!
+//! `arrow_zerocopy_test.rs` hand-adds a `modified_tick` column to pull only
+//! changed entities. This makes change tracking part of the world:
+//! 1. Each component gets `<comp>_added` / `<comp>_changed` tick columns
+//! 2. Every write goes through `ChangeWorld`, which stamps the change tick
+//! 3. Systems query `Changed(position)` / `Added(health)` relative to their
+//!    own last run (a monotonic change tick, like Bevy's)
+//! 4. A Rust-side spatial grid pulls only the delta as Arrow batches
+
+use duckdb::arrow::array::{Array, Float64Array, Int64Array};
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::{Connection, Result};
+use std::collections::HashMap;
+use std::time::Instant;
+
+const SIZE: usize = 100_000; // 100K entities
+const CELL_SIZE: f64 = 10.0;
+
+// ============================================================================
+// Change-tracked world
+// ============================================================================
+
+struct Component {
+    name: &'static str,
+    columns: &'static [(&'static str, &'static str)], // (name, SQL type)
+}
+
+const POSITION: Component = Component { name: "position", columns: &[("x", "DOUBLE"), ("y", "DOUBLE")] };
+const VELOCITY: Component = Component { name: "velocity", columns: &[("vx", "DOUBLE"), ("vy", "DOUBLE")] };
+const HEALTH: Component = Component { name: "health", columns: &[("hp", "INTEGER")] };
+
+#[derive(Clone, Copy)]
+enum Filter {
+    /// Component added or written since the system last ran.
+    Changed(&'static Component),
+    /// Component added since the system last ran.
+    Added(&'static Component),
+}
+
+struct ChangeWorld<'c> {
+    conn: &'c Connection,
+    /// Bumped on every write and every system run; never reused.
+    change_tick: i64,
+    last_run: HashMap<&'static str, i64>,
+    /// Components with tick columns in `entities`; filters may only name these.
+    components: Vec<&'static str>,
+}
+
+impl<'c> ChangeWorld<'c> {
+    fn new(conn: &'c Connection, components: &[&'static Component]) -> Result<Self> {
+        let mut cols = vec!["id BIGINT PRIMARY KEY".to_string()];
+        for c in components {
+            cols.extend(c.columns.iter().map(|(name, ty)| format!("{} {}", name, ty)));
+            cols.push(format!("{}_added BIGINT", c.name));
+            cols.push(format!("{}_changed BIGINT", c.name));
+        }
+        conn.execute_batch(&format!(
+            "DROP TABLE IF EXISTS entities;
+             CREATE TABLE entities ({});",
+            cols.join(", ")
+        ))?;
+        let components = components.iter().map(|c| c.name).collect();
+        Ok(Self { conn, change_tick: 0, last_run: HashMap::new(), components })
+    }
+
+    fn bump(&mut self) -> i64 {
+        self.change_tick += 1;
+        self.change_tick
+    }
+
+    /// Spawn entities `0..count` from a SELECT over `range(count) t(i)` that
+    /// yields the columns of `components`; every component counts as added.
+    fn spawn(&mut self, count: usize, components: &[&Component], select: &str) -> Result<()> {
+        let tick = self.bump();
+        // `select` yields every component's columns before the stamps, so
+        // the tick columns go after all of them.
+        let mut cols = vec!["id".to_string()];
+        let mut stamp_cols = Vec::new();
+        let mut stamps = Vec::new();
+        for c in components {
+            cols.extend(c.columns.iter().map(|(name, _)| name.to_string()));
+            stamp_cols.push(format!("{}_added", c.name));
+            stamp_cols.push(format!("{}_changed", c.name));
+            stamps.push(format!("{tick}, {tick}"));
+        }
+        cols.extend(stamp_cols);
+        self.conn.execute_batch(&format!(
+            "INSERT INTO entities ({}) SELECT i, {}, {} FROM range({}) t(i);",
+            cols.join(", "),
+            select,
+            stamps.join(", "),
+            count
+        ))
+    }
+
+    /// Write component columns; stamps `<comp>_changed` on every touched row.
+    fn write(&mut self, component: &Component, set_clause: &str, filter: &str) -> Result<usize> {
+        let tick = self.bump();
+        self.conn.execute(
+            &format!(
+                "UPDATE entities SET {set_clause}, {c}_changed = {tick}
+                 WHERE {c}_added IS NOT NULL AND ({filter})",
+                c = component.name
+            ),
+            [],
+        )
+    }
+
+    /// Insert a component on entities that don't have it yet.
+    fn add(&mut self, component: &Component, set_clause: &str, filter: &str) -> Result<usize> {
+        let tick = self.bump();
+        self.conn.execute(
+            &format!(
+                "UPDATE entities SET {set_clause}, {c}_added = {tick}, {c}_changed = {tick}
+                 WHERE {c}_added IS NULL AND ({filter})",
+                c = component.name
+            ),
+            [],
+        )
+    }
+
+    /// Errors if a filter names a component the world has no tick columns for.
+    fn filter_sql(&self, system: &str, filters: &[Filter]) -> Result<String> {
+        let since = self.last_run.get(system).copied().unwrap_or(0);
+        let mut preds = Vec::new();
+        for f in filters {
+            let (c, column) = match f {
+                Filter::Changed(c) => (c, "changed"),
+                Filter::Added(c) => (c, "added"),
+            };
+            if !self.components.contains(&c.name) {
+                return Err(duckdb::Error::InvalidParameterName(format!(
+                    "system '{}' filters on unregistered component '{}'",
+                    system, c.name
+                )));
+            }
+            preds.push(format!("{}_{} > {}", c.name, column, since));
+        }
+        Ok(if preds.is_empty() { "true".to_string() } else { preds.join(" AND ") })
+    }
+
+    /// Rows matching `filters` since `system` last ran, as Arrow batches.
+    /// Marks the system as having run.
+    fn run_query_arrow(&mut self, system: &'static str, columns: &[&str], filters: &[Filter]) -> Result<Vec<RecordBatch>> {
+        let sql = format!(
+            "SELECT {} FROM entities WHERE {}",
+            columns.join(", "),
+            self.filter_sql(system, filters)?
+        );
+        let batches = self.conn.prepare(&sql)?.query_arrow([])?.collect();
+        let tick = self.bump();
+        self.last_run.insert(system, tick);
+        Ok(batches)
+    }
+
+    fn run_query_count(&mut self, system: &'static str, filters: &[Filter]) -> Result<i64> {
+        let sql = format!("SELECT COUNT(*) FROM entities WHERE {}", self.filter_sql(system, filters)?);
+        let count = self.conn.query_row(&sql, [], |r| r.get(0))?;
+        let tick = self.bump();
+        self.last_run.insert(system, tick);
+        Ok(count)
+    }
+}
+
+// ============================================================================
+// Rust-side mirror: spatial grid updated from deltas
+// ============================================================================
+
+#[derive(Default)]
+struct GridMirror {
+    cells: HashMap<(i32, i32), Vec<i64>>,
+    cell_of: HashMap<i64, (i32, i32)>,
+}
+
+impl GridMirror {
+    fn cell(x: f64, y: f64) -> (i32, i32) {
+        ((x / CELL_SIZE).floor() as i32, (y / CELL_SIZE).floor() as i32)
+    }
+
+    /// Apply (id, x, y) batches; only entities whose cell changed move.
+    fn apply(&mut self, batches: &[RecordBatch]) -> usize {
+        let mut moved = 0;
+        for batch in batches {
+            let ids = batch.column(0).as_any().downcast_ref::<Int64Array>().expect("id BIGINT");
+            let xs = batch.column(1).as_any().downcast_ref::<Float64Array>().expect("x DOUBLE");
+            let ys = batch.column(2).as_any().downcast_ref::<Float64Array>().expect("y DOUBLE");
+            for i in 0..batch.num_rows() {
+                let id = ids.value(i);
+                let new_cell = Self::cell(xs.value(i), ys.value(i));
+                match self.cell_of.insert(id, new_cell) {
+                    Some(old) if old == new_cell => continue,
+                    Some(old) => {
+                        if let Some(bucket) = self.cells.get_mut(&old) {
+                            bucket.retain(|e| *e != id);
+                        }
+                    }
+                    None => {}
+                }
+                self.cells.entry(new_cell).or_default().push(id);
+                moved += 1;
+            }
+        }
+        moved
+    }
+
+    fn same_as(&self, other: &GridMirror) -> bool {
+        self.cell_of == other.cell_of
+    }
+}
+
+// ============================================================================
+// Benchmarks
+// ============================================================================
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    println!("=== DuckDB Change Detection ===\n");
+    println!("Entity count: {}\n", SIZE);
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch("SET threads TO 8;")?;
+
+    let mut world = ChangeWorld::new(&conn, &[&POSITION, &VELOCITY, &HEALTH])?;
+    world.spawn(
+        SIZE,
+        &[&POSITION, &VELOCITY],
+        "random() * 1000, random() * 1000, (random() - 0.5) * 10, (random() - 0.5) * 10",
+    )?;
+
+    // --- Initial sync: first run sees everything ---
+    println!("--- Initial Sync ---");
+    let mut grid = GridMirror::default();
+    let start = Instant::now();
+    let batches = world.run_query_arrow("grid_sync", &["id", "x", "y"], &[Filter::Changed(&POSITION)])?;
+    let placed = grid.apply(&batches);
+    println!("  First run pulls {} entities: {:?}\n", placed, start.elapsed());
+
+    // --- Per-tick delta sync ---
+    println!("--- Delta Sync (1% of entities move per tick) ---");
+    let ticks = 60;
+    let mut delta_time = std::time::Duration::ZERO;
+    let mut delta_rows = 0;
+    for tick in 0..ticks {
+        let lo = (tick * SIZE / 100) as i64;
+        let hi = lo + (SIZE / 100) as i64;
+        world.write(&POSITION, "x = x + vx, y = y + vy", &format!("id >= {lo} AND id < {hi}"))?;
+
+        let start = Instant::now();
+        let batches = world.run_query_arrow("grid_sync", &["id", "x", "y"], &[Filter::Changed(&POSITION)])?;
+        delta_rows += batches.iter().map(|b| b.num_rows()).sum::<usize>();
+        grid.apply(&batches);
+        delta_time += start.elapsed();
+    }
+    println!("  Avg delta rows/tick: {}", delta_rows / ticks);
+    println!("  Avg delta pull + apply: {:.3} ms", delta_time.as_secs_f64() * 1000.0 / ticks as f64);
+
+    // Baseline: pull everything every tick
+    let start = Instant::now();
+    let mut full = GridMirror::default();
+    for _ in 0..10 {
+        full.cells.clear();
+        full.cell_of.clear();
+        let batches: Vec<RecordBatch> = conn.prepare("SELECT id, x, y FROM entities")?.query_arrow([])?.collect();
+        full.apply(&batches);
+    }
+    let full_time = start.elapsed() / 10;
+    println!("  Full pull + rebuild: {:.3} ms", full_time.as_secs_f64() * 1000.0);
+    let mirror_matches = grid.same_as(&full);
+    println!("  Delta mirror matches full rebuild: {}\n", mirror_matches);
+
+    // --- Added<Health> ---
+    println!("--- Added / Changed Filters ---");
+    world.run_query_count("regen", &[Filter::Added(&HEALTH)])?;
+    let added = world.add(&HEALTH, "hp = 100", "id % 10 = 0")?;
+    let seen = world.run_query_count("regen", &[Filter::Added(&HEALTH)])?;
+    let seen_again = world.run_query_count("regen", &[Filter::Added(&HEALTH)])?;
+    println!("  Added<health> after adding to {}: {} rows, next run: {} rows", added, seen, seen_again);
+
+    // A write that lands after a system ran is seen on its next run
+    world.run_query_count("damage", &[Filter::Changed(&HEALTH)])?;
+    world.write(&HEALTH, "hp = hp - 5", "id % 20 = 0")?;
+    let changed = world.run_query_count("damage", &[Filter::Changed(&HEALTH)])?;
+    let both = world.run_query_count("combo", &[Filter::Changed(&HEALTH), Filter::Changed(&POSITION)])?;
+    println!("  Changed<health> after damaging 5%: {} rows", changed);
+    println!("  Changed<health> + Changed<position> on first run: {} rows\n", both);
+
+    println!("=== Summary ===\n");
+    println!("  • Writes go through ChangeWorld, so change ticks can't be forgotten");
+    println!("  • Each system sees changes since its own last run");
+    println!("  • Delta sync: {:.1}× faster than full pull",
+             full_time.as_secs_f64() / (delta_time.as_secs_f64() / ticks as f64));
+    println!("  • Delta mirror matches full rebuild: {}", mirror_matches);
+
+    if !mirror_matches {
+        return Err("delta mirror differs from the full rebuild".into());
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//! Native Rust Game Function Library for DuckDB
+//!
+//! `duckdb_func_discovery.rs` and `duckdb_math_funcs.rs` go looking for
+//! built-in distance functions. This registers our own in one call
+//! (`game_functions.rs`) and checks them:
+//! 1. Each function in FLOAT, DOUBLE and DOUBLE[2] form against the SQL
+//!    expression it replaces
+//! 2. NULL propagation
+//! 3. Throughput vs the equivalent hand-written SQL on 1M rows
+
+mod arrow_scalar;
+mod game_functions;
+mod simd_kernels;
+
+use duckdb::{Connection, Result};
+use game_functions::{register_game_functions, GAME_FUNCTIONS};
+use std::time::Instant;
+
+const SIZE: usize = 1_000_000;
+
+/// (description, UDF query, reference query); both must return one DOUBLE
+/// (booleans/integers are cast) so they can be compared directly.
+const CHECKS: &[(&str, &str, &str)] = &[
+    ("dist2d DOUBLE", "dist2d(1.0, 2.0, 4.0, 6.0)", "sqrt((4.0-1.0)^2 + (6.0-2.0)^2)"),
+    ("dist2d FLOAT", "dist2d(1.0::FLOAT, 2.0::FLOAT, 4.0::FLOAT, 6.0::FLOAT)", "5.0"),
+    ("dist2d DOUBLE[2]", "dist2d([1.0, 2.0]::DOUBLE[2], [4.0, 6.0]::DOUBLE[2])", "5.0"),
+    ("dist_sq DOUBLE", "dist_sq(1.0, 2.0, 4.0, 6.0)", "25.0"),
+    ("dist_sq DOUBLE[2]", "dist_sq([1.0, 2.0]::DOUBLE[2], [4.0, 6.0]::DOUBLE[2])", "25.0"),
+    ("within_radius DOUBLE", "within_radius(0.0, 0.0, 3.0, 4.0, 5.0)::DOUBLE", "1.0"),
+    ("within_radius FLOAT", "within_radius(0.0::FLOAT, 0.0::FLOAT, 3.0::FLOAT, 4.0::FLOAT, 4.9::FLOAT)::DOUBLE", "0.0"),
+    ("within_radius DOUBLE[2]", "within_radius([0.0, 0.0]::DOUBLE[2], [3.0, 4.0]::DOUBLE[2], 5.0)::DOUBLE", "1.0"),
+    ("within_radius r < 0", "within_radius(0.0, 0.0, 0.0, 0.0, -1.0)::DOUBLE", "0.0"),
+    ("within_radius rows r < 0", "(SELECT count(*) FILTER (WHERE within_radius(0.0, 0.0, 3.0, 4.0, r))::DOUBLE FROM (VALUES (5.0), (-5.0)) t(r))", "1.0"),
+    ("cell_of DOUBLE", "cell_of(-12.5, 10.0)::DOUBLE", "floor(-12.5 / 10.0)"),
+    ("cell_of FLOAT", "cell_of(37.0::FLOAT, 10.0::FLOAT)::DOUBLE", "3.0"),
+    ("cell_of DOUBLE[2]", "list_sum(cell_of([37.0, -12.5]::DOUBLE[2], 10.0)::INTEGER[])::DOUBLE", "3.0 + -2.0"),
+    ("morton2d DOUBLE", "morton2d(30.0, 20.0, 10.0)::DOUBLE", "13.0"), // x=3 (0b11), y=2 (0b10) -> 0b1101
+    ("morton2d DOUBLE[2]", "morton2d([30.0, 20.0]::DOUBLE[2], 10.0)::DOUBLE", "13.0"),
+    ("angle_between DOUBLE", "angle_between(1.0, 0.0, 0.0, 1.0)", "pi() / 2"),
+    ("angle_between DOUBLE[2]", "angle_between([0.0, 1.0]::DOUBLE[2], [1.0, 0.0]::DOUBLE[2])", "-pi() / 2"),
+    ("manhattan DOUBLE", "manhattan(1.0, 2.0, 4.0, 6.0)", "7.0"),
+    ("manhattan FLOAT", "manhattan(1.0::FLOAT, 2.0::FLOAT, 4.0::FLOAT, 6.0::FLOAT)", "7.0"),
+    ("chebyshev DOUBLE", "chebyshev(1.0, 2.0, 4.0, 6.0)", "4.0"),
+    ("chebyshev DOUBLE[2]", "chebyshev([1.0, 2.0]::DOUBLE[2], [4.0, 6.0]::DOUBLE[2])", "4.0"),
+    ("point_in_rect DOUBLE", "point_in_rect(5.0, 5.0, 0.0, 0.0, 10.0, 10.0)::DOUBLE", "1.0"),
+    ("point_in_rect FLOAT", "point_in_rect(11.0::FLOAT, 5.0::FLOAT, 0.0::FLOAT, 0.0::FLOAT, 10.0::FLOAT, 10.0::FLOAT)::DOUBLE", "0.0"),
+    ("point_in_rect DOUBLE[2]", "point_in_rect([10.0, 0.0]::DOUBLE[2], [0.0, 0.0]::DOUBLE[2], [10.0, 10.0]::DOUBLE[2])::DOUBLE", "1.0"),
+];
+
+fn time_query(conn: &Connection, sql: &str, iterations: usize) -> Result<f64> {
+    let mut stmt = conn.prepare(sql)?;
+    let _: f64 = stmt.query_row([], |r| r.get(0))?; // warmup
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let _: f64 = stmt.query_row([], |r| r.get(0))?;
+    }
+    Ok(start.elapsed().as_secs_f64() * 1000.0 / iterations as f64)
+}
+
+fn main() -> Result<()> {
+    println!("=== Native Rust Game Functions for DuckDB ===\n");
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch("SET threads TO 8;")?;
+
+    let start = Instant::now();
+    register_game_functions(&conn)?;
+    println!("  register_game_functions(): {} functions in {:?}\n", GAME_FUNCTIONS.len(), start.elapsed());
+
+    // --- Correctness ---
+    println!("--- Correctness vs SQL ---");
+    let mut failures = 0;
+    for (name, udf, reference) in CHECKS {
+        let got: f64 = conn.query_row(&format!("SELECT ({})::DOUBLE", udf), [], |r| r.get(0))?;
+        let want: f64 = conn.query_row(&format!("SELECT ({})::DOUBLE", reference), [], |r| r.get(0))?;
+        let ok = (got - want).abs() < 1e-5;
+        if !ok {
+            failures += 1;
+        }
+        println!("  {:<26} {:>10.5}  {}", name, got, if ok { "✓" } else { "✗" });
+    }
+
+    let null_out: Option<f64> = conn.query_row("SELECT dist2d(1.0, NULL::DOUBLE, 4.0, 6.0)", [], |r| r.get(0))?;
+    println!("  {:<26} {:>10?}  {}", "NULL propagation", null_out, if null_out.is_none() { "✓" } else { "✗" });
+    if null_out.is_some() {
+        failures += 1;
+    }
+    println!();
+
+    // --- Throughput ---
+    println!("--- Throughput ({} rows) ---", SIZE);
+    conn.execute_batch(&format!(
+        "CREATE TABLE pairs AS
+         SELECT random() * 1000 AS x1, random() * 1000 AS y1,
+                random() * 1000 AS x2, random() * 1000 AS y2
+         FROM range({SIZE});
+         CREATE TABLE pairs_p AS
+         SELECT [x1, y1]::DOUBLE[2] AS p1, [x2, y2]::DOUBLE[2] AS p2 FROM pairs;"
+    ))?;
+
+    let benches: &[(&str, &str)] = &[
+        ("SQL sqrt(dx²+dy²)", "SELECT sum(sqrt((x2-x1)*(x2-x1) + (y2-y1)*(y2-y1))) FROM pairs"),
+        ("dist2d DOUBLE", "SELECT sum(dist2d(x1, y1, x2, y2)) FROM pairs"),
+        ("dist2d DOUBLE[2]", "SELECT sum(dist2d(p1, p2)) FROM pairs_p"),
+        ("SQL radius check", "SELECT count(*)::DOUBLE FROM pairs WHERE (x2-x1)*(x2-x1) + (y2-y1)*(y2-y1) <= 2500"),
+        ("within_radius", "SELECT count(*)::DOUBLE FROM pairs WHERE within_radius(x1, y1, x2, y2, 50.0)"),
+        ("SQL floor(x / 10)", "SELECT sum(floor(x1 / 10.0)::INTEGER)::DOUBLE FROM pairs"),
+        ("cell_of", "SELECT sum(cell_of(x1, 10.0))::DOUBLE FROM pairs"),
+        ("morton2d", "SELECT count(DISTINCT morton2d(x1, y1, 10.0))::DOUBLE FROM pairs"),
+    ];
+    for (name, sql) in benches {
+        let ms = time_query(&conn, sql, 10)?;
+        println!("  {:<20} {:>8.2} ms  ({:.1} ns/row)", name, ms, ms * 1_000_000.0 / SIZE as f64);
+    }
+
+    println!("\n=== Summary ===\n");
+    println!("  • {} functions × FLOAT / DOUBLE / DOUBLE[2] from one register call", GAME_FUNCTIONS.len());
+    println!("  • Correctness failures: {}", failures);
+
+    Ok(())
+}
@@ -0,0 +1,243 @@
+//! Lua Aggregate Functions in DuckDB GROUP BY Queries
+//!
+//! Only scalar Lua UDFs existed so far. `lua_aggregates.rs` lets a script
+//! define init/update/combine/finalize and registers it as a DuckDB
+//! aggregate (through a macro over `lua_aggregate`):
+//! 1. `weighted_threat(power, dist)` per faction, checked against the same
+//!    formula in SQL
+//! 2. A Lua `lua_median(hp)` checked against DuckDB's `median`, including NULL
+//!    rows, an all-NULL group, an empty input and a `try_clone`d connection
+//! 3. Cost against the built-ins, and how many VMs held states
+//! 4. Broken definitions, and names DuckDB already has, are rejected when
+//!    loading
+//! 5. A Lua error fails the query, or only its group under `on_error = "null"`
+
+mod lua_aggregates;
+mod lua_errors;
+mod lua_scripts;
+
+use duckdb::Connection;
+use std::error::Error;
+use std::time::Instant;
+
+const ROWS: usize = 1_000_000;
+
+const AGGREGATES_LUA: &str = r#"
+-- Threat of a faction: unit power weighted by closeness.
+weighted_threat = aggregate{
+  args = 2,
+  init = function() return { sum = 0, weight = 0 } end,
+  update = function(s, n, power, dist)
+    local sum, weight = s.sum, s.weight
+    for i = 0, n - 1 do
+      local w = 1 / (1 + dist[i])
+      sum = sum + power[i] * w
+      weight = weight + w
+    end
+    s.sum, s.weight = sum, weight
+    return s
+  end,
+  combine = function(a, b)
+    a.sum, a.weight = a.sum + b.sum, a.weight + b.weight
+    return a
+  end,
+  finalize = function(s)
+    if s.weight == 0 then return nil end
+    return s.sum / s.weight
+  end,
+}
+
+lua_median = aggregate{
+  init = function() return {} end,
+  update = function(s, n, xs)
+    local k = #s
+    for i = 0, n - 1 do s[k + i + 1] = xs[i] end
+    return s
+  end,
+  combine = function(a, b)
+    local k = #a
+    for i = 1, #b do a[k + i] = b[i] end
+    return a
+  end,
+  finalize = function(s)
+    local n = #s
+    if n == 0 then return nil end
+    table.sort(s)
+    if n % 2 == 1 then return s[(n + 1) / 2] end
+    return (s[n / 2] + s[n / 2 + 1]) / 2
+  end,
+}
+"#;
+
+const FRAGILE_LUA: &str = r#"
+lua_fragile = aggregate{
+  init = function() return { n = 0 } end,
+  update = function(s, n, xs)
+    for i = 0, n - 1 do
+      if xs[i] < 0 then error("negative value") end
+    end
+    s.n = s.n + n
+    return s
+  end,
+  combine = function(a, b) a.n = a.n + b.n; return a end,
+  finalize = function(s) return s.n end,
+}
+"#;
+
+type Groups = Vec<(i64, Option<f64>)>;
+
+fn grouped(conn: &Connection, expr: &str) -> Result<Groups, Box<dyn Error>> {
+    let sql = format!("SELECT faction, {} FROM units GROUP BY faction ORDER BY faction", expr);
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+    Ok(rows.collect::<duckdb::Result<_>>()?)
+}
+
+/// Runs both versions; true if every group agrees within `rel` relative error.
+fn compare(conn: &Connection, label: &str, lua: &str, sql: &str, rel: f64) -> Result<bool, Box<dyn Error>> {
+    let start = Instant::now();
+    let got = grouped(conn, lua)?;
+    let lua_time = start.elapsed();
+    let start = Instant::now();
+    let want = grouped(conn, sql)?;
+    let sql_time = start.elapsed();
+
+    let close = |a: Option<f64>, b: Option<f64>| match (a, b) {
+        (Some(a), Some(b)) => (a - b).abs() <= rel * b.abs().max(1.0),
+        (None, None) => true,
+        _ => false,
+    };
+    let ok = got.len() == want.len() && got.iter().zip(&want).all(|(g, w)| g.0 == w.0 && close(g.1, w.1));
+    println!("  {:<16} {} groups, Lua {:>9.2?} vs built-in {:>9.2?}   {}",
+             label, got.len(), lua_time, sql_time, if ok { "✓ match" } else { "✗ differ" });
+    for ((faction, g), (_, w)) in got.iter().zip(&want).take(3) {
+        println!("      faction {:>2}: lua {:?}  sql {:?}", faction, g, w);
+    }
+    Ok(ok)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== Lua Aggregates for DuckDB ===\n");
+    let conn = lua_aggregates::open_in_memory()?;
+    conn.execute_batch("SET threads TO 8;")?;
+    conn.execute_batch(&format!(
+        "CREATE TABLE units AS
+         SELECT i % 8 AS faction,
+                (10 + (i * 37) % 100)::DOUBLE AS power,
+                (((i * 13) % 500) / 10.0)::DOUBLE AS dist,
+                CASE WHEN i % 97 = 0 THEN NULL ELSE (i * 7919) % 1000 END::DOUBLE AS hp
+         FROM range({ROWS}) t(i);
+         -- A faction whose units all lack hp.
+         INSERT INTO units VALUES (99, 50.0, 1.0, NULL), (99, 70.0, 2.0, NULL);"
+    ))?;
+    let mut failures = 0;
+
+    let defined = lua_aggregates::load_source(&conn, "aggregates.lua", AGGREGATES_LUA)?;
+    println!("Loaded: {}", defined.iter().map(|(n, a)| format!("{}/{}", n, a)).collect::<Vec<_>>().join(", "));
+    let kind: String = conn.query_row(
+        "SELECT any_value(function_type) FROM duckdb_functions() WHERE function_name = 'lua_aggregate'",
+        [],
+        |r| r.get(0),
+    )?;
+    println!("lua_aggregate is a DuckDB {} function\n", kind);
+    if kind != "aggregate" {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 1-2. Against SQL
+    // --------------------------------------------------------------------
+    println!("--- 1. weighted_threat ---");
+    if !compare(&conn, "weighted_threat", "weighted_threat(power, dist)",
+                "sum(power / (1 + dist)) / sum(1 / (1 + dist))", 1e-9)? {
+        failures += 1;
+    }
+
+    println!("\n--- 2. lua_median ---");
+    if !compare(&conn, "lua_median(hp)", "lua_median(hp)", "median(hp)", 0.0)? {
+        failures += 1;
+    }
+    let all_null: Option<f64> = conn.query_row("SELECT lua_median(hp) FROM units WHERE faction = 99", [], |r| r.get(0))?;
+    let empty: Option<f64> = conn.query_row("SELECT lua_median(hp) FROM units WHERE false", [], |r| r.get(0))?;
+    println!("  all-NULL group → {:?}, empty input → {:?}", all_null, empty);
+    if all_null.is_some() || empty.is_some() {
+        failures += 1;
+    }
+    // A clone connects through the database handle `open_in_memory` keeps.
+    let clone = conn.try_clone()?;
+    let cloned: Option<f64> = clone.query_row("SELECT lua_median(hp) FROM units WHERE faction = 1", [], |r| r.get(0))?;
+    let direct: Option<f64> = conn.query_row("SELECT median(hp) FROM units WHERE faction = 1", [], |r| r.get(0))?;
+    println!("  from a try_clone'd connection → {:?} (median {:?})", cloned, direct);
+    if cloned != direct {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 3. Workers
+    // --------------------------------------------------------------------
+    println!("\n--- 3. State VMs ---");
+    let (vms, loads) = lua_scripts::vm_stats();
+    println!("  {} worker VMs held aggregate states, {} script loads; one update call per state per chunk",
+             vms, loads);
+    let reload_errors = lua_scripts::take_errors();
+    if let Some(e) = reload_errors.first() {
+        failures += 1;
+        println!("  ✗ {}: {}", e.script, e.message);
+    }
+
+    // --------------------------------------------------------------------
+    // 4. Rejected definitions
+    // --------------------------------------------------------------------
+    println!("\n--- 4. Rejected definitions ---");
+    let bad = [
+        ("missing combine", "broken = aggregate{ init = function() return 0 end, update = function(s) return s end, finalize = function(s) return s end }"),
+        ("too many args", "wide = aggregate{ args = 9, init = function() end, update = function() end, combine = function() end, finalize = function() end }"),
+        ("bad SQL name", "_G['drop table'] = aggregate{ init = function() end, update = function() end, combine = function() end, finalize = function() end }"),
+        ("shadows built-in", "median = aggregate{ init = function() end, update = function() end, combine = function() end, finalize = function() end }"),
+    ];
+    for (label, source) in bad {
+        match lua_aggregates::load_source(&conn, label, source) {
+            Ok(found) => {
+                failures += 1;
+                println!("  ✗ {:<16} accepted: {:?}", label, found);
+            }
+            Err(e) => println!("  ✓ {:<16} {}", label, e.to_string().lines().next().unwrap_or("")),
+        }
+    }
+
+    // --------------------------------------------------------------------
+    // 5. Lua errors
+    // --------------------------------------------------------------------
+    println!("\n--- 5. Lua errors ---");
+    lua_aggregates::load_source(&conn, "fragile.lua", FRAGILE_LUA)?;
+    lua_errors::set_owner("lua_fragile", "fragile_mod");
+    let fragile = "lua_fragile(CASE WHEN faction = 3 THEN -1 ELSE 1 END)";
+    match grouped(&conn, fragile) {
+        Ok(groups) => {
+            failures += 1;
+            println!("  ✗ on_error = fail: query succeeded with {} groups", groups.len());
+        }
+        Err(e) => println!("  ✓ on_error = fail: {}", e.to_string().lines().next().unwrap_or("")),
+    }
+    lua_errors::set_on_error("fragile_mod", lua_errors::OnError::Null);
+    let groups = grouped(&conn, fragile)?;
+    let nulls: Vec<i64> = groups.iter().filter(|g| g.1.is_none()).map(|g| g.0).collect();
+    let (_, errors) = lua_errors::error_counts().into_iter().find(|(m, _)| m == "fragile_mod").unwrap_or_default();
+    println!("  {} on_error = null: NULL for factions {:?}, {} errors counted for fragile_mod",
+             if nulls == [3] { "✓" } else { "✗" }, nulls, errors);
+    if nulls != [3] {
+        failures += 1;
+    }
+
+    println!("\n=== Summary ===\n");
+    println!("  • A Lua table with init/update/combine/finalize becomes a DuckDB aggregate, behind a macro per name");
+    println!("  • Every DuckDB state holds its own Lua state; workers update in parallel and DuckDB combines them");
+    println!("  • Results match the SQL formulas and DuckDB's median, NULLs and empty groups included");
+    println!("  • Lua errors go through lua_errors: the query fails, or only the group is NULL");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
@@ -4,10 +4,18 @@
 //! Uses VArrowScalar for easy numeric type handling via Arrow arrays.
 //!
 //! Key insight: DuckDB may call VScalar on multiple threads, so we use
-//! thread-local Lua VMs that are lazily initialized with the script.
+//! thread-local Lua VMs that are lazily initialized with the script. They
+//! come from `lua_scripts::with_vm`, so editing the script reloads it.
+//!
+//! A Lua error goes through `lua_errors`: it fails the query naming the chunk
+//! row and its arguments, or makes that row NULL under `on_error = "null"`.
 //!
 //! Example: SELECT * FROM e1, e2 WHERE lua_distance(e1.x, e1.y, e2.x, e2.y) < 50
 
+mod arrow_scalar;
+mod lua_errors;
+mod lua_scripts;
+
 use duckdb::arrow::array::{Array, Float64Array};
 use duckdb::arrow::datatypes::DataType;
 use duckdb::arrow::record_batch::RecordBatch;
@@ -15,7 +23,7 @@ use duckdb::{
     vscalar::{ArrowFunctionSignature, VArrowScalar},
     Connection,
 };
-use std::cell::RefCell;
+use lua_errors::UdfError;
 use std::error::Error;
 use std::sync::Arc;
 use std::time::Instant;
@@ -24,7 +32,7 @@ use std::time::Instant;
 // Thread-Local Lua VM (using mlua/LuaJIT for performance)
 // ============================================================================
 
-// The Lua script is stored in a static so all threads can access it
+// Registered with `lua_scripts` in main; every worker thread's VM loads it
 static LUA_SCRIPT: &str = r#"
     function distance(x1, y1, x2, y2)
         local dx = x2 - x1
@@ -33,19 +41,12 @@ static LUA_SCRIPT: &str = r#"
     end
 "#;
 
-thread_local! {
-    static LUA_VM: RefCell<mlua::Lua> = RefCell::new({
-        let lua = mlua::Lua::new();
-        lua.load(LUA_SCRIPT).exec().expect("Failed to load Lua script");
-        lua
-    });
-}
+const LUA_DISTANCE: &str = "lua_distance";
 
-fn call_lua_distance(x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
-    LUA_VM.with(|vm| {
-        let lua = vm.borrow();
-        let func: mlua::Function = lua.globals().get("distance").expect("distance not found");
-        func.call::<f64>((x1, y1, x2, y2)).expect("distance call failed")
+fn call_lua_distance(x1: f64, y1: f64, x2: f64, y2: f64) -> mlua::Result<f64> {
+    lua_scripts::with_vm(|lua| {
+        let func: mlua::Function = lua.globals().get("distance")?;
+        func.call::<f64>((x1, y1, x2, y2))
     })
 }
 
@@ -62,21 +63,20 @@ impl VArrowScalar for LuaDistanceScalar {
         _state: &Self::State,
         input: RecordBatch,
     ) -> Result<Arc<dyn Array>, Box<dyn Error>> {
-        let x1 = input.column(0).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y1 = input.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
-        let x2 = input.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y2 = input.column(3).as_any().downcast_ref::<Float64Array>().unwrap();
-
-        let result: Vec<f64> = (0..input.num_rows())
-            .map(|i| {
-                call_lua_distance(
-                    x1.value(i),
-                    y1.value(i),
-                    x2.value(i),
-                    y2.value(i),
-                )
-            })
-            .collect();
+        let [x1, y1, x2, y2] = arrow_scalar::doubles(&input)?;
+        let (module, mode) = lua_errors::policy(LUA_DISTANCE);
+
+        let mut result: Vec<Option<f64>> = Vec::with_capacity(input.num_rows());
+        for i in 0..input.num_rows() {
+            let args = [x1.value(i), y1.value(i), x2.value(i), y2.value(i)];
+            match call_lua_distance(args[0], args[1], args[2], args[3]) {
+                Ok(d) => result.push(Some(d)),
+                Err(e) => {
+                    lua_errors::handle(UdfError::from_lua(&module, LUA_DISTANCE, Some(i), &args, &e), mode)?;
+                    result.push(None);
+                }
+            }
+        }
 
         Ok(Arc::new(Float64Array::from(result)))
     }
@@ -107,10 +107,7 @@ impl VArrowScalar for RustDistanceScalar {
         _state: &Self::State,
         input: RecordBatch,
     ) -> Result<Arc<dyn Array>, Box<dyn Error>> {
-        let x1 = input.column(0).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y1 = input.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
-        let x2 = input.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y2 = input.column(3).as_any().downcast_ref::<Float64Array>().unwrap();
+        let [x1, y1, x2, y2] = arrow_scalar::doubles(&input)?;
 
         let result: Vec<f64> = (0..input.num_rows())
             .map(|i| {
@@ -143,11 +140,13 @@ impl VArrowScalar for RustDistanceScalar {
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("=== DuckDB VScalar + Lua UDF Integration ===\n");
 
+    lua_scripts::set_source("distance", LUA_SCRIPT);
+
     // Create DuckDB connection
     let conn = Connection::open_in_memory()?;
 
     // Register VScalar functions
-    conn.register_scalar_function::<LuaDistanceScalar>("lua_distance")?;
+    conn.register_scalar_function::<LuaDistanceScalar>(LUA_DISTANCE)?;
     conn.register_scalar_function::<RustDistanceScalar>("rust_distance")?;
 
     // Create test table
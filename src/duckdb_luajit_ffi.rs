@@ -9,6 +9,19 @@
 //! 3. Pass pointers to Lua via LightUserData
 //! 4. LuaJIT FFI casts and operates directly on memory
 //! 5. Return result as Arrow array (allocated once, written by Lua)
+//!
+//! The script is registered with `lua_scripts` (with `enable_ffi()`), so
+//! each worker thread's VM comes from `lua_scripts::with_vm` and picks up
+//! edits to the script like any other.
+//!
+//! Lua errors go through `lua_errors`. The per-row function reports the
+//! chunk row and its arguments; the FFI batch is one call, so a failure there
+//! covers the whole chunk (all NULL under `on_error = "null"`).
+
+mod arrow_scalar;
+mod lua_errors;
+mod lua_scripts;
+mod simd_kernels;
 
 use duckdb::arrow::array::{Array, Float64Array};
 use duckdb::arrow::datatypes::DataType;
@@ -17,8 +30,8 @@ use duckdb::{
     vscalar::{ArrowFunctionSignature, VArrowScalar},
     Connection,
 };
-use mlua::{LightUserData, Lua};
-use std::cell::RefCell;
+use lua_errors::UdfError;
+use mlua::LightUserData;
 use std::error::Error;
 use std::ffi::c_void;
 use std::sync::Arc;
@@ -92,19 +105,6 @@ print("LuaJIT FFI + DuckDB integration loaded")
 print("JIT status: " .. (jit and jit.status() and "ON" or "OFF"))
 "#;
 
-// ============================================================================
-// Thread-Local Lua VM with FFI support
-// ============================================================================
-
-thread_local! {
-    static LUA_VM: RefCell<Lua> = RefCell::new({
-        // Must use unsafe_new to enable FFI module
-        let lua = unsafe { Lua::unsafe_new() };
-        lua.load(LUA_FFI_SCRIPT).exec().expect("Failed to load Lua FFI script");
-        lua
-    });
-}
-
 // ============================================================================
 // Batch struct matching the Lua FFI definition
 // ============================================================================
@@ -129,6 +129,9 @@ fn get_f64_ptr(array: &Float64Array) -> *const f64 {
     array.values().as_ptr()
 }
 
+const LUA_FFI_DISTANCE: &str = "lua_ffi_distance";
+const LUA_PERROW_DISTANCE: &str = "lua_perrow_distance";
+
 // ============================================================================
 // LuaJIT FFI VArrowScalar: Zero-copy batch processing
 // ============================================================================
@@ -145,10 +148,7 @@ impl VArrowScalar for LuaFfiDistanceScalar {
         let n = input.num_rows();
         
         // Get input arrays
-        let x1 = input.column(0).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y1 = input.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
-        let x2 = input.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y2 = input.column(3).as_any().downcast_ref::<Float64Array>().unwrap();
+        let [x1, y1, x2, y2] = arrow_scalar::doubles(&input)?;
         
         // Allocate output buffer (will be written by Lua)
         let mut out_buffer: Vec<f64> = vec![0.0; n];
@@ -164,13 +164,17 @@ impl VArrowScalar for LuaFfiDistanceScalar {
         };
         
         // Call Lua FFI function
-        LUA_VM.with(|vm| {
-            let lua = vm.borrow();
-            let func: mlua::Function = lua.globals().get("distance_ffi_batch").unwrap();
+        let called = lua_scripts::with_vm(|lua| {
+            let func: mlua::Function = lua.globals().get("distance_ffi_batch")?;
             let ptr = LightUserData(&batch as *const DistanceBatch as *mut c_void);
-            func.call::<()>(ptr).unwrap();
+            func.call::<()>(ptr)
         });
-        
+        if let Err(e) = called {
+            let (module, mode) = lua_errors::policy(LUA_FFI_DISTANCE);
+            lua_errors::handle(UdfError::from_lua(&module, LUA_FFI_DISTANCE, None, &[], &e), mode)?;
+            return Ok(Arc::new(Float64Array::new_null(n)));
+        }
+
         // Convert to Arrow array (the buffer was already written by Lua)
         Ok(Arc::new(Float64Array::from(out_buffer)))
     }
@@ -201,26 +205,27 @@ impl VArrowScalar for LuaPerRowDistanceScalar {
         _state: &Self::State,
         input: RecordBatch,
     ) -> Result<Arc<dyn Array>, Box<dyn Error>> {
-        let x1 = input.column(0).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y1 = input.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
-        let x2 = input.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y2 = input.column(3).as_any().downcast_ref::<Float64Array>().unwrap();
-
-        let result: Vec<f64> = LUA_VM.with(|vm| {
-            let lua = vm.borrow();
-            let func: mlua::Function = lua.globals().get("distance").unwrap();
-            
-            (0..input.num_rows())
-                .map(|i| {
-                    func.call::<f64>((
-                        x1.value(i),
-                        y1.value(i),
-                        x2.value(i),
-                        y2.value(i),
-                    )).unwrap()
-                })
-                .collect()
-        });
+        let [x1, y1, x2, y2] = arrow_scalar::doubles(&input)?;
+
+        let (module, mode) = lua_errors::policy(LUA_PERROW_DISTANCE);
+        let result: Vec<Option<f64>> = lua_scripts::with_vm(|lua| {
+            let func: mlua::Result<mlua::Function> = lua.globals().get("distance");
+
+            let mut out = Vec::with_capacity(input.num_rows());
+            for i in 0..input.num_rows() {
+                let args = [x1.value(i), y1.value(i), x2.value(i), y2.value(i)];
+                match func.as_ref().map_err(Clone::clone).and_then(|f| f.call::<f64>((args[0], args[1], args[2], args[3]))) {
+                    Ok(d) => out.push(Some(d)),
+                    Err(e) => {
+                        if let Err(udf) = lua_errors::handle(UdfError::from_lua(&module, LUA_PERROW_DISTANCE, Some(i), &args, &e), mode) {
+                            return Ok(Err(udf));
+                        }
+                        out.push(None);
+                    }
+                }
+            }
+            Ok(Ok(out))
+        })??;
 
         Ok(Arc::new(Float64Array::from(result)))
     }
@@ -251,18 +256,10 @@ impl VArrowScalar for RustDistanceScalar {
         _state: &Self::State,
         input: RecordBatch,
     ) -> Result<Arc<dyn Array>, Box<dyn Error>> {
-        let x1 = input.column(0).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y1 = input.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
-        let x2 = input.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y2 = input.column(3).as_any().downcast_ref::<Float64Array>().unwrap();
-
-        let result: Vec<f64> = (0..input.num_rows())
-            .map(|i| {
-                let dx = x2.value(i) - x1.value(i);
-                let dy = y2.value(i) - y1.value(i);
-                (dx * dx + dy * dy).sqrt()
-            })
-            .collect();
+        let [x1, y1, x2, y2] = arrow_scalar::doubles(&input)?;
+
+        let mut result = vec![0.0f64; input.num_rows()];
+        simd_kernels::kernels().distances(x1.values(), y1.values(), x2.values(), y2.values(), &mut result);
 
         Ok(Arc::new(Float64Array::from(result)))
     }
@@ -304,11 +301,15 @@ fn run_benchmark(conn: &Connection, name: &str, query: &str, iterations: usize)
 fn main() -> Result<(), Box<dyn Error>> {
     println!("=== DuckDB + LuaJIT FFI Zero-Copy VArrowScalar ===\n");
     
+    // Must come before the first VM is created: `ffi` needs `Lua::unsafe_new`
+    lua_scripts::enable_ffi();
+    lua_scripts::set_source("distance_ffi", LUA_FFI_SCRIPT);
+
     let conn = Connection::open_in_memory()?;
     
     // Register all three scalar functions
-    conn.register_scalar_function::<LuaFfiDistanceScalar>("lua_ffi_distance")?;
-    conn.register_scalar_function::<LuaPerRowDistanceScalar>("lua_perrow_distance")?;
+    conn.register_scalar_function::<LuaFfiDistanceScalar>(LUA_FFI_DISTANCE)?;
+    conn.register_scalar_function::<LuaPerRowDistanceScalar>(LUA_PERROW_DISTANCE)?;
     conn.register_scalar_function::<RustDistanceScalar>("rust_distance")?;
     
     // Test with different cross-join sizes
@@ -5,6 +5,16 @@
 //! 2. Bulk cross-join queries (not per-entity queries)
 //! 3. Arrow result handling
 //! 4. LuaJIT FFI for UDF processing
+//!
+//! The script is registered with `lua_scripts` (with `enable_ffi()`), so
+//! every DuckDB worker gets it on its thread-local VM.
+//!
+//! Lua errors go through `lua_errors`: a failed batch is a query error, or an
+//! all-NULL chunk if the owning mod is set to `on_error = "null"`.
+
+mod arrow_scalar;
+mod lua_errors;
+mod lua_scripts;
 
 use duckdb::arrow::array::{Array, Float64Array};
 use duckdb::arrow::datatypes::DataType;
@@ -13,8 +23,8 @@ use duckdb::{
     vscalar::{ArrowFunctionSignature, VArrowScalar},
     Connection,
 };
-use mlua::{LightUserData, Lua};
-use std::cell::RefCell;
+use lua_errors::UdfError;
+use mlua::LightUserData;
 use std::error::Error;
 use std::ffi::c_void;
 use std::sync::Arc;
@@ -52,13 +62,7 @@ function distance_ffi_batch(batch_ptr)
 end
 "#;
 
-thread_local! {
-    static LUA_VM: RefCell<Lua> = RefCell::new({
-        let lua = unsafe { Lua::unsafe_new() };
-        lua.load(LUA_FFI_SCRIPT).exec().expect("Failed to load Lua FFI script");
-        lua
-    });
-}
+const LUA_FFI_DISTANCE: &str = "lua_ffi_distance";
 
 #[repr(C)]
 struct DistanceBatch {
@@ -78,10 +82,7 @@ impl VArrowScalar for LuaFfiDistanceScalar {
 
     fn invoke(_state: &Self::State, input: RecordBatch) -> Result<Arc<dyn Array>, Box<dyn Error>> {
         let n = input.num_rows();
-        let x1 = input.column(0).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y1 = input.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
-        let x2 = input.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y2 = input.column(3).as_any().downcast_ref::<Float64Array>().unwrap();
+        let [x1, y1, x2, y2] = arrow_scalar::doubles(&input)?;
         
         let mut out_buffer: Vec<f64> = vec![0.0; n];
         
@@ -94,13 +95,19 @@ impl VArrowScalar for LuaFfiDistanceScalar {
             n: n as i64,
         };
         
-        LUA_VM.with(|vm| {
-            let lua = vm.borrow();
-            let func: mlua::Function = lua.globals().get("distance_ffi_batch").unwrap();
+        let called = lua_scripts::with_vm(|lua| {
+            let func: mlua::Function = lua.globals().get("distance_ffi_batch")?;
             let ptr = LightUserData(&batch as *const DistanceBatch as *mut c_void);
-            func.call::<()>(ptr).unwrap();
+            func.call::<()>(ptr)
         });
-        
+
+        // One call covers the whole chunk, so there is no row to report
+        if let Err(e) = called {
+            let (module, mode) = lua_errors::policy(LUA_FFI_DISTANCE);
+            lua_errors::handle(UdfError::from_lua(&module, LUA_FFI_DISTANCE, None, &[], &e), mode)?;
+            return Ok(Arc::new(Float64Array::new_null(n)));
+        }
+
         Ok(Arc::new(Float64Array::from(out_buffer)))
     }
 
@@ -118,10 +125,7 @@ impl VArrowScalar for RustDistanceScalar {
     type State = ();
 
     fn invoke(_state: &Self::State, input: RecordBatch) -> Result<Arc<dyn Array>, Box<dyn Error>> {
-        let x1 = input.column(0).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y1 = input.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
-        let x2 = input.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y2 = input.column(3).as_any().downcast_ref::<Float64Array>().unwrap();
+        let [x1, y1, x2, y2] = arrow_scalar::doubles(&input)?;
 
         let result: Vec<f64> = (0..input.num_rows())
             .map(|i| {
@@ -150,10 +154,13 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("  ✓ Arrow batch result handling");
     println!("  ✓ LuaJIT FFI vectorized UDFs\n");
     
+    lua_scripts::enable_ffi();
+    lua_scripts::set_source("distance_ffi", LUA_FFI_SCRIPT);
+
     let conn = Connection::open_in_memory()?;
     conn.execute_batch("SET threads TO 1;")?;  // Single thread for fair comparison
     
-    conn.register_scalar_function::<LuaFfiDistanceScalar>("lua_ffi_distance")?;
+    conn.register_scalar_function::<LuaFfiDistanceScalar>(LUA_FFI_DISTANCE)?;
     conn.register_scalar_function::<RustDistanceScalar>("rust_distance")?;
     
     // Test different entity counts
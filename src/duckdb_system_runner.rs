@@ -0,0 +1,148 @@
+//! System Runner vs format!-per-Tick SQL
+//!
+//! `prepared_stmt_deep_dive.rs` shows preparing once removes most of the
+//! ~60-100 µs per-query overhead, but `bench_60ups_feasibility` still builds
+//! and parses its SQL every tick. This runs the same Factorio-lite workload:
+//! 1. Baseline: `execute_batch(&format!(...))` per system per tick
+//! 2. `SystemRunner`: SQL declared once with `$dt`, prepared handles reused
+//! 3. Same runner with one legacy system that bakes the tick into its SQL,
+//!    to show how the report flags re-preparing systems
+
+mod system_runner;
+
+use duckdb::types::Value;
+use duckdb::{Connection, Result};
+use std::time::Instant;
+use system_runner::{SystemKind, SystemRunner};
+
+const TICKS: usize = 600;
+
+fn setup(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        DROP TABLE IF EXISTS sim_belts;
+        DROP TABLE IF EXISTS sim_machines;
+        DROP TABLE IF EXISTS sim_trains;
+        DROP TABLE IF EXISTS sim_map;
+
+        CREATE TABLE sim_belts AS
+        SELECT i AS id, random()::FLOAT * 100 AS pos, 8.0::FLOAT AS speed
+        FROM generate_series(1, 200000) AS t(i);
+
+        CREATE TABLE sim_machines AS
+        SELECT i AS id, random()::FLOAT AS progress, true AS active
+        FROM generate_series(1, 20000) AS t(i);
+
+        CREATE TABLE sim_trains AS
+        SELECT i AS id, random()::FLOAT * 1000 AS pos, 50.0::FLOAT AS speed
+        FROM generate_series(1, 1000) AS t(i);
+
+        CREATE TABLE sim_map AS
+        SELECT i AS id, (random() * 500)::INT AS x, (random() * 500)::INT AS y
+        FROM generate_series(1, 50000) AS t(i);
+        CREATE INDEX idx_map_xy ON sim_map(x, y);
+        ",
+    )
+}
+
+fn add_systems(runner: &mut SystemRunner) {
+    runner.add_system("belts", SystemKind::Execute,
+        "UPDATE sim_belts SET pos = pos + speed * $dt");
+    runner.add_system("machines_progress", SystemKind::Execute,
+        "UPDATE sim_machines SET progress = progress + $dt / 2.0 WHERE active");
+    runner.add_system("machines_finish", SystemKind::Execute,
+        "UPDATE sim_machines SET progress = progress - 1.0 WHERE progress >= 1.0");
+    runner.add_system("trains", SystemKind::Execute,
+        "UPDATE sim_trains SET pos = pos + speed * $dt");
+    runner.add_system("collision_query", SystemKind::Query,
+        "SELECT COUNT(*) FROM sim_map WHERE x BETWEEN $qx AND $qx + 10 AND y BETWEEN $qy AND $qy + 10");
+}
+
+fn main() -> Result<()> {
+    println!("=== System Runner: Prepared Handles vs format! per Tick ===\n");
+
+    let conn = Connection::open_in_memory()?;
+    let dt = 1.0 / 60.0;
+
+    // --------------------------------------------------------------------
+    // 1. Baseline: format! + execute_batch every tick
+    // --------------------------------------------------------------------
+    println!("--- 1. format! + execute_batch per tick ---");
+    setup(&conn)?;
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        conn.execute_batch(&format!("UPDATE sim_belts SET pos = pos + speed * {dt};"))?;
+        conn.execute_batch(&format!("UPDATE sim_machines SET progress = progress + {dt} / 2.0 WHERE active;"))?;
+        conn.execute_batch("UPDATE sim_machines SET progress = progress - 1.0 WHERE progress >= 1.0;")?;
+        conn.execute_batch(&format!("UPDATE sim_trains SET pos = pos + speed * {dt};"))?;
+        let _: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sim_map WHERE x BETWEEN 100 AND 110 AND y BETWEEN 100 AND 110",
+            [],
+            |r| r.get(0),
+        )?;
+    }
+    let baseline = start.elapsed().as_secs_f64() * 1e6 / TICKS as f64;
+    println!("  {:.1} µs/tick\n", baseline);
+
+    // --------------------------------------------------------------------
+    // 2. Runner: declared once, prepared once
+    // --------------------------------------------------------------------
+    println!("--- 2. SystemRunner (named params, cached handles) ---");
+    setup(&conn)?;
+    let mut runner = SystemRunner::new(&conn);
+    add_systems(&mut runner);
+    runner.params.set("dt", Value::Double(dt));
+    runner.params.set("qx", Value::Int(100));
+    runner.params.set("qy", Value::Int(100));
+
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        runner.run_tick()?;
+    }
+    let prepared = start.elapsed().as_secs_f64() * 1e6 / TICKS as f64;
+    println!("  {:.1} µs/tick ({:.2}× vs baseline)\n", prepared, baseline / prepared);
+    runner.print_report();
+    println!();
+
+    // --------------------------------------------------------------------
+    // 3. Runner with a legacy system that bakes values into its SQL
+    // --------------------------------------------------------------------
+    println!("--- 3. SystemRunner + one legacy format! system ---");
+    setup(&conn)?;
+    let mut runner = SystemRunner::new(&conn);
+    add_systems(&mut runner);
+    runner.add_dynamic_system("legacy_spawner", SystemKind::Execute, |params| {
+        let tick = match params.get("tick") {
+            Some(Value::BigInt(t)) => *t,
+            _ => 0,
+        };
+        format!("UPDATE sim_trains SET speed = speed * 1.0 WHERE id = {}", tick % 1000 + 1)
+    });
+    runner.params.set("dt", Value::Double(dt));
+    runner.params.set("qx", Value::Int(100));
+    runner.params.set("qy", Value::Int(100));
+
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        runner.run_tick()?;
+    }
+    let mixed = start.elapsed().as_secs_f64() * 1e6 / TICKS as f64;
+    println!("  {:.1} µs/tick\n", mixed);
+    runner.print_report();
+
+    // A system using a parameter nobody set fails the tick instead of panicking
+    let mut runner = SystemRunner::new(&conn);
+    runner.add_system("uses_unset", SystemKind::Execute, "UPDATE sim_trains SET speed = speed * $boost");
+    match runner.run_tick() {
+        Ok(_) => println!("\n  ✗ unset $boost was accepted"),
+        Err(e) => println!("\n  Unset parameter: {}", e),
+    }
+
+    println!("\n=== Summary ===\n");
+    println!("  • Baseline:           {:>8.1} µs/tick", baseline);
+    println!("  • Prepared handles:   {:>8.1} µs/tick", prepared);
+    println!("  • With legacy system: {:>8.1} µs/tick", mixed);
+    println!("  • Systems that generate SQL per tick show up as re-preparing");
+
+    Ok(())
+}
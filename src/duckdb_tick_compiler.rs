@@ -0,0 +1,206 @@
+//! Tick Compiler: Fewer Round-Trips per Tick
+//!
+//! `bench_60ups_feasibility` issues 4 UPDATEs and 10 COUNT queries per tick,
+//! each paying the per-call overhead measured in `true_overhead.rs`. The tick
+//! compiler (`system_runner::compile_tick`):
+//! 1. Merges compatible UPDATE systems on the same table into one statement
+//! 2. Combines all scalar queries into one SELECT
+//! 3. Runs the tick in one transaction and returns every query result together
+//!
+//! Both versions run on identical deterministic data and must end in the
+//! same world state. A second, order-sensitive system list (a subquery that
+//! reads another table between two updates of it) checks that merging never
+//! moves a system past one on a different table.
+
+mod system_runner;
+
+use duckdb::types::Value;
+use duckdb::{Connection, Result};
+use std::time::Instant;
+use system_runner::{compile_tick, ScalarQuery, SystemKind, SystemRunner, UpdateSystem};
+
+const TICKS: usize = 600;
+
+fn setup(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        DROP TABLE IF EXISTS sim_belts;
+        DROP TABLE IF EXISTS sim_machines;
+        DROP TABLE IF EXISTS sim_trains;
+        DROP TABLE IF EXISTS sim_map;
+
+        CREATE TABLE sim_belts AS
+        SELECT i AS id, (i % 100)::DOUBLE AS pos, 8.0::DOUBLE AS speed, 0.0::DOUBLE AS wear
+        FROM generate_series(1, 200000) AS t(i);
+
+        CREATE TABLE sim_machines AS
+        SELECT i AS id, ((i * 37) % 100) / 100.0 AS progress, i % 5 != 0 AS active, 0 AS crafted
+        FROM generate_series(1, 20000) AS t(i);
+
+        CREATE TABLE sim_trains AS
+        SELECT i AS id, (i % 1000)::DOUBLE AS pos, 50.0::DOUBLE AS speed, 1000.0::DOUBLE AS fuel
+        FROM generate_series(1, 1000) AS t(i);
+
+        CREATE TABLE sim_map AS
+        SELECT i AS id, (i * 17) % 500 AS x, (i * 31) % 500 AS y
+        FROM generate_series(1, 50000) AS t(i);
+        ",
+    )
+}
+
+fn updates() -> Vec<UpdateSystem> {
+    vec![
+        UpdateSystem { name: "belt_move", table: "sim_belts", set: vec![("pos", "pos + speed * $dt")], filter: None },
+        UpdateSystem { name: "belt_wear", table: "sim_belts", set: vec![("wear", "wear + speed * $dt * 0.001")], filter: None },
+        UpdateSystem { name: "machine_progress", table: "sim_machines", set: vec![("progress", "progress + $dt / 2.0")], filter: Some("active") },
+        // Reads `progress`, which machine_progress writes: must stay separate
+        UpdateSystem {
+            name: "machine_finish",
+            table: "sim_machines",
+            set: vec![("progress", "progress - 1.0"), ("crafted", "crafted + 1")],
+            filter: Some("progress >= 1.0"),
+        },
+        UpdateSystem { name: "train_move", table: "sim_trains", set: vec![("pos", "pos + speed * $dt")], filter: None },
+        UpdateSystem { name: "train_fuel", table: "sim_trains", set: vec![("fuel", "fuel - speed * $dt * 0.01")], filter: Some("fuel > 0") },
+    ]
+}
+
+/// `machine_output` counts belts past 100 *before* `belt_boost` moves them;
+/// merging `belt_boost` into `belt_wear` would run it first.
+fn ordered_updates() -> Vec<UpdateSystem> {
+    vec![
+        UpdateSystem { name: "belt_wear", table: "sim_belts", set: vec![("wear", "wear + 0.01")], filter: None },
+        UpdateSystem {
+            name: "machine_output",
+            table: "sim_machines",
+            set: vec![("crafted", "crafted + (SELECT count(*) FROM sim_belts WHERE pos > 100)")],
+            filter: None,
+        },
+        UpdateSystem { name: "belt_boost", table: "sim_belts", set: vec![("pos", "pos + 50")], filter: None },
+    ]
+}
+
+/// Every update as its own statement, in declaration order.
+fn separate_runner<'c>(conn: &'c Connection, updates: &[UpdateSystem]) -> SystemRunner<'c> {
+    let mut runner = SystemRunner::new(conn);
+    for u in updates {
+        let sets: Vec<String> = u.set.iter().map(|(c, e)| format!("{c} = {e}")).collect();
+        let filter = u.filter.map(|f| format!(" WHERE {f}")).unwrap_or_default();
+        runner.add_system(u.name, SystemKind::Execute, &format!("UPDATE {} SET {}{}", u.table, sets.join(", "), filter));
+    }
+    runner
+}
+
+const QUERY_NAMES: [&str; 10] = ["q0", "q1", "q2", "q3", "q4", "q5", "q6", "q7", "q8", "q9"];
+
+fn query_sql(i: usize) -> String {
+    let lo = 50 * i;
+    format!("SELECT COUNT(*) FROM sim_map WHERE x BETWEEN {lo} AND {} AND y BETWEEN {lo} AND {}", lo + 10, lo + 10)
+}
+
+fn checksum(conn: &Connection) -> Result<f64> {
+    conn.query_row(
+        "SELECT (SELECT sum(pos) + sum(wear) FROM sim_belts)
+              + (SELECT sum(progress) + sum(crafted) FROM sim_machines)
+              + (SELECT sum(pos) + sum(fuel) FROM sim_trains)",
+        [],
+        |r| r.get(0),
+    )
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    println!("=== Tick Compiler: Merged Systems in One Transaction ===\n");
+
+    let dt = 1.0 / 60.0;
+    let queries: Vec<(String, &'static str)> = (0..10).map(|i| (query_sql(i), QUERY_NAMES[i])).collect();
+
+    // --------------------------------------------------------------------
+    // Baseline: every system and query is its own prepared statement
+    // --------------------------------------------------------------------
+    println!("--- Separate statements (prepared, no merging) ---");
+    let conn_a = Connection::open_in_memory()?;
+    setup(&conn_a)?;
+    let mut runner = separate_runner(&conn_a, &updates());
+    for (sql, name) in &queries {
+        runner.add_system(*name, SystemKind::Query, sql);
+    }
+    runner.params.set("dt", Value::Double(dt));
+
+    let start = Instant::now();
+    let mut separate_results = Vec::new();
+    for _ in 0..TICKS {
+        separate_results = runner.run_tick()?;
+    }
+    let separate = start.elapsed().as_secs_f64() * 1e6 / TICKS as f64;
+    println!("  {} statements/tick: {:.1} µs/tick\n", updates().len() + queries.len(), separate);
+
+    // --------------------------------------------------------------------
+    // Compiled tick
+    // --------------------------------------------------------------------
+    println!("--- Compiled tick ---");
+    let conn_b = Connection::open_in_memory()?;
+    setup(&conn_b)?;
+    let scalar_queries: Vec<ScalarQuery> =
+        (0..10).map(|i| ScalarQuery { name: QUERY_NAMES[i], sql: query_sql(i) }).collect();
+    let mut tick = compile_tick(&conn_b, &updates(), &scalar_queries)?;
+    tick.runner.params.set("dt", Value::Double(dt));
+    tick.print_plan();
+
+    let start = Instant::now();
+    let mut compiled_results = Vec::new();
+    for _ in 0..TICKS {
+        compiled_results = tick.run()?;
+    }
+    let compiled = start.elapsed().as_secs_f64() * 1e6 / TICKS as f64;
+    println!("  {:.1} µs/tick ({:.2}× vs separate)\n", compiled, separate / compiled);
+    tick.runner.print_report();
+
+    // --------------------------------------------------------------------
+    // Same results?
+    // --------------------------------------------------------------------
+    let (a, b) = (checksum(&conn_a)?, checksum(&conn_b)?);
+    let separate_counts: Vec<String> = separate_results
+        .iter()
+        .filter_map(|(_, out)| match out {
+            system_runner::SystemOutput::Rows(rows) => rows.first().and_then(|r| r.first()).map(|v| format!("{:?}", v)),
+            _ => None,
+        })
+        .collect();
+    let compiled_counts: Vec<String> = compiled_results.iter().map(|(_, v)| format!("{:?}", v)).collect();
+
+    // --------------------------------------------------------------------
+    // Order across tables
+    // --------------------------------------------------------------------
+    println!("\n--- Order across tables (subquery between two belt updates) ---");
+    let conn_c = Connection::open_in_memory()?;
+    let conn_d = Connection::open_in_memory()?;
+    setup(&conn_c)?;
+    setup(&conn_d)?;
+    let mut ordered_separate = separate_runner(&conn_c, &ordered_updates());
+    let mut ordered = compile_tick(&conn_d, &ordered_updates(), &[])?;
+    ordered.print_plan();
+    for _ in 0..10 {
+        ordered_separate.run_tick()?;
+        ordered.run()?;
+    }
+    let (c, d) = (checksum(&conn_c)?, checksum(&conn_d)?);
+    let order_kept = (c - d).abs() < 1e-6 * c.abs().max(1.0);
+    println!("  Checksum after 10 ticks: {:.3} vs {:.3} ({})", c, d, if order_kept { "✓" } else { "✗ reordered" });
+
+    let world_matches = (a - b).abs() < 1e-6 * a.abs().max(1.0);
+    let queries_match = separate_counts == compiled_counts;
+    let failures = [world_matches, queries_match, order_kept].iter().filter(|ok| !**ok).count();
+
+    println!("\n=== Summary ===\n");
+    println!("  • Round-trips/tick: {} → {}", tick.round_trips_before, tick.round_trips_after);
+    println!("  • Separate: {:.1} µs/tick, compiled: {:.1} µs/tick", separate, compiled);
+    println!("  • World checksum matches: {} ({:.3} vs {:.3})", world_matches, a, b);
+    println!("  • Query results match: {}", queries_match);
+    println!("  • Declaration order kept across tables: {} ({} statements)", order_kept, ordered.groups.len());
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+//! Transactional Ticks and Speculative Simulation
+//!
+//! Nothing else in the project uses transactions, so a system that fails
+//! mid-tick leaves the world half-updated. This shows:
+//! 1. Plain `run_tick`: a failing system after `movement` leaves movement applied
+//! 2. `run_tick_atomic`: the same failure rolls the whole tick back
+//! 3. `simulate_ahead(n)`: run n ticks, observe, roll back — then check the
+//!    prediction against actually running those ticks
+//! 4. Cost of BEGIN/COMMIT and of a speculative look-ahead
+
+mod system_runner;
+
+use duckdb::types::Value;
+use duckdb::{Connection, Result};
+use std::time::Instant;
+use system_runner::{SystemKind, SystemRunner};
+
+const UNITS: usize = 100_000;
+const FAIL_TICK: i64 = 5;
+
+fn setup(conn: &Connection) -> Result<()> {
+    conn.execute_batch(&format!(
+        "DROP TABLE IF EXISTS units;
+         CREATE TABLE units AS
+         SELECT i AS id,
+                (i % 1000)::DOUBLE AS x, (i / 1000)::DOUBLE AS y,
+                ((i % 7) - 3)::DOUBLE AS vx, ((i % 5) - 2)::DOUBLE AS vy,
+                100 AS hp
+         FROM range({UNITS}) t(i);"
+    ))
+}
+
+fn add_systems(runner: &mut SystemRunner) {
+    runner.add_system("movement", SystemKind::Execute,
+        "UPDATE units SET x = x + vx * $dt, y = y + vy * $dt");
+    runner.add_system("damage_over_time", SystemKind::Execute,
+        "UPDATE units SET hp = hp - 1 WHERE hp > 0 AND id % 10 = $tick % 10");
+}
+
+/// A system that fails on one tick, after `movement` has already run.
+fn add_poison_system(runner: &mut SystemRunner) {
+    runner.add_system("poison", SystemKind::Execute,
+        "UPDATE units SET hp = CASE WHEN $tick = $fail_tick THEN error('poison system failed')::INTEGER ELSE hp END
+         WHERE id = 0");
+}
+
+fn checksum(conn: &Connection) -> Result<f64> {
+    conn.query_row("SELECT sum(x) + sum(y) * 3 + sum(hp) * 7 FROM units", [], |r| r.get(0))
+}
+
+fn new_runner(conn: &Connection, poison: bool) -> SystemRunner<'_> {
+    let mut runner = SystemRunner::new(conn);
+    add_systems(&mut runner);
+    if poison {
+        add_poison_system(&mut runner);
+    }
+    runner.params.set("dt", Value::Double(1.0 / 60.0));
+    runner.params.set("fail_tick", Value::BigInt(FAIL_TICK));
+    runner
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    println!("=== Transactional Ticks & Speculative Simulation ===\n");
+    println!("Units: {}\n", UNITS);
+    let mut failures = 0;
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch("SET threads TO 8;")?;
+
+    // --------------------------------------------------------------------
+    // 1. Without transactions: half-applied tick
+    // --------------------------------------------------------------------
+    println!("--- 1. run_tick (no transaction) ---");
+    setup(&conn)?;
+    let mut runner = new_runner(&conn, true);
+    for _ in 0..FAIL_TICK {
+        runner.run_tick()?;
+    }
+    let before = checksum(&conn)?;
+    let err = runner.run_tick().expect_err("poison system fails on FAIL_TICK");
+    let after = checksum(&conn)?;
+    println!("  Tick {} failed: {}", FAIL_TICK, err);
+    println!("  World changed anyway: {} (movement applied, rest skipped)\n", before != after);
+
+    // --------------------------------------------------------------------
+    // 2. Atomic ticks: failure rolls back the whole tick
+    // --------------------------------------------------------------------
+    println!("--- 2. run_tick_atomic ---");
+    setup(&conn)?;
+    let mut runner = new_runner(&conn, true);
+    for _ in 0..FAIL_TICK {
+        runner.run_tick_atomic()?;
+    }
+    let before = checksum(&conn)?;
+    let err = runner.run_tick_atomic().expect_err("poison system fails on FAIL_TICK");
+    let after = checksum(&conn)?;
+    println!("  Tick {} failed: {}", FAIL_TICK, err);
+    let counter_kept = runner.ticks() == FAIL_TICK as u64;
+    println!("  World unchanged: {}", before == after);
+    println!("  Tick counter still at {}: {}\n", FAIL_TICK, counter_kept);
+    if before != after || !counter_kept {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 3. simulate_ahead: predict, roll back, then verify the prediction
+    // --------------------------------------------------------------------
+    println!("--- 3. simulate_ahead ---");
+    setup(&conn)?;
+    let mut runner = new_runner(&conn, false);
+    for _ in 0..10 {
+        runner.run_tick_atomic()?;
+    }
+    let observe = |conn: &Connection, _tick: u64| -> Result<(f64, i64)> {
+        conn.query_row("SELECT x, (SELECT sum(hp) FROM units) FROM units WHERE id = 42", [], |r| Ok((r.get(0)?, r.get(1)?)))
+    };
+
+    let look_ahead = 30;
+    let before = checksum(&conn)?;
+    let start = Instant::now();
+    let predicted = runner.simulate_ahead(look_ahead, observe)?;
+    let sim_time = start.elapsed();
+    println!("  simulate_ahead({}): {:?} ({:.2} ms/tick)",
+             look_ahead, sim_time, sim_time.as_secs_f64() * 1000.0 / look_ahead as f64);
+    let (world_restored, counter_restored) = (checksum(&conn)? == before, runner.ticks() == 10);
+    println!("  World restored: {}, tick counter restored: {}", world_restored, counter_restored);
+    if !world_restored || !counter_restored {
+        failures += 1;
+    }
+    println!("  Predicted unit 42 x after {} ticks: {:.4}", look_ahead, predicted[look_ahead - 1].0);
+
+    let mut actual = Vec::with_capacity(look_ahead);
+    for _ in 0..look_ahead {
+        runner.run_tick_atomic()?;
+        actual.push(observe(&conn, runner.ticks())?);
+    }
+    println!("  Prediction matches real run: {}\n", predicted == actual);
+    if predicted != actual {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 4. Transaction overhead
+    // --------------------------------------------------------------------
+    println!("--- 4. Overhead ---");
+    let ticks = 300;
+    setup(&conn)?;
+    let mut runner = new_runner(&conn, false);
+    let start = Instant::now();
+    for _ in 0..ticks {
+        runner.run_tick()?;
+    }
+    let plain = start.elapsed().as_secs_f64() * 1e6 / ticks as f64;
+    let start = Instant::now();
+    for _ in 0..ticks {
+        runner.run_tick_atomic()?;
+    }
+    let atomic = start.elapsed().as_secs_f64() * 1e6 / ticks as f64;
+    println!("  run_tick:        {:>8.1} µs/tick", plain);
+    println!("  run_tick_atomic: {:>8.1} µs/tick ({:+.1} µs for BEGIN/COMMIT)", atomic, atomic - plain);
+
+    println!("\n=== Summary ===\n");
+    println!("  • A failing system no longer leaves a half-applied tick");
+    println!("  • simulate_ahead() predicts exactly what the real ticks produce");
+    println!("  • Rollback leaves both the world and the tick counter untouched");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
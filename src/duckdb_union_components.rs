@@ -0,0 +1,333 @@
+//! Tagged-Variant Components via DuckDB UNION Types
+//!
+//! `duckdb_sparse.rs::bench_union_type` shows UNION columns work, but every
+//! read goes through hand-written `union_tag` / `union_extract` SQL. This maps
+//! a Rust enum onto a DuckDB UNION so a component like
+//!
+//!   enum Ai { Idle, Patrol { waypoint, speed }, Chase { target } }
+//!
+//! can be stored, filtered by variant and read back without writing SQL:
+//! 1. `UnionComponent` describes each variant (tag + payload fields)
+//! 2. The UNION type, inserts and SELECT lists are generated from that schema
+//! 3. Bulk inserts go through an Appender-filled staging table
+//! 4. Unit variants are stored as a BOOLEAN member (UNION members need a type)
+
+use duckdb::types::Value;
+use duckdb::{appender_params_from_iter, Connection, Result, Row};
+use std::time::Instant;
+
+const SIZE: usize = 1_000_000; // 1M entities
+
+// ============================================================================
+// Rust enum <-> DuckDB UNION mapping
+// ============================================================================
+
+/// One UNION member: its tag and the STRUCT fields of its payload.
+/// A variant without fields is stored as a BOOLEAN member.
+struct VariantSchema {
+    tag: &'static str,
+    fields: &'static [(&'static str, &'static str)], // (name, SQL type)
+}
+
+trait UnionComponent: Sized {
+    /// All variants in declaration order.
+    const VARIANTS: &'static [VariantSchema];
+
+    /// Tag of the variant held by `self`.
+    fn tag(&self) -> &'static str;
+
+    /// Payload values in the same order as the variant's `fields`.
+    fn payload(&self) -> Vec<Value>;
+
+    /// Rebuild a value from its tag and the payload columns of that variant,
+    /// which start at `first_col` in `row`.
+    fn from_payload(tag: &str, row: &Row<'_>, first_col: usize) -> Result<Self>;
+}
+
+fn member_sql_type(variant: &VariantSchema) -> String {
+    if variant.fields.is_empty() {
+        return "BOOLEAN".to_string();
+    }
+    let fields: Vec<String> = variant
+        .fields
+        .iter()
+        .map(|(name, ty)| format!("{} {}", name, ty))
+        .collect();
+    format!("STRUCT({})", fields.join(", "))
+}
+
+/// `UNION(idle BOOLEAN, patrol STRUCT(...), ...)`
+fn union_sql_type<T: UnionComponent>() -> String {
+    let members: Vec<String> = T::VARIANTS
+        .iter()
+        .map(|v| format!("{} {}", v.tag, member_sql_type(v)))
+        .collect();
+    format!("UNION({})", members.join(", "))
+}
+
+/// `union_value(tag := payload)` reading its payload from staging columns.
+fn member_value_sql(variant: &VariantSchema) -> String {
+    if variant.fields.is_empty() {
+        return format!("union_value({} := true)", variant.tag);
+    }
+    let fields: Vec<String> = variant
+        .fields
+        .iter()
+        .map(|(name, _)| format!("'{}': {}_{}", name, variant.tag, name))
+        .collect();
+    format!("union_value({} := {{{}}})", variant.tag, fields.join(", "))
+}
+
+/// SQL predicate selecting one variant, e.g. `union_tag(ai) = 'chase'`.
+/// Returns `None` for a tag the component does not declare.
+fn variant_filter<T: UnionComponent>(column: &str, tag: &str) -> Option<String> {
+    T::VARIANTS
+        .iter()
+        .find(|v| v.tag == tag)
+        .map(|v| format!("union_tag({}) = '{}'", column, v.tag))
+}
+
+fn create_union_table<T: UnionComponent>(conn: &Connection, table: &str, column: &str) -> Result<()> {
+    conn.execute_batch(&format!(
+        "DROP TABLE IF EXISTS {table};
+         CREATE TABLE {table} (id BIGINT, {column} {});",
+        union_sql_type::<T>()
+    ))
+}
+
+/// Bulk insert: append flattened payloads to a staging table, then build the
+/// UNION values in one INSERT ... SELECT.
+fn insert_union_components<T: UnionComponent>(
+    conn: &Connection,
+    table: &str,
+    rows: &[(i64, T)],
+) -> Result<()> {
+    let mut stage_cols = vec!["id BIGINT".to_string(), "tag VARCHAR".to_string()];
+    for variant in T::VARIANTS {
+        for (name, ty) in variant.fields {
+            stage_cols.push(format!("{}_{} {}", variant.tag, name, ty));
+        }
+    }
+    conn.execute_batch(&format!(
+        "DROP TABLE IF EXISTS _union_stage;
+         CREATE TEMP TABLE _union_stage ({});",
+        stage_cols.join(", ")
+    ))?;
+
+    {
+        let mut appender = conn.appender("_union_stage")?;
+        for (id, component) in rows {
+            let tag = component.tag();
+            let mut values = vec![Value::BigInt(*id), Value::Text(tag.to_string())];
+            let mut payload = Some(component.payload());
+            for variant in T::VARIANTS {
+                if variant.tag == tag {
+                    values.extend(payload.take().unwrap_or_default());
+                } else {
+                    values.extend(variant.fields.iter().map(|_| Value::Null));
+                }
+            }
+            appender.append_row(appender_params_from_iter(values))?;
+        }
+        appender.flush()?;
+    }
+
+    let cases: Vec<String> = T::VARIANTS
+        .iter()
+        .map(|v| format!("WHEN '{}' THEN {}::{}", v.tag, member_value_sql(v), union_sql_type::<T>()))
+        .collect();
+    conn.execute_batch(&format!(
+        "INSERT INTO {table}
+         SELECT id, CASE tag {} END FROM _union_stage;
+         DROP TABLE _union_stage;",
+        cases.join(" ")
+    ))
+}
+
+/// SELECT list: id, tag, then every payload field of every variant.
+fn select_list<T: UnionComponent>(column: &str) -> String {
+    let mut cols = vec!["id".to_string(), format!("union_tag({})::VARCHAR", column)];
+    for variant in T::VARIANTS {
+        for (name, _) in variant.fields {
+            cols.push(format!(
+                "struct_extract(union_extract({}, '{}'), '{}')",
+                column, variant.tag, name
+            ));
+        }
+    }
+    cols.join(", ")
+}
+
+/// Load components, optionally only one variant, back into Rust. A variant
+/// tag the component does not declare is an error, not "no filter".
+fn load_union_components<T: UnionComponent>(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    variant: Option<&str>,
+) -> Result<Vec<(i64, T)>> {
+    let mut sql = format!("SELECT {} FROM {}", select_list::<T>(column), table);
+    if let Some(tag) = variant {
+        let filter = variant_filter::<T>(column, tag).ok_or_else(|| {
+            duckdb::Error::InvalidParameterName(format!("'{}' is not a variant of {}", tag, column))
+        })?;
+        sql.push_str(&format!(" WHERE {}", filter));
+    }
+    sql.push_str(" ORDER BY id");
+
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], |row| {
+        let id: i64 = row.get(0)?;
+        let tag: String = row.get(1)?;
+        let mut first_col = 2;
+        for v in T::VARIANTS {
+            if v.tag == tag {
+                break;
+            }
+            first_col += v.fields.len();
+        }
+        Ok((id, T::from_payload(&tag, row, first_col)?))
+    })?;
+    rows.collect()
+}
+
+// ============================================================================
+// Example component: AI state machine
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ai {
+    Idle,
+    Patrol { waypoint: i32, speed: f32 },
+    Chase { target: i64 },
+}
+
+impl UnionComponent for Ai {
+    const VARIANTS: &'static [VariantSchema] = &[
+        VariantSchema { tag: "idle", fields: &[] },
+        VariantSchema {
+            tag: "patrol",
+            fields: &[("waypoint", "INTEGER"), ("speed", "FLOAT")],
+        },
+        VariantSchema { tag: "chase", fields: &[("target", "BIGINT")] },
+    ];
+
+    fn tag(&self) -> &'static str {
+        match self {
+            Ai::Idle => "idle",
+            Ai::Patrol { .. } => "patrol",
+            Ai::Chase { .. } => "chase",
+        }
+    }
+
+    fn payload(&self) -> Vec<Value> {
+        match self {
+            Ai::Idle => vec![],
+            Ai::Patrol { waypoint, speed } => vec![Value::Int(*waypoint), Value::Float(*speed)],
+            Ai::Chase { target } => vec![Value::BigInt(*target)],
+        }
+    }
+
+    fn from_payload(tag: &str, row: &Row<'_>, first_col: usize) -> Result<Self> {
+        match tag {
+            "idle" => Ok(Ai::Idle),
+            "patrol" => Ok(Ai::Patrol {
+                waypoint: row.get(first_col)?,
+                speed: row.get(first_col + 1)?,
+            }),
+            "chase" => Ok(Ai::Chase { target: row.get(first_col)? }),
+            other => Err(duckdb::Error::InvalidColumnName(format!("unknown Ai variant '{}'", other))),
+        }
+    }
+}
+
+fn make_ai(i: usize) -> Ai {
+    match i % 3 {
+        0 => Ai::Idle,
+        1 => Ai::Patrol { waypoint: (i % 16) as i32, speed: 1.5 },
+        _ => Ai::Chase { target: ((i * 7) % SIZE) as i64 },
+    }
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    println!("=== DuckDB UNION Components with Typed Rust Access ===\n");
+    println!("Entity count: {}\n", SIZE);
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch("SET threads TO 8;")?;
+
+    println!("  Ai UNION type: {}\n", union_sql_type::<Ai>());
+    let mut failures = 0;
+
+    // --- Round trip on a handful of entities ---
+    println!("--- Round Trip ---");
+    let sample: Vec<(i64, Ai)> = (0..6).map(|i| (i as i64, make_ai(i))).collect();
+    create_union_table::<Ai>(&conn, "ai_sample", "ai")?;
+    insert_union_components(&conn, "ai_sample", &sample)?;
+    let loaded: Vec<(i64, Ai)> = load_union_components(&conn, "ai_sample", "ai", None)?;
+    for (id, ai) in &loaded {
+        println!("  entity {}: {:?}", id, ai);
+    }
+    println!("  Round trip matches: {}\n", loaded == sample);
+    if loaded != sample {
+        failures += 1;
+    }
+
+    // --- Bulk insert ---
+    println!("--- Bulk Insert ---");
+    let rows: Vec<(i64, Ai)> = (0..SIZE).map(|i| (i as i64, make_ai(i))).collect();
+    create_union_table::<Ai>(&conn, "ai_components", "ai")?;
+    let start = Instant::now();
+    insert_union_components(&conn, "ai_components", &rows)?;
+    let insert_time = start.elapsed();
+    println!("  Insert {} components: {:?} ({:.1} ns/entity)\n",
+             SIZE, insert_time, insert_time.as_nanos() as f64 / SIZE as f64);
+
+    // --- Filter by variant ---
+    println!("--- Filter by Variant ---");
+    let filter = variant_filter::<Ai>("ai", "chase").expect("chase is an Ai variant");
+    let count_sql = format!("SELECT COUNT(*) FROM ai_components WHERE {}", filter);
+    let mut stmt = conn.prepare(&count_sql)?;
+    let start = Instant::now();
+    let mut chasing = 0i64;
+    for _ in 0..100 {
+        chasing = stmt.query_row([], |r| r.get(0))?;
+    }
+    let filter_time = start.elapsed();
+    println!("  {}", count_sql);
+    println!("  Chasing entities: {} ({:.2} µs/query)\n",
+             chasing, filter_time.as_nanos() as f64 / 100.0 / 1000.0);
+
+    // --- Load one variant back into Rust ---
+    println!("--- Load Variant into Rust ---");
+    let start = Instant::now();
+    let chase: Vec<(i64, Ai)> = load_union_components(&conn, "ai_components", "ai", Some("chase"))?;
+    let load_time = start.elapsed();
+    let expected = rows.iter().filter(|(_, ai)| matches!(ai, Ai::Chase { .. })).count();
+    println!("  Loaded {} Ai::Chase: {:?} ({:.1} ns/entity)",
+             chase.len(), load_time, load_time.as_nanos() as f64 / chase.len().max(1) as f64);
+    let values_match = chase.iter().all(|(id, ai)| rows[*id as usize].1 == *ai);
+    println!("  Count matches Rust: {}", chase.len() == expected);
+    println!("  Values match Rust: {}", values_match);
+    if chase.len() != expected || chasing != expected as i64 || !values_match {
+        failures += 1;
+    }
+    match load_union_components::<Ai>(&conn, "ai_components", "ai", Some("flee")) {
+        Ok(all) => {
+            failures += 1;
+            println!("  ✗ Unknown variant 'flee' loaded {} rows\n", all.len());
+        }
+        Err(e) => println!("  Unknown variant rejected: {}\n", e),
+    }
+
+    println!("=== Summary ===\n");
+    println!("  • UNION type, inserts and SELECT lists generated from UnionComponent");
+    println!("  • Variant filters are plain union_tag() predicates");
+    println!("  • Unit variants cost one BOOLEAN member");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
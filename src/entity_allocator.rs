@@ -0,0 +1,327 @@
+//! Generational Entity IDs for DuckDB and Polars
+//!
+//! Entity ids elsewhere are plain `generate_series` integers: once an entity is
+//! despawned its id can be handed out again, and any `docking_status.spaceship_id`
+//! or `cargo.train_id` still holding it silently points at the new entity.
+//!
+//! This allocator hands out `EntityId { index, generation }`:
+//! 1. Freed indices go on a FIFO free list and come back with generation + 1
+//! 2. Ids are packed into one 64-bit value (generation << 32 | index) so they
+//!    fit a BIGINT column in DuckDB and an Int64 column in Polars
+//! 3. A stale id (old generation) never compares equal to the live one, so
+//!    foreign-key-like columns can be validated against the alive set
+
+use duckdb::{appender_params_from_iter, Connection};
+use polars::prelude::*;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+const SIZE: usize = 1_000_000; // 1M entities
+
+// ============================================================================
+// EntityId + allocator
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct EntityId {
+    index: u32,
+    generation: u32,
+}
+
+impl EntityId {
+    /// Packed form stored in BIGINT / Int64 columns.
+    fn to_bits(self) -> i64 {
+        (((self.generation as u64) << 32) | self.index as u64) as i64
+    }
+
+    fn from_bits(bits: i64) -> Self {
+        let bits = bits as u64;
+        EntityId { index: bits as u32, generation: (bits >> 32) as u32 }
+    }
+}
+
+#[derive(Default)]
+struct EntityAllocator {
+    /// Current generation of every index ever allocated.
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    /// FIFO so a freed index is reused as late as possible.
+    free: VecDeque<u32>,
+    alive_count: usize,
+}
+
+impl EntityAllocator {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn alloc(&mut self) -> EntityId {
+        self.alive_count += 1;
+        if let Some(index) = self.free.pop_front() {
+            self.alive[index as usize] = true;
+            return EntityId { index, generation: self.generations[index as usize] };
+        }
+        let index = u32::try_from(self.generations.len()).expect("entity index space exhausted");
+        self.generations.push(0);
+        self.alive.push(true);
+        EntityId { index, generation: 0 }
+    }
+
+    /// Reuses free indices first, then extends the index space in one go.
+    fn alloc_batch(&mut self, count: usize) -> Vec<EntityId> {
+        let mut ids = Vec::with_capacity(count);
+        while ids.len() < count && !self.free.is_empty() {
+            ids.push(self.alloc());
+        }
+        let fresh = count - ids.len();
+        if fresh == 0 {
+            return ids;
+        }
+        let first = self.generations.len();
+        // Same limit as `alloc`: the last new index must fit in a u32.
+        let last = u32::try_from(first + fresh - 1).expect("entity index space exhausted");
+        self.generations.resize(first + fresh, 0);
+        self.alive.resize(first + fresh, true);
+        self.alive_count += fresh;
+        ids.extend((first as u32..=last).map(|index| EntityId { index, generation: 0 }));
+        ids
+    }
+
+    fn is_alive(&self, id: EntityId) -> bool {
+        let i = id.index as usize;
+        i < self.generations.len() && self.alive[i] && self.generations[i] == id.generation
+    }
+
+    /// Returns false for stale or already-freed ids.
+    fn free(&mut self, id: EntityId) -> bool {
+        if !self.is_alive(id) {
+            return false;
+        }
+        let i = id.index as usize;
+        self.alive[i] = false;
+        // Wrapping: after 2^32 reuses of one slot an ancient id could match
+        // again, which is the usual generational-index trade-off.
+        self.generations[i] = self.generations[i].wrapping_add(1);
+        self.free.push_back(id.index);
+        self.alive_count -= 1;
+        true
+    }
+
+    /// Returns how many ids were actually freed.
+    fn free_batch(&mut self, ids: &[EntityId]) -> usize {
+        ids.iter().filter(|id| self.free(**id)).count()
+    }
+
+    fn len(&self) -> usize {
+        self.alive_count
+    }
+
+    fn alive_bits(&self) -> Vec<i64> {
+        (0..self.generations.len())
+            .filter(|&i| self.alive[i])
+            .map(|i| EntityId { index: i as u32, generation: self.generations[i] }.to_bits())
+            .collect()
+    }
+}
+
+// ============================================================================
+// DuckDB validation
+// ============================================================================
+
+/// Mirror the alive set into `entity_alive(id BIGINT PRIMARY KEY)`.
+fn sync_alive_duckdb(conn: &Connection, alloc: &EntityAllocator) -> duckdb::Result<()> {
+    conn.execute_batch(
+        "DROP TABLE IF EXISTS entity_alive;
+         CREATE TABLE entity_alive (id BIGINT PRIMARY KEY);",
+    )?;
+    let mut appender = conn.appender("entity_alive")?;
+    for bits in alloc.alive_bits() {
+        appender.append_row([bits])?;
+    }
+    appender.flush()
+}
+
+/// Count non-NULL references in `table.column` to ids that are not alive.
+fn dangling_refs_duckdb(conn: &Connection, table: &str, column: &str) -> duckdb::Result<i64> {
+    conn.query_row(
+        &format!(
+            "SELECT COUNT(*) FROM {table} r
+             WHERE r.{column} IS NOT NULL
+               AND NOT EXISTS (SELECT 1 FROM entity_alive a WHERE a.id = r.{column})"
+        ),
+        [],
+        |r| r.get(0),
+    )
+}
+
+/// Drop rows whose reference is dangling (relationship rows die with their entity).
+fn delete_dangling_duckdb(conn: &Connection, table: &str, column: &str) -> duckdb::Result<usize> {
+    conn.execute(
+        &format!(
+            "DELETE FROM {table}
+             WHERE {column} IS NOT NULL
+               AND {column} NOT IN (SELECT id FROM entity_alive)"
+        ),
+        [],
+    )
+}
+
+// ============================================================================
+// Polars validation
+// ============================================================================
+
+fn alive_series(alloc: &EntityAllocator) -> Series {
+    Series::new("alive".into(), alloc.alive_bits())
+}
+
+/// Rows of `df` whose `column` references a dead or stale entity.
+fn dangling_refs_polars(df: &DataFrame, column: &str, alive: &Series) -> PolarsResult<DataFrame> {
+    df.clone()
+        .lazy()
+        .filter(col(column).is_not_null().and(col(column).is_in(alive.clone().lit(), false).not()))
+        .collect()
+}
+
+fn drop_dangling_polars(df: &DataFrame, column: &str, alive: &Series) -> PolarsResult<DataFrame> {
+    df.clone()
+        .lazy()
+        .filter(col(column).is_null().or(col(column).is_in(alive.clone().lit(), false)))
+        .collect()
+}
+
+// ============================================================================
+// Benchmarks
+// ============================================================================
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("=== Generational Entity ID Allocator ===\n");
+    println!("Entity count: {}\n", SIZE);
+
+    // --- Allocator semantics ---
+    println!("--- Allocator Semantics ---");
+    let mut alloc = EntityAllocator::new();
+    let a = alloc.alloc();
+    let b = alloc.alloc();
+    alloc.free(a);
+    let c = alloc.alloc();
+    println!("  a = {:?}, b = {:?}", a, b);
+    println!("  free(a); alloc() = {:?}", c);
+    let (reused, stale_alive) = (c.index == a.index, alloc.is_alive(a));
+    let (double_free_rejected, round_trip) = (!alloc.free(a), EntityId::from_bits(c.to_bits()) == c);
+    println!("  Same index reused: {}", reused);
+    println!("  Stale a is alive: {} (expected false)", stale_alive);
+    println!("  Double free rejected: {}", double_free_rejected);
+    println!("  Bits round trip: {}\n", round_trip);
+    let mut failures = [reused, !stale_alive, double_free_rejected, round_trip].iter().filter(|ok| !**ok).count();
+
+    // --- Bulk alloc/free ---
+    println!("--- Bulk Allocate / Free ---");
+    let mut alloc = EntityAllocator::new();
+    let start = Instant::now();
+    let ships = alloc.alloc_batch(SIZE);
+    println!("  alloc_batch({}): {:?}", SIZE, start.elapsed());
+
+    // Despawn every 4th ship
+    let despawned: Vec<EntityId> = ships.iter().copied().step_by(4).collect();
+    let start = Instant::now();
+    let freed = alloc.free_batch(&despawned);
+    println!("  free_batch({}): {:?}", freed, start.elapsed());
+
+    // Respawn reuses the freed slots with a bumped generation
+    let start = Instant::now();
+    let respawned = alloc.alloc_batch(freed / 2);
+    println!("  alloc_batch({}) from free list: {:?}", respawned.len(), start.elapsed());
+    println!("  Reused generation: {} (expected 1)", respawned[0].generation);
+    println!("  Alive: {} (expected {})\n", alloc.len(), SIZE - freed + freed / 2);
+    if respawned[0].generation != 1 || alloc.len() != SIZE - freed + freed / 2 {
+        failures += 1;
+    }
+
+    let alive = alloc.alive_bits();
+
+    // Relationship rows: every ship docks somewhere; a few still hold stale ids
+    let docking_ship: Vec<i64> = ships.iter().map(|id| id.to_bits()).collect();
+    let docking_target: Vec<i64> = (0..SIZE).map(|i| ships[(i * 7 + 1) % SIZE].to_bits()).collect();
+    let expected_dangling = ships.iter().filter(|id| !alloc.is_alive(**id)).count();
+
+    // --- DuckDB ---
+    println!("--- DuckDB: docking_status.spaceship_id ---");
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch("SET threads TO 8;")?;
+
+    let start = Instant::now();
+    sync_alive_duckdb(&conn, &alloc)?;
+    println!("  Sync {} alive ids: {:?}", alive.len(), start.elapsed());
+
+    conn.execute_batch("CREATE TABLE docking_status (spaceship_id BIGINT, target_id BIGINT);")?;
+    {
+        let mut appender = conn.appender("docking_status")?;
+        for (ship, target) in docking_ship.iter().zip(&docking_target) {
+            appender.append_row(appender_params_from_iter([*ship, *target]))?;
+        }
+        appender.flush()?;
+    }
+
+    let start = Instant::now();
+    let dangling = dangling_refs_duckdb(&conn, "docking_status", "spaceship_id")?;
+    println!("  Dangling spaceship_id: {} in {:?} (expected {})",
+             dangling, start.elapsed(), expected_dangling);
+
+    // A despawned ship's slot was reused: the old reference must not match it
+    let stale_hits: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM docking_status WHERE spaceship_id = ?",
+        [respawned[0].to_bits()],
+        |r| r.get(0),
+    )?;
+    println!("  Rows matching respawned id {:?}: {} (expected 0)", respawned[0], stale_hits);
+    if stale_hits != 0 {
+        failures += 1;
+    }
+
+    let start = Instant::now();
+    let deleted = delete_dangling_duckdb(&conn, "docking_status", "spaceship_id")?;
+    println!("  Delete dangling rows: {} in {:?}", deleted, start.elapsed());
+    let left = dangling_refs_duckdb(&conn, "docking_status", "spaceship_id")?;
+    println!("  Dangling after cleanup: {}\n", left);
+    if left != 0 {
+        failures += 1;
+    }
+
+    // --- Polars ---
+    println!("--- Polars: docking_status.spaceship_id ---");
+    let docking = df!(
+        "spaceship_id" => &docking_ship,
+        "target_id" => &docking_target
+    )?;
+    let alive_s = alive_series(&alloc);
+
+    let start = Instant::now();
+    let dangling_df = dangling_refs_polars(&docking, "spaceship_id", &alive_s)?;
+    println!("  Dangling spaceship_id: {} in {:?} (expected {})",
+             dangling_df.height(), start.elapsed(), expected_dangling);
+
+    let start = Instant::now();
+    let cleaned = drop_dangling_polars(&docking, "spaceship_id", &alive_s)?;
+    println!("  Drop dangling rows: {} → {} in {:?}", docking.height(), cleaned.height(), start.elapsed());
+    let still_dangling = dangling_refs_polars(&cleaned, "spaceship_id", &alive_s)?.height();
+    println!("  Dangling after cleanup: {}\n", still_dangling);
+    if still_dangling != 0 {
+        failures += 1;
+    }
+    let agree = dangling as usize == expected_dangling && dangling_df.height() == expected_dangling;
+    if !agree {
+        failures += 1;
+    }
+
+    println!("=== Summary ===\n");
+    println!("  • Freed indices are reused FIFO with generation + 1");
+    println!("  • Packed ids fit BIGINT / Int64, so both backends compare them directly");
+    println!("  • Stale references show up as dangling instead of aliasing new entities");
+    println!("  • DuckDB and Polars agree with Rust: {}", agree);
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,266 @@
+//! Mutable Column Views for LuaJIT FFI, Written Back via Arrow
+//!
+//! Shared by bins via `mod ffi_columns;`. `DistanceBatch` in
+//! `duckdb_luajit_ffi.rs` gives Lua read-only inputs plus one fresh `out`
+//! buffer. `ColumnBuffers` instead holds a key column and any number of f64
+//! columns in contiguous Rust buffers that Lua may modify in place:
+//!
+//! - `from_duckdb(conn, sql, key, writable)` / `from_polars(df, key, writable)`
+//!   fill the buffers (one memcpy per Arrow batch or column, no per-row calls)
+//! - `lua_view(lua)` returns `{ n = ..., <key> = const int64_t*, <col> = double* }`;
+//!   columns not listed as writable are `const double*`, so writing them is a
+//!   Lua error. The Lua state must be created with `Lua::unsafe_new` for FFI.
+//!   `ffi_view` is the same builder for buffers kept elsewhere (`lua_systems`)
+//! - `write_duckdb(conn, table)` moves the writable buffers into an Arrow
+//!   `RecordBatch` (no copy), scans it into a staging table through the
+//!   `arrow_import` table function (`vtab-arrow`) and applies one
+//!   `UPDATE ... FROM`
+//! - `write_polars(df)` moves each buffer into a `Float64Chunked` (no copy)
+//!   and replaces the column, casting back to its original dtype
+//!
+//! Views point into the buffers: they are valid until the buffers are
+//! written back or dropped, and must not be kept by Lua past that.
+
+#![allow(dead_code)]
+
+use duckdb::arrow::array::{Array, ArrayRef, Float64Array, Int64Array};
+use duckdb::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::vtab::arrow::ArrowVTab;
+use duckdb::vtab::arrow_recordbatch_to_query_params;
+use duckdb::Connection;
+use mlua::{Function, LightUserData, Lua, Table};
+use polars::prelude::{DataFrame, DataType as PolarsType, Float64Chunked, IntoSeries, PolarsError};
+use std::error::Error;
+use std::ffi::c_void;
+use std::sync::Arc;
+
+/// Table function name the Arrow scan is registered under.
+pub const ARROW_IMPORT: &str = "arrow_import";
+/// Rows per slice handed to `arrow_import` (one DuckDB vector).
+const IMPORT_SLICE: usize = 2048;
+
+const VIEW_KEY: &str = "ffi_columns.view";
+const VIEW_LUA: &str = r#"
+local ffi = require("ffi")
+local cast = ffi.cast
+return function(desc)
+  local view = { n = desc.n }
+  if desc.key then view[desc.key] = cast("const int64_t*", desc.ids) end
+  for name, ptr in pairs(desc.write) do view[name] = cast("double*", ptr) end
+  for name, ptr in pairs(desc.read) do view[name] = cast("const double*", ptr) end
+  return view
+end
+"#;
+
+/// `name` as a SQL identifier, so column names are never read as SQL.
+fn quoted(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// `{ [n = n,] [<key> = const int64_t*,] <col> = double* | const double* }`
+/// over Rust buffers: the view builder `ColumnBuffers::lua_view` and
+/// `lua_systems` share. `columns` are (name, values, writable); the
+/// pointers are only valid while the buffers are neither moved nor resized.
+pub fn ffi_view<'a>(
+    lua: &Lua,
+    n: Option<usize>,
+    key: Option<(&str, &mut [i64])>,
+    columns: impl IntoIterator<Item = (&'a str, &'a mut [f64], bool)>,
+) -> mlua::Result<Table> {
+    let make: Function = match lua.named_registry_value::<Option<Function>>(VIEW_KEY)? {
+        Some(f) => f,
+        None => {
+            let f: Function = lua.load(VIEW_LUA).set_name("ffi_columns").eval()?;
+            lua.set_named_registry_value(VIEW_KEY, f.clone())?;
+            f
+        }
+    };
+    let desc = lua.create_table()?;
+    desc.set("n", n)?;
+    if let Some((key, ids)) = key {
+        desc.set("key", key)?;
+        desc.set("ids", LightUserData(ids.as_mut_ptr() as *mut c_void))?;
+    }
+    let (read, write) = (lua.create_table()?, lua.create_table()?);
+    for (name, values, writable) in columns {
+        let target = if writable { &write } else { &read };
+        target.set(name, LightUserData(values.as_mut_ptr() as *mut c_void))?;
+    }
+    desc.set("read", read)?;
+    desc.set("write", write)?;
+    make.call(desc)
+}
+
+pub struct F64Column {
+    pub name: String,
+    pub writable: bool,
+    pub values: Vec<f64>,
+}
+
+pub struct ColumnBuffers {
+    pub key: String,
+    pub ids: Vec<i64>,
+    pub columns: Vec<F64Column>,
+}
+
+pub fn register_arrow_import(conn: &Connection) -> duckdb::Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT count(*) > 0 FROM duckdb_functions() WHERE function_name = ?",
+        [ARROW_IMPORT],
+        |r| r.get(0),
+    )?;
+    if !exists {
+        conn.register_table_function::<ArrowVTab>(ARROW_IMPORT)?;
+    }
+    Ok(())
+}
+
+impl ColumnBuffers {
+    /// `key` must be BIGINT and every other column DOUBLE (cast in the query).
+    pub fn from_batches(schema: &SchemaRef, batches: &[RecordBatch], key: &str, writable: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        let mut ids = None;
+        let mut columns = Vec::new();
+        for (i, field) in schema.fields().iter().enumerate() {
+            let name = field.name().as_str();
+            if name == key {
+                let mut values = Vec::with_capacity(rows);
+                for batch in batches {
+                    let array = batch.column(i).as_any().downcast_ref::<Int64Array>()
+                        .ok_or_else(|| format!("key `{}` is {}, expected BIGINT", key, field.data_type()))?;
+                    values.extend_from_slice(array.values());
+                }
+                ids = Some(values);
+            } else {
+                let mut values = Vec::with_capacity(rows);
+                for batch in batches {
+                    let array = batch.column(i).as_any().downcast_ref::<Float64Array>()
+                        .ok_or_else(|| format!("column `{}` is {}, cast it to DOUBLE", name, field.data_type()))?;
+                    if array.null_count() > 0 {
+                        return Err(format!("column `{}` has NULLs", name).into());
+                    }
+                    values.extend_from_slice(array.values());
+                }
+                columns.push(F64Column { name: name.to_string(), writable: writable.contains(&name), values });
+            }
+        }
+        let ids = ids.ok_or_else(|| format!("query has no key column `{}`", key))?;
+        if let Some(w) = writable.iter().find(|w| !columns.iter().any(|c| c.name == **w)) {
+            return Err(format!("writable column `{}` is not in the query", w).into());
+        }
+        Ok(ColumnBuffers { key: key.to_string(), ids, columns })
+    }
+
+    pub fn from_duckdb(conn: &Connection, sql: &str, key: &str, writable: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let mut stmt = conn.prepare(sql)?;
+        let arrow = stmt.query_arrow([])?;
+        let schema = arrow.get_schema();
+        let batches: Vec<RecordBatch> = arrow.collect();
+        Self::from_batches(&schema, &batches, key, writable)
+    }
+
+    /// Every column of `df` other than `key`, cast to f64, in frame order.
+    pub fn from_polars(df: &DataFrame, key: &str, writable: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let ids: Vec<i64> = df.column(key)?.cast(&PolarsType::Int64)?.i64()?.into_no_null_iter().collect();
+        let mut columns = Vec::new();
+        for column in df.get_columns().iter().filter(|c| c.name().as_str() != key) {
+            let name = column.name().to_string();
+            let series = column.as_materialized_series().cast(&PolarsType::Float64)?;
+            let ca = series.f64()?.rechunk();
+            let values = ca.cont_slice().map_err(|_| format!("column `{}` has NULLs", name))?.to_vec();
+            columns.push(F64Column { writable: writable.contains(&name.as_str()), name, values });
+        }
+        if let Some(w) = writable.iter().find(|w| !columns.iter().any(|c| c.name == **w)) {
+            return Err(format!("writable column `{}` is not in the frame", w).into());
+        }
+        Ok(ColumnBuffers { key: key.to_string(), ids, columns })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn column(&self, name: &str) -> Option<&[f64]> {
+        self.columns.iter().find(|c| c.name == name).map(|c| c.values.as_slice())
+    }
+
+    /// FFI view over the buffers; see the module docs for its lifetime.
+    pub fn lua_view(&mut self, lua: &Lua) -> mlua::Result<Table> {
+        let n = self.ids.len();
+        let columns = self.columns.iter_mut().map(|c| (c.name.as_str(), c.values.as_mut_slice(), c.writable));
+        ffi_view(lua, Some(n), Some((self.key.as_str(), &mut self.ids)), columns)
+    }
+
+    /// Key plus the writable columns. The Vecs become the Arrow buffers as-is.
+    pub fn into_record_batch(self) -> Result<RecordBatch, Box<dyn Error>> {
+        let mut fields = vec![Field::new(self.key.as_str(), DataType::Int64, false)];
+        let mut arrays: Vec<ArrayRef> = vec![Arc::new(Int64Array::from(self.ids))];
+        for c in self.columns.into_iter().filter(|c| c.writable) {
+            fields.push(Field::new(c.name.as_str(), DataType::Float64, false));
+            arrays.push(Arc::new(Float64Array::from(c.values)));
+        }
+        Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+    }
+
+    /// Writes the writable columns into `table`, matching rows on the key.
+    /// Returns the number of rows updated.
+    pub fn write_duckdb(self, conn: &Connection, table: &str) -> Result<usize, Box<dyn Error>> {
+        let key = self.key.clone();
+        let names: Vec<String> = self.columns.iter().filter(|c| c.writable).map(|c| c.name.clone()).collect();
+        if names.is_empty() || self.is_empty() {
+            return Ok(0);
+        }
+        register_arrow_import(conn)?;
+        let batch = self.into_record_batch()?;
+
+        // Qualified wherever DuckDB allows it: unqualified, the DROP would
+        // reach a user's `main._ffi_import` when no temp table exists.
+        let mut stage = vec![format!("{} BIGINT", quoted(&key))];
+        stage.extend(names.iter().map(|n| format!("{} DOUBLE", quoted(n))));
+        conn.execute_batch(&format!(
+            "DROP TABLE IF EXISTS temp._ffi_import;
+             CREATE TEMP TABLE _ffi_import ({});",
+            stage.join(", ")
+        ))?;
+        {
+            let mut insert = conn.prepare(&format!("INSERT INTO temp._ffi_import SELECT * FROM {}(?, ?)", ARROW_IMPORT))?;
+            for offset in (0..batch.num_rows()).step_by(IMPORT_SLICE) {
+                let slice = batch.slice(offset, IMPORT_SLICE.min(batch.num_rows() - offset));
+                insert.execute(arrow_recordbatch_to_query_params(slice))?;
+            }
+        }
+        let sets: Vec<String> = names.iter().map(|n| format!("{0} = s.{0}", quoted(n))).collect();
+        let updated = conn.execute(
+            &format!(
+                "UPDATE {0} SET {1} FROM temp._ffi_import s WHERE {0}.{2} = s.{2}",
+                table,
+                sets.join(", "),
+                quoted(&key)
+            ),
+            [],
+        )?;
+        conn.execute_batch("DROP TABLE temp._ffi_import;")?;
+        Ok(updated)
+    }
+
+    /// Replaces the writable columns of `df`, which must be the frame the
+    /// buffers were taken from (same rows, same order).
+    pub fn write_polars(self, df: &mut DataFrame) -> Result<(), Box<dyn Error>> {
+        let same_rows = df.height() == self.ids.len()
+            && df.column(&self.key)?.cast(&PolarsType::Int64)?.i64()?.into_no_null_iter().eq(self.ids.iter().copied());
+        if !same_rows {
+            return Err(PolarsError::ComputeError("frame rows changed since the buffers were taken".into()).into());
+        }
+        for c in self.columns.into_iter().filter(|c| c.writable) {
+            let dtype = df.column(&c.name)?.dtype().clone();
+            let series = Float64Chunked::from_vec(c.name.as_str().into(), c.values).into_series().cast(&dtype)?;
+            df.with_column(series)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+//! Native Rust Spatial UDF Library
+//!
+//! Shared by bins via `mod game_functions;`. `register_game_functions(&conn)`
+//! adds vectorized VArrowScalar UDFs so game queries don't need hand-written
+//! distance SQL:
+//!
+//!   dist2d, dist_sq, manhattan, chebyshev, angle_between
+//!       (x1, y1, x2, y2) or (p1 DOUBLE[2], p2 DOUBLE[2])
+//!   within_radius    (x1, y1, x2, y2, r) or (p1, p2, r)          -> BOOLEAN (false for r < 0)
+//!   cell_of          (v, cell_size) -> INTEGER, (p, cell_size) -> INTEGER[2]
+//!   morton2d         (x, y, cell_size) or (p, cell_size)         -> BIGINT
+//!   point_in_rect    (x, y, min_x, min_y, max_x, max_y) or (p, min, max) -> BOOLEAN
+//!
+//! Every function accepts FLOAT, DOUBLE and DOUBLE[2] forms. FLOAT inputs give
+//! FLOAT results for the float-valued functions; a NULL in any argument gives NULL.
+//! `dist2d`, `dist_sq` and `within_radius` run on the `simd_kernels` batch
+//! kernels, the rest as one pass over the input columns. Functions are
+//! registered through `arrow_scalar::Listed` so DOUBLE[2] arguments reach
+//! them; bins using this module also need `mod simd_kernels;` and
+//! `mod arrow_scalar;`.
+
+#![allow(dead_code)]
+
+use crate::arrow_scalar::Listed;
+use crate::simd_kernels;
+use duckdb::arrow::array::{
+    Array, ArrayRef, BooleanArray, FixedSizeListArray, Float32Array, Float64Array, Int32Array, Int64Array,
+};
+use duckdb::arrow::buffer::NullBuffer;
+use duckdb::arrow::datatypes::{DataType, Field};
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::{
+    vscalar::{ArrowFunctionSignature, VArrowScalar},
+    Connection,
+};
+use std::borrow::Cow;
+use std::error::Error;
+use std::sync::Arc;
+
+pub fn register_game_functions(conn: &Connection) -> duckdb::Result<()> {
+    conn.register_scalar_function::<Listed<Dist2d>>("dist2d")?;
+    conn.register_scalar_function::<Listed<DistSq>>("dist_sq")?;
+    conn.register_scalar_function::<Listed<WithinRadius>>("within_radius")?;
+    conn.register_scalar_function::<Listed<CellOf>>("cell_of")?;
+    conn.register_scalar_function::<Listed<Morton2d>>("morton2d")?;
+    conn.register_scalar_function::<Listed<AngleBetween>>("angle_between")?;
+    conn.register_scalar_function::<Listed<Manhattan>>("manhattan")?;
+    conn.register_scalar_function::<Listed<Chebyshev>>("chebyshev")?;
+    conn.register_scalar_function::<Listed<PointInRect>>("point_in_rect")?;
+    Ok(())
+}
+
+/// Names registered by `register_game_functions`, for listings.
+pub const GAME_FUNCTIONS: [&str; 9] = [
+    "dist2d", "dist_sq", "within_radius", "cell_of", "morton2d",
+    "angle_between", "manhattan", "chebyshev", "point_in_rect",
+];
+
+// ============================================================================
+// Argument flattening: FLOAT / DOUBLE / DOUBLE[2] -> f64 lanes
+// ============================================================================
+
+fn point_type() -> DataType {
+    DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float64, true)), 2)
+}
+
+fn cell_type() -> DataType {
+    DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Int32, true)), 2)
+}
+
+/// Input columns flattened to f64 lanes: a scalar column gives one lane, a
+/// DOUBLE[2] column gives two (x, y). So `dist2d(p1, p2)` and
+/// `dist2d(x1, y1, x2, y2)` both see lanes `[x1, y1, x2, y2]`. DOUBLE lanes
+/// borrow the Arrow value buffers; only FLOAT (widened) and DOUBLE[2] (split
+/// into x and y) are copied. NULL rows hold arbitrary lane values and are
+/// masked by `nulls` on output.
+struct Args<'a> {
+    lanes: Vec<Cow<'a, [f64]>>,
+    nulls: Option<NullBuffer>,
+    rows: usize,
+    /// All scalar inputs were FLOAT.
+    single: bool,
+}
+
+impl<'a> Args<'a> {
+    fn from_batch(input: &'a RecordBatch) -> Result<Args<'a>, Box<dyn Error>> {
+        let n = input.num_rows();
+        let mut lanes = Vec::new();
+        let mut nulls: Option<NullBuffer> = None;
+        let mut single = true;
+
+        for column in input.columns() {
+            nulls = NullBuffer::union(nulls.as_ref(), column.nulls());
+            match column.data_type() {
+                DataType::Float32 => {
+                    let a = column.as_any().downcast_ref::<Float32Array>().ok_or("expected FLOAT")?;
+                    lanes.push(Cow::Owned(a.values().iter().map(|v| *v as f64).collect()));
+                }
+                DataType::Float64 => {
+                    single = false;
+                    let a = column.as_any().downcast_ref::<Float64Array>().ok_or("expected DOUBLE")?;
+                    lanes.push(Cow::Borrowed(&a.values()[..]));
+                }
+                DataType::FixedSizeList(_, 2) => {
+                    single = false;
+                    let list = column.as_any().downcast_ref::<FixedSizeListArray>().ok_or("expected DOUBLE[2]")?;
+                    let xy = list.values().as_any().downcast_ref::<Float64Array>().ok_or("expected DOUBLE[2]")?;
+                    let base = if n > 0 { list.value_offset(0) as usize } else { 0 };
+                    let (xs, ys): (Vec<f64>, Vec<f64>) =
+                        xy.values()[base..base + 2 * n].chunks_exact(2).map(|p| (p[0], p[1])).unzip();
+                    lanes.push(Cow::Owned(xs));
+                    lanes.push(Cow::Owned(ys));
+                    if xy.null_count() > 0 {
+                        // A NULL element inside the point makes the row NULL too
+                        let inner: NullBuffer =
+                            (0..n).map(|i| xy.is_valid(base + 2 * i) && xy.is_valid(base + 2 * i + 1)).collect();
+                        nulls = NullBuffer::union(nulls.as_ref(), Some(&inner));
+                    }
+                }
+                other => return Err(format!("unsupported argument type {:?}", other).into()),
+            }
+        }
+        Ok(Args { lanes, nulls, rows: n, single })
+    }
+
+    /// Lanes 0..4 as (x1, y1, x2, y2).
+    fn points(&self) -> (&[f64], &[f64], &[f64], &[f64]) {
+        (&self.lanes[0], &self.lanes[1], &self.lanes[2], &self.lanes[3])
+    }
+
+    /// Column computed in bulk, with this batch's NULLs and FLOAT-ness applied.
+    fn float_column(&self, values: Vec<f64>) -> ArrayRef {
+        if self.single {
+            let values: Vec<f32> = values.iter().map(|v| *v as f32).collect();
+            Arc::new(Float32Array::new(values.into(), self.nulls.clone()))
+        } else {
+            Arc::new(Float64Array::new(values.into(), self.nulls.clone()))
+        }
+    }
+
+    fn bool_column(&self, values: impl IntoIterator<Item = bool>) -> ArrayRef {
+        Arc::new(BooleanArray::new(values.into_iter().collect(), self.nulls.clone()))
+    }
+
+    fn i32_column(&self, values: Vec<i32>) -> Int32Array {
+        Int32Array::new(values.into(), self.nulls.clone())
+    }
+
+    fn i64_column(&self, values: Vec<i64>) -> ArrayRef {
+        Arc::new(Int64Array::new(values.into(), self.nulls.clone()))
+    }
+}
+
+fn f32s(n: usize) -> Vec<DataType> {
+    vec![DataType::Float32; n]
+}
+
+fn f64s(n: usize) -> Vec<DataType> {
+    vec![DataType::Float64; n]
+}
+
+/// FLOAT / DOUBLE / DOUBLE[2] signatures for a two-point function with
+/// `extra` trailing scalar arguments (e.g. the radius).
+fn two_point_sigs(extra: usize, ret_single: DataType, ret_double: DataType) -> Vec<ArrowFunctionSignature> {
+    let mut point_args = vec![point_type(), point_type()];
+    point_args.extend(f64s(extra));
+    vec![
+        ArrowFunctionSignature::exact(f32s(4 + extra), ret_single),
+        ArrowFunctionSignature::exact(f64s(4 + extra), ret_double.clone()),
+        ArrowFunctionSignature::exact(point_args, ret_double),
+    ]
+}
+
+fn cell(v: f64, cell_size: f64) -> i32 {
+    (v / cell_size).floor() as i32
+}
+
+/// Interleave the low 32 bits of x and y (x in even bits).
+fn morton_interleave(x: u32, y: u32) -> u64 {
+    fn spread(v: u32) -> u64 {
+        let mut v = v as u64;
+        v = (v | (v << 16)) & 0x0000_FFFF_0000_FFFF;
+        v = (v | (v << 8)) & 0x00FF_00FF_00FF_00FF;
+        v = (v | (v << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        v = (v | (v << 1)) & 0x5555_5555_5555_5555;
+        v
+    }
+    spread(x) | (spread(y) << 1)
+}
+
+// ============================================================================
+// Distance-style functions: (x1, y1, x2, y2) / (p1, p2)
+// ============================================================================
+
+/// Two-point function backed by a `simd_kernels` batch kernel.
+macro_rules! kernel_float_fn {
+    ($name:ident, $kernel:ident) => {
+        pub struct $name;
+
+        impl VArrowScalar for $name {
+            type State = ();
+
+            fn invoke(_state: &Self::State, input: RecordBatch) -> Result<ArrayRef, Box<dyn Error>> {
+                let args = Args::from_batch(&input)?;
+                let (x1, y1, x2, y2) = args.points();
+                let mut out = vec![0.0; args.rows];
+                simd_kernels::kernels().$kernel(x1, y1, x2, y2, &mut out);
+                Ok(args.float_column(out))
+            }
+
+            fn signatures() -> Vec<ArrowFunctionSignature> {
+                two_point_sigs(0, DataType::Float32, DataType::Float64)
+            }
+        }
+    };
+}
+
+/// Two-point function as one pass over the four lanes.
+macro_rules! two_point_float_fn {
+    ($name:ident, |$x1:ident, $y1:ident, $x2:ident, $y2:ident| $body:expr) => {
+        pub struct $name;
+
+        impl VArrowScalar for $name {
+            type State = ();
+
+            fn invoke(_state: &Self::State, input: RecordBatch) -> Result<ArrayRef, Box<dyn Error>> {
+                let args = Args::from_batch(&input)?;
+                let (x1, y1, x2, y2) = args.points();
+                let out = x1
+                    .iter()
+                    .zip(y1)
+                    .zip(x2)
+                    .zip(y2)
+                    .map(|(((&$x1, &$y1), &$x2), &$y2)| $body)
+                    .collect();
+                Ok(args.float_column(out))
+            }
+
+            fn signatures() -> Vec<ArrowFunctionSignature> {
+                two_point_sigs(0, DataType::Float32, DataType::Float64)
+            }
+        }
+    };
+}
+
+kernel_float_fn!(Dist2d, distances);
+kernel_float_fn!(DistSq, distances_sq);
+two_point_float_fn!(Manhattan, |x1, y1, x2, y2| (x2 - x1).abs() + (y2 - y1).abs());
+two_point_float_fn!(Chebyshev, |x1, y1, x2, y2| (x2 - x1).abs().max((y2 - y1).abs()));
+// Signed angle from vector (x1, y1) to vector (x2, y2), in radians (-π, π]
+two_point_float_fn!(AngleBetween, |x1, y1, x2, y2| (x1 * y2 - y1 * x2).atan2(x1 * x2 + y1 * y2));
+
+pub struct WithinRadius;
+
+impl VArrowScalar for WithinRadius {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<ArrayRef, Box<dyn Error>> {
+        let args = Args::from_batch(&input)?;
+        let (x1, y1, x2, y2) = args.points();
+        let radius = &args.lanes[4];
+        // A negative radius matches nothing, rather than its square.
+        let shared = radius.first().copied().unwrap_or(0.0);
+        let radius_sq = shared * shared;
+        // The kernel tests `dist_sq < bound`; for a finite r², `d <= r²` is
+        // exactly `d < r².next_up()`. Only a radius shared by the whole batch
+        // (the usual literal) can use it.
+        if shared >= 0.0 && radius_sq.is_finite() && radius.iter().all(|r| *r == shared) {
+            let mut out = vec![false; args.rows];
+            simd_kernels::kernels().within(x1, y1, x2, y2, radius_sq.next_up(), &mut out);
+            return Ok(args.bool_column(out));
+        }
+        // Per-row radius (e.g. a column): squared distances, then compare.
+        let mut d = vec![0.0; args.rows];
+        simd_kernels::kernels().distances_sq(x1, y1, x2, y2, &mut d);
+        Ok(args.bool_column(d.iter().zip(radius.iter()).map(|(d, r)| *r >= 0.0 && *d <= r * r)))
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        two_point_sigs(1, DataType::Boolean, DataType::Boolean)
+    }
+}
+
+// ============================================================================
+// Grid functions
+// ============================================================================
+
+pub struct CellOf;
+
+impl VArrowScalar for CellOf {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<ArrayRef, Box<dyn Error>> {
+        let args = Args::from_batch(&input)?;
+        if let [v, size] = args.lanes.as_slice() {
+            let cells = v.iter().zip(size.iter()).map(|(v, size)| cell(*v, *size)).collect();
+            return Ok(Arc::new(args.i32_column(cells)));
+        }
+        // (p DOUBLE[2], cell_size) -> INTEGER[2]
+        let (x, y, size) = (&args.lanes[0], &args.lanes[1], &args.lanes[2]);
+        let mut flat = Vec::with_capacity(args.rows * 2);
+        for ((x, y), size) in x.iter().zip(y.iter()).zip(size.iter()) {
+            flat.push(cell(*x, *size));
+            flat.push(cell(*y, *size));
+        }
+        let field = Arc::new(Field::new("item", DataType::Int32, true));
+        Ok(Arc::new(FixedSizeListArray::try_new(field, 2, Arc::new(Int32Array::from(flat)), args.nulls.clone())?))
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        vec![
+            ArrowFunctionSignature::exact(f32s(2), DataType::Int32),
+            ArrowFunctionSignature::exact(f64s(2), DataType::Int32),
+            ArrowFunctionSignature::exact(vec![point_type(), DataType::Float64], cell_type()),
+        ]
+    }
+}
+
+pub struct Morton2d;
+
+impl VArrowScalar for Morton2d {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<ArrayRef, Box<dyn Error>> {
+        // Cell coordinates are reinterpreted as u32, so negative cells wrap
+        // instead of colliding with cell 0.
+        let args = Args::from_batch(&input)?;
+        let (x, y, size) = (&args.lanes[0], &args.lanes[1], &args.lanes[2]);
+        let codes = x
+            .iter()
+            .zip(y.iter())
+            .zip(size.iter())
+            .map(|((x, y), size)| morton_interleave(cell(*x, *size) as u32, cell(*y, *size) as u32) as i64)
+            .collect();
+        Ok(args.i64_column(codes))
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        vec![
+            ArrowFunctionSignature::exact(f32s(3), DataType::Int64),
+            ArrowFunctionSignature::exact(f64s(3), DataType::Int64),
+            ArrowFunctionSignature::exact(vec![point_type(), DataType::Float64], DataType::Int64),
+        ]
+    }
+}
+
+pub struct PointInRect;
+
+impl VArrowScalar for PointInRect {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<ArrayRef, Box<dyn Error>> {
+        // [x, y, min_x, min_y, max_x, max_y]; bounds are inclusive
+        let args = Args::from_batch(&input)?;
+        let [x, y, min_x, min_y, max_x, max_y] = args.lanes.as_slice() else {
+            return Err("point_in_rect: expected 6 coordinates".into());
+        };
+        let inside = (0..args.rows).map(|i| x[i] >= min_x[i] && x[i] <= max_x[i] && y[i] >= min_y[i] && y[i] <= max_y[i]);
+        Ok(args.bool_column(inside))
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        vec![
+            ArrowFunctionSignature::exact(f32s(6), DataType::Boolean),
+            ArrowFunctionSignature::exact(f64s(6), DataType::Boolean),
+            ArrowFunctionSignature::exact(vec![point_type(), point_type(), point_type()], DataType::Boolean),
+        ]
+    }
+}
@@ -0,0 +1,558 @@
+//! Lua Aggregate Functions for DuckDB
+//!
+//! Shared by bins via `mod lua_aggregates;` (needs `mod lua_scripts;` and
+//! `mod lua_errors;`).
+//! duckdb-rs only wraps scalar and table functions, so the aggregate is
+//! registered through DuckDB's C API (`duckdb::ffi`). A script defines
+//!
+//!   lua_median = aggregate{
+//!     args = 1,
+//!     init = function() return {} end,
+//!     update = function(state, n, xs) ... return state end,   -- xs: const double*
+//!     combine = function(a, b) ... return a end,
+//!     finalize = function(state) return ... end,             -- number or nil
+//!   }
+//!
+//! and `load_source`/`load_file` turn every such global into a SQL macro:
+//!
+//!   lua_median(a0) := lua_aggregate('lua_median', a0::DOUBLE)
+//!
+//! `lua_aggregate(name VARCHAR, DOUBLE [, DOUBLE, ...])` is a real DuckDB
+//! aggregate, so `SELECT faction, lua_median(hp) FROM units GROUP BY faction`
+//! streams: every DuckDB state (one per group per worker, plus the ones
+//! DuckDB merges them into) holds a Lua state of its own, made by `init()`
+//! the first time a row reaches it. Per chunk of input, `update` gets each
+//! state's values in one call, as FFI pointers to `n` doubles. DuckDB merges
+//! the workers' partial states with `combine` and calls `finalize` once per
+//! group. Rows with a NULL in any argument are skipped; a state no row
+//! reached is NULL without calling Lua, like `sum` over no rows.
+//!
+//! A Lua error in any of the four goes through `lua_errors` under the
+//! aggregate's name: it fails the query, or, for a mod set to
+//! `on_error = "null"`, marks that state failed so its group ends NULL.
+//!
+//! Lua values can't leave their VM, and DuckDB combines and finalizes states
+//! on whatever thread is free, so the states don't live in the thread-local
+//! `with_vm` VMs. Each worker creates its states in a `lua_scripts::SharedVm`
+//! of its own, and every state remembers its VM. Combining two states from
+//! different VMs copies the source into the target's VM first, which only
+//! works for plain data: nil, booleans, numbers, strings and tables of those
+//! (metatables are dropped). Every closure run on a `SharedVm` leaves its
+//! Lua handles behind: states are kept as `RegistryKey`s, and only those
+//! and plain Rust data come out.
+//!
+//! duckdb-rs keeps its `duckdb_connection` private, and the C API registers
+//! aggregates only through one, so databases that use Lua aggregates are
+//! opened with `open_in_memory` here: it opens the database through the C
+//! API, registers `lua_aggregate` and returns an `AggregateDb`, which derefs
+//! to an ordinary `Connection` and closes the database once that is gone.
+//!
+//! Names that DuckDB already knows (`median`, `sum`, another extension's
+//! function) are refused instead of being replaced by the macro.
+//!
+//! The scripts go through `lua_scripts`, so they hot-reload like any other;
+//! aggregates added by a reload need another `load_*` call for their macro.
+//! `open_in_memory` turns on `lua_scripts::enable_ffi()`, so it must run
+//! before the first Lua VM is created.
+
+#![allow(dead_code)]
+
+use crate::lua_errors::{self, UdfError};
+use crate::lua_scripts::{self, lua_err, Bridge, SharedVm};
+use duckdb::core::FlatVector;
+use duckdb::ffi;
+use duckdb::types::DuckString;
+use duckdb::Connection;
+use mlua::{Function, LightUserData, Lua, MultiValue, RegistryKey, Table, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::{c_void, CString};
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+pub const MAX_AGG_ARITY: usize = 4;
+/// Nesting a state may have when it is copied between VMs.
+const MAX_COPY_DEPTH: usize = 32;
+
+const PRELUDE_NAME: &str = "lua_aggregates/prelude";
+const PRELUDE: &str = r#"
+function aggregate(def)
+  assert(type(def) == "table", "aggregate{} takes a table")
+  for _, f in ipairs({ "init", "update", "combine", "finalize" }) do
+    assert(type(def[f]) == "function", "aggregate{} needs " .. f .. " = function")
+  end
+  def.args = def.args or 1
+  def.__aggregate = true
+  return def
+end
+"#;
+
+/// Calls `def.update` with the argument columns as `const double*`. Kept
+/// out of `_G` (see `lua_scripts::host_function`), and it only casts light
+/// userdata, so a script can't turn a number into a pointer with it.
+const UPDATE_KEY: &str = "lua_aggregates.update";
+const UPDATE: &str = r#"
+local cast = require("ffi").cast
+local function column(p)
+  if p == nil then return nil end
+  assert(type(p) == "userdata", "lua_aggregate: column pointers are light userdata")
+  return cast("const double*", p)
+end
+return function(def, state, n, a, b, c, d)
+  return def.update(state, n, column(a), column(b), column(c), column(d))
+end
+"#;
+
+// ============================================================================
+// Per-state Lua values
+// ============================================================================
+
+/// What a DuckDB state points to once a row has reached it.
+struct AggState {
+    vm: Arc<SharedVm>,
+    name: String,
+    /// The Lua state, in `vm`'s registry.
+    value: RegistryKey,
+    /// A Lua call on this state failed under `OnError::Null`: no more
+    /// calls, and the group finalizes to NULL.
+    failed: bool,
+}
+
+thread_local! {
+    /// VM this worker creates its states in.
+    static HOME: Arc<SharedVm> = Arc::new(SharedVm::new());
+}
+
+/// The DuckDB state memory: one pointer, null until `init()` has run.
+type Slot = *mut AggState;
+
+fn definition(lua: &Lua, name: &str) -> mlua::Result<Table> {
+    let def: Option<Table> = lua.globals().get(name).ok();
+    def.filter(|d| d.raw_get::<bool>("__aggregate").unwrap_or(false))
+        .ok_or_else(|| lua_err(format!("lua_aggregate: no Lua aggregate `{}`", name)))
+}
+
+/// Counts a Lua error against the aggregate's mod. `Ok` means the mod is set
+/// to `on_error = "null"` and the state carries on as failed.
+#[allow(clippy::result_large_err)]
+fn lua_failed(name: &str, err: &mlua::Error) -> Result<(), UdfError> {
+    let (module, mode) = lua_errors::policy(name);
+    lua_errors::handle(UdfError::from_lua(&module, name, None, &[], err), mode)
+}
+
+/// Copies plain data from one VM into another.
+fn copy_value(value: Value, into: &Lua, depth: usize) -> mlua::Result<Value> {
+    Ok(match value {
+        Value::Nil | Value::Boolean(_) | Value::Integer(_) | Value::Number(_) => value,
+        Value::String(s) => Value::String(into.create_string(s.as_bytes())?),
+        Value::Table(t) if depth < MAX_COPY_DEPTH => {
+            let copy = into.create_table()?;
+            for pair in t.pairs::<Value, Value>() {
+                let (k, v) = pair?;
+                copy.raw_set(copy_value(k, into, depth + 1)?, copy_value(v, into, depth + 1)?)?;
+            }
+            Value::Table(copy)
+        }
+        Value::Table(_) => return Err(lua_err("lua_aggregate: state nested too deeply (or cyclic) to combine".into())),
+        other => {
+            return Err(lua_err(format!(
+                "lua_aggregate: a {} in a state can't be combined across VMs; keep states to nil, booleans, numbers, strings and tables",
+                other.type_name()
+            )))
+        }
+    })
+}
+
+/// Reads row `row` of a flat VARCHAR vector.
+fn varchar(vector: &FlatVector, rows: usize, row: usize) -> Option<String> {
+    if vector.row_is_null(row as u64) {
+        return None;
+    }
+    let mut s = vector.as_slice_with_len::<ffi::duckdb_string_t>(rows)[row];
+    Some(DuckString::new(&mut s).as_str().into_owned())
+}
+
+/// Rows of one chunk that reach the same state: the state, the name on the
+/// first such row and each argument's values.
+struct Batch {
+    slot: *mut Slot,
+    name: String,
+    columns: Vec<Vec<f64>>,
+}
+
+unsafe fn update_chunk(input: ffi::duckdb_data_chunk, states: *mut ffi::duckdb_aggregate_state) -> Result<(), Box<dyn Error>> {
+    let rows = ffi::duckdb_data_chunk_get_size(input) as usize;
+    let arity = ffi::duckdb_data_chunk_get_column_count(input) as usize - 1;
+    let names = FlatVector::from(ffi::duckdb_data_chunk_get_vector(input, 0));
+    let args: Vec<FlatVector> = (1..=arity).map(|c| FlatVector::from(ffi::duckdb_data_chunk_get_vector(input, c as u64))).collect();
+    let values: Vec<&[f64]> = args.iter().map(|v| v.as_slice_with_len::<f64>(rows)).collect();
+
+    let mut batches: Vec<Batch> = Vec::new();
+    let mut index: HashMap<*mut Slot, usize> = HashMap::new();
+    for row in 0..rows {
+        if args.iter().any(|v| v.row_is_null(row as u64)) {
+            continue;
+        }
+        let slot = *states.add(row) as *mut Slot;
+        let at = match index.get(&slot) {
+            Some(&at) => at,
+            None => {
+                let name = varchar(&names, rows, row).ok_or("lua_aggregate: name must not be NULL")?;
+                batches.push(Batch { slot, name, columns: vec![Vec::new(); arity] });
+                index.insert(slot, batches.len() - 1);
+                batches.len() - 1
+            }
+        };
+        for (column, v) in batches[at].columns.iter_mut().zip(&values) {
+            column.push(v[row]);
+        }
+    }
+
+    for batch in batches {
+        if (*batch.slot).is_null() {
+            let vm = HOME.with(Arc::clone);
+            let init = vm.with(|lua| {
+                let init: Function = definition(lua, &batch.name)?.get("init")?;
+                lua.create_registry_value(init.call::<Value>(())?)
+            });
+            let (value, failed) = match init {
+                Ok(value) => (value, false),
+                Err(e) => {
+                    lua_failed(&batch.name, &e)?;
+                    (vm.with(|lua| lua.create_registry_value(Value::Nil))?, true)
+                }
+            };
+            *batch.slot = Box::into_raw(Box::new(AggState { vm, name: batch.name.clone(), value, failed }));
+        }
+        let AggState { vm, name, value, failed } = &mut **batch.slot;
+        if *name != batch.name {
+            return Err(format!("lua_aggregate: name changed from `{}` to `{}` within a group", name, batch.name).into());
+        }
+        if *failed {
+            continue;
+        }
+        let n = batch.columns[0].len();
+        let updated = vm.with(|lua| {
+            let update = lua_scripts::host_function(lua, UPDATE_KEY, UPDATE)?;
+            let mut call = vec![
+                Value::Table(definition(lua, name)?),
+                lua.registry_value::<Value>(value)?,
+                Value::Integer(n as mlua::Integer),
+            ];
+            call.extend(batch.columns.iter().map(|c| Value::LightUserData(LightUserData(c.as_ptr() as *mut c_void))));
+            let next: Value = update.call(MultiValue::from_iter(call))?;
+            lua.replace_registry_value(value, next)
+        });
+        if let Err(e) = updated {
+            lua_failed(name, &e)?;
+            *failed = true;
+        }
+    }
+    Ok(())
+}
+
+/// `into = combine(into, partial)`, on `into`'s VM.
+fn merge(lua: &Lua, into: &mut AggState, partial: Value) -> mlua::Result<()> {
+    let combine: Function = definition(lua, &into.name)?.get("combine")?;
+    let merged: Value = combine.call((lua.registry_value::<Value>(&into.value)?, partial))?;
+    lua.replace_registry_value(&mut into.value, merged)
+}
+
+unsafe fn combine_states(
+    source: *mut ffi::duckdb_aggregate_state,
+    target: *mut ffi::duckdb_aggregate_state,
+    count: usize,
+) -> Result<(), Box<dyn Error>> {
+    for i in 0..count {
+        let (from, into) = (*source.add(i) as *mut Slot, *target.add(i) as *mut Slot);
+        if (*from).is_null() {
+            continue;
+        }
+        if (*into).is_null() {
+            // Nothing to merge with: the target takes the source's Lua state over.
+            *into = std::mem::replace(&mut *from, std::ptr::null_mut());
+            continue;
+        }
+        let AggState { vm, name, value, failed } = *Box::from_raw(std::mem::replace(&mut *from, std::ptr::null_mut()));
+        let into = &mut **into;
+        if name != into.name {
+            return Err(format!("lua_aggregate: can't combine `{}` into `{}`", name, into.name).into());
+        }
+        if failed || into.failed {
+            let _ = vm.with(|lua| lua.remove_registry_value(value));
+            into.failed = true;
+            continue;
+        }
+        let merged = if Arc::ptr_eq(&vm, &into.vm) {
+            vm.with(|lua| {
+                let partial: Value = lua.registry_value(&value)?;
+                lua.remove_registry_value(value)?;
+                merge(lua, into, partial)
+            })
+        } else {
+            let into_vm = into.vm.clone();
+            SharedVm::with_pair(&vm, &into_vm, |from_lua, into_lua| {
+                let partial = copy_value(from_lua.registry_value(&value)?, into_lua, 0)?;
+                from_lua.remove_registry_value(value)?;
+                merge(into_lua, into, partial)
+            })
+        };
+        if let Err(e) = merged {
+            lua_failed(&into.name, &e)?;
+            into.failed = true;
+        }
+    }
+    Ok(())
+}
+
+unsafe fn finalize_states(
+    source: *mut ffi::duckdb_aggregate_state,
+    result: ffi::duckdb_vector,
+    count: usize,
+    offset: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut finals = Vec::with_capacity(count);
+    for i in 0..count {
+        let slot = *source.add(i) as *mut Slot;
+        if (*slot).is_null() {
+            finals.push(None);
+            continue;
+        }
+        let state = &**slot;
+        if state.failed {
+            finals.push(None);
+            continue;
+        }
+        let finalized = state.vm.with(|lua| {
+            let finalize: Function = definition(lua, &state.name)?.get("finalize")?;
+            finalize.call::<Option<f64>>(lua.registry_value::<Value>(&state.value)?)
+        });
+        match finalized {
+            Ok(v) => finals.push(v),
+            Err(e) => {
+                lua_failed(&state.name, &e)?;
+                finals.push(None);
+            }
+        }
+    }
+    let mut out = FlatVector::from(result);
+    for (i, value) in finals.into_iter().enumerate() {
+        match value {
+            Some(v) => out.as_mut_slice_with_len::<f64>(offset + count)[offset + i] = v,
+            None => out.set_null(offset + i),
+        }
+    }
+    Ok(())
+}
+
+// ============================================================================
+// C API callbacks
+// ============================================================================
+
+unsafe fn report(info: ffi::duckdb_function_info, result: Result<(), Box<dyn Error>>) {
+    if let Err(e) = result {
+        let message = CString::new(e.to_string().replace('\0', " ")).expect("NULs replaced");
+        ffi::duckdb_aggregate_function_set_error(info, message.as_ptr());
+    }
+}
+
+unsafe extern "C" fn state_size(_info: ffi::duckdb_function_info) -> ffi::idx_t {
+    std::mem::size_of::<Slot>() as ffi::idx_t
+}
+
+unsafe extern "C" fn state_init(_info: ffi::duckdb_function_info, state: ffi::duckdb_aggregate_state) {
+    *(state as *mut Slot) = std::ptr::null_mut();
+}
+
+unsafe extern "C" fn state_update(info: ffi::duckdb_function_info, input: ffi::duckdb_data_chunk, states: *mut ffi::duckdb_aggregate_state) {
+    report(info, update_chunk(input, states));
+}
+
+unsafe extern "C" fn state_combine(
+    info: ffi::duckdb_function_info,
+    source: *mut ffi::duckdb_aggregate_state,
+    target: *mut ffi::duckdb_aggregate_state,
+    count: ffi::idx_t,
+) {
+    report(info, combine_states(source, target, count as usize));
+}
+
+unsafe extern "C" fn state_finalize(
+    info: ffi::duckdb_function_info,
+    source: *mut ffi::duckdb_aggregate_state,
+    result: ffi::duckdb_vector,
+    count: ffi::idx_t,
+    offset: ffi::idx_t,
+) {
+    report(info, finalize_states(source, result, count as usize, offset as usize));
+}
+
+unsafe extern "C" fn state_destroy(states: *mut ffi::duckdb_aggregate_state, count: ffi::idx_t) {
+    for i in 0..count as usize {
+        let slot = *states.add(i) as *mut Slot;
+        if (*slot).is_null() {
+            continue;
+        }
+        let AggState { vm, value, .. } = *Box::from_raw(std::mem::replace(&mut *slot, std::ptr::null_mut()));
+        // A key that can't be removed now is released by the VM's next
+        // registry access anyway.
+        let _ = vm.with(|lua| lua.remove_registry_value(value));
+    }
+}
+
+/// Registers `lua_aggregate(name, DOUBLE x 1..=MAX_AGG_ARITY) -> DOUBLE` on `con`.
+unsafe fn register_raw(con: ffi::duckdb_connection) -> duckdb::Result<()> {
+    let name = CString::new(LuaAggregate::FUNCTION).expect("no NUL in the function name");
+    let mut varchar = ffi::duckdb_create_logical_type(ffi::DUCKDB_TYPE_DUCKDB_TYPE_VARCHAR);
+    let mut double = ffi::duckdb_create_logical_type(ffi::DUCKDB_TYPE_DUCKDB_TYPE_DOUBLE);
+    let mut set = ffi::duckdb_create_aggregate_function_set(name.as_ptr());
+    for arity in 1..=MAX_AGG_ARITY {
+        let mut function = ffi::duckdb_create_aggregate_function();
+        ffi::duckdb_aggregate_function_set_name(function, name.as_ptr());
+        ffi::duckdb_aggregate_function_add_parameter(function, varchar);
+        for _ in 0..arity {
+            ffi::duckdb_aggregate_function_add_parameter(function, double);
+        }
+        ffi::duckdb_aggregate_function_set_return_type(function, double);
+        ffi::duckdb_aggregate_function_set_functions(
+            function,
+            Some(state_size),
+            Some(state_init),
+            Some(state_update),
+            Some(state_combine),
+            Some(state_finalize),
+        );
+        ffi::duckdb_aggregate_function_set_destructor(function, Some(state_destroy));
+        ffi::duckdb_add_aggregate_function_to_set(set, function);
+        ffi::duckdb_destroy_aggregate_function(&mut function);
+    }
+    let state = ffi::duckdb_register_aggregate_function_set(con, set);
+    ffi::duckdb_destroy_aggregate_function_set(&mut set);
+    ffi::duckdb_destroy_logical_type(&mut varchar);
+    ffi::duckdb_destroy_logical_type(&mut double);
+    if state != ffi::DuckDBSuccess {
+        return Err(duckdb::Error::DuckDBFailure(
+            ffi::Error::new(state),
+            Some(format!("could not register {}", LuaAggregate::FUNCTION)),
+        ));
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Registration
+// ============================================================================
+
+pub struct LuaAggregate;
+
+impl Bridge for LuaAggregate {
+    /// Arity.
+    type Def = usize;
+    const FUNCTION: &'static str = "lua_aggregate";
+    const MARKER: &'static str = "__aggregate";
+    const KIND: &'static str = "aggregate";
+    const PRELUDE_NAME: &'static str = PRELUDE_NAME;
+    const PRELUDE: &'static str = PRELUDE;
+
+    /// Only reached for a database `open_in_memory` didn't open: there is no
+    /// raw connection to register through.
+    fn register(_conn: &Connection) -> duckdb::Result<()> {
+        Err(duckdb::Error::InvalidParameterName(format!(
+            "{} is not registered on this database; open it with lua_aggregates::open_in_memory",
+            Self::FUNCTION
+        )))
+    }
+
+    fn read(def: &Table) -> Result<usize, String> {
+        let arity: usize = def.get("args").map_err(|e| e.to_string())?;
+        if !(1..=MAX_AGG_ARITY).contains(&arity) {
+            return Err(format!("{} args, supported 1..={}", arity, MAX_AGG_ARITY));
+        }
+        Ok(arity)
+    }
+
+    fn create_macro(conn: &Connection, name: &str, arity: &usize) -> duckdb::Result<()> {
+        create_macro(conn, name, *arity)
+    }
+}
+
+/// A `Connection` over a database opened through the C API, with the
+/// database handle it came from. duckdb-rs reuses that handle in
+/// `try_clone`, so it is closed only after the connection, on drop, like
+/// `Connection::open_in_memory` does: clones keep working afterwards but
+/// can't be cloned again.
+pub struct AggregateDb {
+    conn: ManuallyDrop<Connection>,
+    db: ffi::duckdb_database,
+}
+
+impl Deref for AggregateDb {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl Drop for AggregateDb {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.conn);
+            ffi::duckdb_close(&mut self.db);
+        }
+    }
+}
+
+/// Opens an in-memory database through the C API, registers
+/// `lua_aggregate` on it, loads the prelude and enables FFI for new VMs.
+pub fn open_in_memory() -> duckdb::Result<AggregateDb> {
+    unsafe {
+        let mut db: ffi::duckdb_database = std::ptr::null_mut();
+        let opened = ffi::duckdb_open(std::ptr::null(), &mut db);
+        if opened != ffi::DuckDBSuccess {
+            return Err(duckdb::Error::DuckDBFailure(ffi::Error::new(opened), Some("could not open an in-memory database".into())));
+        }
+        let mut con: ffi::duckdb_connection = std::ptr::null_mut();
+        let registered = match ffi::duckdb_connect(db, &mut con) {
+            ffi::DuckDBSuccess => register_raw(con),
+            failed => Err(duckdb::Error::DuckDBFailure(ffi::Error::new(failed), Some("could not connect".into()))),
+        };
+        ffi::duckdb_disconnect(&mut con);
+        let conn = match registered.and_then(|()| Connection::open_from_raw(db)) {
+            Ok(conn) => AggregateDb { conn: ManuallyDrop::new(conn), db },
+            Err(e) => {
+                ffi::duckdb_close(&mut db);
+                return Err(e);
+            }
+        };
+        lua_scripts::install::<LuaAggregate>(&conn)?;
+        Ok(conn)
+    }
+}
+
+/// `CREATE OR REPLACE MACRO name(a0, ...)` over `lua_aggregate`; fails if
+/// `name` is taken by anything but an earlier Lua aggregate.
+pub fn create_macro(conn: &Connection, name: &str, arity: usize) -> duckdb::Result<()> {
+    lua_scripts::check_macro_name(conn, name, "lua_aggregate")?;
+    let params: Vec<String> = (0..arity).map(|i| format!("a{}", i)).collect();
+    let args: Vec<String> = params.iter().map(|p| format!("{}::DOUBLE", p)).collect();
+    conn.execute_batch(&format!(
+        "CREATE OR REPLACE MACRO {}({}) AS lua_aggregate('{}', {});",
+        name,
+        params.join(", "),
+        name,
+        args.join(", ")
+    ))
+}
+
+/// Loads an in-memory script and creates a macro per aggregate it defines.
+pub fn load_source(conn: &Connection, script: &str, source: &str) -> Result<Vec<(String, usize)>, Box<dyn Error>> {
+    lua_scripts::define_source::<LuaAggregate>(conn, script, source)
+}
+
+/// Like `load_source`, with the script file-backed (see `lua_scripts::poll_changes`).
+pub fn load_file(conn: &Connection, script: &str, path: &Path) -> Result<Vec<(String, usize)>, Box<dyn Error>> {
+    lua_scripts::define_file::<LuaAggregate>(conn, script, path)
+}
@@ -0,0 +1,324 @@
+//! Lua-Defined ECS Systems Scheduled by the Engine
+//!
+//! Mods so far only supplied scalar UDFs called from SQL. With
+//! `lua_systems.rs` a script registers whole systems with
+//! `system{ name, reads, writes, run = function(view, dt) ... end }` and the
+//! engine runs them over FFI column views:
+//! 1. `movement` (reads velocity, writes position), `regen` (writes health)
+//!    and `poison` (reads position, writes health) from two scripts
+//! 2. The same systems against DuckDB and Polars, checked against a plain
+//!    SQL version of the tick with `world_diff.rs`
+//! 3. Guards: writing a read-only component, a bad declaration
+//! 4. A component with NULLs, a VARCHAR and a BIGINT above 2^53: only the
+//!    changed cells are written, NULLs stay NULL, the rest is not in the view
+//! 5. Where the time goes: gather, Lua, flush
+
+mod ffi_columns;
+mod lua_scripts;
+mod lua_systems;
+mod world_diff;
+
+use duckdb::{params, Connection};
+use lua_systems::{DuckDbWorld, LuaScheduler, PolarsWorld};
+use polars::prelude::*;
+use std::error::Error;
+use std::time::Instant;
+use world_diff::{diff, TableData};
+
+const ENTITIES: usize = 200_000;
+const TICKS: usize = 10;
+const DT: f64 = 1.0 / 60.0;
+
+const MOVEMENT_LUA: &str = r#"
+system{
+  name = "movement", reads = { "velocity" }, writes = { "position" },
+  run = function(view, dt)
+    local p, v = view.position, view.velocity
+    local x, y, vx, vy = p.x, p.y, v.vx, v.vy
+    for i = 0, view.n - 1 do
+      x[i] = x[i] + vx[i] * dt
+      y[i] = y[i] + vy[i] * dt
+    end
+  end,
+}
+"#;
+
+const HEALTH_LUA: &str = r#"
+local REGEN = 5.0
+
+system{
+  name = "regen", writes = { "health" },
+  run = function(view, dt)
+    local hp, max_hp = view.health.hp, view.health.max_hp
+    for i = 0, view.n - 1 do
+      if hp[i] < max_hp[i] then
+        hp[i] = math.min(hp[i] + REGEN * dt, max_hp[i])
+      end
+    end
+  end,
+}
+
+-- Entities standing in the swamp (x < 100) lose health.
+system{
+  name = "poison", reads = { "position" }, writes = { "health" },
+  run = function(view, dt)
+    local x, hp = view.position.x, view.health.hp
+    for i = 0, view.n - 1 do
+      if x[i] < 100 and hp[i] > 0 then
+        hp[i] = math.max(hp[i] - 20 * dt, 0)
+      end
+    end
+  end,
+}
+"#;
+
+/// The same tick as the Lua systems, in SQL.
+const TICK_SQL: &str = "
+    UPDATE position SET x = position.x + v.vx * $1, y = position.y + v.vy * $1
+      FROM velocity v WHERE position.id = v.id;
+    UPDATE health SET hp = least(hp + 5.0 * $1, max_hp) WHERE hp < max_hp;
+    UPDATE health SET hp = greatest(hp - 20 * $1, 0)
+      FROM position p WHERE health.id = p.id AND p.x < 100 AND health.hp > 0;";
+
+struct Components {
+    position: DataFrame,
+    velocity: DataFrame,
+    health: DataFrame,
+}
+
+/// Every entity has a position and health; every third one also moves.
+fn make_components() -> PolarsResult<Components> {
+    let ids: Vec<i64> = (0..ENTITIES as i64).collect();
+    let moving: Vec<i64> = ids.iter().copied().filter(|i| i % 3 == 0).collect();
+    Ok(Components {
+        position: df![
+            "id" => &ids,
+            "x" => ids.iter().map(|&i| (i % 1000) as f64).collect::<Vec<_>>(),
+            "y" => ids.iter().map(|&i| (i / 1000) as f64).collect::<Vec<_>>()
+        ]?,
+        velocity: df![
+            "id" => &moving,
+            "vx" => moving.iter().map(|&i| ((i % 7) as f64 - 3.0) * 4.0).collect::<Vec<_>>(),
+            "vy" => moving.iter().map(|&i| ((i % 5) as f64 - 2.0) * 2.5).collect::<Vec<_>>()
+        ]?,
+        health: df![
+            "id" => &ids,
+            "hp" => ids.iter().map(|&i| (20 + i % 81) as f64).collect::<Vec<_>>(),
+            "max_hp" => vec![100.0; ENTITIES]
+        ]?,
+    })
+}
+
+fn load_duckdb(conn: &Connection, c: &Components) -> Result<(), Box<dyn Error>> {
+    conn.execute_batch(
+        "CREATE TABLE position (id BIGINT, x DOUBLE, y DOUBLE);
+         CREATE TABLE velocity (id BIGINT, vx DOUBLE, vy DOUBLE);
+         CREATE TABLE health (id BIGINT, hp DOUBLE, max_hp DOUBLE);",
+    )?;
+    for (table, df) in [("position", &c.position), ("velocity", &c.velocity), ("health", &c.health)] {
+        let id = df.column("id")?.i64()?;
+        let cols = df.get_columns();
+        let (a, b) = (cols[1].f64()?, cols[2].f64()?);
+        let mut appender = conn.appender(table)?;
+        for i in 0..df.height() {
+            appender.append_row(params![id.get(i), a.get(i), b.get(i)])?;
+        }
+        appender.flush()?;
+    }
+    Ok(())
+}
+
+fn scheduler() -> Result<LuaScheduler, Box<dyn Error>> {
+    let mut s = LuaScheduler::new()?;
+    s.load_script("movement.lua", MOVEMENT_LUA)?;
+    s.load_script("health.lua", HEALTH_LUA)?;
+    Ok(s)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== Lua ECS Systems over FFI Column Views ===\n");
+    println!("{} entities, {} ticks\n", ENTITIES, TICKS);
+    let components = make_components()?;
+    let mut failures = 0;
+
+    // --------------------------------------------------------------------
+    // 1. Registration
+    // --------------------------------------------------------------------
+    println!("--- 1. Registered systems ---");
+    let mut duck_sched = scheduler()?;
+    for s in duck_sched.systems() {
+        println!("  {:<9} reads {:?} writes {:?}", s.name, s.reads, s.writes);
+    }
+
+    // --------------------------------------------------------------------
+    // 2. Run on both backends, compare with SQL
+    // --------------------------------------------------------------------
+    println!("\n--- 2. {} ticks per backend ---", TICKS);
+    let duck_conn = Connection::open_in_memory()?;
+    load_duckdb(&duck_conn, &components)?;
+    let mut duck = DuckDbWorld { conn: &duck_conn };
+
+    let mut polars_sched = scheduler()?;
+    let mut polars = PolarsWorld::default();
+    polars.tables.insert("position".into(), components.position.clone());
+    polars.tables.insert("velocity".into(), components.velocity.clone());
+    polars.tables.insert("health".into(), components.health.clone());
+
+    let sql_conn = Connection::open_in_memory()?;
+    load_duckdb(&sql_conn, &components)?;
+
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        duck_sched.run_tick(&mut duck, DT)?;
+    }
+    let duck_time = start.elapsed();
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        polars_sched.run_tick(&mut polars, DT)?;
+    }
+    let polars_time = start.elapsed();
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        for stmt in TICK_SQL.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            sql_conn.execute(stmt, params![DT])?;
+        }
+    }
+    let sql_time = start.elapsed();
+    println!("  Lua → DuckDB: {:?}/tick   Lua → Polars: {:?}/tick   SQL: {:?}/tick",
+             duck_time / TICKS as u32, polars_time / TICKS as u32, sql_time / TICKS as u32);
+
+    for table in ["position", "health"] {
+        let reference = TableData::from_duckdb(&sql_conn, table, "id")?;
+        let from_duck = TableData::from_duckdb(&duck_conn, table, "id")?;
+        let from_polars = TableData::from_polars(&polars.tables[table], "id")?;
+        let (d1, d2) = (diff(&reference, &from_duck, 1e-9), diff(&reference, &from_polars, 1e-9));
+        let same_sum = reference.checksum() == from_duck.checksum() && reference.checksum() == from_polars.checksum();
+        println!("  {:<9} vs SQL: duckdb {} mismatches, polars {} mismatches, checksums equal: {}",
+                 table, d1.mismatches.len(), d2.mismatches.len(), same_sum);
+        if !d1.is_empty() || !d2.is_empty() {
+            failures += 1;
+            d1.print(5);
+            d2.print(5);
+        }
+    }
+    let poisoned: i64 = duck_conn.query_row(
+        "SELECT count(*) FROM health JOIN position USING (id) WHERE x < 100 AND hp < max_hp",
+        [],
+        |r| r.get(0),
+    )?;
+    println!("  {} entities in the swamp are below max hp", poisoned);
+
+    // --------------------------------------------------------------------
+    // 3. Guards
+    // --------------------------------------------------------------------
+    println!("\n--- 3. Guards ---");
+    let mut sneaky = LuaScheduler::new()?;
+    sneaky.load_script("sneaky.lua", r#"
+        system{ name = "teleport", reads = { "position" }, writes = { "health" },
+                run = function(view) view.position.x[0] = 0 end }
+    "#)?;
+    match sneaky.run_tick(&mut duck, DT) {
+        Ok(()) => {
+            failures += 1;
+            println!("  ✗ write to a read-only component went through");
+        }
+        Err(e) => println!("  ✓ read-only write: {}", e.to_string().lines().next().unwrap_or("")),
+    }
+    let bad_scripts = [
+        ("no components", r#"system{ name = "idle", run = function() end }"#),
+        ("missing run", r#"system{ name = "lazy", writes = { "health" } }"#),
+        ("bad component", r#"system{ name = "inject", writes = { "health; DROP TABLE health" }, run = function() end }"#),
+        ("duplicate name", r#"system{ name = "teleport", writes = { "health" }, run = function() end }"#),
+    ];
+    for (label, source) in bad_scripts {
+        match sneaky.load_script(label, source) {
+            Ok(names) => {
+                failures += 1;
+                println!("  ✗ {:<14} accepted: {:?}", label, names);
+            }
+            Err(e) => println!("  ✓ {:<14} {}", label, e.to_string().lines().next().unwrap_or("")),
+        }
+    }
+    if sneaky.systems().len() != 1 {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 4. NULLs and non-numeric columns
+    // --------------------------------------------------------------------
+    println!("\n--- 4. NULLs, VARCHAR and BIGINT columns ---");
+    const BIG: i64 = (1 << 53) + 1;
+    const ARMOR_LUA: &str = r#"
+        system{ name = "harden", writes = { "armor" },
+                run = function(view)
+                  local a = view.armor
+                  assert(a.label == nil and a.serial == nil, "non-numeric columns must not be in the view")
+                  for i = 0, view.n - 1 do
+                    a.rating[i] = a.rating[i] + 1     -- NULL stays NULL: NaN + 1 is NaN
+                    if view.id[i] == 2 then a.shield[i] = 5 end
+                  end
+                end }
+    "#;
+    let armor_conn = Connection::open_in_memory()?;
+    armor_conn.execute_batch(&format!(
+        "CREATE TABLE armor (id BIGINT, rating DOUBLE, shield DOUBLE, label VARCHAR, serial BIGINT);
+         INSERT INTO armor VALUES (1, 10, NULL, 'plate', {BIG}), (2, NULL, NULL, 'mail', {BIG}), (3, 30, 7, NULL, NULL);"
+    ))?;
+    let mut armor_polars = PolarsWorld::default();
+    armor_polars.tables.insert("armor".into(), df![
+        "id" => [1i64, 2, 3],
+        "rating" => [Some(10.0), None, Some(30.0)],
+        "shield" => [None, None, Some(7.0)],
+        "label" => [Some("plate"), Some("mail"), None],
+        "serial" => [Some(BIG), Some(BIG), None]
+    ]?);
+    let expected = "[(1, Some(11.0), None, Some(\"plate\"), Some(9007199254740993)), \
+                    (2, None, Some(5.0), Some(\"mail\"), Some(9007199254740993)), \
+                    (3, Some(31.0), Some(7.0), None, None)]";
+    type ArmorRow = (i64, Option<f64>, Option<f64>, Option<String>, Option<i64>);
+    for backend in ["duckdb", "polars"] {
+        let mut sched = LuaScheduler::new()?;
+        sched.load_script("armor.lua", ARMOR_LUA)?;
+        let rows: Vec<ArmorRow> = if backend == "duckdb" {
+            sched.run_tick(&mut DuckDbWorld { conn: &armor_conn }, DT)?;
+            let mut stmt = armor_conn.prepare("SELECT * FROM armor ORDER BY id")?;
+            let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)))?;
+            rows.collect::<duckdb::Result<_>>()?
+        } else {
+            sched.run_tick(&mut armor_polars, DT)?;
+            let df = &armor_polars.tables["armor"];
+            let (id, rating, shield) = (df.column("id")?.i64()?, df.column("rating")?.f64()?, df.column("shield")?.f64()?);
+            let (label, serial) = (df.column("label")?.str()?, df.column("serial")?.i64()?);
+            (0..df.height())
+                .map(|i| (id.get(i).unwrap_or(0), rating.get(i), shield.get(i), label.get(i).map(String::from), serial.get(i)))
+                .collect()
+        };
+        let got = format!("{:?}", rows);
+        let ok = got.split_whitespace().eq(expected.split_whitespace());
+        if !ok {
+            failures += 1;
+        }
+        println!("  {} {:<6} {}", if ok { "✓" } else { "✗" }, backend, got);
+    }
+
+    // --------------------------------------------------------------------
+    // 5. Breakdown
+    // --------------------------------------------------------------------
+    println!("\n--- 5. Per-system cost ---");
+    println!("  DuckDB:");
+    duck_sched.print_report();
+    println!("  Polars:");
+    polars_sched.print_report();
+
+    println!("\n=== Summary ===\n");
+    println!("  • Systems are plain Lua functions over FFI pointers; registration is `system{{...}}`");
+    println!("  • Only changed cells are flushed; NULLs survive, non-numeric and BIGINT columns stay out of views");
+    println!("  • The same scripts drive DuckDB and Polars and match the SQL tick");
+    println!("  • Read-only components are `const double*`: writing them fails the system");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
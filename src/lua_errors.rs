@@ -0,0 +1,219 @@
+//! Structured Errors from Lua UDFs
+//!
+//! Shared by bins via `mod lua_errors;`. A Lua error inside a UDF used to
+//! either panic a DuckDB worker thread (`.unwrap()`) or surface as a bare
+//! mlua message. `UdfError` carries what a mod author needs instead: the
+//! mod, the function, the row, the arguments, the Lua message and its
+//! traceback.
+//!
+//! The row is an index into the chunk DuckDB handed the UDF (at most 2048
+//! rows), not into the table: scalar functions are never told where a chunk
+//! starts, so the message says "chunk row". Batch UDFs that fail as a whole
+//! report no row.
+//!
+//! - `set_owner(function, module)` records which mod defined a UDF
+//!   (`mod_loader::register_mods` does this for every declared UDF) and
+//!   `forget_module(module)` drops it all again
+//! - `set_on_error(module, mode)`: `OnError::Fail` turns the first error into
+//!   a query error, `OnError::Null` makes the failing row NULL and carries on
+//! - every error is counted per mod, in either mode; `error_counts()` and
+//!   `last_error(module)` read them back
+//! - `call_rows` runs a `name VARCHAR, args DOUBLE...` chunk row by row
+//!   under those policies, for the UDF bridges that call Lua by name
+//!   (`mod_loader`'s `lua_call`, `lua_vm_pool`'s pooled calls)
+//!
+//! The registry is process-wide, like `lua_scripts`, so DuckDB worker
+//! threads see the same owners, modes and counters.
+
+#![allow(dead_code)]
+
+use duckdb::arrow::array::{Array, Float64Array, StringArray};
+use mlua::{Function, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Mutex, RwLock};
+
+/// Module name for functions no mod has claimed.
+pub const UNOWNED: &str = "<unowned>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnError {
+    #[default]
+    Fail,
+    Null,
+}
+
+impl OnError {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "fail" => Some(OnError::Fail),
+            "null" => Some(OnError::Null),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OnError::Fail => "fail",
+            OnError::Null => "null",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UdfError {
+    pub module: String,
+    pub function: String,
+    /// Row within the chunk the UDF was invoked with; `None` when the call
+    /// covered the whole chunk.
+    pub row: Option<usize>,
+    pub args: Vec<f64>,
+    pub message: String,
+    pub traceback: Option<String>,
+}
+
+impl UdfError {
+    /// Splits mlua's error into message and traceback.
+    pub fn from_lua(module: &str, function: &str, row: Option<usize>, args: &[f64], err: &mlua::Error) -> Self {
+        let (message, traceback) = match err {
+            mlua::Error::CallbackError { traceback, cause } => (cause.to_string(), Some(traceback.clone())),
+            other => {
+                let text = other.to_string();
+                let text = text.strip_prefix("runtime error: ").unwrap_or(&text);
+                match text.split_once("\nstack traceback:") {
+                    Some((message, rest)) => (message.to_string(), Some(format!("stack traceback:{}", rest))),
+                    None => (text.to_string(), None),
+                }
+            }
+        };
+        UdfError { module: module.to_string(), function: function.to_string(), row, args: args.to_vec(), message, traceback }
+    }
+}
+
+impl fmt::Display for UdfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Lua error in mod `{}`, function `{}`, ", self.module, self.function)?;
+        match self.row {
+            Some(row) => write!(f, "chunk row {}", row)?,
+            None => f.write_str("whole chunk")?,
+        }
+        write!(f, " {:?}: {}", self.args, self.message)?;
+        if let Some(traceback) = &self.traceback {
+            write!(f, "\n{}", traceback)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for UdfError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct ModErrors {
+    pub count: u64,
+    pub last: Option<UdfError>,
+}
+
+static OWNERS: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+static MODES: RwLock<BTreeMap<String, OnError>> = RwLock::new(BTreeMap::new());
+static ERRORS: Mutex<BTreeMap<String, ModErrors>> = Mutex::new(BTreeMap::new());
+
+pub fn set_owner(function: &str, module: &str) {
+    OWNERS.write().unwrap().insert(function.to_string(), module.to_string());
+}
+
+pub fn set_on_error(module: &str, mode: OnError) {
+    MODES.write().unwrap().insert(module.to_string(), mode);
+}
+
+/// Drops everything recorded for `module`: its functions' ownership, its
+/// mode and its error counts (`mod_loader::LoadedMods` calls this when a
+/// mod is unloaded).
+pub fn forget_module(module: &str) {
+    OWNERS.write().unwrap().retain(|_, m| m != module);
+    MODES.write().unwrap().remove(module);
+    ERRORS.lock().unwrap().remove(module);
+}
+
+/// (owning mod, its error mode) for `function`.
+pub fn policy(function: &str) -> (String, OnError) {
+    let module = OWNERS.read().unwrap().get(function).cloned().unwrap_or_else(|| UNOWNED.to_string());
+    let mode = MODES.read().unwrap().get(&module).copied().unwrap_or_default();
+    (module, mode)
+}
+
+/// Counts `err` against its mod; returns it back if the mode is `Fail`.
+#[allow(clippy::result_large_err)]
+pub fn handle(err: UdfError, mode: OnError) -> Result<(), UdfError> {
+    let mut errors = ERRORS.lock().unwrap();
+    let entry = errors.entry(err.module.clone()).or_default();
+    entry.count += 1;
+    match mode {
+        OnError::Null => {
+            entry.last = Some(err);
+            Ok(())
+        }
+        OnError::Fail => {
+            entry.last = Some(err.clone());
+            Err(err)
+        }
+    }
+}
+
+/// (mod, errors so far) for every mod that has had one.
+pub fn error_counts() -> Vec<(String, u64)> {
+    ERRORS.lock().unwrap().iter().map(|(m, e)| (m.clone(), e.count)).collect()
+}
+
+pub fn last_error(module: &str) -> Option<UdfError> {
+    ERRORS.lock().unwrap().get(module).and_then(|e| e.last.clone())
+}
+
+pub fn reset_counts() {
+    ERRORS.lock().unwrap().clear();
+}
+
+/// Calls `resolve(owning mod, name)` on every row with no NULLs, with that
+/// row's `args`; rows with a NULL are NULL. A Lua error, or a name that
+/// resolves to nothing, goes through `handle` under the owner's mode: the
+/// first `Fail` stops the chunk and comes back as the inner `Err`.
+pub fn call_rows(
+    names: &StringArray,
+    args: &[&Float64Array],
+    resolve: impl Fn(&str, &str) -> mlua::Result<Option<Function>>,
+) -> mlua::Result<Result<Vec<Option<f64>>, UdfError>> {
+    // (name, function if defined, owning mod, error mode)
+    let mut cached: Option<(&str, Option<Function>, String, OnError)> = None;
+    let mut out = Vec::with_capacity(names.len());
+    let mut values = Vec::with_capacity(args.len());
+    for row in 0..names.len() {
+        if names.is_null(row) || args.iter().any(|a| a.is_null(row)) {
+            out.push(None);
+            continue;
+        }
+        let name = names.value(row);
+        if cached.as_ref().map(|c| c.0) != Some(name) {
+            let (module, mode) = policy(name);
+            cached = Some((name, resolve(&module, name).ok().flatten(), module, mode));
+        }
+        let (_, f, module, mode) = cached.as_ref().expect("cached above");
+        values.clear();
+        values.extend(args.iter().map(|a| a.value(row)));
+        let called = match f {
+            Some(f) => f.call::<f64>(mlua::MultiValue::from_iter(values.iter().map(|v| Value::Number(*v)))),
+            None => Err(mlua::Error::RuntimeError(format!("no Lua function `{}`", name))),
+        };
+        match called {
+            Ok(v) => out.push(Some(v)),
+            Err(e) => {
+                if let Err(udf) = handle(UdfError::from_lua(module, name, Some(row), &values, &e), *mode) {
+                    return Ok(Err(udf));
+                }
+                out.push(None);
+            }
+        }
+    }
+    Ok(Ok(out))
+}
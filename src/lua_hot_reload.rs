@@ -0,0 +1,220 @@
+//! Hot-Reloading a Lua Mod Across DuckDB's Worker Threads
+//!
+//! A VM built once per worker thread from a `static` string can't change its
+//! script without a restart. Using `lua_scripts.rs`, the `lua_damage(hp,
+//! armor)` UDF here calls a `damage` function loaded from a file on disk:
+//! 1. v1 from file; every worker VM loads it lazily
+//! 2. Edit the file, `poll_changes()`: each VM reloads on its next invoke
+//! 3. A syntax error, then a script that errors at top level: both are
+//!    reported, and every VM keeps running the previous version
+//! 4. Fix the script: all VMs pick it up again, and `legacy_damage`, which
+//!    v2 defined and v5 dropped, is cleared rather than left behind
+//! 5. Cost of the per-invoke version check
+//!
+//! A Lua error in `damage` goes through `lua_errors`, like the other UDFs:
+//! it fails the query naming the chunk row, or makes the row NULL under
+//! `on_error = "null"`.
+
+mod lua_errors;
+mod lua_scripts;
+
+use duckdb::arrow::array::{Array, Float64Array};
+use duckdb::arrow::datatypes::DataType;
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::{
+    vscalar::{ArrowFunctionSignature, VArrowScalar},
+    Connection,
+};
+use lua_errors::UdfError;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+const ROWS: usize = 1_000_000;
+
+// ============================================================================
+// lua_damage(hp DOUBLE, armor DOUBLE) -> DOUBLE
+// ============================================================================
+
+const LUA_DAMAGE: &str = "lua_damage";
+
+struct LuaDamageScalar;
+
+impl VArrowScalar for LuaDamageScalar {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<Arc<dyn Array>, Box<dyn Error>> {
+        let hp = input.column(0).as_any().downcast_ref::<Float64Array>().ok_or("lua_damage: hp must be DOUBLE")?;
+        let armor = input.column(1).as_any().downcast_ref::<Float64Array>().ok_or("lua_damage: armor must be DOUBLE")?;
+
+        let (module, mode) = lua_errors::policy(LUA_DAMAGE);
+        let result: Vec<Option<f64>> = lua_scripts::with_vm(|lua| {
+            let damage: mlua::Result<mlua::Function> = lua.globals().get("damage");
+            let mut out = Vec::with_capacity(input.num_rows());
+            for i in 0..input.num_rows() {
+                let args = [hp.value(i), armor.value(i)];
+                match damage.as_ref().map_err(Clone::clone).and_then(|f| f.call::<f64>((args[0], args[1]))) {
+                    Ok(d) => out.push(Some(d)),
+                    Err(e) => {
+                        if let Err(udf) = lua_errors::handle(UdfError::from_lua(&module, LUA_DAMAGE, Some(i), &args, &e), mode) {
+                            return Ok(Err(udf));
+                        }
+                        out.push(None);
+                    }
+                }
+            }
+            Ok(Ok(out))
+        })??;
+
+        Ok(Arc::new(Float64Array::from(result)))
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        vec![ArrowFunctionSignature::exact(vec![DataType::Float64, DataType::Float64], DataType::Float64)]
+    }
+}
+
+// ============================================================================
+// Helpers
+// ============================================================================
+
+const V1: &str = "function damage(hp, armor) return math.max(hp - armor, 0) end\n";
+const V2: &str = "function damage(hp, armor) return math.max(hp * 1.5 - armor, 0) end\n\
+                  function legacy_damage(hp) return hp end\n";
+const V3_SYNTAX_ERROR: &str = "function damage(hp, armor) return hp * 3 - end\n";
+const V4_RUNTIME_ERROR: &str = "function damage(hp, armor) return 0 end\nerror('balance table missing')\n";
+const V5: &str = "local CRIT = 2.0\nfunction damage(hp, armor) return math.max(hp * CRIT - armor, 0) end\n";
+
+fn edit(path: &Path, source: &str) -> Result<(), Box<dyn Error>> {
+    fs::write(path, source)?;
+    for (name, version) in lua_scripts::poll_changes() {
+        println!("  poll_changes(): {} → v{}", name, version);
+    }
+    Ok(())
+}
+
+/// Runs the Lua UDF and the equivalent SQL; true if they agree.
+fn check(conn: &Connection, sql_equivalent: &str) -> Result<bool, Box<dyn Error>> {
+    let start = Instant::now();
+    let lua: f64 = conn.query_row("SELECT sum(lua_damage(hp, armor)) FROM units", [], |r| r.get(0))?;
+    let elapsed = start.elapsed();
+    let want: f64 = conn.query_row(&format!("SELECT sum({}) FROM units", sql_equivalent), [], |r| r.get(0))?;
+    let ok = (lua - want).abs() <= 1e-9 * want.abs().max(1.0);
+    let (vms, reloads) = lua_scripts::vm_stats();
+    println!("  sum(lua_damage) = {:.1} in {:?} (matches `{}`: {}; {} VMs, {} loads total)",
+             lua, elapsed, sql_equivalent, ok, vms, reloads);
+    Ok(ok)
+}
+
+/// Whether this thread's VM (synced to the current version) defines `name`.
+fn defined(name: &str) -> Result<bool, Box<dyn Error>> {
+    Ok(lua_scripts::with_vm(|lua| lua.globals().get::<mlua::Value>(name))? != mlua::Value::Nil)
+}
+
+fn report_errors() -> usize {
+    let errors = lua_scripts::take_errors();
+    if let Some(first) = errors.first() {
+        println!("  {} VM(s) rejected {} v{}: {}", errors.len(), first.script, first.version, first.message);
+    }
+    errors.len()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== Lua Hot Reload Across Thread-Local VMs ===\n");
+
+    let dir = std::env::temp_dir().join("polars_ecs_mods");
+    fs::create_dir_all(&dir)?;
+    let script = dir.join("combat.lua");
+    fs::write(&script, V1)?;
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch("SET threads TO 8;")?;
+    conn.register_scalar_function::<LuaDamageScalar>(LUA_DAMAGE)?;
+    conn.execute_batch(&format!(
+        "CREATE TABLE units AS
+         SELECT (50 + i % 100)::DOUBLE AS hp, (i % 30)::DOUBLE AS armor
+         FROM range({ROWS}) t(i);"
+    ))?;
+    let mut failures = 0;
+
+    println!("--- 1. Load v1 from {} ---", script.display());
+    println!("  load_file(): combat → v{}", lua_scripts::load_file("combat", &script)?);
+    if !check(&conn, "greatest(hp - armor, 0)")? {
+        failures += 1;
+    }
+
+    println!("\n--- 2. Edit: v2 ---");
+    edit(&script, V2)?;
+    if !check(&conn, "greatest(hp * 1.5 - armor, 0)")? {
+        failures += 1;
+    }
+    let legacy = defined("legacy_damage")?;
+    println!("  legacy_damage defined: {}", legacy);
+    if !legacy {
+        failures += 1;
+    }
+
+    println!("\n--- 3a. Edit with a syntax error ---");
+    edit(&script, V3_SYNTAX_ERROR)?;
+    if !check(&conn, "greatest(hp * 1.5 - armor, 0)")? {
+        failures += 1;
+    }
+    if report_errors() == 0 {
+        failures += 1;
+    }
+
+    println!("\n--- 3b. Edit that errors while loading ---");
+    edit(&script, V4_RUNTIME_ERROR)?;
+    if !check(&conn, "greatest(hp * 1.5 - armor, 0)")? {
+        failures += 1;
+    }
+    if report_errors() == 0 {
+        failures += 1;
+    }
+    println!("  (its `damage` was defined before the error, but never published)");
+
+    println!("\n--- 4. Fixed: v5 ---");
+    edit(&script, V5)?;
+    if !check(&conn, "greatest(hp * 2.0 - armor, 0)")? {
+        failures += 1;
+    }
+    if report_errors() != 0 {
+        failures += 1;
+    }
+    let legacy = defined("legacy_damage")?;
+    println!("  legacy_damage defined: {}", legacy);
+    if legacy {
+        failures += 1;
+    }
+
+    println!("\n--- 5. Steady state (no changes) ---");
+    let iterations = 5;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let _: f64 = conn.query_row("SELECT sum(lua_damage(hp, armor)) FROM units", [], |r| r.get(0))?;
+    }
+    let per_query = start.elapsed() / iterations;
+    let start = Instant::now();
+    let checks = 1_000_000;
+    for _ in 0..checks {
+        std::hint::black_box(lua_scripts::version());
+    }
+    println!("  Query: {:?}; version check: {:.1} ns, once per invoke (≤2048 rows)",
+             per_query, start.elapsed().as_nanos() as f64 / checks as f64);
+
+    let _ = fs::remove_dir_all(&dir);
+
+    println!("\n=== Summary ===\n");
+    println!("  • Scripts come from files; a version bump reaches every worker VM on its next invoke");
+    println!("  • Broken reloads are reported and the previous definitions stay live");
+    println!("  • Globals a new version no longer defines are removed");
+    println!("  • Steady-state cost is one atomic load per batch");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
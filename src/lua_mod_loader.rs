@@ -0,0 +1,286 @@
+//! Mod Packages: Loading a Directory of Lua Mods into DuckDB
+//!
+//! Until now "mods" were Rust string constants. This writes a small mods
+//! directory and loads it through `mod_loader.rs`:
+//! 1. Three mods (`core`, `combat` needs core >=1.0, `weather` needs
+//!    combat ^1.0) whose directory order is the reverse of their load order
+//! 2. Their component tables, Lua UDFs (as SQL macros) and systems running
+//!    through one `SystemRunner`
+//! 3. Broken installs: cycle, version conflict, missing dependency,
+//!    duplicate mod, UDF name clash, UDF declared but never defined, a
+//!    manifest that runs code, a script that reads files on load, SQL in a
+//!    component's columns, a UDF named after a DuckDB function, systems
+//!    outside the mod's `permissions`, a component that claims another
+//!    mod's table, a script that redefines another mod's global or a
+//!    standard one, and one that reaches for the string metatable
+//! 4. A UDF that looks for `io`/`os`/`require`, or for the UDFs of mods it
+//!    doesn't depend on, when called finds nothing,
+//!    and its script's patched `math.max` doesn't reach other mods
+
+mod lua_errors;
+mod lua_scripts;
+mod mod_loader;
+mod mod_permissions;
+mod system_runner;
+
+use duckdb::types::Value;
+use duckdb::Connection;
+use mod_loader::{load_mods, write_mod, ModError};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use system_runner::SystemRunner;
+
+const CORE: &str = r#"
+return {
+  name = "core", version = "1.0.0",
+  components = { units = "id BIGINT, x DOUBLE, y DOUBLE, hp DOUBLE, max_hp DOUBLE" },
+  scripts = { "core.lua" },
+  udfs = { regen_amount = 2 },
+  systems = {
+    { name = "regen", sql = "UPDATE units SET hp = least(hp + regen_amount(hp, max_hp) * $dt, max_hp)" },
+  },
+}
+"#;
+const CORE_LUA: &str = "function regen_amount(hp, max_hp) return (max_hp - hp) * 0.5 end\n";
+
+const COMBAT: &str = r#"
+return {
+  name = "combat", version = "1.2.0",
+  dependencies = { core = ">=1.0" },
+  components = { attacks = "attacker BIGINT, target BIGINT, power DOUBLE" },
+  permissions = { read = { units = { "id" } }, write = { units = { "hp" } } },
+  scripts = { "combat.lua" },
+  udfs = { damage = 2 },
+  systems = {
+    { name = "apply_damage", sql = [[
+        UPDATE units SET hp = greatest(hp - d.total, 0)
+        FROM (SELECT target, sum(damage(power, 2.0)) AS total FROM attacks GROUP BY target) d
+        WHERE units.id = d.target]] },
+  },
+}
+"#;
+const COMBAT_LUA: &str = "function damage(power, armor) return math.max(power - armor, 1) end\n";
+
+const WEATHER: &str = r#"
+return {
+  name = "weather", version = "0.3.0",
+  dependencies = { combat = "^1.0", core = "*" },
+  permissions = { read = { units = { "y" } }, write = { units = { "x" } } },
+  scripts = { "weather.lua" },
+  udfs = { wind = 1 },
+  systems = { { name = "drift", sql = "UPDATE units SET x = x + wind(y) * $dt" } },
+}
+"#;
+const WEATHER_LUA: &str = "function wind(y) return math.sin(y / 100) end\n";
+
+const PROBE: &str = r#"return { name = "probe", version = "1.0", scripts = { "probe.lua" }, udfs = { reach = 1 } }"#;
+/// Counts the host globals, and the globals of mods it doesn't depend on,
+/// it can see at call time, not at load time, and patches `math.max`, which
+/// `combat`'s `damage` uses.
+const PROBE_LUA: &str = r#"
+math.max = function() return -1 end
+function reach(_)
+  local seen = { io, os, require, getfenv, loadstring, _G, ffi, debug, damage, regen_amount, wind }
+  local n = 0
+  for i = 1, 11 do
+    if seen[i] ~= nil then n = n + 1 end
+  end
+  return n
+end
+"#;
+
+/// A mod on disk: directory, manifest, and (file, source) scripts.
+type ModFiles<'a> = (&'a str, &'a str, &'a [(&'a str, &'a str)]);
+
+/// Writes `mods` (dir, manifest, scripts) into a fresh directory and tries to
+/// load it into a database of its own; the mods are unloaded again before
+/// that database closes.
+fn try_install(root: &Path, mods: &[ModFiles]) -> Result<Vec<String>, ModError> {
+    let _ = fs::remove_dir_all(root);
+    for (dir, manifest, scripts) in mods {
+        write_mod(root, dir, manifest, scripts).expect("write mod");
+    }
+    let conn = Connection::open_in_memory().expect("open");
+    let mut runner = SystemRunner::new(&conn);
+    let loaded = load_mods(&conn, &mut runner, root)?;
+    Ok(loaded.iter().map(|m| m.name.clone()).collect())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== Mod Loader: Manifests, Dependencies, Load Order ===\n");
+    let root = std::env::temp_dir().join("polars_ecs_mod_loader");
+    let _ = fs::remove_dir_all(&root);
+    let mut failures = 0;
+
+    // --------------------------------------------------------------------
+    // 1. A working install
+    // --------------------------------------------------------------------
+    println!("--- 1. Load ---");
+    let mods_dir = root.join("ok");
+    write_mod(&mods_dir, "a_weather", WEATHER, &[("weather.lua", WEATHER_LUA)])?;
+    write_mod(&mods_dir, "b_combat", COMBAT, &[("combat.lua", COMBAT_LUA)])?;
+    write_mod(&mods_dir, "c_core", CORE, &[("core.lua", CORE_LUA)])?;
+
+    let conn = Connection::open_in_memory()?;
+    let mut runner = SystemRunner::new(&conn);
+    let order = load_mods(&conn, &mut runner, &mods_dir)?;
+    for m in &order {
+        let deps: Vec<String> = m.dependencies.iter().map(|(d, r)| format!("{} {}", d, r)).collect();
+        println!("  {:<8} {}  ({})  udfs: {:?}, systems: {}",
+                 m.name, m.version, m.dir.file_name().unwrap_or_default().to_string_lossy(),
+                 m.udfs.iter().map(|(u, _)| u.as_str()).collect::<Vec<_>>(), m.systems.len());
+        if !deps.is_empty() {
+            println!("           depends on {}", deps.join(", "));
+        }
+    }
+    let names: Vec<String> = order.iter().map(|m| m.name.clone()).collect();
+    if names != ["core", "combat", "weather"] {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 2. Run the mods' systems
+    // --------------------------------------------------------------------
+    println!("\n--- 2. Run ---");
+    conn.execute_batch(
+        "INSERT INTO units SELECT i, i * 10.0, i * 50.0, 60.0, 100.0 FROM range(5) t(i);
+         INSERT INTO attacks VALUES (0, 1, 12.0), (2, 1, 7.0), (3, 4, 2.5);",
+    )?;
+    runner.params.set("dt", Value::Double(0.1));
+    for _ in 0..3 {
+        runner.run_tick()?;
+    }
+    let mut stmt = conn.prepare("SELECT id, round(x, 3), round(hp, 3) FROM units ORDER BY id")?;
+    let rows = stmt.query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, f64>(1)?, r.get::<_, f64>(2)?)))?;
+    for row in rows {
+        let (id, x, hp) = row?;
+        println!("  unit {}  x {:>8.3}  hp {:>7.3}", id, x, hp);
+    }
+    let udf_check: f64 = conn.query_row("SELECT damage(12.0, 2.0) + regen_amount(60.0, 100.0) + wind(0.0)", [], |r| r.get(0))?;
+    println!("  damage(12, 2) + regen_amount(60, 100) + wind(0) = {}", udf_check);
+    if udf_check != 30.0 {
+        failures += 1;
+    }
+    runner.print_report();
+
+    // --------------------------------------------------------------------
+    // 3. Broken installs
+    // --------------------------------------------------------------------
+    println!("\n--- 3. Rejected installs ---");
+    // Several cases bring their own `core` and `combat`, and a mod name is
+    // loaded once at a time, so the install from 1 is unloaded until 4.
+    drop(order);
+    let cycle_a = r#"return { name = "a", version = "1.0", dependencies = { b = "*" } }"#;
+    let cycle_b = r#"return { name = "b", version = "1.0", dependencies = { a = "*" } }"#;
+    let needs_core_2 = COMBAT.replace(">=1.0", ">=2.0");
+    let core_again = CORE.replace("1.0.0", "1.1.0");
+    let clashing = r#"return { name = "rival", version = "1.0", scripts = { "rival.lua" }, udfs = { damage = 2 } }"#;
+    let undefined = r#"return { name = "lazy", version = "1.0", scripts = { "lazy.lua" }, udfs = { heal = 1 } }"#;
+    let none: &[(&str, &str)] = &[];
+    let core_files: &[(&str, &str)] = &[("core.lua", CORE_LUA)];
+    let combat_files: &[(&str, &str)] = &[("combat.lua", COMBAT_LUA)];
+    let rival_files: &[(&str, &str)] = &[("rival.lua", COMBAT_LUA)];
+    let lazy_files: &[(&str, &str)] = &[("lazy.lua", "-- nothing yet\n")];
+    let nosy = r#"return { name = "nosy", version = "1.0", owner = os.getenv("USER") }"#;
+    let snoop = r#"return { name = "snoop", version = "1.0", scripts = { "snoop.lua" } }"#;
+    let snoop_files: &[(&str, &str)] = &[("snoop.lua", "local f = io.open('/etc/passwd')\n")];
+    let injected = r#"return { name = "inject", version = "1.0", components = { loot = "id BIGINT); DROP TABLE units; --" } }"#;
+    let shadow = r#"return { name = "shadow", version = "1.0", scripts = { "shadow.lua" }, udfs = { abs = 1 } }"#;
+    let shadow_files: &[(&str, &str)] = &[("shadow.lua", "function abs(x) return 0 end\n")];
+    let vandal = r#"return { name = "vandal", version = "1.0", dependencies = { core = "*" },
+      systems = { { name = "wipe", sql = "DROP TABLE units" } } }"#;
+    let greedy = r#"return { name = "greedy", version = "1.0", dependencies = { core = "*" },
+      permissions = { read = { units = { "id" } }, write = { units = { "hp" } } },
+      systems = { { name = "teleport", sql = "UPDATE units SET x = 0 WHERE id = 1" } } }"#;
+    let squatter = r#"return { name = "squatter", version = "1.0", dependencies = { core = "*" },
+      components = { units = "id BIGINT, hp DOUBLE" } }"#;
+    let hijack = r#"return { name = "hijack", version = "1.0", dependencies = { combat = "*" }, scripts = { "hijack.lua" } }"#;
+    let hijack_files: &[(&str, &str)] = &[("hijack.lua", "function damage(power, armor) return 0 end\n")];
+    let meddle = r#"return { name = "meddle", version = "1.0", scripts = { "meddle.lua" } }"#;
+    let meddle_files: &[(&str, &str)] = &[("meddle.lua", "getmetatable('').__index = {}\n")];
+    let respell = r#"return { name = "respell", version = "1.0", scripts = { "respell.lua" } }"#;
+    let respell_files: &[(&str, &str)] = &[("respell.lua", "function pairs(t) return next, {} end\n")];
+    let climber = r#"return { name = "climber", version = "1.0", scripts = { "../core/core.lua" } }"#;
+    let rooted = r#"return { name = "rooted", version = "1.0", scripts = { "/etc/passwd" } }"#;
+
+    type Install<'a> = Vec<(&'a str, &'a str, &'a [(&'a str, &'a str)])>;
+    let cases: Vec<(&str, Install)> = vec![
+        ("cycle", vec![("a", cycle_a, none), ("b", cycle_b, none)]),
+        ("version conflict", vec![("core", CORE, core_files), ("combat", needs_core_2.as_str(), combat_files)]),
+        ("missing dependency", vec![("combat", COMBAT, combat_files)]),
+        ("duplicate mod", vec![("core", CORE, core_files), ("core_copy", core_again.as_str(), core_files)]),
+        ("UDF clash", vec![("core", CORE, core_files), ("combat", COMBAT, combat_files), ("rival", clashing, rival_files)]),
+        ("undefined UDF", vec![("lazy", undefined, lazy_files)]),
+        ("code in manifest", vec![("nosy", nosy, none)]),
+        ("io on script load", vec![("snoop", snoop, snoop_files)]),
+        ("SQL in column types", vec![("inject", injected, none)]),
+        ("UDF shadows built-in", vec![("shadow", shadow, shadow_files)]),
+        ("DDL as a system", vec![("core", CORE, core_files), ("vandal", vandal, none)]),
+        ("system beyond grant", vec![("core", CORE, core_files), ("greedy", greedy, none)]),
+        ("claims another table", vec![("core", CORE, core_files), ("squatter", squatter, none)]),
+        ("takes a mod's global", vec![("core", CORE, core_files), ("combat", COMBAT, combat_files), ("hijack", hijack, hijack_files)]),
+        ("string metatable", vec![("meddle", meddle, meddle_files)]),
+        ("redefines stdlib", vec![("respell", respell, respell_files)]),
+        ("script outside mod", vec![("core", CORE, core_files), ("climber", climber, none)]),
+        ("absolute script path", vec![("rooted", rooted, none)]),
+    ];
+    for (i, (label, mods)) in cases.iter().enumerate() {
+        match try_install(&root.join(format!("bad{}", i)), mods) {
+            Ok(order) => {
+                failures += 1;
+                println!("  {:<20} ✗ loaded anyway: {:?}", label, order);
+            }
+            Err(e) => println!("  {:<20} ✓ {}", label, e.to_string().lines().next().unwrap_or("")),
+        }
+    }
+
+    // A mod rejected at registration leaves no component tables behind.
+    let leftover_dir = root.join("leftover");
+    let litter = r#"return { name = "litter", version = "1.0", components = { crumbs = "id BIGINT" },
+      scripts = { "litter.lua" }, udfs = { abs = 1 } }"#;
+    write_mod(&leftover_dir, "litter", litter, &[("litter.lua", "function abs(x) return 0 end\n")])?;
+    let rejected = load_mods(&conn, &mut runner, &leftover_dir).is_err();
+    let crumbs: i64 = conn.query_row("SELECT count(*) FROM duckdb_tables() WHERE table_name = 'crumbs'", [], |r| r.get(0))?;
+    println!("  {:<20} {} rejected: {}, tables left behind: {}", "no partial install", if rejected && crumbs == 0 { "✓" } else { "✗" }, rejected, crumbs);
+    if !rejected || crumbs != 0 {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 4. What a mod UDF sees at runtime
+    // --------------------------------------------------------------------
+    println!("\n--- 4. Runtime environment ---");
+    let probe_dir = root.join("probe");
+    write_mod(&probe_dir, "probe", PROBE, &[("probe.lua", PROBE_LUA)])?;
+    let mut runner = SystemRunner::new(&conn);
+    let _order = load_mods(&conn, &mut runner, &mods_dir)?;
+    let _probe = load_mods(&conn, &mut runner, &probe_dir)?;
+    let reachable: f64 = conn.query_row("SELECT reach(0.0)", [], |r| r.get(0))?;
+    println!("  io/os/require/getfenv/loadstring/_G/ffi/debug or other mods' UDFs visible to a mod UDF: {}", reachable);
+    if reachable != 0.0 {
+        failures += 1;
+    }
+    let damage: f64 = conn.query_row("SELECT damage(12.0, 2.0)", [], |r| r.get(0))?;
+    println!("  damage(12, 2) after the probe patched its math.max: {}", damage);
+    if damage != 10.0 {
+        failures += 1;
+    }
+
+    let _ = fs::remove_dir_all(&root);
+
+    println!("\n=== Summary ===\n");
+    println!("  • Load order comes from dependencies, not directory order: {}", names.join(" → "));
+    println!("  • Each mod's tables, Lua UDFs and systems are registered in that order");
+    println!("  • Cycles, version conflicts, missing/duplicate mods and UDF clashes are rejected up front");
+    println!("  • Systems are checked against the mod's grants; components can't claim others' tables");
+    println!("  • Manifests are data (no stdlib) and their names/types are checked before any DDL");
+    println!("  • Mod scripts see no io/os/ffi, in the load-time check and in every runtime VM");
+    println!("  • Each mod's globals stay in its own env; stdlib tables are per-script copies");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
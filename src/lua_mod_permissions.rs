@@ -0,0 +1,249 @@
+//! Mod Permissions: What a Lua Mod May Read and Write
+//!
+//! `lua_mod_loader.rs` trusts every mod with the whole database. Here each
+//! mod gets its own `db` table from `mod_permissions.rs`, scoped by the
+//! `permissions` block of its manifest:
+//! 1. `healer` (read units.id/hp, write units.hp, owns `heal_log`) does its
+//!    job through `db.query` / `db.execute`
+//! 2. `thief` (read units.id/x/y, write units.y) tries to reach gold, the
+//!    `accounts` table, table functions, DDL and stacked statements; every
+//!    attempt must fail while its granted write still works
+//! 3. The same attempts from a runtime VM (`lua_scripts::with_vm`), whose
+//!    `db` `mod_loader::register_mods` provides
+//! 4. Cost of the checks per `db.query`
+
+mod lua_errors;
+mod lua_scripts;
+mod mod_loader;
+mod mod_permissions;
+mod system_runner;
+
+use duckdb::Connection;
+use mlua::{Function, Lua, Table};
+use mod_loader::write_mod;
+use mod_permissions::{install_db, ModSandbox};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::rc::Rc;
+use std::time::Instant;
+use system_runner::SystemRunner;
+
+const HEALER: &str = r#"
+return {
+  name = "healer", version = "1.0.0",
+  components = { heal_log = "id BIGINT, amount DOUBLE" },
+  scripts = { "healer.lua" },
+  permissions = { read = { units = { "id", "hp" } }, write = { units = { "hp" } } },
+}
+"#;
+const HEALER_LUA: &str = r#"
+function heal()
+  local wounded = db.query("SELECT id, hp FROM units WHERE hp < 50 ORDER BY id")
+  db.execute("INSERT INTO heal_log (id, amount) SELECT id, least(100 - hp, 25) FROM units WHERE hp < 50")
+  local healed = db.execute("UPDATE units SET hp = least(hp + 25, 100) WHERE hp < 50")
+  return #wounded, healed
+end
+"#;
+
+const THIEF: &str = r#"
+return {
+  name = "thief", version = "0.1.0",
+  scripts = { "thief.lua" },
+  permissions = { read = { units = { "id", "x", "y" } }, write = { units = { "y" } } },
+}
+"#;
+const THIEF_LUA: &str = r#"
+local attempts = {
+  { "read granted columns",   function() return #db.query("SELECT id, x, y FROM units") .. " rows" end },
+  { "SELECT * on units",      function()
+      local n = 0
+      for _ in pairs(db.query("SELECT * FROM units LIMIT 1")[1]) do n = n + 1 end
+      return n .. " columns visible"
+    end },
+  { "read hidden column",     function() return db.query("SELECT sum(gold) FROM units") end },
+  { "read other table",       function() return db.query("SELECT * FROM accounts") end },
+  { "schema-qualified",       function() return db.query("SELECT * FROM main.accounts") end },
+  { "table function",         function() return db.query("SELECT * FROM read_csv('/etc/passwd')") end },
+  { "subquery smuggling",     function() return db.query("SELECT id FROM units WHERE id IN (SELECT player FROM accounts)") end },
+  { "CTE over forbidden",     function() return db.query("WITH a AS (SELECT * FROM accounts) SELECT * FROM a") end },
+  { "CTE scope smuggling",    function() return db.query("SELECT * FROM (WITH accounts AS (SELECT 1 a) SELECT a FROM accounts) t, accounts") end },
+  { "CTE shadowing a table",  function() return db.query("WITH accounts AS (SELECT 1 a) SELECT * FROM accounts") end },
+  { "write ungranted column", function() return db.execute("UPDATE units SET x = 0") end },
+  { "write from hidden col",  function() return db.execute("UPDATE units SET y = gold") end },
+  { "write other table",      function() return db.execute("UPDATE accounts SET balance = 0") end },
+  { "delete rows",            function() return db.execute("DELETE FROM units") end },
+  { "DDL",                    function() return db.execute("DROP TABLE accounts") end },
+  { "stacked statements",     function() return db.execute("UPDATE units SET y = 0; DROP TABLE accounts") end },
+  { "escape-string smuggling", function() return db.execute("UPDATE units SET y = length(E'\\''), x = 5") end },
+  { "PRAGMA via query",       function() return db.query("PRAGMA database_list") end },
+  { "granted write",          function() return db.execute("UPDATE units SET y = y + 1 WHERE id < 3") .. " rows" end },
+}
+
+function probe()
+  local results = {}
+  for _, a in ipairs(attempts) do
+    local ok, res = pcall(a[2])
+    results[#results + 1] = { a[1], ok, tostring(res) }
+  end
+  return results
+end
+"#;
+
+/// Attempts the thief is allowed to make; everything else must be denied.
+const THIEF_ALLOWED: [&str; 3] = ["read granted columns", "SELECT * on units", "granted write"];
+
+/// Runs the mod's scripts in a restricted environment of its own whose `db`
+/// is the sandbox.
+fn mod_env(lua: &Lua, m: &mod_loader::ModManifest, sandbox: Rc<ModSandbox>) -> Result<Table, Box<dyn Error>> {
+    let env = lua_scripts::restricted_env(lua)?;
+    env.set("db", install_db(lua, sandbox)?)?;
+    for file in &m.scripts {
+        let source = fs::read_to_string(m.dir.join(file))?;
+        lua.load(source.as_str()).set_name(format!("{}/{}", m.name, file)).set_environment(env.clone()).exec()?;
+    }
+    Ok(env)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== Lua Mod Permissions: Table/Column Grants ===\n");
+    let root = std::env::temp_dir().join("polars_ecs_mod_permissions");
+    let _ = fs::remove_dir_all(&root);
+    write_mod(&root, "healer", HEALER, &[("healer.lua", HEALER_LUA)])?;
+    write_mod(&root, "thief", THIEF, &[("thief.lua", THIEF_LUA)])?;
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch(
+        "CREATE TABLE units AS
+         SELECT i AS id, (i * 10)::DOUBLE AS x, (i * 5)::DOUBLE AS y, (20 + i * 15)::DOUBLE AS hp, (i * 100)::DOUBLE AS gold
+         FROM range(6) t(i);
+         CREATE TABLE accounts (player BIGINT, balance DOUBLE);
+         INSERT INTO accounts VALUES (1, 1000.0), (2, 250.0);",
+    )?;
+    let mut runner = SystemRunner::new(&conn);
+    let mods = mod_loader::load_mods(&conn, &mut runner, &root)?;
+    let lua = Lua::new();
+    let mut failures = 0;
+
+    let mut sandboxes = Vec::new();
+    for m in &mods {
+        let sandbox = Rc::new(ModSandbox::new(&conn, m)?);
+        let fmt = |grants: &BTreeMap<String, BTreeSet<String>>| {
+            let parts: Vec<String> = grants
+                .iter()
+                .map(|(t, cols)| format!("{}({})", t, cols.iter().cloned().collect::<Vec<_>>().join(", ")))
+                .collect();
+            parts.join(" ")
+        };
+        println!("  {:<7} read {}", m.name, fmt(sandbox.readable()));
+        println!("  {:<7} write {}", "", fmt(sandbox.writable()));
+        sandboxes.push((m.name.clone(), sandbox.clone(), mod_env(&lua, m, sandbox)?));
+    }
+    let env_of = |name: &str| sandboxes.iter().find(|(n, _, _)| n == name).map(|(_, s, e)| (s.clone(), e.clone()));
+
+    // --------------------------------------------------------------------
+    // 1. A well-behaved mod
+    // --------------------------------------------------------------------
+    println!("\n--- 1. healer ---");
+    let (_, healer) = env_of("healer").ok_or("healer not loaded")?;
+    let (wounded, healed): (usize, usize) = healer.get::<Function>("heal")?.call(())?;
+    let logged: i64 = conn.query_row("SELECT count(*) FROM heal_log", [], |r| r.get(0))?;
+    let min_hp: f64 = conn.query_row("SELECT min(hp) FROM units", [], |r| r.get(0))?;
+    println!("  {} wounded units read, {} healed, {} logged; min hp now {}", wounded, healed, logged, min_hp);
+    if wounded != 2 || healed != 2 || logged != 2 || min_hp < 45.0 {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 2. A mod probing its limits
+    // --------------------------------------------------------------------
+    println!("\n--- 2. thief ---");
+    let gold_before: f64 = conn.query_row("SELECT sum(gold) FROM units", [], |r| r.get(0))?;
+    let (thief_box, thief) = env_of("thief").ok_or("thief not loaded")?;
+    let results: Table = thief.get::<Function>("probe")?.call(())?;
+    for result in results.sequence_values::<Table>() {
+        let result = result?;
+        let (label, ok, message): (String, bool, String) = (result.get(1)?, result.get(2)?, result.get(3)?);
+        let expected = THIEF_ALLOWED.contains(&label.as_str());
+        let mark = if ok == expected { "✓" } else { "✗" };
+        if ok != expected {
+            failures += 1;
+        }
+        let message = message.lines().next().unwrap_or("").to_string();
+        let shown: String = message.chars().take(90).collect();
+        println!("  {} {:<24} {:<7} {}", mark, label, if ok { "allowed" } else { "denied" }, shown);
+    }
+    let gold_after: f64 = conn.query_row("SELECT sum(gold) FROM units", [], |r| r.get(0))?;
+    let accounts: i64 = conn.query_row("SELECT count(*) FROM accounts", [], |r| r.get(0))?;
+    let x_sum: f64 = conn.query_row("SELECT sum(x) FROM units", [], |r| r.get(0))?;
+    println!("  gold {} → {}, accounts rows {}, sum(x) {}", gold_before, gold_after, accounts, x_sum);
+    if gold_after != gold_before || accounts != 2 || x_sum != 150.0 {
+        failures += 1;
+    }
+    for (name, sandbox, _) in &sandboxes {
+        let (allowed, denied) = sandbox.stats();
+        println!("  {:<7} {} statements allowed, {} denied", name, allowed, denied);
+    }
+
+    // --------------------------------------------------------------------
+    // 3. The thief in a runtime VM
+    // --------------------------------------------------------------------
+    println!("\n--- 3. thief in a runtime VM ---");
+    let results: Vec<(String, bool)> = lua_scripts::with_vm(|lua| {
+        let probe: Option<Function> = lua_scripts::restricted_global(lua, "thief", "probe")?;
+        let probe = probe.ok_or_else(|| lua_scripts::lua_err("thief's `probe` is not loaded".into()))?;
+        let mut out = Vec::new();
+        for result in probe.call::<Table>(())?.sequence_values::<Table>() {
+            let result = result?;
+            out.push((result.get(1)?, result.get(2)?));
+        }
+        Ok(out)
+    })?;
+    let wrong: Vec<&str> =
+        results.iter().filter(|(label, ok)| *ok != THIEF_ALLOWED.contains(&label.as_str())).map(|(l, _)| l.as_str()).collect();
+    println!("  {} attempts, {} with a different outcome {:?}", results.len(), wrong.len(), wrong);
+    let accounts: i64 = conn.query_row("SELECT count(*) FROM accounts", [], |r| r.get(0))?;
+    if results.is_empty() || !wrong.is_empty() || accounts != 2 || !lua_scripts::take_errors().is_empty() {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 4. Overhead
+    // --------------------------------------------------------------------
+    println!("\n--- 4. Check overhead ---");
+    let sql = "SELECT id, x FROM units WHERE y > 10";
+    let iterations = 2_000;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map([], |r| r.get::<_, i64>(0))?;
+        std::hint::black_box(rows.count());
+    }
+    let direct = start.elapsed() / iterations;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        std::hint::black_box(thief_box.query(sql)?);
+    }
+    let sandboxed = start.elapsed() / iterations;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        thief_box.check_select(sql)?;
+    }
+    let check = start.elapsed() / iterations;
+    println!("  direct {:?}, sandboxed {:?} (of which json_serialize_sql check {:?})", direct, sandboxed, check);
+
+    let _ = fs::remove_dir_all(&root);
+
+    println!("\n=== Summary ===\n");
+    println!("  • Each mod sees a schema of views holding only its granted columns");
+    println!("  • Reads must be one SELECT over granted tables; writes one UPDATE/INSERT/DELETE on granted columns");
+    println!("  • What a write reads is re-checked as a SELECT, so hidden columns can't leak through SET or WHERE");
+    println!("  • Runtime VMs get the same `db`, one sandbox connection per VM");
+    println!("  • Per-statement check cost: {:?} (one json_serialize_sql round trip)", check);
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,749 @@
+//! Hot-Reloadable Lua Scripts for Thread-Local VMs
+//!
+//! Shared by bins via `mod lua_scripts;`. A thread-local VM built once from
+//! a `static` script can only change that script by restarting, so the UDF
+//! bins (`duckdb_lua_vscalar.rs`, `duckdb_luajit_ffi.rs`, ...) get their VMs
+//! from here instead, where scripts live in a process-wide registry:
+//!
+//! - `load_file(name, path)` / `set_source(name, src)` add or replace a
+//!   script and bump a global version counter
+//! - `poll_changes()` re-reads every file-backed script and bumps the
+//!   version for the ones whose contents changed
+//! - `with_vm(|lua| ...)` runs on this thread's VM (mlua/LuaJIT). If the
+//!   global version moved since the VM last synced, changed scripts are
+//!   re-executed first, in registration order
+//! - `SharedVm` is the same VM behind a mutex instead of a thread-local, for
+//!   Lua values that outlive one call and move between DuckDB workers
+//!   (`lua_aggregates`' per-group states)
+//!
+//! A script is executed into a scratch environment that falls back to `_G`,
+//! and its globals are copied over only if it ran to completion, so a reload
+//! that fails to compile or errors at the top level leaves the previous
+//! definitions in place. The failure is recorded for `take_errors()`, once
+//! per VM and script version. A successful reload also clears the globals
+//! the previous version defined and the new one doesn't, unless something
+//! else has replaced them since.
+//!
+//! `unload_owner(owner)` removes a mod's scripts again: each VM clears what
+//! they published, the mod's env and its host table on its next sync.
+//!
+//! `enable_ffi()` makes VMs created afterwards load LuaJIT's `ffi` library
+//! (`Lua::unsafe_new`), for scripts that take raw column pointers.
+//!
+//! Untrusted scripts (mods) go in with `load_file_restricted(owner, ...)`.
+//! They run in a `restricted_env`, which sees only the standard globals in
+//! `RESTRICTED_STDLIB` (its own copies of `string`, `math` and `table`) and
+//! what its own mod and the mods it depends on (`set_dependencies`) have
+//! published, so the functions they define
+//! can't reach `io`, `os`, `ffi`, `require`, `getfenv`, `loadstring` or the
+//! host's own globals when they run later, whatever the VM itself was built
+//! with. Their globals go to the owning mod's env rather than `_G`, each
+//! name to one mod only, and `restricted_global` reads them back. What the
+//! host grants a mod (`mod_loader` gives each one a permission-checked `db`)
+//! comes from `set_host_bindings`, built once per VM and readable like the
+//! standard globals, never published.
+//!
+//! `check_macro_name(conn, name, bridge)` guards the bridges that expose Lua
+//! definitions as SQL macros, so a script can't replace a DuckDB function.
+//! Those bridges (`lua_aggregates`, `lua_table_functions`) implement `Bridge`
+//! and share the rest here: `install` loads the prelude and registers the
+//! function once, `discover` runs a script in a scratch VM to find its
+//! definitions, and `define_source` / `define_file` check every name before
+//! loading the script and creating one macro per definition.
+
+#![allow(dead_code)]
+
+use duckdb::Connection;
+use mlua::{Lua, Table, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+#[derive(Debug, Clone)]
+struct Script {
+    name: String,
+    path: Option<PathBuf>,
+    source: Arc<str>,
+    version: u64,
+    /// Untrusted: runs in a `restricted_env` and publishes into this mod's
+    /// env instead of `_G` (see `exec_restricted`).
+    owner: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReloadError {
+    pub script: String,
+    pub version: u64,
+    pub thread: String,
+    pub message: String,
+}
+
+static REGISTRY: RwLock<Vec<Script>> = RwLock::new(Vec::new());
+static VERSION: AtomicU64 = AtomicU64::new(0);
+static ERRORS: Mutex<Vec<ReloadError>> = Mutex::new(Vec::new());
+static VMS_CREATED: AtomicUsize = AtomicUsize::new(0);
+static RELOADS: AtomicUsize = AtomicUsize::new(0);
+static FFI: AtomicBool = AtomicBool::new(false);
+static HOSTS: RwLock<Vec<(String, u64, HostBindings)>> = RwLock::new(Vec::new());
+static HOST_GENERATION: AtomicU64 = AtomicU64::new(0);
+static DEPENDENCIES: RwLock<Vec<(String, Vec<String>)>> = RwLock::new(Vec::new());
+
+/// Fills a table with what the host gives one mod's scripts in one VM.
+pub type HostBindings = Arc<dyn Fn(&Lua, &Table) -> mlua::Result<()> + Send + Sync>;
+
+// ============================================================================
+// Registry (any thread)
+// ============================================================================
+
+fn upsert(name: &str, path: Option<PathBuf>, source: String, owner: Option<String>) -> u64 {
+    let mut registry = REGISTRY.write().unwrap();
+    let version = VERSION.fetch_add(1, Ordering::SeqCst) + 1;
+    let script = Script { name: name.to_string(), path, source: source.into(), version, owner };
+    match registry.iter_mut().find(|s| s.name == name) {
+        Some(existing) => *existing = script,
+        None => registry.push(script),
+    }
+    version
+}
+
+/// Adds or replaces `name` with the contents of `path`. Returns the new version.
+pub fn load_file(name: &str, path: &Path) -> std::io::Result<u64> {
+    let source = fs::read_to_string(path)?;
+    Ok(upsert(name, Some(path.to_path_buf()), source, None))
+}
+
+/// Like `load_file`, for an untrusted script of mod `owner`: it runs in a
+/// `restricted_env` and its globals go to `owner`'s env (`exec_restricted`).
+pub fn load_file_restricted(owner: &str, name: &str, path: &Path) -> std::io::Result<u64> {
+    let source = fs::read_to_string(path)?;
+    Ok(upsert(name, Some(path.to_path_buf()), source, Some(owner.to_string())))
+}
+
+/// Removes every script of mod `owner` along with its host bindings. VMs
+/// drop the globals those scripts published, the mod's env and its host
+/// table (and with it whatever the bindings put there) on their next sync.
+pub fn unload_owner(owner: &str) {
+    let mut registry = REGISTRY.write().unwrap();
+    registry.retain(|s| s.owner.as_deref() != Some(owner));
+    clear_host_bindings(owner);
+    DEPENDENCIES.write().unwrap().retain(|(o, _)| o != owner);
+    VERSION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Adds or replaces `name` with an in-memory source. Returns the new version.
+pub fn set_source(name: &str, source: impl Into<String>) -> u64 {
+    upsert(name, None, source.into(), None)
+}
+
+/// Re-reads file-backed scripts; returns (name, new version) for each one
+/// whose contents changed. Unreadable files keep their last good source.
+pub fn poll_changes() -> Vec<(String, u64)> {
+    let candidates: Vec<(String, PathBuf, Arc<str>, Option<String>)> = REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .filter_map(|s| s.path.clone().map(|p| (s.name.clone(), p, s.source.clone(), s.owner.clone())))
+        .collect();
+
+    let mut changed = Vec::new();
+    for (name, path, old, owner) in candidates {
+        if let Ok(source) = fs::read_to_string(&path) {
+            if *source != *old {
+                changed.push((name.clone(), upsert(&name, Some(path), source, owner.clone())));
+            }
+        }
+    }
+    changed
+}
+
+/// Global version; bumps on every script change.
+pub fn version() -> u64 {
+    VERSION.load(Ordering::SeqCst)
+}
+
+pub fn script_version(name: &str) -> Option<u64> {
+    REGISTRY.read().unwrap().iter().find(|s| s.name == name).map(|s| s.version)
+}
+
+/// (version, source) of `name`, for VMs that aren't built by `with_vm`
+/// (the Piccolo VMs in `lua_udf_threadlocal.rs` and `piccolo_batch.rs`).
+pub fn script_source(name: &str) -> Option<(u64, Arc<str>)> {
+    REGISTRY.read().unwrap().iter().find(|s| s.name == name).map(|s| (s.version, s.source.clone()))
+}
+
+/// Drains the reload failures recorded by all VMs so far.
+pub fn take_errors() -> Vec<ReloadError> {
+    std::mem::take(&mut *ERRORS.lock().unwrap())
+}
+
+/// VMs created from now on get the `ffi` library. Existing VMs keep
+/// whatever they were created with.
+pub fn enable_ffi() {
+    FFI.store(true, Ordering::SeqCst);
+}
+
+/// (VMs created, successful script reloads) across all threads.
+pub fn vm_stats() -> (usize, usize) {
+    (VMS_CREATED.load(Ordering::Relaxed), RELOADS.load(Ordering::Relaxed))
+}
+
+// ============================================================================
+// Thread-local VM
+// ============================================================================
+
+struct ThreadVm {
+    lua: Lua,
+    /// Global version this VM last synced to.
+    synced: u64,
+    /// Script version last attempted, successful or not.
+    attempted: HashMap<String, u64>,
+    /// Globals each script's last successful version published.
+    published: HashMap<String, Vec<(Value, Value)>>,
+    /// Owning mod of each restricted script attempted.
+    owners: HashMap<String, String>,
+}
+
+thread_local! {
+    static VM: RefCell<Option<ThreadVm>> = const { RefCell::new(None) };
+}
+
+/// Standard globals a restricted script may use: no file, process, FFI or
+/// module access, and no way back to the real environment (`getfenv`,
+/// `load*`, `_G`). Every `restricted_env` gets its own copies of the
+/// tables, and a `getmetatable` that refuses strings, so a mod can't patch
+/// what other scripts (or every string's methods) use.
+pub const RESTRICTED_STDLIB: &[&str] = &[
+    "assert", "error", "ipairs", "next", "pairs", "pcall", "print", "rawequal", "rawget", "rawset", "select",
+    "getmetatable", "setmetatable", "tonumber", "tostring", "type", "unpack", "xpcall", "_VERSION", "math",
+    "string", "table",
+];
+
+const RESTRICTED_FACTORY: &str = "lua_scripts.restricted_env";
+/// owner -> that mod's env: the globals its scripts published.
+const MOD_ENVS: &str = "lua_scripts.mod_envs";
+/// Global name -> the mod that published it.
+const MOD_GLOBALS: &str = "lua_scripts.mod_globals";
+/// owner -> { generation, host table } (see `host_table`).
+const MOD_HOSTS: &str = "lua_scripts.mod_hosts";
+
+fn registry_table(lua: &Lua, key: &str) -> mlua::Result<Table> {
+    if let Some(t) = lua.named_registry_value::<Option<Table>>(key)? {
+        return Ok(t);
+    }
+    let t = lua.create_table()?;
+    lua.set_named_registry_value(key, &t)?;
+    Ok(t)
+}
+
+/// A fresh environment table for an untrusted script. Reads see only the
+/// `RESTRICTED_STDLIB` globals, as they were the first time this is called
+/// on `lua` (`ThreadVm::new` calls it before loading any script). Nothing
+/// else in `_G` is reachable, nor is any mod's env; `exec_restricted` adds
+/// the envs of the script's mod and its dependencies.
+pub fn restricted_env(lua: &Lua) -> mlua::Result<Table> {
+    new_restricted_env(lua, None, &[])
+}
+
+/// `restricted_env`, plus `host`'s entries read like standard globals, and
+/// published globals only from the mods named in `visible`.
+fn new_restricted_env(lua: &Lua, host: Option<Table>, visible: &[&str]) -> mlua::Result<Table> {
+    let factory = match lua.named_registry_value::<Option<mlua::Function>>(RESTRICTED_FACTORY)? {
+        Some(f) => f,
+        None => {
+            let f: mlua::Function = lua
+                .load(
+                    r#"
+                    local names, envs, owners = ...
+                    local G, type, pairs, getmetatable, setmetatable, error =
+                      _G, type, pairs, getmetatable, setmetatable, error
+                    local std = {}
+                    for _, k in ipairs(names) do std[k] = G[k] end
+                    local function guarded_getmetatable(v)
+                      if type(v) == "string" then error("getmetatable: not available on strings", 2) end
+                      return getmetatable(v)
+                    end
+                    return function(host, visible)
+                      local allowed = {}
+                      for k, v in pairs(std) do
+                        if type(v) == "table" then
+                          local copy = {}
+                          for name, f in pairs(v) do copy[name] = f end
+                          v = copy
+                        end
+                        allowed[k] = v
+                      end
+                      allowed.getmetatable = guarded_getmetatable
+                      if host ~= nil then
+                        for k, v in pairs(host) do allowed[k] = v end
+                      end
+                      return setmetatable({}, { __index = function(_, k)
+                        local v = allowed[k]
+                        if v ~= nil then return v end
+                        local owner = owners[k]
+                        if owner ~= nil and visible[owner] then return envs[owner][k] end
+                      end, __metatable = false })
+                    end
+                    "#,
+                )
+                .set_name("lua_scripts/restricted_env")
+                .call((RESTRICTED_STDLIB.to_vec(), registry_table(lua, MOD_ENVS)?, registry_table(lua, MOD_GLOBALS)?))?;
+            lua.set_named_registry_value(RESTRICTED_FACTORY, &f)?;
+            f
+        }
+    };
+    let visible = lua.create_table_from(visible.iter().map(|o| (*o, true)))?;
+    factory.call((host, visible))
+}
+
+/// Lets mod `owner`'s scripts read what the mods in `dependencies` (its
+/// transitive ones) publish. Without this they see only their own mod's
+/// globals. Like host bindings, it has to be set before they are loaded.
+pub fn set_dependencies(owner: &str, dependencies: Vec<String>) {
+    let mut deps = DEPENDENCIES.write().unwrap();
+    deps.retain(|(o, _)| o != owner);
+    deps.push((owner.to_string(), dependencies));
+}
+
+/// Gives mod `owner`'s scripts what `install` puts in its table. Each VM
+/// builds that table once, before it runs one of the mod's scripts, so this
+/// has to be set before they are loaded; setting it again only reaches VMs
+/// that run the scripts again.
+pub fn set_host_bindings(owner: &str, install: HostBindings) {
+    let generation = HOST_GENERATION.fetch_add(1, Ordering::SeqCst);
+    let mut hosts = HOSTS.write().unwrap();
+    hosts.retain(|(o, _, _)| o != owner);
+    hosts.push((owner.to_string(), generation, install));
+}
+
+pub fn clear_host_bindings(owner: &str) {
+    HOSTS.write().unwrap().retain(|(o, _, _)| o != owner);
+}
+
+/// This VM's host table for `owner`, built by its current `HostBindings` the
+/// first time it's needed. None if the host gives the mod nothing.
+fn host_table(lua: &Lua, owner: &str) -> mlua::Result<Option<Table>> {
+    let found = HOSTS.read().unwrap().iter().find(|(o, _, _)| o == owner).map(|(_, g, f)| (*g, f.clone()));
+    let Some((generation, install)) = found else {
+        return Ok(None);
+    };
+    let cache = registry_table(lua, MOD_HOSTS)?;
+    if let Some(entry) = cache.raw_get::<Option<Table>>(owner)? {
+        if entry.raw_get::<u64>(1)? == generation {
+            return entry.raw_get(2);
+        }
+    }
+    let host = lua.create_table()?;
+    install(lua, &host)?;
+    cache.raw_set(owner, lua.create_sequence_from([Value::Integer(generation as _), Value::Table(host.clone())])?)?;
+    Ok(Some(host))
+}
+
+/// What mod `owner`'s scripts published as `name`, or nil. Mod UDFs are
+/// resolved through this rather than `_G`.
+pub fn restricted_global<V: mlua::FromLua>(lua: &Lua, owner: &str, name: &str) -> mlua::Result<V> {
+    match registry_table(lua, MOD_ENVS)?.raw_get::<Option<Table>>(owner)? {
+        Some(env) => env.raw_get(name),
+        None => V::from_lua(Value::Nil, lua),
+    }
+}
+
+/// Runs a trusted script in a scratch environment over `_G` and publishes
+/// its globals only if it completes. Globals in `previous` (what the last
+/// version published) that the new version doesn't define are set to nil if
+/// they still hold the old value. Returns what this version published.
+fn exec_isolated(lua: &Lua, name: &str, source: &str, previous: &[(Value, Value)]) -> mlua::Result<Vec<(Value, Value)>> {
+    let env: Table = lua.load("return setmetatable({}, { __index = _G })").eval()?;
+    lua.load(source).set_name(name).set_environment(env.clone()).exec()?;
+    let globals = lua.globals();
+    let mut published = Vec::new();
+    for pair in env.pairs::<Value, Value>() {
+        let (k, v) = pair?;
+        globals.raw_set(k.clone(), v.clone())?;
+        published.push((k, v));
+    }
+    retract(&globals, None, previous, &published)?;
+    Ok(published)
+}
+
+/// Sets the globals in `previous` that `published` doesn't define to nil in
+/// `target`, if they still hold the old value; `owners` (a mod's
+/// `MOD_GLOBALS`) loses them too.
+fn retract(target: &Table, owners: Option<&Table>, previous: &[(Value, Value)], published: &[(Value, Value)]) -> mlua::Result<()> {
+    for (k, old) in previous {
+        if !published.iter().any(|(n, _)| n == k) && target.raw_get::<Value>(k.clone())? == *old {
+            target.raw_set(k.clone(), Value::Nil)?;
+            if let Some(owners) = owners {
+                owners.raw_set(k.clone(), Value::Nil)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `exec_isolated` for an untrusted script of mod `owner`: it runs in a
+/// `restricted_env` with `owner`'s host bindings, sees the globals of
+/// `owner` and its `set_dependencies` only, and publishes into `owner`'s
+/// env, never `_G`. A global
+/// named like a `RESTRICTED_STDLIB` entry or already published by another
+/// mod fails the script, before anything is published.
+pub fn exec_restricted(
+    lua: &Lua,
+    owner: &str,
+    name: &str,
+    source: &str,
+    previous: &[(Value, Value)],
+) -> mlua::Result<Vec<(Value, Value)>> {
+    let deps = DEPENDENCIES.read().unwrap().iter().find(|(o, _)| o == owner).map(|(_, d)| d.clone()).unwrap_or_default();
+    let visible: Vec<&str> = deps.iter().map(String::as_str).chain(std::iter::once(owner)).collect();
+    let env = new_restricted_env(lua, host_table(lua, owner)?, &visible)?;
+    lua.load(source).set_name(name).set_environment(env.clone()).exec()?;
+    let owners = registry_table(lua, MOD_GLOBALS)?;
+    let published: Vec<(Value, Value)> = env.pairs::<Value, Value>().collect::<mlua::Result<_>>()?;
+    for (k, _) in &published {
+        let key = k.to_string()?;
+        if RESTRICTED_STDLIB.contains(&key.as_str()) {
+            return Err(lua_err(format!("{}: `{}` is a standard global and can't be redefined", name, key)));
+        }
+        if let Some(other) = owners.raw_get::<Option<String>>(k.clone())? {
+            if other != owner {
+                return Err(lua_err(format!("{}: global `{}` belongs to mod `{}`", name, key, other)));
+            }
+        }
+    }
+    let envs = registry_table(lua, MOD_ENVS)?;
+    let own = match envs.raw_get::<Option<Table>>(owner)? {
+        Some(t) => t,
+        None => {
+            let t = lua.create_table()?;
+            envs.raw_set(owner, &t)?;
+            t
+        }
+    };
+    for (k, v) in &published {
+        own.raw_set(k.clone(), v.clone())?;
+        owners.raw_set(k.clone(), owner)?;
+    }
+    retract(&own, Some(&owners), previous, &published)?;
+    Ok(published)
+}
+
+impl ThreadVm {
+    fn new() -> Self {
+        VMS_CREATED.fetch_add(1, Ordering::Relaxed);
+        let lua = if FFI.load(Ordering::SeqCst) { unsafe { Lua::unsafe_new() } } else { Lua::new() };
+        // Snapshot the standard globals before any script adds its own.
+        restricted_env(&lua).expect("restricted_env on a fresh VM");
+        ThreadVm {
+            lua,
+            synced: u64::MAX,
+            attempted: HashMap::new(),
+            published: HashMap::new(),
+            owners: HashMap::new(),
+        }
+    }
+
+    fn sync(&mut self) {
+        let current = version();
+        if current == self.synced {
+            return;
+        }
+        let scripts: Vec<Script> = REGISTRY.read().unwrap().clone();
+        // Removed scripts first, so a mod loaded in their place can take the
+        // global names they held.
+        if let Err(e) = self.drop_removed(&scripts) {
+            ERRORS.lock().unwrap().push(ReloadError {
+                script: "<unload>".into(),
+                version: current,
+                thread: format!("{:?}", std::thread::current().id()),
+                message: e.to_string(),
+            });
+        }
+        for script in scripts {
+            if self.attempted.get(&script.name) == Some(&script.version) {
+                continue;
+            }
+            self.attempted.insert(script.name.clone(), script.version);
+            if let Some(owner) = &script.owner {
+                self.owners.insert(script.name.clone(), owner.clone());
+            }
+            let previous = self.published.get(&script.name).map(Vec::as_slice).unwrap_or_default();
+            let (name, source) = (script.name.as_str(), &*script.source);
+            let executed = match &script.owner {
+                Some(owner) => exec_restricted(&self.lua, owner, name, source, previous),
+                None => exec_isolated(&self.lua, name, source, previous),
+            };
+            match executed {
+                Ok(published) => {
+                    RELOADS.fetch_add(1, Ordering::Relaxed);
+                    self.published.insert(script.name.clone(), published);
+                }
+                Err(e) => ERRORS.lock().unwrap().push(ReloadError {
+                    script: script.name.clone(),
+                    version: script.version,
+                    thread: format!("{:?}", std::thread::current().id()),
+                    message: e.to_string(),
+                }),
+            }
+        }
+        self.synced = current;
+    }
+
+    /// Clears what scripts no longer in `scripts` published, and forgets the
+    /// env, globals and host table of every mod left with no scripts.
+    fn drop_removed(&mut self, scripts: &[Script]) -> mlua::Result<()> {
+        let names: HashSet<&str> = scripts.iter().map(|s| s.name.as_str()).collect();
+        let removed: Vec<String> = self.attempted.keys().filter(|n| !names.contains(n.as_str())).cloned().collect();
+        for name in removed {
+            self.attempted.remove(&name);
+            let previous = self.published.remove(&name).unwrap_or_default();
+            match self.owners.remove(&name) {
+                Some(owner) => {
+                    if let Some(env) = registry_table(&self.lua, MOD_ENVS)?.raw_get::<Option<Table>>(owner)? {
+                        retract(&env, Some(&registry_table(&self.lua, MOD_GLOBALS)?), &previous, &[])?;
+                    }
+                }
+                None => retract(&self.lua.globals(), None, &previous, &[])?,
+            }
+        }
+
+        let live: HashSet<&str> = scripts.iter().filter_map(|s| s.owner.as_deref()).collect();
+        for key in [MOD_ENVS, MOD_HOSTS, MOD_GLOBALS] {
+            // MOD_ENVS and MOD_HOSTS are keyed by owner, MOD_GLOBALS maps to one.
+            let table = registry_table(&self.lua, key)?;
+            let mut gone = Vec::new();
+            for pair in table.pairs::<Value, Value>() {
+                let (k, v) = pair?;
+                let owner = if key == MOD_GLOBALS { &v } else { &k };
+                if !owner.as_str().is_some_and(|o| live.contains(&*o)) {
+                    gone.push(k);
+                }
+            }
+            for k in gone {
+                table.raw_set(k, Value::Nil)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f` on this thread's VM after bringing it up to the current script
+/// version. Costs one atomic load when nothing changed.
+pub fn with_vm<R>(f: impl FnOnce(&Lua) -> mlua::Result<R>) -> mlua::Result<R> {
+    VM.with(|cell| {
+        let mut slot = cell.borrow_mut();
+        let vm = slot.get_or_insert_with(ThreadVm::new);
+        vm.sync();
+        f(&vm.lua)
+    })
+}
+
+// ============================================================================
+// Shared VMs
+// ============================================================================
+
+/// A VM synced like `with_vm`'s but kept behind a mutex instead of in a
+/// thread-local, for values that have to stay reachable from whichever
+/// thread DuckDB hands them to next (`lua_aggregates` keeps its per-group
+/// states in these).
+pub struct SharedVm(Mutex<ThreadVm>);
+
+// `Lua` is `!Send` because its handles share non-atomic reference counts.
+// `with` and `with_pair` are `unsafe` so that their callers promise no
+// handle outlives the call; the VM then only ever moves between threads as
+// a whole, under its mutex.
+unsafe impl Send for SharedVm {}
+unsafe impl Sync for SharedVm {}
+
+impl SharedVm {
+    pub fn new() -> Self {
+        SharedVm(Mutex::new(ThreadVm::new()))
+    }
+
+    /// Runs `f` on this VM after bringing it up to the current script version.
+    ///
+    /// # Safety
+    ///
+    /// No Lua handle (`Value`, `Table`, `Function`, `LuaString`, ...) made
+    /// from `f`'s `&Lua` may outlive the call: not in the result, not in a
+    /// captured variable, not in a thread-local. Keep values in the VM's
+    /// registry and hand out `RegistryKey`s or plain data instead.
+    pub unsafe fn with<R: Send>(&self, f: impl FnOnce(&Lua) -> mlua::Result<R>) -> mlua::Result<R> {
+        let mut vm = self.0.lock().unwrap();
+        vm.sync();
+        f(&vm.lua)
+    }
+
+    /// Runs `f` with `a` and `b` both locked, for moving values between
+    /// them. They are locked in address order, so two threads pairing the
+    /// same VMs can't deadlock. `a` and `b` must be different VMs.
+    ///
+    /// # Safety
+    ///
+    /// As for `with`, for handles from either VM.
+    pub unsafe fn with_pair<R: Send>(a: &SharedVm, b: &SharedVm, f: impl FnOnce(&Lua, &Lua) -> mlua::Result<R>) -> mlua::Result<R> {
+        assert!(!std::ptr::eq(a, b), "SharedVm::with_pair on one VM");
+        let a_first = (a as *const SharedVm) < (b as *const SharedVm);
+        let first = if a_first { a } else { b }.0.lock().unwrap();
+        let second = if a_first { b } else { a }.0.lock().unwrap();
+        let (mut a, mut b) = if a_first { (first, second) } else { (second, first) };
+        a.sync();
+        b.sync();
+        f(&a.lua, &b.lua)
+    }
+}
+
+impl Default for SharedVm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ============================================================================
+// SQL names for Lua definitions
+// ============================================================================
+
+/// Refuses `name` if DuckDB already has a function by that name, unless it
+/// is a macro over `bridge` (an earlier load of the same definition). The
+/// bridges use `CREATE OR REPLACE MACRO`, which would otherwise silently
+/// replace built-ins and other extensions' functions.
+pub fn check_macro_name(conn: &Connection, name: &str, bridge: &str) -> duckdb::Result<()> {
+    let foreign: i64 = conn.query_row(
+        "SELECT count(*) FROM duckdb_functions()
+         WHERE function_name = lower($1)
+           AND NOT (function_type IN ('macro', 'table_macro') AND macro_definition LIKE '%' || $2 || '(%')",
+        [name, bridge],
+        |r| r.get(0),
+    )?;
+    if foreign > 0 {
+        return Err(duckdb::Error::InvalidParameterName(format!(
+            "`{}` is already a DuckDB function; pick another name for the Lua definition",
+            name
+        )));
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Bridges: one SQL macro per Lua definition
+// ============================================================================
+
+/// A kind of Lua definition exposed to SQL through one registered function
+/// (`lua_aggregate`, `lua_table`) and a macro per definition over it.
+pub trait Bridge {
+    /// What `read` makes of one definition.
+    type Def;
+    /// The registered DuckDB function the macros call.
+    const FUNCTION: &'static str;
+    /// Field the prelude's constructor sets on every definition table.
+    const MARKER: &'static str;
+    /// Used in error messages: "aggregate", "table function".
+    const KIND: &'static str;
+    const PRELUDE_NAME: &'static str;
+    const PRELUDE: &'static str;
+
+    fn register(conn: &Connection) -> duckdb::Result<()>;
+    /// Checks one definition table.
+    fn read(def: &Table) -> Result<Self::Def, String>;
+    fn create_macro(conn: &Connection, name: &str, def: &Self::Def) -> duckdb::Result<()>;
+}
+
+/// Every definition a script makes, as (name, `Bridge::read` result).
+pub type Defs<B> = Vec<(String, <B as Bridge>::Def)>;
+
+pub fn valid_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn lua_err(message: String) -> mlua::Error {
+    mlua::Error::RuntimeError(message)
+}
+
+/// The function `source` returns, compiled once per VM and kept in the named
+/// registry under `key`. For bridge helpers that take raw pointers: scripts
+/// can't reach the registry, so only Rust can call them.
+pub fn host_function(lua: &Lua, key: &str, source: &str) -> mlua::Result<mlua::Function> {
+    if let Some(f) = lua.named_registry_value::<Option<mlua::Function>>(key)? {
+        return Ok(f);
+    }
+    let f: mlua::Function = lua.load(source).set_name(key).eval()?;
+    lua.set_named_registry_value(key, &f)?;
+    Ok(f)
+}
+
+/// Enables FFI for new VMs, loads `B`'s prelude and registers `B::FUNCTION`.
+pub fn install<B: Bridge>(conn: &Connection) -> duckdb::Result<()> {
+    enable_ffi();
+    if script_version(B::PRELUDE_NAME).is_none() {
+        set_source(B::PRELUDE_NAME, B::PRELUDE);
+    }
+    let exists: bool = conn.query_row(
+        "SELECT count(*) > 0 FROM duckdb_functions() WHERE function_name = $1",
+        [B::FUNCTION],
+        |r| r.get(0),
+    )?;
+    if !exists {
+        B::register(conn)?;
+    }
+    Ok(())
+}
+
+/// Runs `source` after `B`'s prelude in a scratch VM; returns every
+/// definition it makes, sorted by name.
+pub fn discover<B: Bridge>(script: &str, source: &str) -> Result<Defs<B>, Box<dyn Error>> {
+    let scratch = unsafe { Lua::unsafe_new() };
+    scratch.load(B::PRELUDE).set_name(B::PRELUDE_NAME).exec()?;
+    scratch.load(source).set_name(script).exec()?;
+    let mut found = Vec::new();
+    for pair in scratch.globals().pairs::<Value, Value>() {
+        let (Value::String(name), Value::Table(def)) = pair? else { continue };
+        if !def.raw_get::<bool>(B::MARKER).unwrap_or(false) {
+            continue;
+        }
+        let name = name.to_str()?.to_string();
+        if !valid_ident(&name) {
+            return Err(format!("{} name {:?} is not a SQL identifier", B::KIND, name).into());
+        }
+        let def = B::read(&def).map_err(|e| format!("{} `{}`: {}", B::KIND, name, e))?;
+        found.push((name, def));
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(found)
+}
+
+fn define<B: Bridge>(conn: &Connection, script: &str, source: &str, path: Option<&Path>) -> Result<Defs<B>, Box<dyn Error>> {
+    install::<B>(conn)?;
+    let found = discover::<B>(script, source)?;
+    // Check every name before loading, so a clash leaves nothing half-defined
+    for (name, _) in &found {
+        check_macro_name(conn, name, B::FUNCTION)?;
+    }
+    match path {
+        Some(path) => {
+            load_file(script, path)?;
+        }
+        None => {
+            set_source(script, source);
+        }
+    }
+    for (name, def) in &found {
+        B::create_macro(conn, name, def)?;
+    }
+    Ok(found)
+}
+
+/// Loads an in-memory script and creates a macro per `B` definition in it.
+pub fn define_source<B: Bridge>(conn: &Connection, script: &str, source: &str) -> Result<Defs<B>, Box<dyn Error>> {
+    define::<B>(conn, script, source, None)
+}
+
+/// Like `define_source`, with the script file-backed (see `poll_changes`).
+pub fn define_file<B: Bridge>(conn: &Connection, script: &str, path: &Path) -> Result<Defs<B>, Box<dyn Error>> {
+    let source = fs::read_to_string(path)?;
+    define::<B>(conn, script, &source, Some(path))
+}
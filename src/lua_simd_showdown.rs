@@ -3,6 +3,8 @@
 //! Can we beat DuckDB's internal SIMD by using Lua as a thin dispatch layer
 //! to hand-optimized Rust SIMD code?
 
+mod simd_kernels;
+
 use duckdb::Connection;
 use piccolo::{Callback, CallbackReturn, Closure, Executor, FromValue, Function, Lua, StashedExecutor};
 use mlua::{Lua as MluaLua, Function as MluaFunction, Result as MluaResult};
@@ -10,81 +12,15 @@ use std::cell::RefCell;
 use std::time::Instant;
 
 // ============================================================
-// Rust SIMD distance calculations (using std::arch intrinsics)
+// Rust SIMD distance calculations (simd_kernels.rs, runtime dispatch)
 // ============================================================
 
-#[cfg(target_arch = "x86_64")]
-use std::arch::x86_64::*;
-
-/// Batch distance calculation using AVX2 (processes 4 pairs at once)
-#[cfg(target_arch = "x86_64")]
-#[target_feature(enable = "avx2")]
-unsafe fn simd_distances_avx2(
-    x1: &[f64], y1: &[f64],
-    x2: &[f64], y2: &[f64],
-    results: &mut [f64],
-) {
-    let n = x1.len();
-    let chunks = n / 4;
-    
-    for i in 0..chunks {
-        let base = i * 4;
-        
-        // Load 4 x1, y1, x2, y2 values
-        let vx1 = _mm256_loadu_pd(x1.as_ptr().add(base));
-        let vy1 = _mm256_loadu_pd(y1.as_ptr().add(base));
-        let vx2 = _mm256_loadu_pd(x2.as_ptr().add(base));
-        let vy2 = _mm256_loadu_pd(y2.as_ptr().add(base));
-        
-        // dx = x2 - x1, dy = y2 - y1
-        let dx = _mm256_sub_pd(vx2, vx1);
-        let dy = _mm256_sub_pd(vy2, vy1);
-        
-        // dist_sq = dx*dx + dy*dy
-        let dx2 = _mm256_mul_pd(dx, dx);
-        let dy2 = _mm256_mul_pd(dy, dy);
-        let dist_sq = _mm256_add_pd(dx2, dy2);
-        
-        // sqrt for actual distance
-        let dist = _mm256_sqrt_pd(dist_sq);
-        
-        _mm256_storeu_pd(results.as_mut_ptr().add(base), dist);
-    }
-    
-    // Handle remainder
-    for i in (chunks * 4)..n {
-        let dx = x2[i] - x1[i];
-        let dy = y2[i] - y1[i];
-        results[i] = (dx * dx + dy * dy).sqrt();
-    }
-}
-
-/// Scalar fallback
-fn simd_distances_scalar(
-    x1: &[f64], y1: &[f64],
-    x2: &[f64], y2: &[f64],
-    results: &mut [f64],
-) {
-    for i in 0..x1.len() {
-        let dx = x2[i] - x1[i];
-        let dy = y2[i] - y1[i];
-        results[i] = (dx * dx + dy * dy).sqrt();
-    }
-}
-
 fn batch_distances(
     x1: &[f64], y1: &[f64],
     x2: &[f64], y2: &[f64],
     results: &mut [f64],
 ) {
-    #[cfg(target_arch = "x86_64")]
-    {
-        if is_x86_feature_detected!("avx2") {
-            unsafe { simd_distances_avx2(x1, y1, x2, y2, results) };
-            return;
-        }
-    }
-    simd_distances_scalar(x1, y1, x2, y2, results);
+    simd_kernels::kernels().distances(x1, y1, x2, y2, results);
 }
 
 // ============================================================
@@ -178,11 +114,7 @@ fn reset_piccolo() {
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("=== WILD EXPERIMENT: Lua UDF → Rust SIMD vs DuckDB SIMD ===\n");
     
-    #[cfg(target_arch = "x86_64")]
-    {
-        println!("AVX2 available: {}", is_x86_feature_detected!("avx2"));
-        println!("AVX available: {}", is_x86_feature_detected!("avx"));
-    }
+    println!("SIMD kernels: {:?}", simd_kernels::kernels().level);
     println!();
 
     for n in [1000, 5000, 10000, 50000] {
@@ -352,7 +284,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     println!("=== Analysis ===\n");
-    println!("  Pure Rust SIMD:   Direct AVX2, zero overhead");
+    println!("  Pure Rust SIMD:   Dispatched kernel, zero overhead");
     println!("  LuaJIT→SIMD:      ~1µs call overhead (JIT-compiled dispatch)");
     println!("  Piccolo→SIMD:     ~3µs call overhead (interpreter)");
     println!("  DuckDB SIMD:      ~60-300µs overhead (query engine)");
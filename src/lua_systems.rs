@@ -0,0 +1,494 @@
+//! Lua-Defined ECS Systems over FFI Column Views
+//!
+//! Shared by bins via `mod lua_systems;` (needs `mod ffi_columns;` and
+//! `mod lua_scripts;`). Until now Lua only ran as scalar UDFs inside SQL.
+//! Here a script registers whole systems:
+//!
+//!   system{ name = "regen", reads = { "health" }, writes = { "health" },
+//!           run = function(view, dt)
+//!             local h = view.health
+//!             for i = 0, view.n - 1 do h.hp[i] = math.min(h.hp[i] + dt, h.max_hp[i]) end
+//!           end }
+//!
+//! Components are tables keyed by `id`; a system sees every entity that has
+//! all of its `reads` and `writes` components, sorted by id. Each tick,
+//! `LuaScheduler::run_tick` runs the systems in registration order:
+//! 1. `SystemWorld::gather` copies the matching columns (as DOUBLE) into
+//!    contiguous Rust buffers
+//! 2. `run(view, dt)` gets LuaJIT FFI pointers to them: `view.id` and the
+//!    read components are `const`, so writing them raises a Lua error
+//! 3. `SystemWorld::flush` writes back only the cells that changed: per
+//!    column, the rows whose value differs bitwise from the gathered one
+//!
+//! Only columns a double holds exactly are in a view (DOUBLE, FLOAT and
+//! integers up to 32 bits); VARCHAR, BIGINT, DECIMAL and the like are left
+//! out and never written. A NULL reads as NaN and stays NULL unless the
+//! system stores a different value in that cell, so NaN arithmetic on a NULL
+//! (`h.hp[i] + dt`) keeps it NULL, like SQL.
+//!
+//! `DuckDbWorld` flushes each changed column as one Arrow batch through
+//! `ffi_columns::ColumnBuffers::write_duckdb`; `PolarsWorld` through one left
+//! join per component.
+//! Views point into buffers owned by Rust and are only valid during `run`.
+//!
+//! A `LuaScheduler` runs trusted scripts only. Its VM has the full standard
+//! library plus `ffi`, and systems get raw column pointers, so a script can
+//! do anything the process can. Never `load_script` a mod's files into one.
+//! Mod scripts go through `mod_loader::register_mods`, which runs them in
+//! `lua_scripts`' restricted envs, and a mod's `systems` are SQL checked
+//! against its grants for a `SystemRunner`.
+
+#![allow(dead_code)]
+
+use duckdb::arrow::array::{Array, Float64Array, Int64Array};
+use crate::ffi_columns::{ffi_view, ColumnBuffers, F64Column};
+use crate::lua_scripts::valid_ident;
+use duckdb::Connection;
+use mlua::{Function, Lua, Table};
+use polars::prelude::*;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+const PRELUDE: &str = r#"
+__lua_systems = {}
+
+function system(def)
+  assert(type(def) == "table", "system{} takes a table")
+  assert(type(def.name) == "string", "system{} needs a name")
+  assert(type(def.run) == "function", "system '" .. def.name .. "' needs run = function(view, dt)")
+  __lua_systems[#__lua_systems + 1] = def
+end
+"#;
+
+// ============================================================================
+// Column views
+// ============================================================================
+
+pub struct ComponentColumns {
+    pub component: String,
+    pub writable: bool,
+    /// (column, values), one value per matched entity.
+    pub columns: Vec<(String, Vec<f64>)>,
+    /// Copy of `columns` taken at gather time, for writable components.
+    original: Vec<Vec<f64>>,
+}
+
+pub struct ColumnView {
+    pub ids: Vec<i64>,
+    pub components: Vec<ComponentColumns>,
+}
+
+impl ColumnView {
+    fn new(ids: Vec<i64>, components: Vec<ComponentColumns>) -> Self {
+        let mut view = ColumnView { ids, components };
+        for c in view.components.iter_mut().filter(|c| c.writable) {
+            c.original = c.columns.iter().map(|(_, v)| v.clone()).collect();
+        }
+        view
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Rows where column `k` of `c` changed, compared bitwise so NaN (and
+    /// so NULL) and -0.0 round-trip without spurious writes.
+    pub fn changed_rows(&self, c: &ComponentColumns, k: usize) -> Vec<usize> {
+        let (now, before) = (&c.columns[k].1, &c.original[k]);
+        (0..self.len()).filter(|&i| now[i].to_bits() != before[i].to_bits()).collect()
+    }
+
+    /// `{ n, id = const int64_t*, <component> = { <col> = double* } }`,
+    /// built with `ffi_columns::ffi_view`; read-only components get `const
+    /// double*`. Valid until `self` is dropped or its buffers reallocated.
+    fn lua_view(&mut self, lua: &Lua) -> mlua::Result<Table> {
+        let n = self.ids.len();
+        let view = ffi_view(lua, Some(n), Some(("id", &mut self.ids)), [])?;
+        for c in &mut self.components {
+            let writable = c.writable;
+            let columns = c.columns.iter_mut().map(|(name, values)| (name.as_str(), values.as_mut_slice(), writable));
+            view.set(c.component.as_str(), ffi_view(lua, None, None, columns)?)?;
+        }
+        Ok(view)
+    }
+}
+
+/// Components in view order: reads, then writes not already listed.
+fn component_list(reads: &[String], writes: &[String]) -> Vec<(String, bool)> {
+    let mut out: Vec<(String, bool)> = Vec::new();
+    for name in reads.iter().chain(writes) {
+        if !out.iter().any(|(n, _)| n == name) {
+            out.push((name.clone(), writes.contains(name)));
+        }
+    }
+    out
+}
+
+pub trait SystemWorld {
+    fn backend(&self) -> &'static str;
+    /// Columns of every entity that has all of `reads` and `writes`, sorted by id.
+    fn gather(&mut self, reads: &[String], writes: &[String]) -> Result<ColumnView, Box<dyn Error>>;
+    /// Writes changed cells of the writable components back; returns how many.
+    fn flush(&mut self, view: &ColumnView) -> Result<usize, Box<dyn Error>>;
+}
+
+// ============================================================================
+// DuckDB
+// ============================================================================
+
+pub struct DuckDbWorld<'c> {
+    pub conn: &'c Connection,
+}
+
+/// DuckDB types a double represents exactly.
+const EXACT_IN_F64: &str = "'DOUBLE', 'FLOAT', 'INTEGER', 'SMALLINT', 'TINYINT', 'UINTEGER', 'USMALLINT', 'UTINYINT'";
+
+fn duckdb_columns(conn: &Connection, table: &str) -> duckdb::Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT column_name FROM information_schema.columns
+         WHERE table_schema = 'main' AND table_name = ? AND column_name <> 'id' AND data_type IN ({})
+         ORDER BY ordinal_position",
+        EXACT_IN_F64
+    ))?;
+    let rows = stmt.query_map([table], |r| r.get::<_, String>(0))?;
+    rows.collect()
+}
+
+impl SystemWorld for DuckDbWorld<'_> {
+    fn backend(&self) -> &'static str {
+        "duckdb"
+    }
+
+    fn gather(&mut self, reads: &[String], writes: &[String]) -> Result<ColumnView, Box<dyn Error>> {
+        let comps = component_list(reads, writes);
+        let first = &comps.first().ok_or("system has no components")?.0;
+        let mut select = vec![format!("{}.id::BIGINT", first)];
+        let mut from = first.clone();
+        let mut components = Vec::new();
+        for (i, (comp, writable)) in comps.iter().enumerate() {
+            let names = duckdb_columns(self.conn, comp)?;
+            if names.is_empty() {
+                return Err(format!("component `{}` not found or has no numeric columns", comp).into());
+            }
+            for name in &names {
+                select.push(format!("{}.{}::DOUBLE", comp, name));
+            }
+            if i > 0 {
+                from.push_str(&format!(" JOIN {} USING (id)", comp));
+            }
+            components.push(ComponentColumns {
+                component: comp.clone(),
+                writable: *writable,
+                columns: names.into_iter().map(|n| (n, Vec::new())).collect(),
+                original: Vec::new(),
+            });
+        }
+
+        let sql = format!("SELECT {} FROM {} ORDER BY 1", select.join(", "), from);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut ids = Vec::new();
+        for batch in stmt.query_arrow([])? {
+            let id = batch.column(0).as_any().downcast_ref::<Int64Array>().ok_or("id is not BIGINT")?;
+            ids.extend_from_slice(id.values());
+            let mut col = 1;
+            for c in &mut components {
+                for (_, values) in &mut c.columns {
+                    let array = batch.column(col).as_any().downcast_ref::<Float64Array>().ok_or("column is not DOUBLE")?;
+                    if array.null_count() == 0 {
+                        values.extend_from_slice(array.values());
+                    } else {
+                        values.extend(array.iter().map(|v| v.unwrap_or(f64::NAN)));
+                    }
+                    col += 1;
+                }
+            }
+        }
+        Ok(ColumnView::new(ids, components))
+    }
+
+    fn flush(&mut self, view: &ColumnView) -> Result<usize, Box<dyn Error>> {
+        let mut written = 0;
+        for c in view.components.iter().filter(|c| c.writable) {
+            for (k, (name, values)) in c.columns.iter().enumerate() {
+                let rows = view.changed_rows(c, k);
+                if rows.is_empty() {
+                    continue;
+                }
+                let changed = ColumnBuffers {
+                    key: "id".into(),
+                    ids: rows.iter().map(|&i| view.ids[i]).collect(),
+                    columns: vec![F64Column {
+                        name: name.clone(),
+                        writable: true,
+                        values: rows.iter().map(|&i| values[i]).collect(),
+                    }],
+                };
+                written += changed.write_duckdb(self.conn, &c.component)?;
+            }
+        }
+        Ok(written)
+    }
+}
+
+// ============================================================================
+// Polars
+// ============================================================================
+
+#[derive(Default)]
+pub struct PolarsWorld {
+    /// Component name -> frame with an `id` column.
+    pub tables: BTreeMap<String, DataFrame>,
+}
+
+/// Non-id columns whose type a double represents exactly.
+fn polars_columns(df: &DataFrame) -> Vec<String> {
+    df.get_columns()
+        .iter()
+        .filter(|c| c.name().as_str() != "id")
+        .filter(|c| {
+            matches!(
+                c.dtype(),
+                DataType::Float64 | DataType::Float32 | DataType::Int32 | DataType::Int16 | DataType::Int8
+                    | DataType::UInt32 | DataType::UInt16 | DataType::UInt8
+            )
+        })
+        .map(|c| c.name().to_string())
+        .collect()
+}
+
+impl SystemWorld for PolarsWorld {
+    fn backend(&self) -> &'static str {
+        "polars"
+    }
+
+    fn gather(&mut self, reads: &[String], writes: &[String]) -> Result<ColumnView, Box<dyn Error>> {
+        let comps = component_list(reads, writes);
+        let mut joined: Option<LazyFrame> = None;
+        let mut components = Vec::new();
+        for (comp, writable) in &comps {
+            let df = self.tables.get(comp).ok_or_else(|| format!("component `{}` not found", comp))?;
+            let names = polars_columns(df);
+            if names.is_empty() {
+                return Err(format!("component `{}` has no numeric columns", comp).into());
+            }
+            let mut exprs = vec![col("id").cast(DataType::Int64)];
+            exprs.extend(names.iter().map(|n| {
+                col(n.as_str()).cast(DataType::Float64).fill_null(lit(f64::NAN)).alias(format!("{}.{}", comp, n))
+            }));
+            let frame = df.clone().lazy().select(exprs);
+            joined = Some(match joined {
+                None => frame,
+                Some(acc) => acc.join(frame, [col("id")], [col("id")], JoinArgs::new(JoinType::Inner)),
+            });
+            components.push(ComponentColumns {
+                component: comp.clone(),
+                writable: *writable,
+                columns: names.into_iter().map(|n| (n, Vec::new())).collect(),
+                original: Vec::new(),
+            });
+        }
+        let joined = joined
+            .ok_or("system has no components")?
+            .sort(["id"], SortMultipleOptions::default())
+            .collect()?;
+
+        let ids: Vec<i64> = joined.column("id")?.i64()?.into_no_null_iter().collect();
+        for c in &mut components {
+            for (name, values) in &mut c.columns {
+                let column = joined.column(&format!("{}.{}", c.component, name))?;
+                *values = column.f64()?.into_no_null_iter().collect();
+            }
+        }
+        Ok(ColumnView::new(ids, components))
+    }
+
+    fn flush(&mut self, view: &ColumnView) -> Result<usize, Box<dyn Error>> {
+        let mut written = 0;
+        for c in view.components.iter().filter(|c| c.writable) {
+            // Per column, the rows it changed in; a row goes out if any column changed
+            let per_column: Vec<Vec<usize>> = (0..c.columns.len()).map(|k| view.changed_rows(c, k)).collect();
+            let mut rows: Vec<usize> = per_column.iter().flatten().copied().collect();
+            rows.sort_unstable();
+            rows.dedup();
+            if rows.is_empty() {
+                continue;
+            }
+            let df = self.tables.get(&c.component).ok_or("component disappeared")?;
+            let id_type = df.column("id")?.dtype().clone();
+            let mut update = vec![Column::new("id".into(), rows.iter().map(|&i| view.ids[i]).collect::<Vec<i64>>())];
+            let mut touched = Vec::new();
+            for ((name, values), changed) in c.columns.iter().zip(&per_column) {
+                if changed.is_empty() {
+                    continue;
+                }
+                // NULL where this column kept its value, so the join leaves the cell alone
+                let new: Vec<Option<f64>> =
+                    rows.iter().map(|&i| changed.binary_search(&i).is_ok().then_some(values[i])).collect();
+                update.push(Column::new(format!("__new_{}", name).into(), new));
+                touched.push(name);
+            }
+            let update = DataFrame::new(update)?.lazy().with_column(col("id").cast(id_type));
+
+            let mut exprs = Vec::new();
+            for name in touched {
+                let dtype = df.column(name)?.dtype().clone();
+                let new = format!("__new_{}", name);
+                exprs.push(
+                    when(col(new.as_str()).is_null())
+                        .then(col(name.as_str()))
+                        .otherwise(col(new.as_str()).cast(dtype))
+                        .alias(name.as_str()),
+                );
+            }
+            let keep: Vec<Expr> = df.get_column_names().into_iter().map(|n| col(n.as_str())).collect();
+            let updated = df
+                .clone()
+                .lazy()
+                .join(update, [col("id")], [col("id")], JoinArgs::new(JoinType::Left))
+                .with_columns(exprs)
+                .select(keep)
+                .collect()?;
+            self.tables.insert(c.component.clone(), updated);
+            written += per_column.iter().map(Vec::len).sum::<usize>();
+        }
+        Ok(written)
+    }
+}
+
+// ============================================================================
+// Scheduler
+// ============================================================================
+
+pub struct LuaSystem {
+    pub name: String,
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+    run: Function,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct LuaSystemStats {
+    pub runs: u64,
+    pub rows: u64,
+    pub flushed: u64,
+    pub gather_time: Duration,
+    pub run_time: Duration,
+    pub flush_time: Duration,
+}
+
+pub struct LuaScheduler {
+    lua: Lua,
+    systems: Vec<LuaSystem>,
+    stats: Vec<LuaSystemStats>,
+}
+
+impl LuaScheduler {
+    /// A scheduler with a fresh FFI VM. Only for trusted scripts: nothing
+    /// loaded into it is sandboxed.
+    pub fn new() -> mlua::Result<Self> {
+        // FFI needs the unsafe standard library set.
+        let lua = unsafe { Lua::unsafe_new() };
+        lua.load(PRELUDE).set_name("lua_systems").exec()?;
+        Ok(LuaScheduler { lua, systems: Vec::new(), stats: Vec::new() })
+    }
+
+    pub fn lua(&self) -> &Lua {
+        &self.lua
+    }
+
+    pub fn systems(&self) -> &[LuaSystem] {
+        &self.systems
+    }
+
+    /// Runs a script and picks up the systems it registered. Returns their
+    /// names; on error none of the script's systems are kept.
+    pub fn load_script(&mut self, name: &str, source: &str) -> mlua::Result<Vec<String>> {
+        let registered: Table = self.lua.globals().get("__lua_systems")?;
+        let known = self.systems.len();
+        let result = self.lua.load(source).set_name(name).exec().and_then(|()| self.collect_systems(&registered));
+        if result.is_err() {
+            self.systems.truncate(known);
+            self.stats.truncate(known);
+            for i in (known + 1..=registered.raw_len()).rev() {
+                registered.raw_set(i, mlua::Value::Nil)?;
+            }
+        }
+        result
+    }
+
+    fn collect_systems(&mut self, registered: &Table) -> mlua::Result<Vec<String>> {
+        let mut added = Vec::new();
+        for def in registered.sequence_values::<Table>().skip(self.systems.len()) {
+            let def = def?;
+            let system_name: String = def.get("name")?;
+            let list = |key: &str| -> mlua::Result<Vec<String>> {
+                Ok(def.get::<Option<Vec<String>>>(key)?.unwrap_or_default())
+            };
+            let (reads, writes) = (list("reads")?, list("writes")?);
+            let bad = |message: String| mlua::Error::RuntimeError(format!("system `{}`: {}", system_name, message));
+            if self.systems.iter().any(|s| s.name == system_name) {
+                return Err(bad("registered twice".into()));
+            }
+            if reads.is_empty() && writes.is_empty() {
+                return Err(bad("declares no components".into()));
+            }
+            if let Some(c) = reads.iter().chain(&writes).find(|c| !valid_ident(c)) {
+                return Err(bad(format!("bad component name {:?}", c)));
+            }
+            self.systems.push(LuaSystem { name: system_name.clone(), reads, writes, run: def.get("run")? });
+            self.stats.push(LuaSystemStats::default());
+            added.push(system_name);
+        }
+        Ok(added)
+    }
+
+    /// Runs every system once against `world`: gather, `run(view, dt)`, flush.
+    pub fn run_tick(&mut self, world: &mut dyn SystemWorld, dt: f64) -> Result<(), Box<dyn Error>> {
+        for (system, stats) in self.systems.iter().zip(&mut self.stats) {
+            let backend = world.backend();
+            let fail = |e: &dyn std::fmt::Display| format!("system `{}` ({}): {}", system.name, backend, e);
+
+            let start = Instant::now();
+            let mut view = world.gather(&system.reads, &system.writes).map_err(|e| fail(&e))?;
+            stats.gather_time += start.elapsed();
+
+            let start = Instant::now();
+            let typed = view.lua_view(&self.lua).map_err(|e| fail(&e))?;
+            system.run.call::<()>((typed, dt)).map_err(|e| fail(&e))?;
+            stats.run_time += start.elapsed();
+
+            let start = Instant::now();
+            let flushed = world.flush(&view).map_err(|e| fail(&e))?;
+            stats.flush_time += start.elapsed();
+
+            stats.runs += 1;
+            stats.rows += view.len() as u64;
+            stats.flushed += flushed as u64;
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Vec<(String, LuaSystemStats)> {
+        self.systems.iter().map(|s| s.name.clone()).zip(self.stats.iter().copied()).collect()
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats.iter_mut().for_each(|s| *s = LuaSystemStats::default());
+    }
+
+    pub fn print_report(&self) {
+        println!("  {:<12} {:>5} {:>10} {:>10} {:>11} {:>11} {:>11}",
+                 "system", "runs", "rows/run", "flush/run", "gather", "lua", "flush");
+        for (name, s) in self.stats() {
+            let runs = s.runs.max(1);
+            println!("  {:<12} {:>5} {:>10} {:>10} {:>11.2?} {:>11.2?} {:>11.2?}",
+                     name, s.runs, s.rows / runs, s.flushed / runs,
+                     s.gather_time / runs as u32, s.run_time / runs as u32, s.flush_time / runs as u32);
+        }
+    }
+}
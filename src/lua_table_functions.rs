@@ -0,0 +1,626 @@
+//! Lua Table Functions for DuckDB
+//!
+//! Shared by bins via `mod lua_table_functions;` (needs `mod lua_scripts;`).
+//! The other Lua bridges transform columns; mods also need to produce rows
+//! (spawn waves, loot tables). A script defines
+//!
+//!   lua_spawn_wave = table_function{
+//!     args = { "VARCHAR", "BIGINT" },
+//!     columns = { "id BIGINT", "kind VARCHAR", "x DOUBLE" },
+//!     rows = function(kind, count) ... coroutine.yield(id, kind, x) ... end,
+//!   }
+//!
+//! and `load_source`/`load_file` turn every such global into a table macro:
+//!
+//!   lua_spawn_wave(a0, a1) := TABLE SELECT * FROM lua_table('lua_spawn_wave',
+//!     s0 := coalesce(a0::VARCHAR, ''), b1 := coalesce(a1::BIGINT, 0),
+//!     nulls := 1 * (a0 IS NULL)::BIGINT + 2 * (a1 IS NULL)::BIGINT)
+//!
+//! so `SELECT * FROM lua_spawn_wave('goblins', 50)` works like a built-in.
+//! Names DuckDB already has (`range`, `read_csv`) are refused. `lua_table` is
+//! one `VTab`: the bind step reads the definition for the result columns,
+//! and argument `i` arrives as a typed named parameter (`b`/`d`/`s`/`t` for
+//! BIGINT/DOUBLE/VARCHAR/BOOLEAN, then `i`). Two ways to produce rows:
+//!
+//! - `rows = function(...)` yields one row per `coroutine.yield(...)`, with
+//!   `nil` for NULL. The coroutine is resumed a chunk (`CHUNK_ROWS` rows) at
+//!   a time on a generator thread of its own, at most one chunk ahead of the
+//!   scan, so nothing bounds the row count and a `LIMIT` stops the generator.
+//!   Finished generator threads wait for the next scan with their VM, up to
+//!   `GENERATOR_POOL` of them; a scan only pays for a new OS thread and a
+//!   fresh VM (script loads included) when they are all busy
+//! - `fill = function(out, n, offset, ...)` writes rows `offset..offset+n`
+//!   straight into DuckDB's output vectors through `out.<col>` FFI pointers
+//!   (`double*` / `int64_t*`, so BIGINT and DOUBLE columns only) and returns
+//!   how many it wrote; fewer than `n` ends the scan. Nothing is kept in Lua
+//!   between calls, so any worker VM can serve the next chunk
+//!
+//! Scripts run on the `lua_scripts` thread-local VMs and hot-reload like any
+//! other; functions added by a reload need another `load_*` call for their
+//! macro. `install` turns on `lua_scripts::enable_ffi()`, so it must run
+//! before the first Lua VM is created.
+
+#![allow(dead_code)]
+
+use crate::lua_scripts::{self, lua_err, valid_ident, Bridge};
+use duckdb::core::{DataChunkHandle, FlatVector, Inserter, LogicalTypeHandle, LogicalTypeId};
+use duckdb::vtab::{BindInfo, InitInfo, TableFunctionInfo, VTab};
+use duckdb::Connection;
+use mlua::{Function, LightUserData, Lua, MultiValue, Table, ThreadStatus, Value};
+use std::error::Error;
+use std::ffi::c_void;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SendError, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+/// Rows per scan call (one DuckDB vector).
+pub const CHUNK_ROWS: usize = 2048;
+/// Idle generator threads (each with its VM) kept for later `rows` scans.
+pub const GENERATOR_POOL: usize = 4;
+pub const MAX_TABLE_ARITY: usize = 8;
+
+const PRELUDE_NAME: &str = "lua_table_functions/prelude";
+const PRELUDE: &str = r#"
+function table_function(def)
+  assert(type(def) == "table", "table_function{} takes a table")
+  assert(type(def.columns) == "table" and #def.columns > 0,
+         "table_function{} needs columns = { \"name TYPE\", ... }")
+  assert((type(def.rows) == "function") ~= (type(def.fill) == "function"),
+         "table_function{} needs exactly one of rows = function or fill = function")
+  def.args = def.args or {}
+  def.__table_function = true
+  return def
+end
+"#;
+
+/// Calls `def.fill` with the output columns as writable FFI pointers. Kept
+/// out of `_G` (see `lua_scripts::host_function`), and it only casts light
+/// userdata, so a script can't turn a number into a pointer with it.
+const FILL_KEY: &str = "lua_table_functions.fill";
+const FILL: &str = r#"
+local cast = require("ffi").cast
+local function columns(out, ptrs, ctype)
+  for name, ptr in pairs(ptrs) do
+    assert(type(ptr) == "userdata", "lua_table: column pointers are light userdata")
+    out[name] = cast(ctype, ptr)
+  end
+end
+return function(def, desc, n, offset, ...)
+  local out = {}
+  columns(out, desc.double, "double*")
+  columns(out, desc.bigint, "int64_t*")
+  return def.fill(out, n, offset, ...)
+end
+"#;
+
+// ============================================================================
+// Definitions
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColType {
+    Bigint,
+    Double,
+    Varchar,
+    Boolean,
+}
+
+impl ColType {
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "BIGINT" => Some(ColType::Bigint),
+            "DOUBLE" => Some(ColType::Double),
+            "VARCHAR" => Some(ColType::Varchar),
+            "BOOLEAN" => Some(ColType::Boolean),
+            _ => None,
+        }
+    }
+
+    pub fn sql(self) -> &'static str {
+        match self {
+            ColType::Bigint => "BIGINT",
+            ColType::Double => "DOUBLE",
+            ColType::Varchar => "VARCHAR",
+            ColType::Boolean => "BOOLEAN",
+        }
+    }
+
+    fn logical(self) -> LogicalTypeHandle {
+        LogicalTypeHandle::from(match self {
+            ColType::Bigint => LogicalTypeId::Bigint,
+            ColType::Double => LogicalTypeId::Double,
+            ColType::Varchar => LogicalTypeId::Varchar,
+            ColType::Boolean => LogicalTypeId::Boolean,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Rows,
+    Fill,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableDef {
+    pub mode: Mode,
+    pub args: Vec<ColType>,
+    pub columns: Vec<(String, ColType)>,
+}
+
+/// Reads and checks a `table_function{}` table.
+fn read_def(def: &Table) -> Result<TableDef, String> {
+    let e = |e: mlua::Error| e.to_string();
+    let mode = if def.get::<Option<Function>>("fill").map_err(e)?.is_some() { Mode::Fill } else { Mode::Rows };
+
+    let mut args = Vec::new();
+    for t in def.get::<Table>("args").map_err(e)?.sequence_values::<String>() {
+        let t = t.map_err(e)?;
+        args.push(ColType::parse(&t).ok_or_else(|| format!("unsupported argument type {:?}", t))?);
+    }
+    if args.len() > MAX_TABLE_ARITY {
+        return Err(format!("{} args, supported up to {}", args.len(), MAX_TABLE_ARITY));
+    }
+
+    let mut columns: Vec<(String, ColType)> = Vec::new();
+    for decl in def.get::<Table>("columns").map_err(e)?.sequence_values::<String>() {
+        let decl = decl.map_err(e)?;
+        let mut parts = decl.split_whitespace();
+        let (Some(name), Some(ty), None) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(format!("column {:?} is not \"name TYPE\"", decl));
+        };
+        if !valid_ident(name) || columns.iter().any(|(c, _)| c == name) {
+            return Err(format!("column name {:?} is not a unique SQL identifier", name));
+        }
+        let ty = ColType::parse(ty).ok_or_else(|| format!("column `{}` has unsupported type {}", name, ty))?;
+        if mode == Mode::Fill && !matches!(ty, ColType::Bigint | ColType::Double) {
+            return Err(format!("fill functions write BIGINT/DOUBLE only; column `{}` is {}", name, ty.sql()));
+        }
+        columns.push((name.to_string(), ty));
+    }
+    Ok(TableDef { mode, args, columns })
+}
+
+fn lookup(lua: &Lua, name: &str) -> mlua::Result<Table> {
+    lua.globals()
+        .get::<Option<Table>>(name)?
+        .filter(|d| d.raw_get::<bool>("__table_function").unwrap_or(false))
+        .ok_or_else(|| lua_err(format!("lua_table: no Lua table function `{}`", name)))
+}
+
+// ============================================================================
+// Arguments: typed named parameters
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Num(f64),
+    Str(String),
+}
+
+impl Arg {
+    fn into_lua(self, lua: &Lua) -> mlua::Result<Value> {
+        Ok(match self {
+            Arg::Null => Value::Nil,
+            Arg::Bool(b) => Value::Boolean(b),
+            Arg::Int(i) => Value::Integer(i as mlua::Integer),
+            Arg::Num(n) => Value::Number(n),
+            Arg::Str(s) => Value::String(lua.create_string(&s)?),
+        })
+    }
+}
+
+/// Named parameter carrying argument `index` of type `ty`: `b0`, `d1`, `s2`, `t3`.
+pub fn arg_param(ty: ColType, index: usize) -> String {
+    let prefix = match ty {
+        ColType::Bigint => 'b',
+        ColType::Double => 'd',
+        ColType::Varchar => 's',
+        ColType::Boolean => 't',
+    };
+    format!("{}{}", prefix, index)
+}
+
+fn arg_params() -> impl Iterator<Item = (String, ColType)> {
+    (0..MAX_TABLE_ARITY).flat_map(|i| {
+        [ColType::Bigint, ColType::Double, ColType::Varchar, ColType::Boolean].map(move |ty| (arg_param(ty, i), ty))
+    })
+}
+
+/// Reads the arguments `def` declares. duckdb-rs can't test a parameter
+/// value for NULL (reading one throws inside DuckDB), so the macro passes
+/// `coalesce`d values and a `nulls` bitmask, bit `i` set for NULL argument `i`.
+fn read_args(bind: &BindInfo, name: &str, def: &TableDef) -> Result<Vec<Arg>, String> {
+    let nulls = bind.get_named_parameter("nulls").map_or(0, |v| v.to_int64());
+    let mut args = Vec::with_capacity(def.args.len());
+    for (i, ty) in def.args.iter().enumerate() {
+        let param = arg_param(*ty, i);
+        let value = bind
+            .get_named_parameter(&param)
+            .ok_or_else(|| format!("argument {} ({}) missing: pass {} := ...", i + 1, ty.sql(), param))?;
+        args.push(if nulls & (1 << i) != 0 {
+            Arg::Null
+        } else {
+            match ty {
+                ColType::Bigint => Arg::Int(value.to_int64()),
+                ColType::Boolean => Arg::Bool(value.to_int64() != 0),
+                ColType::Varchar => Arg::Str(value.to_string()),
+                ColType::Double => {
+                    let text = value.to_string();
+                    Arg::Num(text.parse().map_err(|_| format!("argument {} is not a DOUBLE: {:?}", i + 1, text))?)
+                }
+            }
+        });
+    }
+    let expected: Vec<String> = def.args.iter().enumerate().map(|(i, ty)| arg_param(*ty, i)).collect();
+    if let Some((extra, _)) = arg_params().find(|(p, _)| !expected.contains(p) && bind.get_named_parameter(p).is_some()) {
+        return Err(format!("unexpected parameter {} ({} takes {} arguments)", extra, name, def.args.len()));
+    }
+    Ok(args)
+}
+
+// ============================================================================
+// Generated rows (`rows` mode)
+// ============================================================================
+
+enum Buffer {
+    Bigint(Vec<Option<i64>>),
+    Double(Vec<Option<f64>>),
+    Varchar(Vec<Option<String>>),
+    Boolean(Vec<Option<bool>>),
+}
+
+impl Buffer {
+    fn new(ty: ColType) -> Self {
+        match ty {
+            ColType::Bigint => Buffer::Bigint(Vec::with_capacity(CHUNK_ROWS)),
+            ColType::Double => Buffer::Double(Vec::with_capacity(CHUNK_ROWS)),
+            ColType::Varchar => Buffer::Varchar(Vec::with_capacity(CHUNK_ROWS)),
+            ColType::Boolean => Buffer::Boolean(Vec::with_capacity(CHUNK_ROWS)),
+        }
+    }
+
+    fn push(&mut self, value: Value) -> Result<(), String> {
+        let bad = |v: &Value, ty: &str| Err(format!("got {}, expected {}", v.type_name(), ty));
+        match (self, value) {
+            (Buffer::Bigint(b), Value::Nil) => b.push(None),
+            (Buffer::Bigint(b), Value::Integer(i)) => b.push(Some(i)),
+            (Buffer::Bigint(b), Value::Number(n)) if n.fract() == 0.0 => b.push(Some(n as i64)),
+            (Buffer::Bigint(_), v) => return bad(&v, "an integer"),
+            (Buffer::Double(b), Value::Nil) => b.push(None),
+            (Buffer::Double(b), Value::Integer(i)) => b.push(Some(i as f64)),
+            (Buffer::Double(b), Value::Number(n)) => b.push(Some(n)),
+            (Buffer::Double(_), v) => return bad(&v, "a number"),
+            (Buffer::Varchar(b), Value::Nil) => b.push(None),
+            (Buffer::Varchar(b), Value::String(s)) => b.push(Some(s.to_str().map_err(|e| e.to_string())?.to_string())),
+            (Buffer::Varchar(_), v) => return bad(&v, "a string"),
+            (Buffer::Boolean(b), Value::Nil) => b.push(None),
+            (Buffer::Boolean(b), Value::Boolean(x)) => b.push(Some(x)),
+            (Buffer::Boolean(_), v) => return bad(&v, "a boolean"),
+        }
+        Ok(())
+    }
+}
+
+fn write_fixed<T: Copy>(vector: &mut FlatVector, values: &[Option<T>]) {
+    let slice = vector.as_mut_slice::<T>();
+    let mut nulls = Vec::new();
+    for (i, v) in values.iter().enumerate() {
+        match v {
+            Some(v) => slice[i] = *v,
+            None => nulls.push(i),
+        }
+    }
+    for i in nulls {
+        vector.set_null(i);
+    }
+}
+
+/// Up to `CHUNK_ROWS` rows, one buffer per column; fewer means the
+/// generator finished. Errors cross the channel as text, since
+/// `mlua::Error` isn't `Send`.
+type Chunk = Result<(Vec<Buffer>, usize), String>;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Generator threads waiting for a scan, newest last.
+static IDLE: Mutex<Vec<Sender<Job>>> = Mutex::new(Vec::new());
+
+/// Runs `job` on an idle generator thread, or spawns one if none is idle.
+/// A thread that finishes its job collects its VM's garbage and goes back
+/// to `IDLE`, unless `GENERATOR_POOL` threads already wait there.
+fn run_generator(mut job: Job) {
+    loop {
+        let idle = IDLE.lock().unwrap().pop();
+        let Some(idle) = idle else { break };
+        match idle.send(job) {
+            Ok(()) => return,
+            Err(SendError(back)) => job = back,
+        }
+    }
+    thread::spawn(move || loop {
+        job();
+        let _ = lua_scripts::with_vm(|lua| lua.gc_collect());
+        let (tx, rx) = mpsc::channel();
+        {
+            let mut idle = IDLE.lock().unwrap();
+            if idle.len() >= GENERATOR_POOL {
+                return;
+            }
+            idle.push(tx);
+        }
+        match rx.recv() {
+            Ok(next) => job = next,
+            Err(_) => return,
+        }
+    });
+}
+
+/// Starts the generator on a generator thread and returns the chunks it yields.
+///
+/// A coroutine lives in one VM and mlua VMs can't move between threads,
+/// while DuckDB may call `func` for one scan from different workers. So each
+/// scan gets a thread (and its `lua_scripts` VM) to itself that resumes the
+/// coroutine `CHUNK_ROWS` times per chunk. The channel holds nothing, so the
+/// generator runs at most one chunk ahead of the scan; when the scan is
+/// dropped (query done, `LIMIT` reached) the next send fails and the thread
+/// drops the coroutine without resuming it again.
+fn start_rows(bind: &LuaTableBind) -> Receiver<Chunk> {
+    let (tx, rx) = mpsc::sync_channel(0);
+    let name = bind.name.clone();
+    let columns = bind.def.columns.clone();
+    let args = bind.args.clone();
+    run_generator(Box::new(move || {
+        let generated = lua_scripts::with_vm(|lua| {
+            let def = lookup(lua, &name)?;
+            let thread = lua.create_thread(def.get::<Function>("rows")?)?;
+            let mut resume = MultiValue::new();
+            for a in args {
+                resume.push_back(a.into_lua(lua)?);
+            }
+            let mut rows = 0;
+            loop {
+                let mut buffers: Vec<Buffer> = columns.iter().map(|(_, ty)| Buffer::new(*ty)).collect();
+                let mut n = 0;
+                while n < CHUNK_ROWS {
+                    let row: MultiValue = thread.resume(std::mem::take(&mut resume))?;
+                    if thread.status() != ThreadStatus::Resumable {
+                        break;
+                    }
+                    if row.len() != buffers.len() {
+                        return Err(lua_err(format!(
+                            "lua_table `{}`: row {} has {} values, expected {}",
+                            name, rows, row.len(), buffers.len()
+                        )));
+                    }
+                    for ((buffer, value), (column, _)) in buffers.iter_mut().zip(row).zip(&columns) {
+                        buffer
+                            .push(value)
+                            .map_err(|e| lua_err(format!("lua_table `{}`: row {} column `{}`: {}", name, rows, column, e)))?;
+                    }
+                    rows += 1;
+                    n += 1;
+                }
+                if tx.send(Ok((buffers, n))).is_err() || n < CHUNK_ROWS {
+                    return Ok(());
+                }
+            }
+        });
+        if let Err(e) = generated {
+            let _ = tx.send(Err(e.to_string()));
+        }
+    }));
+    rx
+}
+
+// ============================================================================
+// lua_table(name VARCHAR, b0 := ..., s1 := ..., nulls := ...)
+// ============================================================================
+
+pub struct LuaTableBind {
+    name: String,
+    def: TableDef,
+    args: Vec<Arg>,
+}
+
+#[derive(Default)]
+struct Scan {
+    offset: usize,
+    done: bool,
+    rows: Option<Receiver<Chunk>>,
+}
+
+pub struct LuaTableInit {
+    scan: Mutex<Scan>,
+}
+
+pub struct LuaTableFunction;
+
+impl LuaTableFunction {
+    fn fill(bind: &LuaTableBind, offset: usize, output: &mut DataChunkHandle) -> mlua::Result<usize> {
+        lua_scripts::with_vm(|lua| {
+            let fill = lua_scripts::host_function(lua, FILL_KEY, FILL)?;
+            let def = lookup(lua, &bind.name)?;
+            let (double, bigint) = (lua.create_table()?, lua.create_table()?);
+            for (i, (name, ty)) in bind.def.columns.iter().enumerate() {
+                let mut vector = output.flat_vector(i);
+                let (target, ptr) = match ty {
+                    ColType::Bigint => (&bigint, vector.as_mut_slice::<i64>().as_mut_ptr() as *mut c_void),
+                    _ => (&double, vector.as_mut_slice::<f64>().as_mut_ptr() as *mut c_void),
+                };
+                target.set(name.as_str(), LightUserData(ptr))?;
+            }
+            let desc = lua.create_table()?;
+            desc.set("double", double)?;
+            desc.set("bigint", bigint)?;
+
+            let mut call = vec![
+                Value::Table(def),
+                Value::Table(desc),
+                Value::Integer(CHUNK_ROWS as mlua::Integer),
+                Value::Integer(offset as mlua::Integer),
+            ];
+            for a in &bind.args {
+                call.push(a.clone().into_lua(lua)?);
+            }
+            let written: Option<f64> = fill.call(MultiValue::from_iter(call))?;
+            match written {
+                Some(n) if n.fract() == 0.0 && (0.0..=CHUNK_ROWS as f64).contains(&n) => Ok(n as usize),
+                other => Err(lua_err(format!(
+                    "lua_table `{}`: fill must return a row count in 0..={}, got {:?}",
+                    bind.name, CHUNK_ROWS, other
+                ))),
+            }
+        })
+    }
+
+    fn emit(bind: &LuaTableBind, scan: &mut Scan, output: &mut DataChunkHandle) -> mlua::Result<usize> {
+        let rows = scan.rows.get_or_insert_with(|| start_rows(bind));
+        let (buffers, n) = rows
+            .recv()
+            .map_err(|_| lua_err(format!("lua_table `{}`: generator thread stopped", bind.name)))?
+            .map_err(lua_err)?;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let mut vector = output.flat_vector(i);
+            match buffer {
+                Buffer::Bigint(b) => write_fixed(&mut vector, b),
+                Buffer::Double(b) => write_fixed(&mut vector, b),
+                Buffer::Boolean(b) => write_fixed(&mut vector, b),
+                Buffer::Varchar(b) => {
+                    for (row, s) in b.iter().enumerate() {
+                        match s {
+                            Some(s) => vector.insert(row, s.as_str()),
+                            None => vector.set_null(row),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl VTab for LuaTableFunction {
+    type InitData = LuaTableInit;
+    type BindData = LuaTableBind;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        let name = bind.get_parameter(0).to_string();
+        let def = lua_scripts::with_vm(|lua| {
+            let table = lookup(lua, &name)?;
+            read_def(&table).map_err(|e| lua_err(format!("lua_table `{}`: {}", name, e)))
+        })?;
+        let args = read_args(bind, &name, &def).map_err(|e| format!("lua_table `{}`: {}", name, e))?;
+        for (column, ty) in &def.columns {
+            bind.add_result_column(column, ty.logical());
+        }
+        Ok(LuaTableBind { name, def, args })
+    }
+
+    fn init(_: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(LuaTableInit { scan: Mutex::new(Scan::default()) })
+    }
+
+    fn func(func: &TableFunctionInfo<Self>, output: &mut DataChunkHandle) -> Result<(), Box<dyn Error>> {
+        let bind = func.get_bind_data();
+        let mut scan = func.get_init_data().scan.lock().map_err(|_| "lua_table: scan state poisoned")?;
+        if scan.done {
+            output.set_len(0);
+            return Ok(());
+        }
+        let n = match bind.def.mode {
+            Mode::Fill => Self::fill(bind, scan.offset, output)?,
+            Mode::Rows => Self::emit(bind, &mut scan, output)?,
+        };
+        scan.offset += n;
+        scan.done = n < CHUNK_ROWS;
+        output.set_len(n);
+        Ok(())
+    }
+
+    fn parameters() -> Option<Vec<LogicalTypeHandle>> {
+        Some(vec![LogicalTypeHandle::from(LogicalTypeId::Varchar)])
+    }
+
+    fn named_parameters() -> Option<Vec<(String, LogicalTypeHandle)>> {
+        let mut params: Vec<(String, LogicalTypeHandle)> = arg_params().map(|(p, ty)| (p, ty.logical())).collect();
+        params.push(("nulls".to_string(), LogicalTypeHandle::from(LogicalTypeId::Bigint)));
+        Some(params)
+    }
+}
+
+// ============================================================================
+// Registration
+// ============================================================================
+
+impl Bridge for LuaTableFunction {
+    type Def = TableDef;
+    const FUNCTION: &'static str = "lua_table";
+    const MARKER: &'static str = "__table_function";
+    const KIND: &'static str = "table function";
+    const PRELUDE_NAME: &'static str = PRELUDE_NAME;
+    const PRELUDE: &'static str = PRELUDE;
+
+    fn register(conn: &Connection) -> duckdb::Result<()> {
+        conn.register_table_function::<LuaTableFunction>(Self::FUNCTION)
+    }
+
+    fn read(def: &Table) -> Result<TableDef, String> {
+        read_def(def)
+    }
+
+    fn create_macro(conn: &Connection, name: &str, def: &TableDef) -> duckdb::Result<()> {
+        create_macro(conn, name, &def.args)
+    }
+}
+
+/// Enables FFI for new VMs, loads the prelude and registers `lua_table`.
+pub fn install(conn: &Connection) -> duckdb::Result<()> {
+    lua_scripts::install::<LuaTableFunction>(conn)
+}
+
+/// `CREATE OR REPLACE MACRO name(a0, ...) AS TABLE` over `lua_table`; fails
+/// if `name` is taken by anything but an earlier Lua table function.
+pub fn create_macro(conn: &Connection, name: &str, args: &[ColType]) -> duckdb::Result<()> {
+    lua_scripts::check_macro_name(conn, name, "lua_table")?;
+    let params: Vec<String> = (0..args.len()).map(|i| format!("a{}", i)).collect();
+    let mut named: Vec<String> = params
+        .iter()
+        .zip(args)
+        .enumerate()
+        .map(|(i, (p, ty))| {
+            let default = match ty {
+                ColType::Bigint => "0",
+                ColType::Double => "0.0",
+                ColType::Varchar => "''",
+                ColType::Boolean => "false",
+            };
+            format!("{} := coalesce({}::{}, {})", arg_param(*ty, i), p, ty.sql(), default)
+        })
+        .collect();
+    if !params.is_empty() {
+        let bits: Vec<String> = params.iter().enumerate().map(|(i, p)| format!("{} * ({} IS NULL)::BIGINT", 1 << i, p)).collect();
+        named.push(format!("nulls := {}", bits.join(" + ")));
+    }
+    conn.execute_batch(&format!(
+        "CREATE OR REPLACE MACRO {}({}) AS TABLE SELECT * FROM lua_table('{}'{});",
+        name,
+        params.join(", "),
+        name,
+        named.iter().map(|n| format!(", {}", n)).collect::<String>()
+    ))
+}
+
+/// Loads an in-memory script and creates a table macro per function it defines.
+pub fn load_source(conn: &Connection, script: &str, source: &str) -> Result<Vec<(String, TableDef)>, Box<dyn Error>> {
+    lua_scripts::define_source::<LuaTableFunction>(conn, script, source)
+}
+
+/// Like `load_source`, with the script file-backed (see `lua_scripts::poll_changes`).
+pub fn load_file(conn: &Connection, script: &str, path: &Path) -> Result<Vec<(String, TableDef)>, Box<dyn Error>> {
+    lua_scripts::define_file::<LuaTableFunction>(conn, script, path)
+}
@@ -0,0 +1,208 @@
+//! Lua UDF Errors as Query Errors, Not Panics
+//!
+//! A typo in a mod script used to take down a DuckDB worker thread. With
+//! `lua_errors.rs` the `lua_call` bridge in `mod_loader.rs` reports Lua
+//! errors with the mod, function, row, arguments and Lua traceback:
+//! 1. `buggy` (on_error = "fail") calls an undefined helper on a few rows:
+//!    the query fails with a structured message and the connection keeps
+//!    working
+//! 2. `tolerant` (on_error = "null") errors on some levels: those rows come
+//!    back NULL and every error is counted
+//! 3. Switching `buggy` to NULL mode at runtime; a UDF returning a string;
+//!    an unowned function name
+//! 4. 1M rows on 8 threads with failing rows: still an error, never a panic
+//! 5. Per-mod error counters
+
+mod lua_errors;
+mod lua_scripts;
+mod mod_loader;
+mod mod_permissions;
+mod system_runner;
+
+use duckdb::Connection;
+use lua_errors::OnError;
+use mod_loader::write_mod;
+use std::error::Error;
+use std::fs;
+use std::time::Instant;
+use system_runner::SystemRunner;
+
+const ROWS: usize = 1_000_000;
+
+const STABLE: &str = r#"
+return { name = "stable", version = "1.0.0", scripts = { "stable.lua" }, udfs = { damage = 2 } }
+"#;
+const STABLE_LUA: &str = "function damage(power, armor) return math.max(power - armor, 1) end\n";
+
+const BUGGY: &str = r#"
+return { name = "buggy", version = "0.1.0", scripts = { "buggy.lua" }, udfs = { crit = 2, label = 1 } }
+"#;
+const BUGGY_LUA: &str = r#"
+local function roll(power, luck)
+  if luck >= 0.999 then
+    return clmap(power * 3, 0, 100)   -- typo: clamp
+  end
+  return power
+end
+
+function crit(power, luck) return roll(power, luck) end
+function label(x) return "crit!" end
+"#;
+
+const TOLERANT: &str = r#"
+return {
+  name = "tolerant", version = "2.0.0", scripts = { "tolerant.lua" }, udfs = { bonus = 1 },
+  on_error = "null",
+}
+"#;
+const TOLERANT_LUA: &str = r#"
+function bonus(level)
+  if level % 50 == 0 then error("no bonus table for level " .. level) end
+  return level * 0.1
+end
+"#;
+
+/// The query's error, if it failed.
+fn query_error(conn: &Connection, sql: &str) -> Option<String> {
+    conn.query_row(sql, [], |r| r.get::<_, Option<f64>>(0)).err().map(|e| e.to_string())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== Structured Lua UDF Errors ===\n");
+    let root = std::env::temp_dir().join("polars_ecs_udf_errors");
+    let _ = fs::remove_dir_all(&root);
+    write_mod(&root, "stable", STABLE, &[("stable.lua", STABLE_LUA)])?;
+    write_mod(&root, "buggy", BUGGY, &[("buggy.lua", BUGGY_LUA)])?;
+    write_mod(&root, "tolerant", TOLERANT, &[("tolerant.lua", TOLERANT_LUA)])?;
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch(&format!(
+        "SET threads TO 8;
+         CREATE TABLE units AS
+         SELECT i AS id, (10 + i % 90)::DOUBLE AS power, (i % 7)::DOUBLE AS armor,
+                (((i * 7919) % 1000) / 1000.0)::DOUBLE AS luck, (1 + i % 200)::DOUBLE AS level
+         FROM range({ROWS}) t(i);"
+    ))?;
+    let mut runner = SystemRunner::new(&conn);
+    let mods = mod_loader::load_mods(&conn, &mut runner, &root)?;
+    for m in &mods {
+        println!("Loaded {:<9} on_error = {}", m.name, m.on_error);
+    }
+    let unlucky: i64 = conn.query_row("SELECT count(*) FROM units WHERE luck >= 0.999", [], |r| r.get(0))?;
+    let no_bonus: i64 = conn.query_row("SELECT count(*) FROM units WHERE level % 50 = 0", [], |r| r.get(0))?;
+    let mut failures = 0;
+
+    // --------------------------------------------------------------------
+    // 1. Fail mode
+    // --------------------------------------------------------------------
+    println!("\n--- 1. buggy.crit, on_error = fail ---");
+    let small = "SELECT sum(crit(power, luck)) FROM units WHERE id < 5000";
+    match query_error(&conn, small) {
+        Some(message) => {
+            for line in message.lines().take(6) {
+                println!("  | {}", line);
+            }
+            let structured = ["mod `buggy`", "function `crit`", "row ", "clmap", "stack traceback"]
+                .iter()
+                .all(|part| message.contains(part));
+            println!("  names mod, function, row, cause and traceback: {}", if structured { "✓" } else { "✗" });
+            if !structured {
+                failures += 1;
+            }
+        }
+        None => {
+            failures += 1;
+            println!("  ✗ query succeeded");
+        }
+    }
+    let after: f64 = conn.query_row("SELECT sum(damage(power, armor)) FROM units WHERE id < 5000", [], |r| r.get(0))?;
+    println!("  same connection afterwards: sum(damage) = {}", after);
+
+    // --------------------------------------------------------------------
+    // 2. NULL mode
+    // --------------------------------------------------------------------
+    println!("\n--- 2. tolerant.bonus, on_error = null ---");
+    let start = Instant::now();
+    let (rows, with_bonus): (i64, i64) =
+        conn.query_row("SELECT count(*), count(bonus(level)) FROM units", [], |r| Ok((r.get(0)?, r.get(1)?)))?;
+    let nulls = rows - with_bonus;
+    println!("  {} rows, {} NULL (expected {}) in {:?}", rows, nulls, no_bonus, start.elapsed());
+    if let Some(e) = lua_errors::last_error("tolerant") {
+        let row = e.row.map_or_else(|| "whole chunk".to_string(), |r| format!("chunk row {}", r));
+        println!("  last error: {} {:?}: {}", row, e.args, e.message);
+    }
+    if nulls != no_bonus {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 3. Runtime switch and other failures
+    // --------------------------------------------------------------------
+    println!("\n--- 3. Switching and other failures ---");
+    lua_errors::set_on_error("buggy", OnError::Null);
+    let (rows, crits): (i64, i64) =
+        conn.query_row("SELECT count(*), count(crit(power, luck)) FROM units", [], |r| Ok((r.get(0)?, r.get(1)?)))?;
+    println!("  buggy → null: {} of {} rows NULL (expected {})", rows - crits, rows, unlucky);
+    if rows - crits != unlucky {
+        failures += 1;
+    }
+    lua_errors::set_on_error("buggy", OnError::Fail);
+
+    let cases = [
+        ("returns a string", "SELECT label(1.0)", "label"),
+        ("unowned function", "SELECT lua_call('nope', 1.0)", lua_errors::UNOWNED),
+    ];
+    for (label, sql, expect) in cases {
+        match query_error(&conn, sql) {
+            Some(message) if message.contains(expect) => {
+                println!("  ✓ {:<17} {}", label, message.lines().next().unwrap_or(""));
+            }
+            other => {
+                failures += 1;
+                println!("  ✗ {:<17} {:?}", label, other);
+            }
+        }
+    }
+
+    // --------------------------------------------------------------------
+    // 4. Parallel
+    // --------------------------------------------------------------------
+    println!("\n--- 4. {} rows on 8 threads, {} failing ---", ROWS, unlucky);
+    let start = Instant::now();
+    let failed = query_error(&conn, "SELECT sum(crit(power, luck)) FROM units").is_some();
+    println!("  query failed cleanly: {} ({:?})", failed, start.elapsed());
+    let (vms, _) = lua_scripts::vm_stats();
+    println!("  {} worker VMs, all still usable:", vms);
+    let total: f64 = conn.query_row("SELECT sum(damage(power, armor)) FROM units", [], |r| r.get(0))?;
+    let want: f64 = conn.query_row("SELECT sum(greatest(power - armor, 1)) FROM units", [], |r| r.get(0))?;
+    println!("  sum(damage) = {} (SQL {})", total, want);
+    if !failed || total != want {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 5. Counters
+    // --------------------------------------------------------------------
+    println!("\n--- 5. Errors per mod ---");
+    let counts = lua_errors::error_counts();
+    for (module, count) in &counts {
+        println!("  {:<10} {}", module, count);
+    }
+    let count_of = |m: &str| counts.iter().find(|(n, _)| n == m).map_or(0, |(_, c)| *c);
+    // Fail mode counts one error per failing batch; NULL mode every row.
+    if count_of("tolerant") != no_bonus as u64 || count_of("buggy") < unlucky as u64 + 3 || count_of("stable") != 0 {
+        failures += 1;
+    }
+
+    println!("\n=== Summary ===\n");
+    println!("  • Lua errors in UDFs become query errors naming mod, function, row, arguments and traceback");
+    println!("  • on_error = \"null\" turns failing rows into NULL; the mode can change at runtime");
+    println!("  • Every error is counted against its mod, in either mode");
+    println!("  • Failed checks: {}", failures);
+
+    let _ = fs::remove_dir_all(&root);
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
@@ -8,58 +8,82 @@
 //! 2. Lua scripts define functions like `distance(x1, y1, x2, y2)`
 //! 3. DuckDB VScalar calls into the thread-local Lua VM
 //! 4. Combine SQL power (JOINs, filters) with Lua flexibility (spatial logic)
+//! 5. The script comes from the `lua_scripts` registry (`set_source` or
+//!    `load_file` + `poll_changes`); each VM checks its version on the next
+//!    call and rebuilds from the new source. A version that fails to load
+//!    keeps the previous VM and returns the Lua error from that call
+
+mod lua_scripts;
 
 use piccolo::{Closure, Executor, FromValue, Function, Lua, StashedExecutor};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::error::Error;
 use std::time::Instant;
 
+/// Registry name of the UDF script.
+const SCRIPT: &str = "udfs";
+
 // Thread-local Lua VM storage
 // Each DuckDB worker thread would have its own Piccolo instance
 thread_local! {
-    static LUA_VM: RefCell<Option<LuaState>> = RefCell::new(None);
+    static LUA_VM: RefCell<Option<LuaState>> = const { RefCell::new(None) };
+    /// Registry version this thread last checked.
+    static SYNCED: Cell<Option<u64>> = const { Cell::new(None) };
+    /// Script version last tried on this thread, loaded or not.
+    static ATTEMPTED: Cell<Option<u64>> = const { Cell::new(None) };
 }
 
 /// Wrapper for Lua state that includes a reusable executor
 struct LuaState {
     lua: Lua,
     executor: StashedExecutor,
+    version: u64,
 }
 
-/// Initialize the thread-local Lua VM with a script
-fn init_lua_vm(script: &str) {
-    LUA_VM.with(|vm| {
-        let mut vm_ref = vm.borrow_mut();
-        if vm_ref.is_none() {
-            let mut lua = Lua::full();
-            
-            // Load and execute the script to define global functions
-            let executor = lua.try_enter(|ctx| {
-                let closure = Closure::load(ctx, None, script.as_bytes())
-                    .expect("Failed to compile Lua script");
-                Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
-            }).expect("Failed to create executor");
-            
-            // Run the script to completion (defines functions in globals)
-            lua.execute::<()>(&executor).expect("Failed to execute Lua script");
-            
-            *vm_ref = Some(LuaState { lua, executor });
-        }
-    });
+/// Builds a VM and runs the script to completion (defines functions in globals)
+fn load_vm(script: &str, version: u64) -> Result<LuaState, Box<dyn Error>> {
+    let mut lua = Lua::full();
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, script.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+    Ok(LuaState { lua, executor, version })
+}
+
+/// Brings this thread's VM up to the registered script version; one atomic
+/// load when nothing changed. Each version is tried once per thread; if it
+/// fails, the error is returned and the previous VM (if any) stays in use.
+fn sync_lua_vm() -> Result<(), Box<dyn Error>> {
+    let current = lua_scripts::version();
+    if SYNCED.get() == Some(current) {
+        return Ok(());
+    }
+    let (version, source) = lua_scripts::script_source(SCRIPT).ok_or("no `udfs` script registered")?;
+    if ATTEMPTED.replace(Some(version)) != Some(version) {
+        let state = load_vm(&source, version).map_err(|e| format!("`{}` version {}: {}", SCRIPT, version, e))?;
+        LUA_VM.with(|vm| *vm.borrow_mut() = Some(state));
+    }
+    SYNCED.set(Some(current));
+    Ok(())
 }
 
 /// Call a Lua function with f64 arguments, return f64
-fn call_lua_f64(func_name: &'static str, args: &[f64]) -> f64 {
+fn call_lua_f64(func_name: &'static str, args: &[f64]) -> Result<f64, Box<dyn Error>> {
+    if !matches!(args.len(), 1 | 2 | 4 | 5) {
+        return Err(format!("{}: unsupported argument count {}", func_name, args.len()).into());
+    }
+    sync_lua_vm()?;
     LUA_VM.with(|vm| {
         let mut vm_ref = vm.borrow_mut();
-        let state = vm_ref.as_mut().expect("Lua VM not initialized");
+        let state = vm_ref.as_mut().ok_or("Lua VM not initialized")?;
         
         // Get function and call it
         state.lua.try_enter(|ctx| {
             // Get the function from globals
             let globals = ctx.globals();
             let func_value = globals.get(ctx, func_name);
-            let func: Function = Function::from_value(ctx, func_value)
-                .map_err(|e| piccolo::Error::from(e))?;
+            let func: Function = Function::from_value(ctx, func_value)?;
             
             // Restart executor with the function and arguments
             let executor = ctx.fetch(&state.executor);
@@ -68,23 +92,27 @@ fn call_lua_f64(func_name: &'static str, args: &[f64]) -> f64 {
                 2 => executor.restart(ctx, func, (args[0], args[1])),
                 4 => executor.restart(ctx, func, (args[0], args[1], args[2], args[3])),
                 5 => executor.restart(ctx, func, (args[0], args[1], args[2], args[3], args[4])),
-                _ => panic!("Unsupported arg count: {}", args.len()),
+                _ => unreachable!("argument count checked above"),
             }
             Ok(())
-        }).expect("Failed to set up function call");
+        })?;
         
         // Execute and get result
-        state.lua.execute::<f64>(&state.executor)
-            .expect("Lua function call failed")
+        Ok(state.lua.execute::<f64>(&state.executor)?)
     })
 }
 
+/// Version of the VM this thread is running.
+fn vm_version() -> Option<u64> {
+    LUA_VM.with(|vm| vm.borrow().as_ref().map(|s| s.version))
+}
+
 /// Simple Rust implementation for comparison
 fn rust_distance(x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
     ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt()
 }
 
-fn main() {
+fn main() -> Result<(), Box<dyn Error>> {
     println!("=== Thread-Local Lua VM for DuckDB UDFs ===\n");
     
     // The Lua script that mods would provide
@@ -112,7 +140,8 @@ fn main() {
     // Initialize the Lua VM
     println!("Initializing Lua VM...");
     let start = Instant::now();
-    init_lua_vm(lua_script);
+    lua_scripts::set_source(SCRIPT, lua_script);
+    sync_lua_vm()?;
     println!("  Init time: {:?}\n", start.elapsed());
     
     // === Benchmark: Lua distance vs Rust distance ===
@@ -122,7 +151,7 @@ fn main() {
     
     // Warm up
     for _ in 0..100 {
-        let _ = call_lua_f64("distance", &[0.0, 0.0, 3.0, 4.0]);
+        call_lua_f64("distance", &[0.0, 0.0, 3.0, 4.0])?;
     }
     
     // Lua distance
@@ -131,7 +160,7 @@ fn main() {
     for i in 0..iterations {
         let x1 = (i % 100) as f64;
         let y1 = (i / 100) as f64;
-        lua_sum += call_lua_f64("distance", &[x1, y1, 500.0, 500.0]);
+        lua_sum += call_lua_f64("distance", &[x1, y1, 500.0, 500.0])?;
     }
     let lua_time = start.elapsed();
     
@@ -174,7 +203,7 @@ fn main() {
     let mut lua_matches = 0;
     for (x1, y1) in &positions {
         for (x2, y2) in &positions {
-            let dist = call_lua_f64("distance", &[*x1, *y1, *x2, *y2]);
+            let dist = call_lua_f64("distance", &[*x1, *y1, *x2, *y2])?;
             if dist < 50.0 {
                 lua_matches += 1;
             }
@@ -207,6 +236,23 @@ fn main() {
     println!("  Overhead:  {:>6.1}×", lua_query_time.as_secs_f64() / rust_query_time.as_secs_f64());
     println!();
     
+    // === Hot reload ===
+    println!("=== Hot Reload ===\n");
+
+    let before = call_lua_f64("damage_falloff", &[25.0, 100.0])?;
+    lua_scripts::set_source(SCRIPT, lua_script.replace("return 1 - (distance / max_range)", "return (1 - distance / max_range) ^ 2"));
+    let after = call_lua_f64("damage_falloff", &[25.0, 100.0])?;
+    println!("  damage_falloff(25, 100): {} → {} after set_source (VM version {:?})", before, after, vm_version());
+
+    lua_scripts::set_source(SCRIPT, "function damage_falloff(d, r) return oops(");
+    match call_lua_f64("damage_falloff", &[25.0, 100.0]) {
+        Ok(v) => println!("  ❌ broken script loaded: {}", v),
+        Err(e) => println!("  Broken reload surfaced: {}", e),
+    }
+    let kept = call_lua_f64("damage_falloff", &[25.0, 100.0])?;
+    println!("  Next call keeps the previous version: {} (VM version {:?})", kept, vm_version());
+    println!();
+    
     // === Frame budget analysis ===
     println!("=== Frame Budget Analysis ===\n");
     
@@ -269,4 +315,6 @@ fn main() {
     println!("  │  • Pre-loaded with mod scripts                          │");
     println!("  │  • ~{:.0} ns per function call                            │", lua_per_call);
     println!("  └─────────────────────────────────────────────────────────┘");
+
+    Ok(())
 }
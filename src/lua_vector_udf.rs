@@ -6,6 +6,15 @@
 //! 3. Pure Rust baseline
 //!
 //! Goal: Determine if vectorized Lua calls reduce FFI overhead.
+//!
+//! The script is registered with `lua_scripts`, so each DuckDB worker runs it
+//! on its thread-local VM. Lua errors go through `lua_errors`: the per-row
+//! UDF reports the chunk row and its arguments, the batch UDF the whole
+//! chunk, and under `on_error = "null"` the failed rows become NULL.
+
+mod arrow_scalar;
+mod lua_errors;
+mod lua_scripts;
 
 use duckdb::arrow::array::{Array, Float64Array};
 use duckdb::arrow::datatypes::DataType;
@@ -14,13 +23,13 @@ use duckdb::{
     vscalar::{ArrowFunctionSignature, VArrowScalar},
     Connection,
 };
-use std::cell::RefCell;
+use lua_errors::UdfError;
 use std::error::Error;
 use std::sync::Arc;
 use std::time::Instant;
 
 // ============================================================================
-// Thread-Local Lua VM with both per-row and batch functions
+// Lua script with both per-row and batch functions
 // ============================================================================
 
 static LUA_SCRIPT: &str = r#"
@@ -60,40 +69,33 @@ static LUA_SCRIPT: &str = r#"
     end
 "#;
 
-thread_local! {
-    static LUA_VM: RefCell<mlua::Lua> = RefCell::new({
-        let lua = mlua::Lua::new();
-        lua.load(LUA_SCRIPT).exec().expect("Failed to load Lua script");
-        lua
-    });
-}
+const LUA_PER_ROW: &str = "lua_per_row";
+const LUA_BATCH: &str = "lua_batch";
 
 // Per-row call
-fn call_lua_distance(x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
-    LUA_VM.with(|vm| {
-        let lua = vm.borrow();
-        let func: mlua::Function = lua.globals().get("distance").unwrap();
-        func.call::<f64>((x1, y1, x2, y2)).unwrap()
+fn call_lua_distance(x1: f64, y1: f64, x2: f64, y2: f64) -> mlua::Result<f64> {
+    lua_scripts::with_vm(|lua| {
+        let func: mlua::Function = lua.globals().get("distance")?;
+        func.call::<f64>((x1, y1, x2, y2))
     })
 }
 
 // Batch call - pass arrays to Lua
-fn call_lua_distance_batch(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64]) -> Vec<f64> {
-    LUA_VM.with(|vm| {
-        let lua = vm.borrow();
-        let func: mlua::Function = lua.globals().get("distance_batch").unwrap();
-        
+fn call_lua_distance_batch(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64]) -> mlua::Result<Vec<f64>> {
+    lua_scripts::with_vm(|lua| {
+        let func: mlua::Function = lua.globals().get("distance_batch")?;
+
         // Convert Rust slices to Lua tables
-        let x1_table = lua.create_sequence_from(x1.iter().copied()).unwrap();
-        let y1_table = lua.create_sequence_from(y1.iter().copied()).unwrap();
-        let x2_table = lua.create_sequence_from(x2.iter().copied()).unwrap();
-        let y2_table = lua.create_sequence_from(y2.iter().copied()).unwrap();
-        
+        let x1_table = lua.create_sequence_from(x1.iter().copied())?;
+        let y1_table = lua.create_sequence_from(y1.iter().copied())?;
+        let x2_table = lua.create_sequence_from(x2.iter().copied())?;
+        let y2_table = lua.create_sequence_from(y2.iter().copied())?;
+
         // Call Lua function with tables
-        let result: mlua::Table = func.call((x1_table, y1_table, x2_table, y2_table)).unwrap();
-        
+        let result: mlua::Table = func.call((x1_table, y1_table, x2_table, y2_table))?;
+
         // Convert Lua table back to Vec
-        result.sequence_values::<f64>().map(|r| r.unwrap()).collect()
+        result.sequence_values::<f64>().collect()
     })
 }
 
@@ -110,15 +112,21 @@ impl VArrowScalar for LuaPerRowScalar {
         _state: &Self::State,
         input: RecordBatch,
     ) -> Result<Arc<dyn Array>, Box<dyn Error>> {
-        let x1 = input.column(0).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y1 = input.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
-        let x2 = input.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y2 = input.column(3).as_any().downcast_ref::<Float64Array>().unwrap();
+        let [x1, y1, x2, y2] = arrow_scalar::doubles(&input)?;
+        let (module, mode) = lua_errors::policy(LUA_PER_ROW);
 
         // N Lua calls
-        let result: Vec<f64> = (0..input.num_rows())
-            .map(|i| call_lua_distance(x1.value(i), y1.value(i), x2.value(i), y2.value(i)))
-            .collect();
+        let mut result: Vec<Option<f64>> = Vec::with_capacity(input.num_rows());
+        for i in 0..input.num_rows() {
+            let args = [x1.value(i), y1.value(i), x2.value(i), y2.value(i)];
+            match call_lua_distance(args[0], args[1], args[2], args[3]) {
+                Ok(d) => result.push(Some(d)),
+                Err(e) => {
+                    lua_errors::handle(UdfError::from_lua(&module, LUA_PER_ROW, Some(i), &args, &e), mode)?;
+                    result.push(None);
+                }
+            }
+        }
 
         Ok(Arc::new(Float64Array::from(result)))
     }
@@ -144,10 +152,7 @@ impl VArrowScalar for LuaBatchScalar {
         _state: &Self::State,
         input: RecordBatch,
     ) -> Result<Arc<dyn Array>, Box<dyn Error>> {
-        let x1 = input.column(0).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y1 = input.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
-        let x2 = input.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y2 = input.column(3).as_any().downcast_ref::<Float64Array>().unwrap();
+        let [x1, y1, x2, y2] = arrow_scalar::doubles(&input)?;
 
         // Extract slices
         let x1_slice: Vec<f64> = (0..input.num_rows()).map(|i| x1.value(i)).collect();
@@ -155,8 +160,15 @@ impl VArrowScalar for LuaBatchScalar {
         let x2_slice: Vec<f64> = (0..input.num_rows()).map(|i| x2.value(i)).collect();
         let y2_slice: Vec<f64> = (0..input.num_rows()).map(|i| y2.value(i)).collect();
 
-        // 1 Lua call with all data
-        let result = call_lua_distance_batch(&x1_slice, &y1_slice, &x2_slice, &y2_slice);
+        // 1 Lua call with all data, so there is no row to report
+        let result = match call_lua_distance_batch(&x1_slice, &y1_slice, &x2_slice, &y2_slice) {
+            Ok(result) => result,
+            Err(e) => {
+                let (module, mode) = lua_errors::policy(LUA_BATCH);
+                lua_errors::handle(UdfError::from_lua(&module, LUA_BATCH, None, &[], &e), mode)?;
+                return Ok(Arc::new(Float64Array::new_null(input.num_rows())));
+            }
+        };
 
         Ok(Arc::new(Float64Array::from(result)))
     }
@@ -182,10 +194,7 @@ impl VArrowScalar for RustScalar {
         _state: &Self::State,
         input: RecordBatch,
     ) -> Result<Arc<dyn Array>, Box<dyn Error>> {
-        let x1 = input.column(0).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y1 = input.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
-        let x2 = input.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
-        let y2 = input.column(3).as_any().downcast_ref::<Float64Array>().unwrap();
+        let [x1, y1, x2, y2] = arrow_scalar::doubles(&input)?;
 
         let result: Vec<f64> = (0..input.num_rows())
             .map(|i| {
@@ -210,7 +219,7 @@ impl VArrowScalar for RustScalar {
 // Micro-benchmark outside DuckDB
 // ============================================================================
 
-fn microbenchmark() {
+fn microbenchmark() -> mlua::Result<()> {
     println!("=== Micro-Benchmark (Direct Lua Calls) ===\n");
     
     let n = 10_000;
@@ -221,20 +230,20 @@ fn microbenchmark() {
     
     // Warmup
     for i in 0..100 {
-        let _ = call_lua_distance(x1[i], y1[i], x2[i], y2[i]);
+        call_lua_distance(x1[i], y1[i], x2[i], y2[i])?;
     }
-    let _ = call_lua_distance_batch(&x1[..100], &y1[..100], &x2[..100], &y2[..100]);
+    call_lua_distance_batch(&x1[..100], &y1[..100], &x2[..100], &y2[..100])?;
     
     // Per-row calls
     let start = Instant::now();
     let _results: Vec<f64> = (0..n)
         .map(|i| call_lua_distance(x1[i], y1[i], x2[i], y2[i]))
-        .collect();
+        .collect::<mlua::Result<_>>()?;
     let per_row_time = start.elapsed();
     
     // Batch call
     let start = Instant::now();
-    let _results = call_lua_distance_batch(&x1, &y1, &x2, &y2);
+    let _results = call_lua_distance_batch(&x1, &y1, &x2, &y2)?;
     let batch_time = start.elapsed();
     
     // Pure Rust
@@ -274,6 +283,7 @@ fn microbenchmark() {
         speedup,
         if speedup > 1.0 { "faster ✅" } else { "slower ❌" });
     println!();
+    Ok(())
 }
 
 // ============================================================================
@@ -282,16 +292,17 @@ fn microbenchmark() {
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("=== DuckDB Vector UDF + Lua Investigation ===\n");
-    
+    lua_scripts::set_source("distance", LUA_SCRIPT);
+
     // Run micro-benchmark first
-    microbenchmark();
+    microbenchmark()?;
     
     // Create DuckDB connection
     let conn = Connection::open_in_memory()?;
 
     // Register all scalar functions
-    conn.register_scalar_function::<LuaPerRowScalar>("lua_per_row")?;
-    conn.register_scalar_function::<LuaBatchScalar>("lua_batch")?;
+    conn.register_scalar_function::<LuaPerRowScalar>(LUA_PER_ROW)?;
+    conn.register_scalar_function::<LuaBatchScalar>(LUA_BATCH)?;
     conn.register_scalar_function::<RustScalar>("rust_distance")?;
 
     // Create test table
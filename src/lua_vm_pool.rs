@@ -0,0 +1,443 @@
+//! Pooled Lua VMs with State Isolation Between Queries
+//!
+//! Shared by bins via `mod lua_vm_pool;`. The UDF bridges so far
+//! (`lua_scripts::with_vm`, `lua_call`) keep one VM per DuckDB worker thread
+//! for the life of the process, so whatever a mod leaves in a global (a
+//! counter, a cache, a patched `math.sqrt`) is still there in the next,
+//! unrelated query or tick. A `VmPool` instead:
+//!
+//! - owns `vms` VMs, each on its own worker thread. DuckDB threads hand
+//!   chunks over one shared queue, so 16 DuckDB threads and a 4-VM pool
+//!   still means 4 VMs (mlua's `Lua` is `!Send` without the `send` feature,
+//!   so the VMs stay put and the work moves)
+//! - runs the setup scripts once per VM, then snapshots every table
+//!   reachable from `_G` and the string metatable (contents and
+//!   metatables): the clean post-load environment
+//! - resets a VM before a job per `Reset`: never (the thread-local
+//!   behaviour), once per epoch, or before every job. Calls through SQL
+//!   start a new epoch with each DuckDB transaction (each statement, in
+//!   autocommit); `with_vm` callers call `begin_epoch()` at each tick
+//! - records jobs, resets, reset time and `used_memory()` per VM
+//!
+//! `Restore::Snapshot` rewrites the snapshotted tables in place. Tables a
+//! script only reaches through upvalues (`local cache = {}` at file scope)
+//! are not visible from `_G` and keep their state; `Restore::Rebuild`
+//! rebuilds the VM from the scripts instead, at the cost of a reload. A
+//! per-epoch reset ends with a full GC so `used_memory` reflects the live
+//! heap.
+//!
+//! `register(conn, sql_name, pool)` exposes a pool as
+//! `sql_name(name VARCHAR, a DOUBLE [, b, c, d]) -> DOUBLE`, calling the Lua
+//! global `name`; errors follow the owning mod's `lua_errors` policy. It is
+//! a macro over `<sql_name>_tx(txid_current(), name, ...)`, which is how a
+//! chunk tells the pool which transaction it belongs to.
+
+#![allow(dead_code)]
+
+use crate::lua_errors;
+use duckdb::arrow::array::{Array, Float64Array, Int64Array, StringArray};
+use duckdb::arrow::datatypes::DataType;
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::vscalar::{ArrowFunctionSignature, VArrowScalar};
+use duckdb::Connection;
+use mlua::{Lua, Table, Value};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Most arguments a pooled UDF can take.
+pub const MAX_POOLED_ARITY: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reset {
+    /// Globals persist for the life of the pool.
+    Never,
+    /// Reset before the first job of each epoch: each DuckDB transaction
+    /// for SQL calls, each `begin_epoch()` for `with_vm`.
+    PerEpoch,
+    /// Reset before every job that follows another job.
+    EveryJob,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restore {
+    /// Rewrite the tables reachable from `_G` to their post-load contents.
+    Snapshot,
+    /// Throw the VM away and run the setup scripts again.
+    Rebuild,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+    pub vms: usize,
+    pub reset: Reset,
+    pub restore: Restore,
+    /// Load LuaJIT's `ffi` library (`Lua::unsafe_new`).
+    pub ffi: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig { vms: 4, reset: Reset::PerEpoch, restore: Restore::Snapshot, ffi: false }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PoolError {
+    /// A setup script failed on VM `vm`; the pool was not created.
+    Setup { vm: usize, message: String },
+    /// The job returned a Lua error.
+    Lua(String),
+    /// The pool is shutting down, or a worker died mid-job.
+    Closed,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::Setup { vm, message } => write!(f, "setup failed on VM {}: {}", vm, message),
+            PoolError::Lua(message) => f.write_str(message),
+            PoolError::Closed => f.write_str("Lua VM pool is closed"),
+        }
+    }
+}
+
+impl Error for PoolError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct VmStats {
+    pub id: usize,
+    pub jobs: u64,
+    pub resets: u64,
+    pub reset_time: Duration,
+    /// `used_memory()` after the last job.
+    pub used_memory: usize,
+    pub peak_memory: usize,
+}
+
+// ============================================================================
+// One VM (worker thread only)
+// ============================================================================
+
+struct TableSnapshot {
+    table: Table,
+    entries: Vec<(Value, Value)>,
+    metatable: Option<Table>,
+}
+
+struct Vm {
+    lua: Lua,
+    snapshot: Vec<TableSnapshot>,
+    /// Epoch of the last job run.
+    epoch: u64,
+    /// A job has run since the last reset.
+    dirty: bool,
+}
+
+/// Every table reachable from `_G` and the string metatable, through values
+/// and metatables.
+fn snapshot(lua: &Lua) -> mlua::Result<Vec<TableSnapshot>> {
+    let mut pending = vec![lua.globals()];
+    pending.extend(lua.load("return getmetatable('')").eval::<Option<Table>>()?);
+    let mut seen = HashSet::new();
+    let mut tables = Vec::new();
+    while let Some(table) = pending.pop() {
+        if !seen.insert(table.to_pointer()) {
+            continue;
+        }
+        let entries: Vec<(Value, Value)> = table.pairs::<Value, Value>().collect::<mlua::Result<_>>()?;
+        pending.extend(entries.iter().filter_map(|(_, v)| v.as_table().cloned()));
+        let metatable = table.metatable();
+        pending.extend(metatable.clone());
+        tables.push(TableSnapshot { table, entries, metatable });
+    }
+    Ok(tables)
+}
+
+fn restore(snapshot: &[TableSnapshot]) -> mlua::Result<()> {
+    for s in snapshot {
+        let keys: Vec<Value> = s.table.pairs::<Value, Value>().map(|kv| kv.map(|(k, _)| k)).collect::<mlua::Result<_>>()?;
+        for k in keys {
+            s.table.raw_set(k, Value::Nil)?;
+        }
+        for (k, v) in &s.entries {
+            s.table.raw_set(k.clone(), v.clone())?;
+        }
+        s.table.set_metatable(s.metatable.clone());
+    }
+    Ok(())
+}
+
+impl Vm {
+    fn new(scripts: &[(String, String)], ffi: bool) -> mlua::Result<Self> {
+        // FFI needs the unsafe standard library set.
+        let lua = if ffi { unsafe { Lua::unsafe_new() } } else { Lua::new() };
+        for (name, source) in scripts {
+            lua.load(source.as_str()).set_name(name.as_str()).exec()?;
+        }
+        let snapshot = snapshot(&lua)?;
+        Ok(Vm { lua, snapshot, epoch: 0, dirty: false })
+    }
+
+    fn reset(&mut self, config: &PoolConfig, scripts: &[(String, String)]) -> mlua::Result<()> {
+        if config.restore == Restore::Rebuild {
+            // Scripts already loaded once, so this only fails on OOM and the
+            // like; fall back to the snapshot then.
+            if let Ok(vm) = Vm::new(scripts, config.ffi) {
+                *self = vm;
+                return Ok(());
+            }
+        }
+        restore(&self.snapshot)?;
+        if config.reset == Reset::PerEpoch {
+            self.lua.gc_collect()?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Pool
+// ============================================================================
+
+struct Job {
+    epoch: u64,
+    run: Box<dyn FnOnce(&Lua) + Send>,
+}
+
+pub struct VmPool {
+    config: PoolConfig,
+    sender: Option<Sender<Job>>,
+    epoch: AtomicU64,
+    /// (DuckDB transaction, epoch) of the last SQL call.
+    sql_epoch: Mutex<Option<(i64, u64)>>,
+    stats: Arc<Mutex<Vec<VmStats>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+fn worker(
+    id: usize,
+    config: PoolConfig,
+    scripts: Arc<[(String, String)]>,
+    jobs: Arc<Mutex<Receiver<Job>>>,
+    stats: Arc<Mutex<Vec<VmStats>>>,
+    ready: Sender<Result<(), PoolError>>,
+) {
+    let mut vm = match Vm::new(&scripts, config.ffi) {
+        Ok(vm) => vm,
+        Err(e) => {
+            let _ = ready.send(Err(PoolError::Setup { vm: id, message: e.to_string() }));
+            return;
+        }
+    };
+    let _ = ready.send(Ok(()));
+    drop(ready);
+
+    loop {
+        // The lock is held only while waiting; whichever idle VM gets it
+        // takes the next job.
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let due = vm.dirty
+            && match config.reset {
+                Reset::Never => false,
+                Reset::PerEpoch => vm.epoch != job.epoch,
+                Reset::EveryJob => true,
+            };
+        let mut reset_time = None;
+        if due {
+            let start = Instant::now();
+            // A failed restore leaves the VM as it was; the job still runs.
+            if vm.reset(&config, &scripts).is_ok() {
+                reset_time = Some(start.elapsed());
+            }
+        }
+        vm.epoch = job.epoch;
+        vm.dirty = true;
+        (job.run)(&vm.lua);
+
+        let used = vm.lua.used_memory();
+        let mut stats = stats.lock().unwrap();
+        let s = &mut stats[id];
+        s.jobs += 1;
+        if let Some(elapsed) = reset_time {
+            s.resets += 1;
+            s.reset_time += elapsed;
+        }
+        s.used_memory = used;
+        s.peak_memory = s.peak_memory.max(used);
+    }
+}
+
+impl VmPool {
+    /// Starts `config.vms` VMs and runs `scripts` (name, source) on each, in
+    /// order. Fails if any VM fails to load them.
+    pub fn new(config: PoolConfig, scripts: &[(&str, &str)]) -> Result<Self, PoolError> {
+        let vms = config.vms.max(1);
+        let scripts: Arc<[(String, String)]> =
+            scripts.iter().map(|(name, source)| (name.to_string(), source.to_string())).collect();
+        let (sender, receiver) = mpsc::channel();
+        let jobs = Arc::new(Mutex::new(receiver));
+        let stats = Arc::new(Mutex::new((0..vms).map(|id| VmStats { id, ..Default::default() }).collect()));
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let workers = (0..vms)
+            .map(|id| {
+                let (scripts, jobs, stats, ready) = (scripts.clone(), jobs.clone(), stats.clone(), ready_tx.clone());
+                thread::Builder::new()
+                    .name(format!("lua-pool-{}", id))
+                    .spawn(move || worker(id, config, scripts, jobs, stats, ready))
+                    .expect("spawn Lua pool worker")
+            })
+            .collect();
+        drop(ready_tx);
+
+        // Dropping the pool on error closes the queue and joins the rest.
+        let pool = VmPool { config: PoolConfig { vms, ..config }, sender: Some(sender), epoch: AtomicU64::new(0), sql_epoch: Mutex::new(None), stats, workers };
+        for _ in 0..vms {
+            ready_rx.recv().map_err(|_| PoolError::Closed)??;
+        }
+        Ok(pool)
+    }
+
+    pub fn config(&self) -> PoolConfig {
+        self.config
+    }
+
+    /// Starts a new tick for `with_vm` callers; SQL calls start their own
+    /// (see `transaction_epoch`). With `Reset::PerEpoch` each VM is reset
+    /// before its first job in the new epoch. Returns the new epoch.
+    pub fn begin_epoch(&self) -> u64 {
+        self.epoch.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// The epoch for a SQL call in DuckDB transaction `txid`: the previous
+    /// SQL call's if that ran in the same transaction, a new one otherwise.
+    /// Chunks of concurrent queries that interleave start new epochs too,
+    /// costing resets but never sharing globals.
+    fn transaction_epoch(&self, txid: i64) -> u64 {
+        let mut last = self.sql_epoch.lock().unwrap();
+        match *last {
+            Some((current, epoch)) if current == txid => epoch,
+            _ => {
+                let epoch = self.begin_epoch();
+                *last = Some((txid, epoch));
+                epoch
+            }
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    /// Runs `f` on the next idle VM and waits for it. Lua values cannot
+    /// leave the VM's thread, so `R` must be plain data.
+    pub fn with_vm<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Lua) -> mlua::Result<R> + Send + 'static,
+    ) -> Result<R, PoolError> {
+        self.run_in(self.epoch(), f)
+    }
+
+    fn run_in<R: Send + 'static>(
+        &self,
+        epoch: u64,
+        f: impl FnOnce(&Lua) -> mlua::Result<R> + Send + 'static,
+    ) -> Result<R, PoolError> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let job = Job {
+            epoch,
+            run: Box::new(move |lua| {
+                let _ = tx.send(f(lua).map_err(|e| e.to_string()));
+            }),
+        };
+        self.sender.as_ref().ok_or(PoolError::Closed)?.send(job).map_err(|_| PoolError::Closed)?;
+        rx.recv().map_err(|_| PoolError::Closed)?.map_err(PoolError::Lua)
+    }
+
+    /// Per-VM counters, as of each VM's last completed job.
+    pub fn stats(&self) -> Vec<VmStats> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    pub fn used_memory(&self) -> usize {
+        self.stats.lock().unwrap().iter().map(|s| s.used_memory).sum()
+    }
+}
+
+impl Drop for VmPool {
+    fn drop(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// ============================================================================
+// SQL bridge
+// ============================================================================
+
+/// `<sql_name>_tx(txid BIGINT, name VARCHAR, a DOUBLE [, b, c, d]) -> DOUBLE`
+/// on the pool it was registered with; `txid` picks the epoch.
+pub struct PooledCall;
+
+impl VArrowScalar for PooledCall {
+    type State = Option<Arc<VmPool>>;
+
+    fn invoke(state: &Self::State, input: RecordBatch) -> Result<Arc<dyn Array>, Box<dyn Error>> {
+        let pool = state.as_ref().ok_or("pooled Lua call registered without a pool")?;
+        let txids = input.column(0).as_any().downcast_ref::<Int64Array>().ok_or("lua pool: txid must be BIGINT")?;
+        if input.column(1).as_any().downcast_ref::<StringArray>().is_none() {
+            return Err("lua pool: name must be VARCHAR".into());
+        }
+        if (2..input.num_columns()).any(|c| input.column(c).as_any().downcast_ref::<Float64Array>().is_none()) {
+            return Err("lua pool: arguments must be DOUBLE".into());
+        }
+        let epoch = if txids.is_empty() { pool.epoch() } else { pool.transaction_epoch(txids.value(0)) };
+
+        let result = pool.run_in(epoch, move |lua| {
+            let names = input.column(1).as_any().downcast_ref::<StringArray>().expect("checked above");
+            let args: Vec<&Float64Array> = (2..input.num_columns())
+                .map(|c| input.column(c).as_any().downcast_ref::<Float64Array>().expect("checked above"))
+                .collect();
+            let globals = lua.globals();
+            lua_errors::call_rows(names, &args, |_, name| globals.get(name))
+        })?;
+        Ok(Arc::new(Float64Array::from(result?)))
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        (1..=MAX_POOLED_ARITY)
+            .map(|n| {
+                let mut args = vec![DataType::Int64, DataType::Utf8];
+                args.extend(std::iter::repeat_n(DataType::Float64, n));
+                ArrowFunctionSignature::exact(args, DataType::Float64)
+            })
+            .collect()
+    }
+}
+
+/// Registers `sql_name` as a call into `pool`: `<sql_name>_tx` plus a macro
+/// per arity that passes it the current transaction.
+pub fn register(conn: &Connection, sql_name: &str, pool: &Arc<VmPool>) -> duckdb::Result<()> {
+    let raw = format!("{}_tx", sql_name);
+    conn.register_scalar_function_with_state::<PooledCall>(&raw, &Some(pool.clone()))?;
+    let overloads: Vec<String> = (1..=MAX_POOLED_ARITY)
+        .map(|n| {
+            let params: Vec<String> = (0..n).map(|i| format!("a{}", i)).collect();
+            let params = params.join(", ");
+            format!("(name, {0}) AS {1}(txid_current()::BIGINT, name, {0})", params, raw)
+        })
+        .collect();
+    conn.execute_batch(&format!("CREATE OR REPLACE MACRO {} {};", sql_name, overloads.join(", ")))
+}
@@ -0,0 +1,238 @@
+//! Lua VM Pool: Isolated Queries, Bounded VMs
+//!
+//! With `lua_vm_pool.rs` a mod's globals no longer outlive the query (or
+//! tick) that set them, and the number of VMs no longer follows DuckDB's
+//! thread count:
+//! 1. A leaky mod (global counter, patched `math.sqrt`, file-local cache)
+//!    queried twice under each reset policy; every statement is an epoch of
+//!    its own, and a transaction is one
+//! 2. 8 DuckDB threads against 1, 2, 4 and 8 VMs: VM count, jobs per VM,
+//!    throughput
+//! 3. Cost of resetting: never vs per epoch vs every job, snapshot vs
+//!    rebuild
+//! 4. 50 ticks of a mod that appends to a global table: memory per VM with
+//!    and without per-tick resets
+//! 5. A setup script that fails on load
+
+mod lua_errors;
+mod lua_vm_pool;
+
+use duckdb::Connection;
+use lua_vm_pool::{PoolConfig, Reset, Restore, VmPool};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Instant;
+
+const ROWS: usize = 1_000_000;
+const TICKS: usize = 50;
+
+const LEAKY_LUA: &str = r#"
+calls = 0
+seen = {}
+local cache = {}
+
+function count(x)
+  calls = calls + 1
+  return calls
+end
+
+function tamper(x)
+  math.sqrt = function() return -1 end
+  return x
+end
+
+function root(x)
+  return math.sqrt(x)
+end
+
+function cached(x)
+  cache[#cache + 1] = x
+  return #cache
+end
+
+function remember(x)
+  seen[#seen + 1] = { x = x, tag = string.rep("x", 32) .. x }
+  return #seen
+end
+
+function damage(hp, armor)
+  return math.max(hp - armor, 0)
+end
+"#;
+
+fn pool(vms: usize, reset: Reset, restore: Restore) -> Result<Arc<VmPool>, Box<dyn Error>> {
+    Ok(Arc::new(VmPool::new(PoolConfig { vms, reset, restore, ffi: false }, &[("leaky.lua", LEAKY_LUA)])?))
+}
+
+fn query_f64(conn: &Connection, sql: &str) -> Result<f64, Box<dyn Error>> {
+    Ok(conn.query_row(sql, [], |r| r.get(0))?)
+}
+
+fn kib(bytes: usize) -> f64 {
+    bytes as f64 / 1024.0
+}
+
+// ============================================================================
+// Main
+// ============================================================================
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== Lua VM Pool: Isolated Queries, Bounded VMs ===\n");
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch(&format!(
+        "SET threads TO 8;
+         CREATE TABLE units AS
+         SELECT (i % 1000)::DOUBLE AS hp, (i % 37)::DOUBLE AS armor FROM range({ROWS}) t(i);"
+    ))?;
+    let mut failures = 0;
+
+    // --------------------------------------------------------------------
+    // 1. Isolation
+    // --------------------------------------------------------------------
+    println!("--- 1. Leaky mod, 1 VM, two queries per check ---");
+    let sqrt_sum = query_f64(&conn, "SELECT sum(sqrt(i::DOUBLE)) FROM range(10000) t(i)")?;
+    let policies = [
+        ("never", Reset::Never, Restore::Snapshot),
+        ("per epoch", Reset::PerEpoch, Restore::Snapshot),
+        ("per epoch/rebuild", Reset::PerEpoch, Restore::Rebuild),
+        ("every job", Reset::EveryJob, Restore::Snapshot),
+    ];
+    println!("  {:<18} {:>8} {:>8}  {:<15} {:>10}", "reset", "count 1", "count 2", "math.sqrt", "cache 2");
+    for (i, (label, reset, restore)) in policies.into_iter().enumerate() {
+        let p = pool(1, reset, restore)?;
+        let f = format!("lua_iso{}", i);
+        lua_vm_pool::register(&conn, &f, &p)?;
+        let run = |sql: String| query_f64(&conn, &sql);
+        let q = |func: &str| format!("SELECT max({}('{}', i::DOUBLE)) FROM range(10000) t(i)", f, func);
+        let first = run(q("count"))?;
+        let second = run(q("count"))?;
+        run(q("tamper"))?;
+        let sqrt = run(format!("SELECT sum({}('root', i::DOUBLE)) FROM range(10000) t(i)", f))?;
+        run(q("cached"))?;
+        let cache = run(q("cached"))?;
+
+        let sqrt_ok = (sqrt - sqrt_sum).abs() <= 1e-9 * sqrt_sum;
+        println!("  {:<18} {:>8} {:>8}  {:<15} {:>10}", label, first, second,
+                 if sqrt_ok { "restored" } else { "still patched" }, cache);
+        let expect_isolated = reset != Reset::Never;
+        if (first == second) != expect_isolated || sqrt_ok != expect_isolated {
+            failures += 1;
+            println!("  ✗ {} did not {} globals", label, if expect_isolated { "reset" } else { "keep" });
+        }
+        if restore == Restore::Rebuild && cache != 10000.0 {
+            failures += 1;
+            println!("  ✗ rebuild kept the file-local cache");
+        }
+    }
+    println!("  (every job resets per DuckDB chunk, hence counts ≤ 2048; the file-local");
+    println!("   `cache` is only reachable through an upvalue, so only rebuild clears it)");
+
+    let p = pool(1, Reset::PerEpoch, Restore::Snapshot)?;
+    lua_vm_pool::register(&conn, "lua_iso_tx", &p)?;
+    let q = "SELECT max(lua_iso_tx('count', i::DOUBLE)) FROM range(10000) t(i)";
+    conn.execute_batch("BEGIN TRANSACTION;")?;
+    let (first, second) = (query_f64(&conn, q)?, query_f64(&conn, q)?);
+    conn.execute_batch("COMMIT;")?;
+    let after = query_f64(&conn, q)?;
+    println!("  per epoch, one transaction: count {} then {}; next statement {}", first, second, after);
+    if second != 2.0 * first || after != first {
+        failures += 1;
+        println!("  ✗ a transaction should be one epoch");
+    }
+
+    // --------------------------------------------------------------------
+    // 2. Bounded VM count
+    // --------------------------------------------------------------------
+    println!("\n--- 2. {} rows, 8 DuckDB threads ---", ROWS);
+    let want = query_f64(&conn, "SELECT sum(greatest(hp - armor, 0)) FROM units")?;
+    for vms in [1, 2, 4, 8] {
+        let p = pool(vms, Reset::PerEpoch, Restore::Snapshot)?;
+        let f = format!("lua_vms{}", vms);
+        lua_vm_pool::register(&conn, &f, &p)?;
+        let sql = format!("SELECT sum({}('damage', hp, armor)) FROM units", f);
+        query_f64(&conn, &sql)?; // warm up
+        let start = Instant::now();
+        let got = query_f64(&conn, &sql)?;
+        let elapsed = start.elapsed();
+        let stats = p.stats();
+        let jobs: Vec<u64> = stats.iter().map(|s| s.jobs).collect();
+        let ok = got == want;
+        if !ok || stats.len() != vms {
+            failures += 1;
+        }
+        println!("  {} VM(s): {:>9.2?}  {:>6.1} ns/row  jobs per VM {:?}  {}",
+                 vms, elapsed, elapsed.as_nanos() as f64 / ROWS as f64, jobs, if ok { "✓" } else { "✗" });
+    }
+
+    // --------------------------------------------------------------------
+    // 3. Reset cost
+    // --------------------------------------------------------------------
+    println!("\n--- 3. Reset cost, 4 VMs ---");
+    for (label, reset, restore) in [
+        ("never", Reset::Never, Restore::Snapshot),
+        ("per epoch", Reset::PerEpoch, Restore::Snapshot),
+        ("every job", Reset::EveryJob, Restore::Snapshot),
+        ("every job/rebuild", Reset::EveryJob, Restore::Rebuild),
+    ] {
+        let p = pool(4, reset, restore)?;
+        let f = format!("lua_cost_{}", label.replace([' ', '/'], "_"));
+        lua_vm_pool::register(&conn, &f, &p)?;
+        let sql = format!("SELECT sum({}('damage', hp, armor)) FROM units", f);
+        query_f64(&conn, &sql)?;
+        let start = Instant::now();
+        query_f64(&conn, &sql)?;
+        let elapsed = start.elapsed();
+        let stats = p.stats();
+        let resets: u64 = stats.iter().map(|s| s.resets).sum();
+        let reset_time: f64 = stats.iter().map(|s| s.reset_time.as_secs_f64()).sum();
+        println!("  {:<18} {:>9.2?}  {:>6.1} ns/row  {:>4} resets, {:>7.1} µs each",
+                 label, elapsed, elapsed.as_nanos() as f64 / ROWS as f64, resets,
+                 if resets > 0 { reset_time * 1e6 / resets as f64 } else { 0.0 });
+    }
+
+    // --------------------------------------------------------------------
+    // 4. Memory per VM over ticks
+    // --------------------------------------------------------------------
+    println!("\n--- 4. {} ticks appending to a global table, 2 VMs ---", TICKS);
+    for (label, reset) in [("never", Reset::Never), ("per tick", Reset::PerEpoch)] {
+        let p = pool(2, reset, Restore::Snapshot)?;
+        let f = format!("lua_mem_{}", label.replace(' ', "_"));
+        lua_vm_pool::register(&conn, &f, &p)?;
+        let mut last = 0.0;
+        for _ in 0..TICKS {
+            last = query_f64(&conn, &format!("SELECT max({}('remember', i::DOUBLE)) FROM range(4000) t(i)", f))?;
+        }
+        let per_vm: Vec<String> = p.stats().iter().map(|s| format!("{:.0}", kib(s.used_memory))).collect();
+        let peak = p.stats().iter().map(|s| s.peak_memory).max().unwrap_or(0);
+        println!("  {:<9} last tick saw {:>6} entries on one VM; KiB per VM [{}], peak {:.0} KiB",
+                 label, last, per_vm.join(", "), kib(peak));
+        if reset == Reset::PerEpoch && last > 4000.0 {
+            failures += 1;
+            println!("  ✗ per-tick reset kept entries from earlier ticks");
+        }
+    }
+
+    // --------------------------------------------------------------------
+    // 5. Setup failure
+    // --------------------------------------------------------------------
+    println!("\n--- 5. Setup failure ---");
+    match VmPool::new(PoolConfig::default(), &[("broken.lua", "function damage(hp) return hp * end")]) {
+        Ok(_) => {
+            failures += 1;
+            println!("  ✗ pool created from a script that does not compile");
+        }
+        Err(e) => println!("  ✓ {}", e.to_string().lines().next().unwrap_or("")),
+    }
+
+    println!("\n=== Summary ===\n");
+    println!("  • Per-epoch resets give each query or tick the post-load globals, including stdlib tables");
+    println!("  • VM count is the pool size, not DuckDB's thread count; chunks queue for the next idle VM");
+    println!("  • Snapshot restore misses upvalue-only state; rebuild clears it at the cost of a reload");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Mutable LuaJIT FFI Column Views Written Back to the World
+//!
+//! `DistanceBatch` in `duckdb_luajit_ffi.rs` gives Lua read-only inputs and a
+//! fresh `out` buffer per call. With `ffi_columns.rs` Lua edits component
+//! columns in place (`x[i] = x[i] + vx[i] * dt`) and the edited buffers go
+//! back in bulk:
+//! 1. DuckDB: query → buffers → Lua → Arrow batch → `arrow_import` → one
+//!    UPDATE; compared with the same tick in SQL
+//! 2. Polars: frame → buffers → Lua → columns replaced without a copy;
+//!    compared with the same tick as an expression
+//! 3. The writable Vecs become the Arrow buffers as-is (pointer check)
+//! 4. Guards: writing a read-only column, writing back into the wrong frame,
+//!    a user table named like the staging table
+//! 5. Baseline: per-row `UPDATE ... WHERE id = ?` write-back
+
+mod ffi_columns;
+mod world_diff;
+
+use duckdb::arrow::array::{Array, Float64Array};
+use duckdb::{params, Connection};
+use ffi_columns::ColumnBuffers;
+use mlua::{Function, Lua};
+use polars::prelude::*;
+use std::error::Error;
+use std::time::{Duration, Instant};
+use world_diff::{diff, TableData};
+
+const ROWS: usize = 1_000_000;
+const DT: f64 = 1.0 / 60.0;
+const PER_ROW_SAMPLE: usize = 10_000;
+
+const SCRIPT: &str = r#"
+function integrate(view, dt)
+  local x, y, vx, vy = view.x, view.y, view.vx, view.vy
+  for i = 0, view.n - 1 do
+    x[i] = x[i] + vx[i] * dt
+    y[i] = y[i] + vy[i] * dt
+  end
+end
+
+function cheat(view)
+  view.vx[0] = 1e9
+end
+"#;
+
+const UNITS_SQL: &str = "
+    SELECT i AS id, (i % 1000)::DOUBLE AS x, (i // 1000)::DOUBLE AS y,
+           ((i % 7) - 3) * 1.5::DOUBLE AS vx, ((i % 11) - 5) * 0.5::DOUBLE AS vy,
+           (50 + i % 51)::INTEGER AS hp
+    FROM range({rows}) t(i)";
+
+fn make_polars(conn: &Connection) -> Result<DataFrame, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT id, x, y, vx, vy, hp FROM units_ref ORDER BY id")?;
+    let rows: Vec<(i64, f64, f64, f64, f64, i32)> = stmt
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?, r.get(5)?)))?
+        .collect::<duckdb::Result<_>>()?;
+    Ok(df![
+        "id" => rows.iter().map(|r| r.0).collect::<Vec<_>>(),
+        "x" => rows.iter().map(|r| r.1).collect::<Vec<_>>(),
+        "y" => rows.iter().map(|r| r.2).collect::<Vec<_>>(),
+        "vx" => rows.iter().map(|r| r.3).collect::<Vec<_>>(),
+        "vy" => rows.iter().map(|r| r.4).collect::<Vec<_>>(),
+        "hp" => rows.iter().map(|r| r.5).collect::<Vec<_>>()
+    ]?)
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== Mutable LuaJIT FFI Column Views ===\n");
+    println!("{} rows\n", ROWS);
+
+    // FFI needs the unsafe standard library set.
+    let lua = unsafe { Lua::unsafe_new() };
+    lua.load(SCRIPT).set_name("integrate.lua").exec()?;
+    let integrate: Function = lua.globals().get("integrate")?;
+    let cheat: Function = lua.globals().get("cheat")?;
+
+    let conn = Connection::open_in_memory()?;
+    let units = UNITS_SQL.replace("{rows}", &ROWS.to_string());
+    conn.execute_batch(&format!("CREATE TABLE units AS {units}; CREATE TABLE units_ref AS {units};"))?;
+    let mut failures = 0;
+
+    // --------------------------------------------------------------------
+    // 1. DuckDB round trip
+    // --------------------------------------------------------------------
+    println!("--- 1. DuckDB ---");
+    let start = Instant::now();
+    let mut buffers = ColumnBuffers::from_duckdb(&conn, "SELECT id, x, y, vx, vy FROM units", "id", &["x", "y"])?;
+    let load = start.elapsed();
+    let start = Instant::now();
+    integrate.call::<()>((buffers.lua_view(&lua)?, DT))?;
+    let run = start.elapsed();
+    let start = Instant::now();
+    let updated = buffers.write_duckdb(&conn, "units")?;
+    let duck_write = start.elapsed();
+
+    let start = Instant::now();
+    conn.execute("UPDATE units_ref SET x = x + vx * ?, y = y + vy * ?", params![DT, DT])?;
+    let sql = start.elapsed();
+    println!("  load {:.1} ms, lua {:.1} ms, write back {:.1} ms ({} rows)   vs SQL UPDATE {:.1} ms",
+             ms(load), ms(run), ms(duck_write), updated, ms(sql));
+    let d = diff(&TableData::from_duckdb(&conn, "units_ref", "id")?, &TableData::from_duckdb(&conn, "units", "id")?, 1e-9);
+    println!("  vs SQL tick: {} mismatches", d.mismatches.len());
+    if updated != ROWS || !d.is_empty() {
+        failures += 1;
+        d.print(5);
+    }
+
+    // --------------------------------------------------------------------
+    // 2. Polars round trip
+    // --------------------------------------------------------------------
+    println!("\n--- 2. Polars ---");
+    let mut df = make_polars(&conn)?;
+    // units_ref has already moved once; compare against one more tick.
+    let expected = df
+        .clone()
+        .lazy()
+        .with_columns([(col("x") + col("vx") * lit(DT)).alias("x"), (col("y") + col("vy") * lit(DT)).alias("y")])
+        .collect()?;
+    let start = Instant::now();
+    let mut buffers = ColumnBuffers::from_polars(&df, "id", &["x", "y"])?;
+    let load = start.elapsed();
+    let start = Instant::now();
+    integrate.call::<()>((buffers.lua_view(&lua)?, DT))?;
+    let run = start.elapsed();
+    let start = Instant::now();
+    buffers.write_polars(&mut df)?;
+    let write = start.elapsed();
+    println!("  load {:.1} ms, lua {:.1} ms, write back {:.1} ms", ms(load), ms(run), ms(write));
+    let d = diff(&TableData::from_polars(&expected, "id")?, &TableData::from_polars(&df, "id")?, 1e-9);
+    let hp_type = df.column("hp")?.dtype().clone();
+    println!("  vs Polars expression: {} mismatches; untouched hp keeps dtype {}", d.mismatches.len(), hp_type);
+    if !d.is_empty() || hp_type != DataType::Int32 {
+        failures += 1;
+        d.print(5);
+    }
+
+    // --------------------------------------------------------------------
+    // 3. No copy on the way out
+    // --------------------------------------------------------------------
+    println!("\n--- 3. Buffers move into Arrow ---");
+    let buffers = ColumnBuffers::from_duckdb(&conn, "SELECT id, x, vx FROM units LIMIT 1000", "id", &["x"])?;
+    let before = buffers.column("x").map(|x| x.as_ptr()).unwrap_or(std::ptr::null());
+    let batch = buffers.into_record_batch()?;
+    let x = batch.column(1).as_any().downcast_ref::<Float64Array>().ok_or("x is not f64")?;
+    let same = x.values().as_ptr() == before;
+    println!("  x buffer {:p} → Arrow {:p}: {}", before, x.values().as_ptr(), if same { "same allocation" } else { "copied" });
+    if !same || batch.num_columns() != 2 {
+        failures += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 4. Guards
+    // --------------------------------------------------------------------
+    println!("\n--- 4. Guards ---");
+    let mut buffers = ColumnBuffers::from_polars(&df, "id", &["x", "y"])?;
+    match cheat.call::<()>(buffers.lua_view(&lua)?) {
+        Ok(()) => {
+            failures += 1;
+            println!("  ✗ wrote a read-only column");
+        }
+        Err(e) => println!("  ✓ read-only column: {}", e.to_string().lines().next().unwrap_or("")),
+    }
+    let mut shorter = df.slice(0, ROWS / 2);
+    match buffers.write_polars(&mut shorter) {
+        Ok(()) => {
+            failures += 1;
+            println!("  ✗ wrote {} rows into a {}-row frame", ROWS, ROWS / 2);
+        }
+        Err(e) => println!("  ✓ wrong frame: {}", e),
+    }
+    conn.execute_batch("CREATE TABLE main._ffi_import AS SELECT 42 AS keep;")?;
+    ColumnBuffers::from_duckdb(&conn, "SELECT id, x FROM units LIMIT 10", "id", &["x"])?.write_duckdb(&conn, "units")?;
+    match conn.query_row("SELECT keep FROM main._ffi_import", [], |r| r.get::<_, i64>(0)) {
+        Ok(42) => println!("  ✓ main._ffi_import untouched by the staging table"),
+        other => {
+            failures += 1;
+            println!("  ✗ main._ffi_import after write back: {:?}", other);
+        }
+    }
+
+    // --------------------------------------------------------------------
+    // 5. Per-row baseline
+    // --------------------------------------------------------------------
+    println!("\n--- 5. Per-row write-back ({} rows, extrapolated) ---", PER_ROW_SAMPLE);
+    let sample = ColumnBuffers::from_duckdb(
+        &conn,
+        &format!("SELECT id, x, y FROM units ORDER BY id LIMIT {}", PER_ROW_SAMPLE),
+        "id",
+        &["x", "y"],
+    )?;
+    let (xs, ys) = (sample.column("x").unwrap_or(&[]), sample.column("y").unwrap_or(&[]));
+    let start = Instant::now();
+    {
+        let mut stmt = conn.prepare("UPDATE units SET x = ?, y = ? WHERE id = ?")?;
+        for (i, id) in sample.ids.iter().enumerate() {
+            stmt.execute(params![xs[i], ys[i], *id])?;
+        }
+    }
+    let per_row = start.elapsed();
+    let projected = per_row.as_secs_f64() * (ROWS as f64 / PER_ROW_SAMPLE as f64);
+    println!("  {:.1} ms for {} rows → ~{:.1} s for {} (Arrow write-back: {:.1} ms)",
+             ms(per_row), PER_ROW_SAMPLE, projected, ROWS, ms(duck_write));
+
+    println!("\n=== Summary ===\n");
+    println!("  • Lua writes straight into Rust-owned buffers through `double*` views");
+    println!("  • DuckDB write-back: one Arrow batch, scanned by `arrow_import`, one UPDATE");
+    println!("  • Polars write-back: each buffer becomes the new column without a copy");
+    println!("  • Read-only columns are `const double*`; a stale frame is refused");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,791 @@
+//! Mod Packages: Manifests, Dependencies and Load Order
+//!
+//! Shared by bins via `mod mod_loader;` (needs `mod lua_scripts;`,
+//! `mod lua_errors;`, `mod mod_permissions;` and `mod system_runner;`
+//! alongside). A mods directory holds one subdirectory per mod, each with a
+//! `mod.lua` that returns its manifest (evaluated with no standard library
+//! and an empty environment, so it is data only):
+//!
+//!   return {
+//!     name = "combat", version = "1.2.0",
+//!     dependencies = { core = ">=1.0" },         -- "*", "=1.2.0", ">=1.0", "^1.2"
+//!     components = { attack = "id BIGINT, power DOUBLE" },
+//!     scripts = { "combat.lua" },                -- run in every Lua VM, in order
+//!     udfs = { damage = 2 },                     -- Lua global -> SQL function, arity
+//!     on_error = "null",                         -- "fail" (default) or "null", see lua_errors.rs
+//!     systems = { { name = "apply_damage", sql = "UPDATE ..." } },
+//!     permissions = {                            -- see mod_permissions.rs
+//!       read = { units = { "id", "hp" } }, write = { units = { "hp" } },
+//!     },
+//!   }
+//!
+//! `read_mods` parses the manifests, `resolve_load_order` sorts them
+//! topologically (dependencies first, ties by name) and rejects missing
+//! dependencies, unsatisfied versions, duplicates and cycles, and
+//! `register_mods` creates the component tables, loads the scripts into
+//! `lua_scripts` with the mod's `db` (see mod_permissions.rs), exposes each UDF as a SQL macro over `lua_call(name, ...)`
+//! and adds the systems to a `SystemRunner` as `<mod>.<system>`. It returns
+//! a `LoadedMods` that borrows the connection and unloads the mods again
+//! when dropped; a mod name can't be loaded twice at once. A Lua error
+//! in a UDF becomes a `lua_errors::UdfError` naming the mod, function and
+//! row, and is counted against the mod.
+//!
+//! Component tables are created in `main` and tagged with the mod's name; a
+//! name taken by any other table is refused. Systems go through the mod's
+//! `ModSandbox::check_write`, so they touch only what its `permissions` and
+//! own components allow, and run as the checked `main.<table>` statement.
+//!
+//! Manifests are untrusted: mod, table, column, UDF and system names must be
+//! plain identifiers, column types come from `COMPONENT_TYPES`, script paths
+//! must resolve inside the mod's directory, and a UDF may not take a name
+//! DuckDB or another mod already uses. A mod that fails to register leaves
+//! no component tables behind. Scripts run through
+//! `lua_scripts::exec_restricted`, both in the load-time check and in every
+//! runtime VM, so they never see `io`, `os`, `ffi` or the host's globals,
+//! and what they define stays in the mod's own env, readable only by the
+//! mod itself and the mods that (transitively) depend on it.
+
+#![allow(dead_code)]
+
+use crate::lua_errors::{self, OnError};
+use crate::lua_scripts;
+use crate::mod_permissions::{install_db, ModSandbox};
+use crate::system_runner::{SystemKind, SystemRunner};
+use duckdb::arrow::array::{Array, Float64Array, StringArray};
+use duckdb::arrow::datatypes::DataType;
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::vscalar::{ArrowFunctionSignature, VArrowScalar};
+use duckdb::Connection;
+use mlua::{Lua, LuaOptions, StdLib, Table};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+pub const MANIFEST: &str = "mod.lua";
+/// Most arguments a mod UDF can take through `lua_call`.
+pub const MAX_UDF_ARITY: usize = 4;
+/// Column types a component table may declare.
+pub const COMPONENT_TYPES: &[&str] = &[
+    "BOOLEAN", "TINYINT", "SMALLINT", "INTEGER", "BIGINT", "UTINYINT", "USMALLINT", "UINTEGER", "UBIGINT", "FLOAT",
+    "DOUBLE", "VARCHAR", "DATE", "TIMESTAMP",
+];
+
+// ============================================================================
+// Versions
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    /// "1", "1.2" and "1.2.3"; missing parts are 0.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.trim().split('.').map(|p| p.parse::<u32>().ok());
+        let major = parts.next()??;
+        let minor = parts.next().unwrap_or(Some(0))?;
+        let patch = parts.next().unwrap_or(Some(0))?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Version { major, minor, patch })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionReq {
+    Any,
+    Exact(Version),
+    AtLeast(Version),
+    /// `^1.2`: same major, at least 1.2.
+    Compatible(Version),
+}
+
+impl VersionReq {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s == "*" {
+            return Some(VersionReq::Any);
+        }
+        if let Some(v) = s.strip_prefix(">=") {
+            return Version::parse(v).map(VersionReq::AtLeast);
+        }
+        if let Some(v) = s.strip_prefix('^') {
+            return Version::parse(v).map(VersionReq::Compatible);
+        }
+        Version::parse(s.strip_prefix('=').unwrap_or(s)).map(VersionReq::Exact)
+    }
+
+    pub fn matches(&self, v: Version) -> bool {
+        match *self {
+            VersionReq::Any => true,
+            VersionReq::Exact(req) => v == req,
+            VersionReq::AtLeast(req) => v >= req,
+            VersionReq::Compatible(req) => v.major == req.major && v >= req,
+        }
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionReq::Any => write!(f, "*"),
+            VersionReq::Exact(v) => write!(f, "={}", v),
+            VersionReq::AtLeast(v) => write!(f, ">={}", v),
+            VersionReq::Compatible(v) => write!(f, "^{}", v),
+        }
+    }
+}
+
+// ============================================================================
+// Manifest
+// ============================================================================
+
+#[derive(Debug, Clone)]
+pub struct ModManifest {
+    pub name: String,
+    pub version: Version,
+    pub dir: PathBuf,
+    pub dependencies: Vec<(String, VersionReq)>,
+    /// (table, column definitions), normalized to `name TYPE, ...`
+    pub components: Vec<(String, String)>,
+    pub scripts: Vec<String>,
+    /// (Lua global / SQL name, arity)
+    pub udfs: Vec<(String, usize)>,
+    /// What a failing UDF call does to the query.
+    pub on_error: OnError,
+    /// (system name, SQL), in declared order
+    pub systems: Vec<(String, String)>,
+    /// (table, columns) the mod may read / write; "*" means every column.
+    pub read: Vec<(String, Vec<String>)>,
+    pub write: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Debug)]
+pub enum ModError {
+    Manifest { dir: PathBuf, message: String },
+    Duplicate { name: String, dirs: Vec<PathBuf> },
+    MissingDependency { module: String, dependency: String },
+    VersionConflict { module: String, dependency: String, required: VersionReq, found: Version },
+    Cycle(Vec<String>),
+    UdfConflict { udf: String, mods: Vec<String> },
+    Register { module: String, message: String },
+}
+
+impl fmt::Display for ModError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModError::Manifest { dir, message } => write!(f, "{}: bad manifest: {}", dir.display(), message),
+            ModError::Duplicate { name, dirs } => {
+                let dirs: Vec<String> = dirs.iter().map(|d| d.display().to_string()).collect();
+                write!(f, "mod `{}` found more than once: {}", name, dirs.join(", "))
+            }
+            ModError::MissingDependency { module, dependency } => {
+                write!(f, "mod `{}` depends on `{}`, which is not installed", module, dependency)
+            }
+            ModError::VersionConflict { module, dependency, required, found } => {
+                write!(f, "mod `{}` needs `{}` {}, but {} is installed", module, dependency, required, found)
+            }
+            ModError::Cycle(mods) => write!(f, "dependency cycle; cannot order: {}", mods.join(", ")),
+            ModError::UdfConflict { udf, mods } => {
+                write!(f, "UDF `{}` declared by more than one mod: {}", udf, mods.join(", "))
+            }
+            ModError::Register { module, message } => write!(f, "mod `{}` failed to register: {}", module, message),
+        }
+    }
+}
+
+impl Error for ModError {}
+
+/// Refuses anything but a plain SQL identifier; manifest names end up in DDL.
+fn ident(what: &str, name: &str) -> mlua::Result<()> {
+    if lua_scripts::valid_ident(name) {
+        Ok(())
+    } else {
+        Err(lua_scripts::lua_err(format!("{} name {:?} is not a SQL identifier", what, name)))
+    }
+}
+
+/// Script paths are relative to the mod's directory and may not leave it:
+/// no root, prefix, `.` or `..` components.
+fn relative_script(file: &str) -> mlua::Result<()> {
+    if file.is_empty() || !Path::new(file).components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(lua_scripts::lua_err(format!("script path {:?} must be a relative path inside the mod", file)));
+    }
+    Ok(())
+}
+
+/// Checks `id BIGINT, power DOUBLE` column by column against `COMPONENT_TYPES`.
+fn component_columns(table: &str, columns: &str) -> mlua::Result<String> {
+    let mut out = Vec::new();
+    for column in columns.split(',') {
+        let parts: Vec<&str> = column.split_whitespace().collect();
+        let [name, ty] = parts.as_slice() else {
+            return Err(lua_scripts::lua_err(format!("component `{}`: column {:?} is not \"name TYPE\"", table, column.trim())));
+        };
+        ident("column", name)?;
+        let ty = ty.to_uppercase();
+        if !COMPONENT_TYPES.contains(&ty.as_str()) {
+            return Err(lua_scripts::lua_err(format!(
+                "component `{}`: column `{}` has type {}, supported {}", table, name, ty, COMPONENT_TYPES.join(", ")
+            )));
+        }
+        out.push(format!("{} {}", name, ty));
+    }
+    Ok(out.join(", "))
+}
+
+fn parse_manifest(dir: &Path) -> Result<ModManifest, ModError> {
+    let bad = |message: String| ModError::Manifest { dir: dir.to_path_buf(), message };
+    let source = fs::read_to_string(dir.join(MANIFEST)).map_err(|e| bad(e.to_string()))?;
+
+    // The manifest is data: no standard library and an empty environment,
+    // so it cannot reach io/os, load files or see anything but literals.
+    let lua = Lua::new_with(StdLib::NONE, LuaOptions::default()).map_err(|e| bad(e.to_string()))?;
+    let parse = || -> mlua::Result<ModManifest> {
+        let t: Table = lua.load(source.as_str()).set_name(MANIFEST).set_environment(lua.create_table()?).eval()?;
+        let name: String = t.get("name")?;
+        ident("mod", &name)?;
+        let version: String = t.get("version")?;
+        let version = Version::parse(&version)
+            .ok_or_else(|| mlua::Error::RuntimeError(format!("bad version {:?}", version)))?;
+
+        let mut dependencies = Vec::new();
+        if let Some(deps) = t.get::<Option<Table>>("dependencies")? {
+            for pair in deps.pairs::<String, String>() {
+                let (name, req) = pair?;
+                let req = VersionReq::parse(&req).ok_or_else(|| {
+                    mlua::Error::RuntimeError(format!("bad version requirement {:?} for `{}`", req, name))
+                })?;
+                dependencies.push((name, req));
+            }
+        }
+        let mut components = Vec::new();
+        if let Some(comps) = t.get::<Option<Table>>("components")? {
+            for pair in comps.pairs::<String, String>() {
+                let (table, columns) = pair?;
+                ident("component", &table)?;
+                components.push((table.to_lowercase(), component_columns(&table, &columns)?));
+            }
+        }
+        let mut scripts = Vec::new();
+        if let Some(list) = t.get::<Option<Table>>("scripts")? {
+            for file in list.sequence_values::<String>() {
+                let file = file?;
+                relative_script(&file)?;
+                scripts.push(file);
+            }
+        }
+        let mut udfs = Vec::new();
+        if let Some(list) = t.get::<Option<Table>>("udfs")? {
+            for pair in list.pairs::<String, usize>() {
+                let (name, arity) = pair?;
+                ident("UDF", &name)?;
+                if !(1..=MAX_UDF_ARITY).contains(&arity) {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "UDF `{}` has arity {}, supported 1..={}", name, arity, MAX_UDF_ARITY
+                    )));
+                }
+                udfs.push((name, arity));
+            }
+        }
+        let on_error = match t.get::<Option<String>>("on_error")? {
+            None => OnError::default(),
+            Some(mode) => OnError::parse(&mode).ok_or_else(|| {
+                mlua::Error::RuntimeError(format!("bad on_error {:?}, expected \"fail\" or \"null\"", mode))
+            })?,
+        };
+        let mut systems = Vec::new();
+        if let Some(list) = t.get::<Option<Table>>("systems")? {
+            for system in list.sequence_values::<Table>() {
+                let system = system?;
+                let system_name: String = system.get("name")?;
+                ident("system", &system_name)?;
+                systems.push((system_name, system.get("sql")?));
+            }
+        }
+        let (mut read, mut write) = (Vec::new(), Vec::new());
+        if let Some(perms) = t.get::<Option<Table>>("permissions")? {
+            for (key, out) in [("read", &mut read), ("write", &mut write)] {
+                if let Some(tables) = perms.get::<Option<Table>>(key)? {
+                    for pair in tables.pairs::<String, Vec<String>>() {
+                        out.push(pair?);
+                    }
+                }
+            }
+        }
+        // Lua tables have no order; keep output deterministic.
+        dependencies.sort_by(|a, b| a.0.cmp(&b.0));
+        components.sort();
+        udfs.sort();
+        read.sort();
+        write.sort();
+
+        Ok(ModManifest {
+            name,
+            version,
+            dir: dir.to_path_buf(),
+            dependencies,
+            components,
+            scripts,
+            udfs,
+            on_error,
+            systems,
+            read,
+            write,
+        })
+    };
+    parse().map_err(|e| bad(e.to_string()))
+}
+
+/// Reads every `<dir>/*/mod.lua`, in directory-name order.
+pub fn read_mods(dir: &Path) -> Result<Vec<ModManifest>, ModError> {
+    let entries = fs::read_dir(dir).map_err(|e| ModError::Manifest { dir: dir.to_path_buf(), message: e.to_string() })?;
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.join(MANIFEST).is_file())
+        .collect();
+    dirs.sort();
+    dirs.iter().map(|d| parse_manifest(d)).collect()
+}
+
+// ============================================================================
+// Load order
+// ============================================================================
+
+/// Validates dependencies and returns the mods in load order.
+pub fn resolve_load_order(mods: Vec<ModManifest>) -> Result<Vec<ModManifest>, ModError> {
+    let mut by_name: BTreeMap<String, ModManifest> = BTreeMap::new();
+    for m in mods {
+        if let Some(existing) = by_name.get(&m.name) {
+            return Err(ModError::Duplicate { name: m.name.clone(), dirs: vec![existing.dir.clone(), m.dir.clone()] });
+        }
+        by_name.insert(m.name.clone(), m);
+    }
+
+    for m in by_name.values() {
+        for (dep, req) in &m.dependencies {
+            let found = by_name
+                .get(dep)
+                .ok_or_else(|| ModError::MissingDependency { module: m.name.clone(), dependency: dep.clone() })?;
+            if !req.matches(found.version) {
+                return Err(ModError::VersionConflict {
+                    module: m.name.clone(),
+                    dependency: dep.clone(),
+                    required: *req,
+                    found: found.version,
+                });
+            }
+        }
+    }
+
+    let mut udf_owners: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for m in by_name.values() {
+        for (udf, _) in &m.udfs {
+            udf_owners.entry(udf).or_default().push(m.name.clone());
+        }
+    }
+    if let Some((udf, mods)) = udf_owners.into_iter().find(|(_, mods)| mods.len() > 1) {
+        return Err(ModError::UdfConflict { udf: udf.to_string(), mods });
+    }
+
+    // Kahn's algorithm; the BTreeSet makes ties load alphabetically.
+    let mut remaining: BTreeMap<String, BTreeSet<String>> = by_name
+        .values()
+        .map(|m| (m.name.clone(), m.dependencies.iter().map(|(d, _)| d.clone()).collect()))
+        .collect();
+    let mut ready: BTreeSet<String> = remaining.iter().filter(|(_, deps)| deps.is_empty()).map(|(n, _)| n.clone()).collect();
+    let mut order = Vec::with_capacity(by_name.len());
+    while let Some(name) = ready.pop_first() {
+        remaining.remove(&name);
+        for (other, deps) in remaining.iter_mut() {
+            if deps.remove(&name) && deps.is_empty() {
+                ready.insert(other.clone());
+            }
+        }
+        order.push(by_name.remove(&name).expect("mod present"));
+    }
+    if !remaining.is_empty() {
+        return Err(ModError::Cycle(remaining.into_keys().collect()));
+    }
+    Ok(order)
+}
+
+// ============================================================================
+// Registration
+// ============================================================================
+
+/// `lua_call(name VARCHAR, a DOUBLE [, b, c, d]) -> DOUBLE`: calls the Lua
+/// function `name` on this thread's VM, from its owning mod's env
+/// (`lua_scripts::restricted_global`) or, for a name no mod owns, `_G`. Mod
+/// UDFs are macros over this. Errors follow the owning mod's `lua_errors`
+/// policy.
+pub struct LuaCall;
+
+impl VArrowScalar for LuaCall {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<Arc<dyn Array>, Box<dyn Error>> {
+        let names = input.column(0).as_any().downcast_ref::<StringArray>().ok_or("lua_call: name must be VARCHAR")?;
+        let args: Vec<&Float64Array> = (1..input.num_columns())
+            .map(|c| input.column(c).as_any().downcast_ref::<Float64Array>().ok_or("lua_call: arguments must be DOUBLE"))
+            .collect::<Result<_, _>>()?;
+
+        let result = lua_scripts::with_vm(|lua| {
+            let globals = lua.globals();
+            lua_errors::call_rows(names, &args, |module, name| {
+                if module == lua_errors::UNOWNED {
+                    globals.get(name)
+                } else {
+                    lua_scripts::restricted_global(lua, module, name)
+                }
+            })
+        })?;
+        Ok(Arc::new(Float64Array::from(result?)))
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        (1..=MAX_UDF_ARITY)
+            .map(|n| {
+                let mut args = vec![DataType::Utf8];
+                args.extend(std::iter::repeat_n(DataType::Float64, n));
+                ArrowFunctionSignature::exact(args, DataType::Float64)
+            })
+            .collect()
+    }
+}
+
+/// Registers `lua_call` unless this connection already has it.
+pub fn register_lua_call(conn: &Connection) -> duckdb::Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT count(*) > 0 FROM duckdb_functions() WHERE function_name = 'lua_call'",
+        [],
+        |r| r.get(0),
+    )?;
+    if !exists {
+        conn.register_scalar_function::<LuaCall>("lua_call")?;
+    }
+    Ok(())
+}
+
+fn script_name(m: &ModManifest, file: &str) -> String {
+    format!("{}/{}", m.name, file)
+}
+
+/// `file` inside `m.dir` with symlinks resolved; refuses a path that ends up
+/// outside the mod's directory.
+fn script_path(m: &ModManifest, file: &str) -> Result<PathBuf, String> {
+    let dir = m.dir.canonicalize().map_err(|e| format!("{}: {}", m.dir.display(), e))?;
+    let path = dir.join(file).canonicalize().map_err(|e| format!("{}: {}", file, e))?;
+    if !path.starts_with(&dir) {
+        return Err(format!("script {:?} resolves outside the mod directory", file));
+    }
+    Ok(path)
+}
+
+/// A VM for load-time checks: string/table/math only, and no base-library
+/// file access, so a script's top-level code can't touch the host. Scripts
+/// run through `lua_scripts::exec_restricted`, as they do at runtime.
+fn scratch_vm() -> mlua::Result<Lua> {
+    let lua = Lua::new_with(StdLib::STRING | StdLib::TABLE | StdLib::MATH, LuaOptions::default())?;
+    let globals = lua.globals();
+    for name in ["dofile", "loadfile"] {
+        globals.set(name, mlua::Value::Nil)?;
+    }
+    Ok(lua)
+}
+
+/// `m`'s transitive dependencies among `mods` (in load order), in load order.
+fn dependency_closure<'m>(m: &ModManifest, mods: &'m [ModManifest]) -> Vec<&'m ModManifest> {
+    let position = mods.iter().position(|d| d.name == m.name).unwrap_or(mods.len());
+    // Walking backwards through a topological order reaches every dependent
+    // before its dependencies, so one pass finds the whole closure.
+    let mut needed: BTreeSet<&str> = m.dependencies.iter().map(|(d, _)| d.as_str()).collect();
+    for d in mods[..position].iter().rev() {
+        if needed.contains(d.name.as_str()) {
+            needed.extend(d.dependencies.iter().map(|(n, _)| n.as_str()));
+        }
+    }
+    mods[..position].iter().filter(|d| needed.contains(d.name.as_str())).collect()
+}
+
+/// Runs the mod's scripts, after those of its (transitive) dependencies, in
+/// a scratch VM of its own and checks every declared UDF exists in the mod's
+/// env, so a broken mod fails at load time rather than inside the first
+/// query. `mods` is in load order; as at runtime, only the dependencies'
+/// globals are visible (`register_mods` sets them first).
+fn check_scripts(m: &ModManifest, mods: &[ModManifest]) -> Result<(), String> {
+    let scratch = scratch_vm().map_err(|e| e.to_string())?;
+    lua_scripts::restricted_env(&scratch).map_err(|e| e.to_string())?;
+    for owner in dependency_closure(m, mods).into_iter().chain(std::iter::once(m)) {
+        for file in &owner.scripts {
+            let source = fs::read_to_string(script_path(owner, file)?).map_err(|e| format!("{}: {}", file, e))?;
+            lua_scripts::exec_restricted(&scratch, &owner.name, &script_name(owner, file), &source, &[])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    for (udf, _) in &m.udfs {
+        let f: Option<mlua::Function> = lua_scripts::restricted_global(&scratch, &m.name, udf).map_err(|e| e.to_string())?;
+        if f.is_none() {
+            return Err(format!("declares UDF `{}` but its scripts don't define it", udf));
+        }
+    }
+    Ok(())
+}
+
+/// Comment that marks a component table as `module`'s.
+fn owner_tag(module: &str) -> String {
+    format!("mod:{}", module)
+}
+
+/// Whether `table` still has to be created for `m`; false if an earlier load
+/// of `m` created it. Any other existing table or view (the host's, another
+/// mod's) is refused: a component is fully writable by its mod.
+fn needs_component(conn: &Connection, m: &ModManifest, table: &str) -> Result<bool, ModError> {
+    let fail = |message: String| ModError::Register { module: m.name.clone(), message };
+    let existing: Option<Option<String>> = conn
+        .query_row(
+            "SELECT comment FROM duckdb_tables() WHERE table_name = $1
+             UNION ALL SELECT comment FROM duckdb_views() WHERE NOT internal AND view_name = $1",
+            [table],
+            |r| r.get(0),
+        )
+        .map(Some)
+        .or_else(|e| match e {
+            duckdb::Error::QueryReturnedNoRows => Ok(None),
+            e => Err(e),
+        })
+        .map_err(|e| fail(e.to_string()))?;
+    match existing {
+        None => Ok(true),
+        Some(Some(tag)) if tag == owner_tag(&m.name) => Ok(false),
+        Some(_) => Err(fail(format!("component `{}` names a table the mod doesn't own", table))),
+    }
+}
+
+/// Creates `m`'s new component tables, all or none, tagged with its name.
+fn create_components(conn: &Connection, m: &ModManifest, tables: &[(&String, &String)]) -> duckdb::Result<()> {
+    let mut sql = String::from("BEGIN TRANSACTION;");
+    for (table, columns) in tables {
+        sql += &format!(
+            "CREATE TABLE main.{table} ({columns}); COMMENT ON TABLE main.{table} IS '{}';",
+            owner_tag(&m.name)
+        );
+    }
+    if let Err(e) = conn.execute_batch(&(sql + "COMMIT;")) {
+        let _ = conn.execute_batch("ROLLBACK;");
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Names of the mods some live `LoadedMods` holds. Scripts, host bindings
+/// and `lua_errors` entries are process-wide and keyed by mod name, so one
+/// name is loaded once at a time.
+static LOADED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// The master sandbox behind one mod's `db` bindings. Emptied on unload,
+/// under the lock, so no VM clones it once the connection may be gone.
+type SharedSandbox = Arc<Mutex<Option<ModSandbox>>>;
+
+/// The mods one `register_mods` call loaded, in load order. Dropping it
+/// unloads them: their scripts, globals and host bindings leave every VM
+/// (`lua_scripts::unload_owner`), their `lua_errors` entries go and their
+/// UDF macros are dropped. Component tables stay, as does anything already
+/// added to a `SystemRunner`; it can't run those systems once their mod is
+/// gone. It borrows the connection, so nothing it registered outlives the
+/// database.
+pub struct LoadedMods<'conn> {
+    conn: &'conn Connection,
+    mods: Vec<ModManifest>,
+    sandboxes: Vec<SharedSandbox>,
+}
+
+impl LoadedMods<'_> {
+    pub fn iter(&self) -> std::slice::Iter<'_, ModManifest> {
+        self.mods.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a LoadedMods<'_> {
+    type Item = &'a ModManifest;
+    type IntoIter = std::slice::Iter<'a, ModManifest>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.mods.iter()
+    }
+}
+
+impl Drop for LoadedMods<'_> {
+    fn drop(&mut self) {
+        for sandbox in &self.sandboxes {
+            sandbox.lock().unwrap().take();
+        }
+        let mut loaded = LOADED.lock().unwrap();
+        for m in self.mods.iter().rev() {
+            lua_scripts::unload_owner(&m.name);
+            for (udf, _) in &m.udfs {
+                // Only macros this mod created: a UDF that clashed belongs to someone else.
+                if lua_errors::policy(udf).0 == m.name {
+                    let _ = self.conn.execute_batch(&format!("DROP MACRO IF EXISTS {};", udf));
+                }
+            }
+            lua_errors::forget_module(&m.name);
+            loaded.remove(&m.name);
+        }
+    }
+}
+
+/// Registers mods (already in load order) into `conn`, the Lua VMs and
+/// `runner`; they stay loaded until the returned `LoadedMods` is dropped. On
+/// an error the mods this call already registered are unloaded again.
+pub fn register_mods<'conn>(
+    conn: &'conn Connection,
+    runner: &mut SystemRunner,
+    mods: &[ModManifest],
+) -> Result<LoadedMods<'conn>, ModError> {
+    {
+        let mut loaded = LOADED.lock().unwrap();
+        if let Some(m) = mods.iter().find(|m| loaded.contains(&m.name)) {
+            return Err(ModError::Register { module: m.name.clone(), message: "already loaded".into() });
+        }
+        loaded.extend(mods.iter().map(|m| m.name.clone()));
+    }
+    // From here on, dropping `guard` releases every name claimed above.
+    let mut guard = LoadedMods { conn, mods: mods.to_vec(), sandboxes: Vec::new() };
+
+    // A mod's scripts read only its own globals and its dependencies'.
+    for m in mods {
+        let deps = dependency_closure(m, mods).into_iter().map(|d| d.name.clone()).collect();
+        lua_scripts::set_dependencies(&m.name, deps);
+    }
+    for m in mods {
+        check_scripts(m, mods).map_err(|message| ModError::Register { module: m.name.clone(), message })?;
+    }
+
+    let fail = |m: &ModManifest, e: &dyn fmt::Display| ModError::Register { module: m.name.clone(), message: e.to_string() };
+    register_lua_call(conn).map_err(|e| ModError::Register { module: "<loader>".into(), message: e.to_string() })?;
+    for m in mods {
+        // Everything that doesn't need the tables is checked before any is
+        // created, so a mod that fails here leaves no tables behind.
+        let mut new_tables = Vec::new();
+        for (table, columns) in &m.components {
+            if needs_component(conn, m, table)? {
+                new_tables.push((table, columns));
+            }
+        }
+        // Names before macros, so a clash leaves none of this mod's macros defined.
+        for (udf, _) in &m.udfs {
+            let (owner, _) = lua_errors::policy(udf);
+            if owner != lua_errors::UNOWNED && owner != m.name {
+                return Err(ModError::UdfConflict { udf: udf.clone(), mods: vec![owner, m.name.clone()] });
+            }
+            lua_scripts::check_macro_name(conn, udf, "lua_call").map_err(|e| fail(m, &e))?;
+        }
+        let scripts: Vec<(&String, PathBuf)> = m
+            .scripts
+            .iter()
+            .map(|file| script_path(m, file).map(|path| (file, path)))
+            .collect::<Result<_, _>>()
+            .map_err(|e| fail(m, &e))?;
+
+        create_components(conn, m, &new_tables).map_err(|e| fail(m, &e))?;
+        // The sandbox and systems are checked against the tables and UDF
+        // macros, so they go after creating them; a failure drops the tables
+        // this call created.
+        if let Err(e) = register_runtime(conn, runner, m, &scripts, &mut guard.sandboxes) {
+            for (table, _) in &new_tables {
+                let _ = conn.execute_batch(&format!("DROP TABLE IF EXISTS main.{};", table));
+            }
+            return Err(e);
+        }
+    }
+    Ok(guard)
+}
+
+/// The part of `register_mods` that runs once `m`'s tables exist.
+fn register_runtime(
+    conn: &Connection,
+    runner: &mut SystemRunner,
+    m: &ModManifest,
+    scripts: &[(&String, PathBuf)],
+    sandboxes: &mut Vec<SharedSandbox>,
+) -> Result<(), ModError> {
+    let fail = |e: &dyn fmt::Display| ModError::Register { module: m.name.clone(), message: e.to_string() };
+    let sandbox = ModSandbox::new(conn, m).map_err(|e| fail(&e))?;
+    lua_errors::set_on_error(&m.name, m.on_error);
+    for (udf, arity) in &m.udfs {
+        lua_errors::set_owner(udf, &m.name);
+        let params: Vec<String> = (0..*arity).map(|i| format!("a{}", i)).collect();
+        let args: Vec<String> = params.iter().map(|p| format!("{}::DOUBLE", p)).collect();
+        conn.execute_batch(&format!(
+            "CREATE OR REPLACE MACRO {}({}) AS lua_call('{}', {});",
+            udf,
+            params.join(", "),
+            udf,
+            args.join(", ")
+        ))
+        .map_err(|e| fail(&e))?;
+    }
+    // A system runs with the mod's grants, like its `db.execute`.
+    let mut checked = Vec::with_capacity(m.systems.len());
+    for (system, sql) in &m.systems {
+        let p = sandbox.check_write(sql).map_err(|e| fail(&format!("system `{}`: {}", system, e)))?;
+        checked.push((system, p.rewritten));
+    }
+
+    // Bound before the scripts are loaded, so no VM runs them without `db`.
+    // Each VM gets its own connection, cloned from the master sandbox while
+    // the `LoadedMods` holding it (and so the database) is alive.
+    let shared: SharedSandbox = Arc::new(Mutex::new(Some(sandbox)));
+    sandboxes.push(shared.clone());
+    let module = m.name.clone();
+    lua_scripts::set_host_bindings(
+        &m.name,
+        Arc::new(move |lua, host| {
+            let sandbox = match &*shared.lock().unwrap() {
+                Some(master) => master.try_clone().map_err(|e| lua_scripts::lua_err(e.to_string()))?,
+                None => return Err(lua_scripts::lua_err(format!("mod `{}` is unloaded", module))),
+            };
+            host.set("db", install_db(lua, Rc::new(sandbox))?)
+        }),
+    );
+    for (file, path) in scripts {
+        lua_scripts::load_file_restricted(&m.name, &script_name(m, file), path).map_err(|e| fail(&e))?;
+    }
+    for (system, sql) in checked {
+        runner.add_system(format!("{}.{}", m.name, system), SystemKind::Execute, &sql);
+    }
+    Ok(())
+}
+
+/// `read_mods` + `resolve_load_order` + `register_mods`.
+pub fn load_mods<'conn>(conn: &'conn Connection, runner: &mut SystemRunner, dir: &Path) -> Result<LoadedMods<'conn>, ModError> {
+    let order = resolve_load_order(read_mods(dir)?)?;
+    register_mods(conn, runner, &order)
+}
+
+/// Test support for the mod demos: writes `root/dir` with `manifest` as its
+/// `mod.lua` plus the given (file, source) scripts.
+pub fn write_mod(root: &Path, dir: &str, manifest: &str, scripts: &[(&str, &str)]) -> std::io::Result<()> {
+    let dir = root.join(dir);
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(MANIFEST), manifest)?;
+    for (file, source) in scripts {
+        fs::write(dir.join(file), source)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,862 @@
+//! Per-Mod Table/Column Permissions for Lua `db` Bindings
+//!
+//! Shared by bins via `mod mod_permissions;` (needs `mod mod_loader;`). A mod
+//! manifest may declare what it touches:
+//!
+//!   permissions = {
+//!     read  = { units = { "id", "hp" }, buffs = { "*" } },
+//!     write = { units = { "hp" } },
+//!   }
+//!
+//! A mod's own `components` are implicitly readable and writable in full,
+//! and a write grant implies reading the same columns.
+//! `ModSandbox::new` turns the grants into a schema `mod_<name>` holding one
+//! view per readable table with only the granted columns, and a connection
+//! whose `search_path` points at it. `install_db` exposes that connection to
+//! Lua as `db.query(sql)` / `db.execute(sql)`:
+//!
+//! - Reads must be a single SELECT. `json_serialize_sql` gives its AST; any
+//!   table function, schema/catalog-qualified table or table outside the
+//!   read grants is refused. Columns are enforced by the views: a hidden
+//!   column simply does not exist for the binder.
+//! - Writes must be a single `UPDATE t SET ...`, `INSERT INTO t (cols) ...` or
+//!   `DELETE FROM t [WHERE ...]` (DuckDB's serializer only handles SELECT, so
+//!   these are parsed here). Target columns must be write-granted, DELETE
+//!   needs the whole table. Everything the statement reads (SET expressions,
+//!   FROM, WHERE, the INSERT source) is rebuilt as a SELECT and put through
+//!   the read check plus the binder, then the statement runs against
+//!   `main.<t>`.
+//!
+//! Anything else (DDL, PRAGMA, COPY, ATTACH, stacked statements, quoted
+//! identifiers, RETURNING, `E'...'`/backslash/dollar-quoted strings) is
+//! refused with "permission denied: ...".
+//!
+//! `mod_loader::register_mods` puts every manifest system through
+//! `check_write` too, so a system can't do what the mod's `db` couldn't, and
+//! gives the mod's scripts that `db` in every runtime VM, each over its own
+//! `try_clone` of the sandbox.
+
+#![allow(dead_code)]
+
+use crate::mod_loader::ModManifest;
+use duckdb::types::Value as DbValue;
+use duckdb::Connection;
+use mlua::{Lua, Table};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Denied(pub String);
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "permission denied: {}", self.0)
+    }
+}
+
+impl Error for Denied {}
+
+fn deny<T>(message: impl Into<String>) -> Result<T, Denied> {
+    Err(Denied(message.into()))
+}
+
+// ============================================================================
+// Minimal JSON (for json_serialize_sql output)
+// ============================================================================
+
+#[derive(Debug)]
+enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Obj(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    s: &'a [u8],
+    i: usize,
+}
+
+impl JsonParser<'_> {
+    fn ws(&mut self) {
+        while self.s.get(self.i).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.i += 1;
+        }
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        self.ws();
+        if self.s.get(self.i) == Some(&c) {
+            self.i += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}' at byte {}", c as char, self.i))
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if self.s[self.i..].starts_with(word.as_bytes()) {
+            self.i += word.len();
+            Ok(value)
+        } else {
+            Err(format!("bad literal at byte {}", self.i))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.ws();
+        match self.s.get(self.i) {
+            Some(b'{') => {
+                self.i += 1;
+                let mut fields = Vec::new();
+                if self.eat(b'}') {
+                    return Ok(Json::Obj(fields));
+                }
+                loop {
+                    self.ws();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    if !self.eat(b',') {
+                        self.expect(b'}')?;
+                        return Ok(Json::Obj(fields));
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.i += 1;
+                let mut items = Vec::new();
+                if self.eat(b']') {
+                    return Ok(Json::Arr(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    if !self.eat(b',') {
+                        self.expect(b']')?;
+                        return Ok(Json::Arr(items));
+                    }
+                }
+            }
+            Some(b'"') => Ok(Json::Str(self.string()?)),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(_) => {
+                let start = self.i;
+                while self.s.get(self.i).is_some_and(|c| b"+-0123456789.eE".contains(c)) {
+                    self.i += 1;
+                }
+                let text = std::str::from_utf8(&self.s[start..self.i]).unwrap_or("");
+                text.parse().map(Json::Num).map_err(|_| format!("bad number at byte {}", start))
+            }
+            None => Err("unexpected end of input".into()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.s.get(self.i) != Some(&b'"') {
+            return Err(format!("expected string at byte {}", self.i));
+        }
+        self.i += 1;
+        let mut out = Vec::new();
+        loop {
+            let c = *self.s.get(self.i).ok_or("unterminated string")?;
+            self.i += 1;
+            match c {
+                b'"' => return String::from_utf8(out).map_err(|e| e.to_string()),
+                b'\\' => {
+                    let e = *self.s.get(self.i).ok_or("unterminated escape")?;
+                    self.i += 1;
+                    match e {
+                        b'n' => out.push(b'\n'),
+                        b't' => out.push(b'\t'),
+                        b'r' => out.push(b'\r'),
+                        b'b' => out.push(8),
+                        b'f' => out.push(12),
+                        b'u' => {
+                            let hex = self.s.get(self.i..self.i + 4).ok_or("short \\u escape")?;
+                            self.i += 4;
+                            let code = u32::from_str_radix(std::str::from_utf8(hex).unwrap_or(""), 16)
+                                .map_err(|e| e.to_string())?;
+                            let ch = char::from_u32(code).unwrap_or('\u{fffd}');
+                            out.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        other => out.push(other),
+                    }
+                }
+                other => out.push(other),
+            }
+        }
+    }
+}
+
+fn parse_json(s: &str) -> Result<Json, String> {
+    let mut p = JsonParser { s: s.as_bytes(), i: 0 };
+    let v = p.value()?;
+    p.ws();
+    if p.i != s.len() {
+        return Err(format!("trailing data at byte {}", p.i));
+    }
+    Ok(v)
+}
+
+// ============================================================================
+// Read check: one SELECT, known tables only
+// ============================================================================
+
+#[derive(Debug, Default)]
+struct SelectRefs {
+    /// (catalog, schema, table) as written; empty strings when unqualified.
+    /// References to a CTE in scope are not included.
+    tables: Vec<(String, String, String)>,
+    table_functions: Vec<String>,
+    /// Every CTE name defined anywhere in the statement.
+    ctes: BTreeSet<String>,
+}
+
+fn lower_text(node: &Json, key: &str) -> String {
+    node.get(key).and_then(Json::as_str).unwrap_or("").to_lowercase()
+}
+
+/// Collects table references under `node`. A query node's `cte_map` names
+/// are only in scope inside that node (its FROM, subqueries and the CTE
+/// bodies), so a CTE defined in a subquery does not hide a table of the
+/// same name referenced outside it.
+fn collect_refs(node: &Json, scope: &mut Vec<String>, refs: &mut SelectRefs) {
+    let depth = scope.len();
+    if let Some(Json::Arr(entries)) = node.get("cte_map").and_then(|m| m.get("map")) {
+        for entry in entries {
+            let name = lower_text(entry, "key");
+            refs.ctes.insert(name.clone());
+            scope.push(name);
+        }
+    }
+    match node.get("type").and_then(Json::as_str) {
+        Some("BASE_TABLE") => {
+            let (catalog, schema, table) =
+                (lower_text(node, "catalog_name"), lower_text(node, "schema_name"), lower_text(node, "table_name"));
+            let is_cte = catalog.is_empty() && schema.is_empty() && scope.contains(&table);
+            if !is_cte {
+                refs.tables.push((catalog, schema, table));
+            }
+        }
+        Some("TABLE_FUNCTION") => {
+            let name = node.get("function").map(|f| lower_text(f, "function_name")).unwrap_or_default();
+            refs.table_functions.push(name);
+        }
+        _ => {}
+    }
+    match node {
+        Json::Arr(items) => items.iter().for_each(|v| collect_refs(v, scope, refs)),
+        Json::Obj(fields) => fields.iter().for_each(|(_, v)| collect_refs(v, scope, refs)),
+        _ => {}
+    }
+    scope.truncate(depth);
+}
+
+fn analyze_select(conn: &Connection, sql: &str) -> Result<SelectRefs, Denied> {
+    let json: String = conn
+        .query_row("SELECT json_serialize_sql(?::VARCHAR)", [sql], |r| r.get(0))
+        .map_err(|e| Denied(format!("cannot analyze query: {}", e)))?;
+    let root = parse_json(&json).map_err(|e| Denied(format!("cannot analyze query: {}", e)))?;
+    if let Some(Json::Bool(true)) = root.get("error") {
+        let message = root.get("error_message").and_then(Json::as_str).unwrap_or("not a SELECT");
+        return deny(format!("only single SELECT statements can be queried ({})", message));
+    }
+    let statements = match root.get("statements") {
+        Some(Json::Arr(s)) => s,
+        _ => return deny("cannot analyze query"),
+    };
+    if statements.len() != 1 {
+        return deny(format!("one statement at a time, got {}", statements.len()));
+    }
+
+    let mut refs = SelectRefs::default();
+    collect_refs(&statements[0], &mut Vec::new(), &mut refs);
+    Ok(refs)
+}
+
+// ============================================================================
+// Write check: UPDATE / INSERT / DELETE on one table
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteKind {
+    Update,
+    Insert,
+    Delete,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParsedWrite {
+    pub kind: WriteKind,
+    pub table: String,
+    /// Columns assigned (UPDATE) or inserted (INSERT); empty for DELETE.
+    pub columns: Vec<String>,
+    /// The statement with its target qualified as `main.<table>`.
+    pub rewritten: String,
+    /// A SELECT reading everything the statement reads.
+    pub probe: String,
+}
+
+/// Copy of `sql` with string literals, quoted identifiers, comments and
+/// anything inside parentheses blanked, so keywords, commas and `=` left in
+/// it are top-level. Same length as `sql`.
+///
+/// Only `''`-doubling is understood, so anything that quotes differently
+/// (`E'...'` escape strings, backslashes in literals, `$$` / `$tag$` dollar
+/// quotes) is refused rather than guessed at: a literal that ends somewhere
+/// else than the masker thinks would hide or invent top-level SET targets.
+fn mask_top_level(sql: &str) -> Result<Vec<u8>, Denied> {
+    let s = sql.as_bytes();
+    let mut out = s.to_vec();
+    let (mut i, mut depth) = (0, 0usize);
+    while i < s.len() {
+        let start = i;
+        match s[i] {
+            b'\'' | b'"' => {
+                let quote = s[i];
+                if quote == b'\''
+                    && i > 0
+                    && s[i - 1].eq_ignore_ascii_case(&b'e')
+                    && (i < 2 || !is_word(s[i - 2]))
+                {
+                    return deny("E'...' escape strings are not supported");
+                }
+                i += 1;
+                while i < s.len() {
+                    if quote == b'\'' && s[i] == b'\\' {
+                        return deny("backslashes in string literals are not supported");
+                    }
+                    if s[i] == quote {
+                        if s.get(i + 1) == Some(&quote) {
+                            i += 2;
+                            continue;
+                        }
+                        break;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            b'-' if s.get(i + 1) == Some(&b'-') => {
+                while i < s.len() && s[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if s.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < s.len() && !s[i..].starts_with(b"*/") {
+                    i += 1;
+                }
+                i += 2;
+            }
+            b'(' => {
+                depth += 1;
+                i += 1;
+            }
+            b')' => {
+                depth = depth.saturating_sub(1);
+                i += 1;
+            }
+            b'$' if is_dollar_quote(&s[i..]) => return deny("dollar-quoted strings are not supported"),
+            _ if depth > 0 => i += 1,
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+        let end = i.min(s.len());
+        out[start..end].iter_mut().for_each(|c| *c = b' ');
+    }
+    Ok(out)
+}
+
+/// `$$` or `$tag$` at the start of `s`; `$1` and `$name` parameters are not.
+fn is_dollar_quote(s: &[u8]) -> bool {
+    let tag = s[1..].iter().take_while(|&&c| is_word(c)).count();
+    s.get(1 + tag) == Some(&b'$') && !s.get(1).is_some_and(|c| c.is_ascii_digit())
+}
+
+fn is_word(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// Position of top-level keyword `kw` (case-insensitive, whole word) at or after `from`.
+fn find_keyword(masked: &[u8], kw: &str, from: usize) -> Option<usize> {
+    let kw = kw.as_bytes();
+    (from..masked.len().saturating_sub(kw.len() - 1)).find(|&i| {
+        masked[i..i + kw.len()].eq_ignore_ascii_case(kw)
+            && (i == 0 || !is_word(masked[i - 1]))
+            && masked.get(i + kw.len()).is_none_or(|&c| !is_word(c))
+    })
+}
+
+fn skip_ws(s: &[u8], mut i: usize) -> usize {
+    while s.get(i).is_some_and(|c| c.is_ascii_whitespace()) {
+        i += 1;
+    }
+    i
+}
+
+/// Expects keyword `kw` as the next word after `at`; returns the end of it.
+fn expect_keyword(sql: &str, at: usize, kw: &str) -> Result<usize, Denied> {
+    let i = skip_ws(sql.as_bytes(), at);
+    let end = i + kw.len();
+    let matches = sql.get(i..end).is_some_and(|w| w.eq_ignore_ascii_case(kw))
+        && sql.as_bytes().get(end).is_none_or(|&c| !is_word(c));
+    if matches {
+        Ok(end)
+    } else {
+        deny(format!("expected {} after `{}`", kw, sql[..at].trim()))
+    }
+}
+
+/// Reads a plain identifier after `at`; returns (lowercased name, end).
+fn ident_at(sql: &str, at: usize) -> Result<(String, usize), Denied> {
+    let s = sql.as_bytes();
+    let start = skip_ws(s, at);
+    if s.get(start) == Some(&b'"') {
+        return deny("quoted identifiers are not supported");
+    }
+    let mut end = start;
+    while s.get(end).copied().is_some_and(is_word) {
+        end += 1;
+    }
+    if end == start || s[start].is_ascii_digit() {
+        return deny(format!("expected a table or column name at `{}`", sql[start..].trim()));
+    }
+    if s.get(skip_ws(s, end)) == Some(&b'.') {
+        return deny("only unqualified table names are allowed");
+    }
+    Ok((sql[start..end].to_lowercase(), end))
+}
+
+/// Splits `sql[range]` at top-level commas (per `masked`).
+fn split_top_level<'a>(sql: &'a str, masked: &[u8], start: usize, end: usize) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut from = start;
+    for i in start..end {
+        if masked[i] == b',' {
+            parts.push(sql[from..i].trim());
+            from = i + 1;
+        }
+    }
+    parts.push(sql[from..end].trim());
+    parts
+}
+
+pub fn parse_write(sql: &str) -> Result<ParsedWrite, Denied> {
+    let sql = sql.trim();
+    let sql = sql.strip_suffix(';').unwrap_or(sql).trim_end();
+    let masked = mask_top_level(sql)?;
+    if masked.contains(&b';') {
+        return deny("one statement at a time");
+    }
+    if find_keyword(&masked, "RETURNING", 0).is_some() {
+        return deny("RETURNING is not supported");
+    }
+    let first_end = sql.bytes().position(|c| !is_word(c)).unwrap_or(sql.len());
+    let verb = sql[..first_end].to_uppercase();
+    let len = sql.len();
+
+    match verb.as_str() {
+        "UPDATE" => {
+            let (table, table_end) = ident_at(sql, first_end)?;
+            let set_end = expect_keyword(sql, table_end, "SET")?;
+            let from = find_keyword(&masked, "FROM", set_end);
+            let where_ = find_keyword(&masked, "WHERE", set_end);
+            let set_stop = from.or(where_).unwrap_or(len);
+
+            let (mut columns, mut exprs) = (Vec::new(), Vec::new());
+            let mut at = set_end;
+            for assignment in split_top_level(sql, &masked, set_end, set_stop) {
+                let offset = sql[at..].find(assignment).map(|o| at + o).unwrap_or(at);
+                let eq = (offset..offset + assignment.len())
+                    .find(|&i| masked[i] == b'=')
+                    .ok_or_else(|| Denied(format!("bad assignment `{}`", assignment)))?;
+                let (column, column_end) = ident_at(sql, offset)?;
+                if !sql[column_end..eq].trim().is_empty() {
+                    return deny(format!("bad assignment `{}`", assignment));
+                }
+                columns.push(column);
+                exprs.push(sql[eq + 1..offset + assignment.len()].trim().to_string());
+                at = offset + assignment.len();
+            }
+            let from_clause = from.map(|f| format!(", {}", sql[f + 4..where_.unwrap_or(len)].trim()));
+            let where_clause = where_.map(|w| format!(" WHERE {}", sql[w + 5..].trim()));
+            Ok(ParsedWrite {
+                kind: WriteKind::Update,
+                probe: format!(
+                    "SELECT {} FROM {}{}{}",
+                    exprs.join(", "),
+                    table,
+                    from_clause.unwrap_or_default(),
+                    where_clause.unwrap_or_default()
+                ),
+                rewritten: format!("UPDATE main.{} {}", table, &sql[table_end..]),
+                table,
+                columns,
+            })
+        }
+        "DELETE" => {
+            let from_end = expect_keyword(sql, first_end, "FROM")?;
+            let (table, table_end) = ident_at(sql, from_end)?;
+            let rest = sql[table_end..].trim();
+            let where_clause = if rest.is_empty() {
+                String::new()
+            } else {
+                let where_end = expect_keyword(sql, table_end, "WHERE")?;
+                format!(" WHERE {}", sql[where_end..].trim())
+            };
+            Ok(ParsedWrite {
+                kind: WriteKind::Delete,
+                probe: format!("SELECT 1 FROM {}{}", table, where_clause),
+                rewritten: format!("DELETE FROM main.{} {}", table, rest),
+                table,
+                columns: Vec::new(),
+            })
+        }
+        "INSERT" => {
+            let into_end = expect_keyword(sql, first_end, "INTO")?;
+            let (table, table_end) = ident_at(sql, into_end)?;
+            let open = skip_ws(sql.as_bytes(), table_end);
+            if sql.as_bytes().get(open) != Some(&b'(') {
+                return deny("INSERT needs an explicit column list");
+            }
+            let close = sql[open..].find(')').map(|c| open + c).ok_or_else(|| Denied("unclosed column list".into()))?;
+            let mut columns = Vec::new();
+            for name in sql[open + 1..close].split(',') {
+                let (column, end) = ident_at(name, 0)?;
+                if !name[end..].trim().is_empty() {
+                    return deny(format!("bad column `{}`", name.trim()));
+                }
+                columns.push(column);
+            }
+            let source = sql[close + 1..].trim();
+            let head = source.split(|c: char| !c.is_ascii_alphanumeric()).next().unwrap_or("").to_uppercase();
+            let probe = match head.as_str() {
+                "VALUES" => format!("SELECT * FROM ({}) v", source),
+                "SELECT" | "WITH" => source.to_string(),
+                _ => return deny("INSERT source must be VALUES or SELECT"),
+            };
+            Ok(ParsedWrite {
+                kind: WriteKind::Insert,
+                probe,
+                rewritten: format!("INSERT INTO main.{} ({}) {}", table, columns.join(", "), source),
+                table,
+                columns,
+            })
+        }
+        _ => deny(format!("`{}` statements are not allowed; use UPDATE, INSERT or DELETE", verb)),
+    }
+}
+
+// ============================================================================
+// Sandbox
+// ============================================================================
+
+fn columns_of(conn: &Connection, table: &str) -> duckdb::Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT column_name FROM information_schema.columns
+         WHERE table_schema = 'main' AND table_name = ? ORDER BY ordinal_position",
+    )?;
+    let rows = stmt.query_map([table], |r| r.get::<_, String>(0))?;
+    rows.collect()
+}
+
+/// Column names and rows of a `ModSandbox::query`.
+pub type Rows = (Vec<String>, Vec<Vec<DbValue>>);
+
+pub struct ModSandbox {
+    pub module: String,
+    pub schema: String,
+    conn: Connection,
+    read: BTreeMap<String, BTreeSet<String>>,
+    write: BTreeMap<String, BTreeSet<String>>,
+    /// Tables with every column writable (required for DELETE).
+    full_write: BTreeSet<String>,
+    /// Shared with every `try_clone`.
+    allowed: Arc<AtomicU64>,
+    denied: Arc<AtomicU64>,
+}
+
+impl ModSandbox {
+    /// Resolves the mod's grants against `main` and (re)creates its views.
+    pub fn new(conn: &Connection, m: &ModManifest) -> Result<Self, Box<dyn Error>> {
+        let mut read: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut write: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut full_write = BTreeSet::new();
+
+        let own = m.components.iter().map(|(t, _)| (t.clone(), vec!["*".to_string()]));
+        let grants = m.read.iter().cloned().map(|g| (false, g)).chain(own.clone().map(|g| (false, g)));
+        let grants = grants.chain(m.write.iter().cloned().chain(own).map(|g| (true, g)));
+        for (is_write, (table, wanted)) in grants {
+            let table = table.to_lowercase();
+            let all = columns_of(conn, &table)?;
+            if all.is_empty() {
+                return Err(format!("mod `{}`: permission on unknown table `{}`", m.name, table).into());
+            }
+            let mut cols = BTreeSet::new();
+            for c in &wanted {
+                if c == "*" {
+                    cols.extend(all.iter().cloned());
+                } else if all.contains(&c.to_lowercase()) {
+                    cols.insert(c.to_lowercase());
+                } else {
+                    return Err(format!("mod `{}`: permission on unknown column `{}.{}`", m.name, table, c).into());
+                }
+            }
+            // Writing a column implies seeing it.
+            read.entry(table.clone()).or_default().extend(cols.iter().cloned());
+            if is_write {
+                write.entry(table.clone()).or_default().extend(cols);
+                if write[&table].len() == all.len() {
+                    full_write.insert(table);
+                }
+            }
+        }
+
+        let schema = format!("mod_{}", m.name.to_lowercase());
+        conn.execute_batch(&format!("DROP SCHEMA IF EXISTS {} CASCADE; CREATE SCHEMA {};", schema, schema))?;
+        for (table, cols) in &read {
+            // Keep the table's column order rather than the set's.
+            let ordered: Vec<String> = columns_of(conn, table)?.into_iter().filter(|c| cols.contains(c)).collect();
+            conn.execute_batch(&format!(
+                "CREATE VIEW {}.{} AS SELECT {} FROM main.{};",
+                schema,
+                table,
+                ordered.join(", "),
+                table
+            ))?;
+        }
+        let sandboxed = conn.try_clone()?;
+        sandboxed.execute_batch(&format!("SET search_path = '{}';", schema))?;
+
+        Ok(ModSandbox {
+            module: m.name.clone(),
+            schema,
+            conn: sandboxed,
+            read,
+            write,
+            full_write,
+            allowed: Arc::new(AtomicU64::new(0)),
+            denied: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// The same views, grants and counters over a connection of its own,
+    /// for a VM on another thread.
+    pub fn try_clone(&self) -> duckdb::Result<Self> {
+        let conn = self.conn.try_clone()?;
+        conn.execute_batch(&format!("SET search_path = '{}';", self.schema))?;
+        Ok(ModSandbox {
+            module: self.module.clone(),
+            schema: self.schema.clone(),
+            conn,
+            read: self.read.clone(),
+            write: self.write.clone(),
+            full_write: self.full_write.clone(),
+            allowed: self.allowed.clone(),
+            denied: self.denied.clone(),
+        })
+    }
+
+    pub fn readable(&self) -> &BTreeMap<String, BTreeSet<String>> {
+        &self.read
+    }
+
+    pub fn writable(&self) -> &BTreeMap<String, BTreeSet<String>> {
+        &self.write
+    }
+
+    pub fn check_select(&self, sql: &str) -> Result<(), Denied> {
+        let refs = analyze_select(&self.conn, sql)?;
+        if let Some(f) = refs.table_functions.first() {
+            return deny(format!("table function `{}` is not allowed", f));
+        }
+        for (catalog, schema, table) in &refs.tables {
+            if !catalog.is_empty() || (!schema.is_empty() && *schema != self.schema) {
+                let qualified = [catalog.as_str(), schema.as_str(), table.as_str()];
+                let qualified: Vec<&str> = qualified.into_iter().filter(|p| !p.is_empty()).collect();
+                return deny(format!("`{}`: only unqualified table names are allowed", qualified.join(".")));
+            }
+            if !self.read.contains_key(table) {
+                return deny(format!("no read permission on `{}`", table));
+            }
+        }
+        // A CTE named after a real table makes every reference to that name
+        // depend on scoping rules; refuse rather than guess.
+        for cte in &refs.ctes {
+            let shadowed: i64 = self
+                .conn
+                .query_row(
+                    "SELECT (SELECT count(*) FROM duckdb_tables() WHERE table_name = $1)
+                          + (SELECT count(*) FROM duckdb_views() WHERE NOT internal AND view_name = $1)",
+                    [cte],
+                    |r| r.get(0),
+                )
+                .map_err(|e| Denied(format!("cannot analyze query: {}", e)))?;
+            if shadowed > 0 {
+                return deny(format!("CTE `{}` shadows a table", cte));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check_write(&self, sql: &str) -> Result<ParsedWrite, Denied> {
+        let p = parse_write(sql)?;
+        let Some(allowed) = self.write.get(&p.table) else {
+            return deny(format!("no write permission on `{}`", p.table));
+        };
+        if p.kind == WriteKind::Delete && !self.full_write.contains(&p.table) {
+            return deny(format!("DELETE needs write permission on every column of `{}`", p.table));
+        }
+        if let Some(c) = p.columns.iter().find(|c| !allowed.contains(*c)) {
+            return deny(format!("no write permission on `{}.{}`", p.table, c));
+        }
+        self.check_select(&p.probe)?;
+        // Binding the probe against the views rejects hidden columns.
+        self.conn.prepare(&p.probe).map_err(|e| Denied(format!("reads outside the grant: {}", e)))?;
+        Ok(p)
+    }
+
+    fn count<T>(&self, r: Result<T, Denied>) -> Result<T, Denied> {
+        let counter = if r.is_ok() { &self.allowed } else { &self.denied };
+        counter.fetch_add(1, Ordering::Relaxed);
+        r
+    }
+
+    /// Runs a checked SELECT; returns (column names, rows).
+    pub fn query(&self, sql: &str) -> Result<Rows, Box<dyn Error>> {
+        self.count(self.check_select(sql))?;
+        let mut stmt = self.conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+        let names: Vec<String> = rows.as_ref().map(|s| s.column_names()).unwrap_or_default();
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            out.push((0..names.len()).map(|i| row.get::<_, DbValue>(i)).collect::<duckdb::Result<Vec<_>>>()?);
+        }
+        Ok((names, out))
+    }
+
+    /// Runs a checked UPDATE/INSERT/DELETE; returns the affected row count.
+    pub fn execute(&self, sql: &str) -> Result<usize, Box<dyn Error>> {
+        let p = self.count(self.check_write(sql))?;
+        Ok(self.conn.execute(&p.rewritten, [])?)
+    }
+
+    /// (statements allowed, statements denied).
+    pub fn stats(&self) -> (u64, u64) {
+        (self.allowed.load(Ordering::Relaxed), self.denied.load(Ordering::Relaxed))
+    }
+}
+
+// ============================================================================
+// Lua binding
+// ============================================================================
+
+fn to_lua(lua: &Lua, v: DbValue) -> mlua::Result<mlua::Value> {
+    Ok(match v {
+        DbValue::Null => mlua::Value::Nil,
+        DbValue::Boolean(b) => mlua::Value::Boolean(b),
+        DbValue::TinyInt(n) => mlua::Value::Number(n as f64),
+        DbValue::SmallInt(n) => mlua::Value::Number(n as f64),
+        DbValue::Int(n) => mlua::Value::Number(n as f64),
+        DbValue::BigInt(n) => mlua::Value::Number(n as f64),
+        DbValue::HugeInt(n) => mlua::Value::Number(n as f64),
+        DbValue::UTinyInt(n) => mlua::Value::Number(n as f64),
+        DbValue::USmallInt(n) => mlua::Value::Number(n as f64),
+        DbValue::UInt(n) => mlua::Value::Number(n as f64),
+        DbValue::UBigInt(n) => mlua::Value::Number(n as f64),
+        DbValue::Float(f) => mlua::Value::Number(f as f64),
+        DbValue::Double(f) => mlua::Value::Number(f),
+        DbValue::Text(s) => mlua::Value::String(lua.create_string(&s)?),
+        other => mlua::Value::String(lua.create_string(format!("{:?}", other))?),
+    })
+}
+
+/// Builds the mod's `db` table: `db.query(sql)` returns an array of row
+/// tables keyed by column name, `db.execute(sql)` the affected row count.
+/// Denials and SQL errors are raised as Lua errors.
+pub fn install_db(lua: &Lua, sandbox: Rc<ModSandbox>) -> mlua::Result<Table> {
+    let db = lua.create_table()?;
+    let sb = sandbox.clone();
+    db.set(
+        "query",
+        lua.create_function(move |lua, sql: String| {
+            let (names, rows) = sb.query(&sql).map_err(|e| mlua::Error::RuntimeError(e.to_string()))?;
+            let out = lua.create_table()?;
+            for (i, row) in rows.into_iter().enumerate() {
+                let t = lua.create_table()?;
+                for (name, v) in names.iter().zip(row) {
+                    t.set(name.as_str(), to_lua(lua, v)?)?;
+                }
+                out.set(i + 1, t)?;
+            }
+            Ok(out)
+        })?,
+    )?;
+    db.set(
+        "execute",
+        lua.create_function(move |_, sql: String| {
+            sandbox.execute(&sql).map_err(|e| mlua::Error::RuntimeError(e.to_string()))
+        })?,
+    )?;
+    Ok(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_string_cannot_hide_set_targets() {
+        // Read as an unterminated '' literal, this looked like it only set `hp`.
+        let err = parse_write(r"UPDATE units SET hp = length(E'\''), x = 5").unwrap_err();
+        assert!(err.0.contains("escape strings"), "{}", err);
+    }
+
+    #[test]
+    fn backslashes_and_dollar_quotes_are_refused() {
+        assert!(parse_write(r"UPDATE units SET hp = length('\'), x = 5").is_err());
+        assert!(parse_write("UPDATE units SET hp = length($$'$$), x = 5").is_err());
+        assert!(parse_write("UPDATE units SET hp = length($q$'$q$), x = 5").is_err());
+    }
+
+    #[test]
+    fn parameters_and_doubled_quotes_still_parse() {
+        let p = parse_write("UPDATE units SET x = x + vx * $dt, name = 'it''s' WHERE hp > $1").unwrap();
+        assert_eq!(p.columns, ["x", "name"]);
+    }
+}
@@ -0,0 +1,371 @@
+//! Piccolo Batch UDFs over Arrow Columns
+//!
+//! Shared by bins via `mod piccolo_batch;` (needs `mod lua_scripts;`
+//! alongside). `lua_vm_comparison.rs` only
+//! measures Piccolo one call per row (`call_f64_4`), while the LuaJIT side
+//! gets whole columns through FFI. Piccolo has no FFI, so a batch is handed
+//! over as safe views instead. A script defines
+//!
+//!   function distance(n, x1, y1, x2, y2, out)
+//!     for i = 1, n do out[i] = ... x1[i] ... end
+//!   end
+//!
+//! and three `VArrowScalar`s call it on this thread's Piccolo VM:
+//!
+//! - `piccolo_batch(name, a DOUBLE [, b, c, d])`: every column is a userdata
+//!   view whose `__index` / `__newindex` / `__len` callbacks read the Arrow
+//!   buffer and write the output buffer in place. Inputs are read-only;
+//!   views are revoked when the call returns, so a view kept in a global
+//!   errors instead of touching freed memory
+//! - `piccolo_table(...)`: the same function, but the inputs are copied into
+//!   fresh Lua tables and `out` is read back from a table (bulk table fill)
+//! - `piccolo_row(name, ...)`: one call per row returning the value, as in
+//!   `lua_vm_comparison.rs`
+//!
+//! The script is the `lua_scripts` entry named `SCRIPT`, so `set_source`,
+//! `load_file` and `poll_changes` reach Piccolo VMs as well as mlua ones:
+//! each thread's VM is rebuilt from it on the next call after its version
+//! changes. mlua VMs run it too, so it has to be plain Lua. `name` must be
+//! the same for a whole chunk in the batch modes. Rows with a NULL input
+//! are NULL.
+
+#![allow(dead_code)]
+
+use duckdb::arrow::array::{Array, Float64Array, StringArray};
+use duckdb::arrow::datatypes::DataType;
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::vscalar::{ArrowFunctionSignature, VArrowScalar};
+use crate::lua_scripts;
+use duckdb::Connection;
+use piccolo::{
+    Callback, CallbackReturn, Closure, Context, Executor, FromValue, Function, Lua, StashedExecutor, StashedTable,
+    Table, UserData, Value,
+};
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+pub const MAX_BATCH_ARITY: usize = 4;
+/// The `lua_scripts` entry every Piccolo VM runs.
+pub const SCRIPT: &str = "piccolo_batch.lua";
+
+static VMS_CREATED: AtomicUsize = AtomicUsize::new(0);
+
+pub fn vms_created() -> usize {
+    VMS_CREATED.load(Ordering::Relaxed)
+}
+
+// ============================================================================
+// Column views
+// ============================================================================
+
+/// A userdata over one f64 buffer. `live` is shared by every view of a call
+/// and cleared when the call returns.
+struct ColumnView {
+    name: String,
+    ptr: *mut f64,
+    len: usize,
+    writable: bool,
+    live: Rc<Cell<bool>>,
+}
+
+impl ColumnView {
+    fn slot(&self, i: i64) -> Result<usize, String> {
+        if !self.live.get() {
+            return Err(format!("column view `{}` used after its batch returned", self.name));
+        }
+        if i < 1 || i as usize > self.len {
+            return Err(format!("index {} out of range for `{}` (1..={})", i, self.name, self.len));
+        }
+        Ok(i as usize - 1)
+    }
+}
+
+fn runtime_error<'gc>(ctx: Context<'gc>, message: String) -> piccolo::Error<'gc> {
+    piccolo::Error::from_value(Value::String(ctx.intern(message.as_bytes())))
+}
+
+/// Metatable shared by all views of a VM.
+fn view_metatable<'gc>(ctx: Context<'gc>) -> Result<Table<'gc>, piccolo::Error<'gc>> {
+    let mt = Table::new(&ctx);
+    mt.set(ctx, "__index", Callback::from_fn(&ctx, |ctx, _exec, mut stack| {
+        let (ud, i): (UserData, i64) = stack.consume(ctx)?;
+        let view = ud.downcast_static::<ColumnView>()?;
+        let slot = view.slot(i).map_err(|e| runtime_error(ctx, e))?;
+        // SAFETY: `slot` is in bounds and the buffer outlives the call (`live`).
+        stack.push_back(Value::Number(unsafe { *view.ptr.add(slot) }));
+        Ok(CallbackReturn::Return)
+    }))?;
+    mt.set(ctx, "__newindex", Callback::from_fn(&ctx, |ctx, _exec, mut stack| {
+        let (ud, i, v): (UserData, i64, f64) = stack.consume(ctx)?;
+        let view = ud.downcast_static::<ColumnView>()?;
+        if !view.writable {
+            return Err(runtime_error(ctx, format!("column `{}` is read-only", view.name)));
+        }
+        let slot = view.slot(i).map_err(|e| runtime_error(ctx, e))?;
+        // SAFETY: as above; only the output buffer is writable.
+        unsafe { *view.ptr.add(slot) = v };
+        Ok(CallbackReturn::Return)
+    }))?;
+    mt.set(ctx, "__len", Callback::from_fn(&ctx, |ctx, _exec, mut stack| {
+        let ud: UserData = stack.consume(ctx)?;
+        let view = ud.downcast_static::<ColumnView>()?;
+        stack.push_back(Value::Integer(view.len as i64));
+        Ok(CallbackReturn::Return)
+    }))?;
+    Ok(mt)
+}
+
+// ============================================================================
+// Thread-local VM
+// ============================================================================
+
+struct PiccoloVm {
+    lua: Lua,
+    executor: StashedExecutor,
+    view_meta: StashedTable,
+    /// `SCRIPT`'s version this VM was built from.
+    version: u64,
+    /// `lua_scripts::version()` when that was last checked.
+    synced: u64,
+}
+
+thread_local! {
+    static VM: RefCell<Option<PiccoloVm>> = const { RefCell::new(None) };
+}
+
+impl PiccoloVm {
+    fn new(version: u64, source: &str, synced: u64) -> Result<Self, Box<dyn Error>> {
+        VMS_CREATED.fetch_add(1, Ordering::Relaxed);
+        let mut lua = Lua::full();
+        let (executor, view_meta) = lua.try_enter(|ctx| {
+            let closure = Closure::load(ctx, None, source.as_bytes())?;
+            Ok((ctx.stash(Executor::start(ctx, closure.into(), ())), ctx.stash(view_metatable(ctx)?)))
+        })?;
+        lua.execute::<()>(&executor)?;
+        Ok(PiccoloVm { lua, executor, view_meta, version, synced })
+    }
+}
+
+fn with_vm<R>(f: impl FnOnce(&mut PiccoloVm) -> Result<R, Box<dyn Error>>) -> Result<R, Box<dyn Error>> {
+    VM.with(|cell| {
+        let mut slot = cell.borrow_mut();
+        let current = lua_scripts::version();
+        if slot.as_ref().map(|vm| vm.synced) != Some(current) {
+            let (version, source) = lua_scripts::script_source(SCRIPT).ok_or("piccolo_batch: no script registered")?;
+            match slot.as_mut() {
+                Some(vm) if vm.version == version => vm.synced = current,
+                _ => *slot = Some(PiccoloVm::new(version, &source, current)?),
+            }
+        }
+        f(slot.as_mut().expect("created above"))
+    })
+}
+
+fn lookup<'gc>(ctx: Context<'gc>, name: &str) -> Result<Function<'gc>, piccolo::Error<'gc>> {
+    Function::from_value(ctx, ctx.globals().get(ctx, ctx.intern(name.as_bytes())))
+        .map_err(|_| runtime_error(ctx, format!("no Lua function `{}`", name)))
+}
+
+/// Restarts the executor on `func(args...)`; Lua has no splat for a Vec.
+fn restart<'gc>(ctx: Context<'gc>, executor: &StashedExecutor, func: Function<'gc>, args: Vec<Value<'gc>>) -> Result<(), piccolo::Error<'gc>> {
+    let executor = ctx.fetch(executor);
+    match args.as_slice() {
+        [a] => executor.restart(ctx, func, (*a,)),
+        [a, b] => executor.restart(ctx, func, (*a, *b)),
+        [a, b, c] => executor.restart(ctx, func, (*a, *b, *c)),
+        [a, b, c, d] => executor.restart(ctx, func, (*a, *b, *c, *d)),
+        [a, b, c, d, e] => executor.restart(ctx, func, (*a, *b, *c, *d, *e)),
+        [a, b, c, d, e, f] => executor.restart(ctx, func, (*a, *b, *c, *d, *e, *f)),
+        other => return Err(runtime_error(ctx, format!("{} arguments, supported up to 6", other.len()))),
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Calls
+// ============================================================================
+
+/// `name(n, in1, ..., out)` with userdata views; `out` is written in place.
+pub fn call_views(name: &str, inputs: &[&[f64]], out: &mut [f64]) -> Result<(), Box<dyn Error>> {
+    with_vm(|vm| {
+        let PiccoloVm { lua, executor, view_meta, .. } = vm;
+        let live = Rc::new(Cell::new(true));
+        let view = |name: String, ptr: *mut f64, len: usize, writable: bool| ColumnView {
+            name,
+            ptr,
+            len,
+            writable,
+            live: live.clone(),
+        };
+        let mut views: Vec<ColumnView> = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, col)| view(format!("arg{}", i + 1), col.as_ptr() as *mut f64, col.len(), false))
+            .collect();
+        views.push(view("out".into(), out.as_mut_ptr(), out.len(), true));
+
+        lua.try_enter(|ctx| {
+            let meta = ctx.fetch(&*view_meta);
+            let mut args = vec![Value::Integer(out.len() as i64)];
+            for v in views.drain(..) {
+                let ud = UserData::new_static(&ctx, v);
+                ud.set_metatable(&ctx, Some(meta));
+                args.push(Value::UserData(ud));
+            }
+            restart(ctx, executor, lookup(ctx, name)?, args)
+        })?;
+        let result = lua.execute::<()>(executor);
+        live.set(false);
+        Ok(result?)
+    })
+}
+
+/// Same contract as `call_views`, with every column copied into a Lua table.
+pub fn call_tables(name: &str, inputs: &[&[f64]], out: &mut [f64]) -> Result<(), Box<dyn Error>> {
+    with_vm(|vm| {
+        let PiccoloVm { lua, executor, .. } = vm;
+        let out_table = lua.try_enter(|ctx| {
+            let mut args = vec![Value::Integer(out.len() as i64)];
+            for col in inputs {
+                let t = Table::new(&ctx);
+                for (i, v) in col.iter().enumerate() {
+                    t.set(ctx, i as i64 + 1, *v)?;
+                }
+                args.push(Value::Table(t));
+            }
+            let out_table = Table::new(&ctx);
+            args.push(Value::Table(out_table));
+            restart(ctx, executor, lookup(ctx, name)?, args)?;
+            Ok(ctx.stash(out_table))
+        })?;
+        lua.execute::<()>(executor)?;
+        lua.try_enter(|ctx| {
+            let t = ctx.fetch(&out_table);
+            for (i, slot) in out.iter_mut().enumerate() {
+                let v = t.get(ctx, i as i64 + 1);
+                *slot = f64::from_value(ctx, v)
+                    .map_err(|_| runtime_error(ctx, format!("out[{}] is {}, expected a number", i + 1, v.type_name())))?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    })
+}
+
+/// `name(a, b, ...)` once per row, as in `lua_vm_comparison.rs`.
+pub fn call_rows(name: &str, inputs: &[&[f64]], out: &mut [f64]) -> Result<(), Box<dyn Error>> {
+    with_vm(|vm| {
+        let PiccoloVm { lua, executor, .. } = vm;
+        for (row, slot) in out.iter_mut().enumerate() {
+            lua.try_enter(|ctx| {
+                let args = inputs.iter().map(|col| Value::Number(col[row])).collect();
+                restart(ctx, executor, lookup(ctx, name)?, args)
+            })?;
+            *slot = lua.execute::<f64>(executor)?;
+        }
+        Ok(())
+    })
+}
+
+// ============================================================================
+// VArrowScalars
+// ============================================================================
+
+pub type BatchFn = fn(&str, &[&[f64]], &mut [f64]) -> Result<(), Box<dyn Error>>;
+
+fn invoke_with(input: RecordBatch, label: &str, call: BatchFn) -> Result<Arc<dyn Array>, Box<dyn Error>> {
+    let n = input.num_rows();
+    let names = input.column(0).as_any().downcast_ref::<StringArray>().ok_or_else(|| format!("{}: name must be VARCHAR", label))?;
+    let args: Vec<&Float64Array> = (1..input.num_columns())
+        .map(|c| input.column(c).as_any().downcast_ref::<Float64Array>().ok_or_else(|| format!("{}: arguments must be DOUBLE", label)))
+        .collect::<Result<_, _>>()?;
+    if n == 0 {
+        return Ok(Arc::new(Float64Array::from(Vec::<f64>::new())));
+    }
+    if names.null_count() > 0 || (1..n).any(|i| names.value(i) != names.value(0)) {
+        return Err(format!("{}: function name must be one non-NULL constant", label).into());
+    }
+    let inputs: Vec<&[f64]> = args.iter().map(|a| &a.values()[..]).collect();
+    let mut out = vec![0.0; n];
+    call(names.value(0), &inputs, &mut out)?;
+
+    if args.iter().all(|a| a.null_count() == 0) {
+        return Ok(Arc::new(Float64Array::from(out)));
+    }
+    Ok(Arc::new(
+        out.into_iter()
+            .enumerate()
+            .map(|(i, v)| if args.iter().any(|a| a.is_null(i)) { None } else { Some(v) })
+            .collect::<Float64Array>(),
+    ))
+}
+
+fn signatures() -> Vec<ArrowFunctionSignature> {
+    (1..=MAX_BATCH_ARITY)
+        .map(|n| {
+            let mut args = vec![DataType::Utf8];
+            args.extend(std::iter::repeat_n(DataType::Float64, n));
+            ArrowFunctionSignature::exact(args, DataType::Float64)
+        })
+        .collect()
+}
+
+pub struct PiccoloBatch;
+
+impl VArrowScalar for PiccoloBatch {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<Arc<dyn Array>, Box<dyn Error>> {
+        invoke_with(input, "piccolo_batch", call_views)
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        signatures()
+    }
+}
+
+pub struct PiccoloTable;
+
+impl VArrowScalar for PiccoloTable {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<Arc<dyn Array>, Box<dyn Error>> {
+        invoke_with(input, "piccolo_table", call_tables)
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        signatures()
+    }
+}
+
+pub struct PiccoloRow;
+
+impl VArrowScalar for PiccoloRow {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<Arc<dyn Array>, Box<dyn Error>> {
+        invoke_with(input, "piccolo_row", call_rows)
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        signatures()
+    }
+}
+
+/// Registers `piccolo_batch`, `piccolo_table` and `piccolo_row` unless present.
+pub fn register(conn: &Connection) -> duckdb::Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT count(*) > 0 FROM duckdb_functions() WHERE function_name = 'piccolo_batch'",
+        [],
+        |r| r.get(0),
+    )?;
+    if !exists {
+        conn.register_scalar_function::<PiccoloBatch>("piccolo_batch")?;
+        conn.register_scalar_function::<PiccoloTable>("piccolo_table")?;
+        conn.register_scalar_function::<PiccoloRow>("piccolo_row")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,264 @@
+//! Piccolo Batch UDFs vs LuaJIT FFI
+//!
+//! `lua_vm_comparison.rs` pits Piccolo's per-row `call_f64_4` against
+//! LuaJIT, and `duckdb_luajit_ffi.rs` gives LuaJIT whole columns, so the safe
+//! VM looks slow partly by construction. With `piccolo_batch.rs` Piccolo gets
+//! whole DuckDB chunks too:
+//! 1. The same distance UDF six ways: SQL, LuaJIT FFI batch, LuaJIT per
+//!    row, Piccolo userdata views, Piccolo table fill, Piccolo per row; all
+//!    checked against SQL
+//! 2. Throughput on one thread, and the batch vs per-row speedup per VM
+//! 3. Guards on the safe views: writing an input, indexing past the end, a
+//!    view kept after its batch, a missing `out`, mixed function names; a
+//!    reload of the Piccolo script reaching its VMs
+//!
+//! Both scripts are registered with `lua_scripts` (with `enable_ffi()`): the
+//! LuaJIT UDFs run on its thread-local VMs, and the Piccolo VMs rebuild from
+//! the `piccolo_batch::SCRIPT` entry.
+
+mod lua_scripts;
+mod piccolo_batch;
+
+use duckdb::arrow::array::{Array, Float64Array};
+use duckdb::arrow::datatypes::DataType;
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::vscalar::{ArrowFunctionSignature, VArrowScalar};
+use duckdb::Connection;
+use mlua::LightUserData;
+use std::error::Error;
+use std::ffi::c_void;
+use std::sync::Arc;
+use std::time::Instant;
+
+const ROWS: usize = 200_000;
+
+const PICCOLO_LUA: &str = r#"
+function distance(n, x1, y1, x2, y2, out)
+  for i = 1, n do
+    local dx, dy = x2[i] - x1[i], y2[i] - y1[i]
+    out[i] = math.sqrt(dx * dx + dy * dy)
+  end
+end
+
+function distance_row(x1, y1, x2, y2)
+  local dx, dy = x2 - x1, y2 - y1
+  return math.sqrt(dx * dx + dy * dy)
+end
+
+-- Guards
+function write_input(n, x, out) x[1] = 0 end
+function overrun(n, x, out) out[n + 1] = 0 end
+function keep(n, x, out) saved = x; for i = 1, n do out[i] = 0 end end
+function use_kept(n, x, out) out[1] = saved[1] end
+function forget_out(n, x, out) end
+"#;
+
+const LUAJIT_LUA: &str = r#"
+local ffi = require("ffi")
+local cast = ffi.cast
+
+function distance_ffi(n, x1, y1, x2, y2, out)
+  x1, y1 = cast("const double*", x1), cast("const double*", y1)
+  x2, y2 = cast("const double*", x2), cast("const double*", y2)
+  out = cast("double*", out)
+  for i = 0, n - 1 do
+    local dx, dy = x2[i] - x1[i], y2[i] - y1[i]
+    out[i] = math.sqrt(dx * dx + dy * dy)
+  end
+end
+
+function distance_row(x1, y1, x2, y2)
+  local dx, dy = x2 - x1, y2 - y1
+  return math.sqrt(dx * dx + dy * dy)
+end
+"#;
+
+// ============================================================================
+// LuaJIT reference UDFs
+// ============================================================================
+
+fn columns(input: &RecordBatch) -> Result<Vec<&Float64Array>, Box<dyn Error>> {
+    (0..input.num_columns())
+        .map(|c| input.column(c).as_any().downcast_ref::<Float64Array>().ok_or_else(|| "arguments must be DOUBLE".into()))
+        .collect()
+}
+
+fn distance_signature() -> Vec<ArrowFunctionSignature> {
+    vec![ArrowFunctionSignature::exact(vec![DataType::Float64; 4], DataType::Float64)]
+}
+
+struct JitFfiDistance;
+
+impl VArrowScalar for JitFfiDistance {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<Arc<dyn Array>, Box<dyn Error>> {
+        let cols = columns(&input)?;
+        let mut out = vec![0.0; input.num_rows()];
+        lua_scripts::with_vm(|lua| {
+            let f: mlua::Function = lua.globals().get("distance_ffi")?;
+            let ptr = |a: &Float64Array| LightUserData(a.values().as_ptr() as *mut c_void);
+            f.call::<()>((
+                out.len(),
+                ptr(cols[0]),
+                ptr(cols[1]),
+                ptr(cols[2]),
+                ptr(cols[3]),
+                LightUserData(out.as_mut_ptr() as *mut c_void),
+            ))
+        })?;
+        Ok(Arc::new(Float64Array::from(out)))
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        distance_signature()
+    }
+}
+
+struct JitRowDistance;
+
+impl VArrowScalar for JitRowDistance {
+    type State = ();
+
+    fn invoke(_state: &Self::State, input: RecordBatch) -> Result<Arc<dyn Array>, Box<dyn Error>> {
+        let cols = columns(&input)?;
+        let out = lua_scripts::with_vm(|lua| {
+            let f: mlua::Function = lua.globals().get("distance_row")?;
+            (0..input.num_rows())
+                .map(|i| f.call::<f64>((cols[0].value(i), cols[1].value(i), cols[2].value(i), cols[3].value(i))))
+                .collect::<mlua::Result<Vec<f64>>>()
+        })?;
+        Ok(Arc::new(Float64Array::from(out)))
+    }
+
+    fn signatures() -> Vec<ArrowFunctionSignature> {
+        distance_signature()
+    }
+}
+
+// ============================================================================
+// Main
+// ============================================================================
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== Piccolo Batch UDFs vs LuaJIT FFI ===\n");
+    lua_scripts::enable_ffi();
+    lua_scripts::set_source(piccolo_batch::SCRIPT, PICCOLO_LUA);
+    lua_scripts::set_source("distance.lua", LUAJIT_LUA);
+
+    let conn = Connection::open_in_memory()?;
+    // One thread: per-row cost without parallelism muddying the ratios.
+    conn.execute_batch(&format!(
+        "SET threads TO 1;
+         CREATE TABLE pairs AS
+         SELECT (i % 1000)::DOUBLE AS x1, (i // 1000)::DOUBLE AS y1,
+                ((i * 7) % 1000)::DOUBLE AS x2, ((i * 13) % 1000)::DOUBLE AS y2
+         FROM range({ROWS}) t(i);"
+    ))?;
+    piccolo_batch::register(&conn)?;
+    conn.register_scalar_function::<JitFfiDistance>("jit_ffi_distance")?;
+    conn.register_scalar_function::<JitRowDistance>("jit_row_distance")?;
+    let mut failures = 0;
+
+    // --------------------------------------------------------------------
+    // 1-2. Correctness and throughput
+    // --------------------------------------------------------------------
+    println!("--- 1. {} rows, 1 thread ---", ROWS);
+    let variants = [
+        ("SQL", "sqrt((x2 - x1) * (x2 - x1) + (y2 - y1) * (y2 - y1))"),
+        ("LuaJIT FFI batch", "jit_ffi_distance(x1, y1, x2, y2)"),
+        ("LuaJIT per row", "jit_row_distance(x1, y1, x2, y2)"),
+        ("Piccolo views", "piccolo_batch('distance', x1, y1, x2, y2)"),
+        ("Piccolo tables", "piccolo_table('distance', x1, y1, x2, y2)"),
+        ("Piccolo per row", "piccolo_row('distance_row', x1, y1, x2, y2)"),
+    ];
+    let mut timings = Vec::new();
+    let mut reference = None;
+    for (label, expr) in variants {
+        let sql = format!("SELECT sum({}) FROM pairs", expr);
+        let _: f64 = conn.query_row(&sql, [], |r| r.get(0))?; // warm up VMs and the JIT
+        let start = Instant::now();
+        let sum: f64 = conn.query_row(&sql, [], |r| r.get(0))?;
+        let elapsed = start.elapsed();
+        let want = *reference.get_or_insert(sum);
+        let ok = (sum - want).abs() <= 1e-9 * want.abs();
+        if !ok {
+            failures += 1;
+        }
+        let ns = elapsed.as_nanos() as f64 / ROWS as f64;
+        println!("  {:<17} {:>9.2?}  {:>7.1} ns/row  sum {:.3}  {}", label, elapsed, ns, sum, if ok { "✓" } else { "✗" });
+        timings.push((label, ns));
+    }
+
+    println!("\n--- 2. Ratios ---");
+    let ns = |label: &str| timings.iter().find(|(l, _)| *l == label).map_or(f64::NAN, |(_, n)| *n);
+    let ffi = ns("LuaJIT FFI batch");
+    for (label, n) in &timings {
+        println!("  {:<17} {:>6.1}× LuaJIT FFI", label, n / ffi);
+    }
+    println!("  batch speedup: LuaJIT {:.1}× (FFI vs per row), Piccolo {:.1}× (views vs per row), tables {:.1}×",
+             ns("LuaJIT per row") / ffi, ns("Piccolo per row") / ns("Piccolo views"),
+             ns("Piccolo per row") / ns("Piccolo tables"));
+    println!("  {} Piccolo VM(s) created", piccolo_batch::vms_created());
+
+    // --------------------------------------------------------------------
+    // 3. Guards
+    // --------------------------------------------------------------------
+    println!("\n--- 3. Guards ---");
+    let xs = vec![1.0, 2.0, 3.0];
+    let mut out = vec![0.0; 3];
+    let guards: [(&str, &str, piccolo_batch::BatchFn); 5] = [
+        ("write input", "write_input", piccolo_batch::call_views),
+        ("index past end", "overrun", piccolo_batch::call_views),
+        ("kept view", "use_kept", piccolo_batch::call_views),
+        ("missing out", "forget_out", piccolo_batch::call_tables),
+        ("unknown function", "nope", piccolo_batch::call_views),
+    ];
+    piccolo_batch::call_views("keep", &[&xs], &mut out)?;
+    for (label, function, call) in guards {
+        match call(function, &[&xs], &mut out) {
+            Ok(()) => {
+                failures += 1;
+                println!("  ✗ {:<17} accepted", label);
+            }
+            Err(e) => println!("  ✓ {:<17} {}", label, e.to_string().lines().next().unwrap_or("")),
+        }
+    }
+    match conn.query_row(
+        "SELECT sum(piccolo_batch(CASE WHEN x1 < 500 THEN 'distance' ELSE 'other' END, x1, y1, x2, y2)) FROM pairs",
+        [],
+        |r| r.get::<_, f64>(0),
+    ) {
+        Ok(_) => {
+            failures += 1;
+            println!("  ✗ mixed names       accepted");
+        }
+        Err(e) => println!("  ✓ mixed names       {}", e.to_string().lines().next().unwrap_or("")),
+    }
+    let created = piccolo_batch::vms_created();
+    let double = "function double(n, x, out) for i = 1, n do out[i] = 2 * x[i] end end";
+    lua_scripts::set_source(piccolo_batch::SCRIPT, format!("{}\n{}", PICCOLO_LUA, double));
+    let reloaded = piccolo_batch::call_views("double", &[&xs], &mut out);
+    let rebuilt = piccolo_batch::vms_created() - created;
+    match reloaded {
+        Ok(()) if out == [2.0, 4.0, 6.0] && rebuilt == 1 => {
+            println!("  ✓ reload            `double` from the new version, VM rebuilt once")
+        }
+        other => {
+            failures += 1;
+            let error = other.err().map(|e| e.to_string());
+            println!("  ✗ reload            {:?}, out {:?}, {} VM(s) rebuilt", error, out, rebuilt);
+        }
+    }
+
+    println!("\n=== Summary ===\n");
+    println!("  • Piccolo now gets whole chunks: userdata views over the Arrow buffers, or bulk-filled tables");
+    println!("  • Views are read-only for inputs, bounds-checked and revoked after the call; no FFI needed");
+    println!("  • LuaJIT FFI stays the ceiling; compare the batch columns above, not per-row against FFI");
+    println!("  • Failed checks: {}", failures);
+
+    if failures > 0 {
+        return Err(format!("{} self-check(s) failed", failures).into());
+    }
+    Ok(())
+}
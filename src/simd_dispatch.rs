@@ -0,0 +1,189 @@
+//! SIMD Distance Kernels: Runtime Dispatch, Verification and Benchmarks
+//!
+//! Exercises `simd_kernels.rs`:
+//! 1. Reports which level `kernels()` picked on this CPU
+//! 2. Property check: every supported level against scalar on random inputs
+//!    of random lengths (bit-identical distances, identical masks/indices)
+//! 3. Throughput per level for distances, within-radius and pair filtering
+//! 4. A grid query whose per-cell inner loop is `filter_within` over SoA columns
+
+mod simd_kernels;
+
+use rand::{Rng, SeedableRng};
+use simd_kernels::{kernels, Kernels, Level};
+use std::collections::HashMap;
+use std::time::Instant;
+
+const WORLD: f64 = 1000.0;
+
+fn random_vec(rng: &mut impl Rng, n: usize) -> Vec<f64> {
+    (0..n).map(|_| rng.gen_range(0.0..WORLD)).collect()
+}
+
+// ============================================================================
+// Property check against scalar
+// ============================================================================
+
+fn check_level(k: &Kernels, cases: usize, rng: &mut impl Rng) -> Result<(), String> {
+    let scalar = Kernels::for_level(Level::Scalar).expect("scalar always available");
+    for case in 0..cases {
+        let n = rng.gen_range(0..300);
+        let (x1, y1) = (random_vec(rng, n), random_vec(rng, n));
+        let (x2, y2) = (random_vec(rng, n), random_vec(rng, n));
+        let radius_sq = rng.gen_range(0.0..WORLD * WORLD / 4.0);
+        let (qx, qy) = (rng.gen_range(0.0..WORLD), rng.gen_range(0.0..WORLD));
+
+        let (mut want, mut got) = (vec![0.0; n], vec![0.0; n]);
+        scalar.distances(&x1, &y1, &x2, &y2, &mut want);
+        k.distances(&x1, &y1, &x2, &y2, &mut got);
+        if want.iter().zip(&got).any(|(a, b)| a.to_bits() != b.to_bits()) {
+            return Err(format!("distances differ (case {}, n = {})", case, n));
+        }
+        scalar.distances_sq(&x1, &y1, &x2, &y2, &mut want);
+        k.distances_sq(&x1, &y1, &x2, &y2, &mut got);
+        if want.iter().zip(&got).any(|(a, b)| a.to_bits() != b.to_bits()) {
+            return Err(format!("distances_sq differ (case {}, n = {})", case, n));
+        }
+
+        let (mut want, mut got) = (vec![false; n], vec![false; n]);
+        scalar.within(&x1, &y1, &x2, &y2, radius_sq, &mut want);
+        k.within(&x1, &y1, &x2, &y2, radius_sq, &mut got);
+        if want != got {
+            return Err(format!("within differs (case {}, n = {})", case, n));
+        }
+
+        let (mut want, mut got) = (Vec::new(), Vec::new());
+        scalar.filter_within(qx, qy, &x1, &y1, radius_sq, &mut want);
+        k.filter_within(qx, qy, &x1, &y1, radius_sq, &mut got);
+        if want != got {
+            return Err(format!("filter_within differs (case {}, n = {})", case, n));
+        }
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Grid query: SoA cells + filter_within
+// ============================================================================
+
+#[derive(Default)]
+struct Cell {
+    ids: Vec<u32>,
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+}
+
+fn build_grid(xs: &[f64], ys: &[f64], cell_size: f64) -> HashMap<(i32, i32), Cell> {
+    let mut grid: HashMap<(i32, i32), Cell> = HashMap::new();
+    for i in 0..xs.len() {
+        let cell = grid
+            .entry(((xs[i] / cell_size) as i32, (ys[i] / cell_size) as i32))
+            .or_default();
+        cell.ids.push(i as u32);
+        cell.xs.push(xs[i]);
+        cell.ys.push(ys[i]);
+    }
+    grid
+}
+
+/// Number of (i, j) with i != j and distance < radius.
+fn grid_neighbour_count(k: &Kernels, grid: &HashMap<(i32, i32), Cell>, xs: &[f64], ys: &[f64], radius: f64) -> usize {
+    let mut hits = Vec::new();
+    let mut count = 0;
+    for i in 0..xs.len() {
+        let (cx, cy) = ((xs[i] / radius) as i32, (ys[i] / radius) as i32);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let Some(cell) = grid.get(&(cx + dx, cy + dy)) else { continue };
+                hits.clear();
+                k.filter_within(xs[i], ys[i], &cell.xs, &cell.ys, radius * radius, &mut hits);
+                count += hits.iter().filter(|&&h| cell.ids[h as usize] != i as u32).count();
+            }
+        }
+    }
+    count
+}
+
+fn main() {
+    println!("=== SIMD Distance Kernels with Runtime Dispatch ===\n");
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+
+    println!("--- CPU Support ---");
+    for level in Level::ALL {
+        println!("  {:<8} {}", format!("{:?}", level), if level.is_supported() { "yes" } else { "no" });
+    }
+    println!("  Selected: {:?}\n", kernels().level);
+
+    println!("--- Property Check vs Scalar (1000 random cases per level) ---");
+    let mut all_ok = true;
+    for level in Level::ALL {
+        match Kernels::for_level(level) {
+            None => println!("  {:?}: skipped (unsupported)", level),
+            Some(k) => match check_level(&k, 1000, &mut rng) {
+                Ok(()) => println!("  {:?}: ok", level),
+                Err(e) => {
+                    all_ok = false;
+                    println!("  {:?}: MISMATCH — {}", level, e);
+                }
+            },
+        }
+    }
+    println!();
+
+    println!("--- Throughput (1M pairs) ---");
+    let n = 1_000_000;
+    let (x1, y1) = (random_vec(&mut rng, n), random_vec(&mut rng, n));
+    let (x2, y2) = (random_vec(&mut rng, n), random_vec(&mut rng, n));
+    let mut dist = vec![0.0; n];
+    let mut mask = vec![false; n];
+    let mut idx = Vec::with_capacity(n);
+    let iterations = 20;
+    for level in Level::ALL {
+        let Some(k) = Kernels::for_level(level) else { continue };
+
+        let start = Instant::now();
+        for _ in 0..iterations {
+            k.distances(&x1, &y1, &x2, &y2, &mut dist);
+        }
+        let d_time = start.elapsed().as_nanos() as f64 / (iterations * n) as f64;
+
+        let start = Instant::now();
+        for _ in 0..iterations {
+            k.within(&x1, &y1, &x2, &y2, 100.0 * 100.0, &mut mask);
+        }
+        let w_time = start.elapsed().as_nanos() as f64 / (iterations * n) as f64;
+
+        let start = Instant::now();
+        for _ in 0..iterations {
+            idx.clear();
+            k.filter_within(500.0, 500.0, &x1, &y1, 100.0 * 100.0, &mut idx);
+        }
+        let f_time = start.elapsed().as_nanos() as f64 / (iterations * n) as f64;
+
+        println!("  {:<7} distances {:.3} ns/pair, within {:.3} ns/pair, filter {:.3} ns/point",
+                 format!("{:?}", level), d_time, w_time, f_time);
+    }
+    println!();
+
+    println!("--- Grid Query (100K entities, radius 10) ---");
+    let m = 100_000;
+    let radius = 10.0;
+    let (xs, ys) = (random_vec(&mut rng, m), random_vec(&mut rng, m));
+    let grid = build_grid(&xs, &ys, radius);
+    let mut counts = Vec::new();
+    for level in Level::ALL {
+        let Some(k) = Kernels::for_level(level) else { continue };
+        let start = Instant::now();
+        let count = grid_neighbour_count(&k, &grid, &xs, &ys, radius);
+        println!("  {:<7} {:>8.2} ms  ({} neighbour pairs)",
+                 format!("{:?}", level), start.elapsed().as_secs_f64() * 1000.0, count);
+        counts.push(count);
+    }
+    let grid_ok = counts.windows(2).all(|w| w[0] == w[1]);
+    println!("  All levels agree: {}\n", grid_ok);
+
+    println!("=== Summary ===\n");
+    println!("  • Level chosen once at startup; unsupported levels are unreachable");
+    println!("  • All supported levels match scalar: {}", all_ok && grid_ok);
+}
@@ -0,0 +1,441 @@
+//! Distance Kernels with Runtime CPU Dispatch
+//!
+//! Shared by bins via `mod simd_kernels;`. Every kernel has scalar, SSE2,
+//! AVX2 and AVX-512 variants; `kernels()` picks the best one the CPU supports
+//! once, on first use, via `is_x86_feature_detected!`. `Kernels::for_level`
+//! returns `None` for a level the CPU can't run, so the `#[target_feature]`
+//! functions are unreachable without a successful check.
+//!
+//! All variants do the same sub/mul/add/sqrt sequence without FMA, so their
+//! results are bit-identical to the scalar version.
+
+#![allow(dead_code)]
+
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Scalar,
+    Sse2,
+    Avx2,
+    Avx512,
+}
+
+impl Level {
+    pub const ALL: [Level; 4] = [Level::Scalar, Level::Sse2, Level::Avx2, Level::Avx512];
+
+    pub fn is_supported(self) -> bool {
+        match self {
+            Level::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            Level::Sse2 => is_x86_feature_detected!("sse2"),
+            #[cfg(target_arch = "x86_64")]
+            Level::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            Level::Avx512 => is_x86_feature_detected!("avx512f"),
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => false,
+        }
+    }
+}
+
+type DistancesFn = fn(&[f64], &[f64], &[f64], &[f64], &mut [f64]);
+type WithinFn = fn(&[f64], &[f64], &[f64], &[f64], f64, &mut [bool]);
+type FilterFn = fn(f64, f64, &[f64], &[f64], f64, &mut Vec<u32>);
+
+/// One set of kernels for a single instruction-set level.
+#[derive(Clone, Copy)]
+pub struct Kernels {
+    pub level: Level,
+    distances: DistancesFn,
+    distances_sq: DistancesFn,
+    within: WithinFn,
+    filter: FilterFn,
+}
+
+impl Kernels {
+    /// Kernels for `level`, or `None` if this CPU can't run them.
+    pub fn for_level(level: Level) -> Option<Kernels> {
+        if !level.is_supported() {
+            return None;
+        }
+        Some(match level {
+            Level::Scalar => Kernels {
+                level,
+                distances: scalar::distances,
+                distances_sq: scalar::distances_sq,
+                within: scalar::within,
+                filter: scalar::filter_within,
+            },
+            #[cfg(target_arch = "x86_64")]
+            Level::Sse2 => Kernels {
+                level,
+                distances: |a, b, c, d, out| unsafe { sse2::distances(a, b, c, d, out) },
+                distances_sq: |a, b, c, d, out| unsafe { sse2::distances_sq(a, b, c, d, out) },
+                within: |a, b, c, d, r, out| unsafe { sse2::within(a, b, c, d, r, out) },
+                filter: |x, y, xs, ys, r, out| unsafe { sse2::filter_within(x, y, xs, ys, r, out) },
+            },
+            #[cfg(target_arch = "x86_64")]
+            Level::Avx2 => Kernels {
+                level,
+                distances: |a, b, c, d, out| unsafe { avx2::distances(a, b, c, d, out) },
+                distances_sq: |a, b, c, d, out| unsafe { avx2::distances_sq(a, b, c, d, out) },
+                within: |a, b, c, d, r, out| unsafe { avx2::within(a, b, c, d, r, out) },
+                filter: |x, y, xs, ys, r, out| unsafe { avx2::filter_within(x, y, xs, ys, r, out) },
+            },
+            #[cfg(target_arch = "x86_64")]
+            Level::Avx512 => Kernels {
+                level,
+                distances: |a, b, c, d, out| unsafe { avx512::distances(a, b, c, d, out) },
+                distances_sq: |a, b, c, d, out| unsafe { avx512::distances_sq(a, b, c, d, out) },
+                within: |a, b, c, d, r, out| unsafe { avx512::within(a, b, c, d, r, out) },
+                filter: |x, y, xs, ys, r, out| unsafe { avx512::filter_within(x, y, xs, ys, r, out) },
+            },
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => unreachable!("is_supported() is false off x86_64"),
+        })
+    }
+
+    /// Best level this CPU supports.
+    pub fn detect() -> Kernels {
+        Level::ALL
+            .iter()
+            .rev()
+            .find_map(|&level| Kernels::for_level(level))
+            .expect("scalar kernels are always available")
+    }
+
+    /// `out[i] = |(x2[i], y2[i]) - (x1[i], y1[i])|`
+    pub fn distances(&self, x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        let n = out.len();
+        assert!(x1.len() == n && y1.len() == n && x2.len() == n && y2.len() == n, "length mismatch");
+        (self.distances)(x1, y1, x2, y2, out)
+    }
+
+    /// `out[i] = |(x2[i], y2[i]) - (x1[i], y1[i])|²`
+    pub fn distances_sq(&self, x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        let n = out.len();
+        assert!(x1.len() == n && y1.len() == n && x2.len() == n && y2.len() == n, "length mismatch");
+        (self.distances_sq)(x1, y1, x2, y2, out)
+    }
+
+    /// `out[i] = dist_sq(i) < radius_sq`
+    pub fn within(&self, x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], radius_sq: f64, out: &mut [bool]) {
+        let n = out.len();
+        assert!(x1.len() == n && y1.len() == n && x2.len() == n && y2.len() == n, "length mismatch");
+        (self.within)(x1, y1, x2, y2, radius_sq, out)
+    }
+
+    /// Append to `out` the indices `i` with `dist_sq((x, y), (xs[i], ys[i])) < radius_sq`.
+    /// This is the inner loop of a grid query over one cell's SoA columns.
+    pub fn filter_within(&self, x: f64, y: f64, xs: &[f64], ys: &[f64], radius_sq: f64, out: &mut Vec<u32>) {
+        assert_eq!(xs.len(), ys.len(), "length mismatch");
+        (self.filter)(x, y, xs, ys, radius_sq, out)
+    }
+}
+
+static SELECTED: OnceLock<Kernels> = OnceLock::new();
+
+/// Process-wide kernels, detected on first call.
+pub fn kernels() -> &'static Kernels {
+    SELECTED.get_or_init(Kernels::detect)
+}
+
+// ============================================================================
+// Scalar (reference + remainder handling)
+// ============================================================================
+
+mod scalar {
+    #[inline(always)]
+    fn dist_sq(x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        let dx = x2 - x1;
+        let dy = y2 - y1;
+        dx * dx + dy * dy
+    }
+
+    pub fn distances(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        distances_from(0, x1, y1, x2, y2, out)
+    }
+
+    pub fn distances_sq(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        distances_sq_from(0, x1, y1, x2, y2, out)
+    }
+
+    pub fn within(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], radius_sq: f64, out: &mut [bool]) {
+        within_from(0, x1, y1, x2, y2, radius_sq, out)
+    }
+
+    pub fn filter_within(x: f64, y: f64, xs: &[f64], ys: &[f64], radius_sq: f64, out: &mut Vec<u32>) {
+        filter_from(0, x, y, xs, ys, radius_sq, out)
+    }
+
+    // `*_from(start, ..)` finish the tail the vector loops leave behind.
+
+    pub fn distances_from(start: usize, x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        for i in start..out.len() {
+            out[i] = dist_sq(x1[i], y1[i], x2[i], y2[i]).sqrt();
+        }
+    }
+
+    pub fn distances_sq_from(start: usize, x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        for i in start..out.len() {
+            out[i] = dist_sq(x1[i], y1[i], x2[i], y2[i]);
+        }
+    }
+
+    pub fn within_from(start: usize, x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], radius_sq: f64, out: &mut [bool]) {
+        for i in start..out.len() {
+            out[i] = dist_sq(x1[i], y1[i], x2[i], y2[i]) < radius_sq;
+        }
+    }
+
+    pub fn filter_from(start: usize, x: f64, y: f64, xs: &[f64], ys: &[f64], radius_sq: f64, out: &mut Vec<u32>) {
+        for i in start..xs.len() {
+            if dist_sq(x, y, xs[i], ys[i]) < radius_sq {
+                out.push(i as u32);
+            }
+        }
+    }
+}
+
+// ============================================================================
+// SSE2: 2 lanes
+// ============================================================================
+
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use super::scalar;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn dist_sq(x1: __m128d, y1: __m128d, x2: __m128d, y2: __m128d) -> __m128d {
+        let dx = _mm_sub_pd(x2, x1);
+        let dy = _mm_sub_pd(y2, y1);
+        _mm_add_pd(_mm_mul_pd(dx, dx), _mm_mul_pd(dy, dy))
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn distances(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        let n = out.len() / 2 * 2;
+        for i in (0..n).step_by(2) {
+            let d = dist_sq(
+                _mm_loadu_pd(x1.as_ptr().add(i)),
+                _mm_loadu_pd(y1.as_ptr().add(i)),
+                _mm_loadu_pd(x2.as_ptr().add(i)),
+                _mm_loadu_pd(y2.as_ptr().add(i)),
+            );
+            _mm_storeu_pd(out.as_mut_ptr().add(i), _mm_sqrt_pd(d));
+        }
+        scalar::distances_from(n, x1, y1, x2, y2, out);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn distances_sq(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        let n = out.len() / 2 * 2;
+        for i in (0..n).step_by(2) {
+            let d = dist_sq(
+                _mm_loadu_pd(x1.as_ptr().add(i)),
+                _mm_loadu_pd(y1.as_ptr().add(i)),
+                _mm_loadu_pd(x2.as_ptr().add(i)),
+                _mm_loadu_pd(y2.as_ptr().add(i)),
+            );
+            _mm_storeu_pd(out.as_mut_ptr().add(i), d);
+        }
+        scalar::distances_sq_from(n, x1, y1, x2, y2, out);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn within(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], radius_sq: f64, out: &mut [bool]) {
+        let n = out.len() / 2 * 2;
+        let r = _mm_set1_pd(radius_sq);
+        for i in (0..n).step_by(2) {
+            let d = dist_sq(
+                _mm_loadu_pd(x1.as_ptr().add(i)),
+                _mm_loadu_pd(y1.as_ptr().add(i)),
+                _mm_loadu_pd(x2.as_ptr().add(i)),
+                _mm_loadu_pd(y2.as_ptr().add(i)),
+            );
+            let mask = _mm_movemask_pd(_mm_cmplt_pd(d, r));
+            out[i] = mask & 1 != 0;
+            out[i + 1] = mask & 2 != 0;
+        }
+        scalar::within_from(n, x1, y1, x2, y2, radius_sq, out);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn filter_within(x: f64, y: f64, xs: &[f64], ys: &[f64], radius_sq: f64, out: &mut Vec<u32>) {
+        let n = xs.len() / 2 * 2;
+        let (qx, qy, r) = (_mm_set1_pd(x), _mm_set1_pd(y), _mm_set1_pd(radius_sq));
+        for i in (0..n).step_by(2) {
+            let d = dist_sq(qx, qy, _mm_loadu_pd(xs.as_ptr().add(i)), _mm_loadu_pd(ys.as_ptr().add(i)));
+            let mut mask = _mm_movemask_pd(_mm_cmplt_pd(d, r));
+            while mask != 0 {
+                out.push(i as u32 + mask.trailing_zeros());
+                mask &= mask - 1;
+            }
+        }
+        scalar::filter_from(n, x, y, xs, ys, radius_sq, out);
+    }
+}
+
+// ============================================================================
+// AVX2: 4 lanes
+// ============================================================================
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use super::scalar;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn dist_sq(x1: __m256d, y1: __m256d, x2: __m256d, y2: __m256d) -> __m256d {
+        let dx = _mm256_sub_pd(x2, x1);
+        let dy = _mm256_sub_pd(y2, y1);
+        _mm256_add_pd(_mm256_mul_pd(dx, dx), _mm256_mul_pd(dy, dy))
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn distances(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        let n = out.len() / 4 * 4;
+        for i in (0..n).step_by(4) {
+            let d = dist_sq(
+                _mm256_loadu_pd(x1.as_ptr().add(i)),
+                _mm256_loadu_pd(y1.as_ptr().add(i)),
+                _mm256_loadu_pd(x2.as_ptr().add(i)),
+                _mm256_loadu_pd(y2.as_ptr().add(i)),
+            );
+            _mm256_storeu_pd(out.as_mut_ptr().add(i), _mm256_sqrt_pd(d));
+        }
+        scalar::distances_from(n, x1, y1, x2, y2, out);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn distances_sq(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        let n = out.len() / 4 * 4;
+        for i in (0..n).step_by(4) {
+            let d = dist_sq(
+                _mm256_loadu_pd(x1.as_ptr().add(i)),
+                _mm256_loadu_pd(y1.as_ptr().add(i)),
+                _mm256_loadu_pd(x2.as_ptr().add(i)),
+                _mm256_loadu_pd(y2.as_ptr().add(i)),
+            );
+            _mm256_storeu_pd(out.as_mut_ptr().add(i), d);
+        }
+        scalar::distances_sq_from(n, x1, y1, x2, y2, out);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn within(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], radius_sq: f64, out: &mut [bool]) {
+        let n = out.len() / 4 * 4;
+        let r = _mm256_set1_pd(radius_sq);
+        for i in (0..n).step_by(4) {
+            let d = dist_sq(
+                _mm256_loadu_pd(x1.as_ptr().add(i)),
+                _mm256_loadu_pd(y1.as_ptr().add(i)),
+                _mm256_loadu_pd(x2.as_ptr().add(i)),
+                _mm256_loadu_pd(y2.as_ptr().add(i)),
+            );
+            let mask = _mm256_movemask_pd(_mm256_cmp_pd::<_CMP_LT_OQ>(d, r));
+            for lane in 0..4 {
+                out[i + lane] = mask & (1 << lane) != 0;
+            }
+        }
+        scalar::within_from(n, x1, y1, x2, y2, radius_sq, out);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn filter_within(x: f64, y: f64, xs: &[f64], ys: &[f64], radius_sq: f64, out: &mut Vec<u32>) {
+        let n = xs.len() / 4 * 4;
+        let (qx, qy, r) = (_mm256_set1_pd(x), _mm256_set1_pd(y), _mm256_set1_pd(radius_sq));
+        for i in (0..n).step_by(4) {
+            let d = dist_sq(qx, qy, _mm256_loadu_pd(xs.as_ptr().add(i)), _mm256_loadu_pd(ys.as_ptr().add(i)));
+            let mut mask = _mm256_movemask_pd(_mm256_cmp_pd::<_CMP_LT_OQ>(d, r));
+            while mask != 0 {
+                out.push(i as u32 + mask.trailing_zeros());
+                mask &= mask - 1;
+            }
+        }
+        scalar::filter_from(n, x, y, xs, ys, radius_sq, out);
+    }
+}
+
+// ============================================================================
+// AVX-512: 8 lanes
+// ============================================================================
+
+#[cfg(target_arch = "x86_64")]
+mod avx512 {
+    use super::scalar;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx512f")]
+    unsafe fn dist_sq(x1: __m512d, y1: __m512d, x2: __m512d, y2: __m512d) -> __m512d {
+        let dx = _mm512_sub_pd(x2, x1);
+        let dy = _mm512_sub_pd(y2, y1);
+        _mm512_add_pd(_mm512_mul_pd(dx, dx), _mm512_mul_pd(dy, dy))
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn distances(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        let n = out.len() / 8 * 8;
+        for i in (0..n).step_by(8) {
+            let d = dist_sq(
+                _mm512_loadu_pd(x1.as_ptr().add(i)),
+                _mm512_loadu_pd(y1.as_ptr().add(i)),
+                _mm512_loadu_pd(x2.as_ptr().add(i)),
+                _mm512_loadu_pd(y2.as_ptr().add(i)),
+            );
+            _mm512_storeu_pd(out.as_mut_ptr().add(i), _mm512_sqrt_pd(d));
+        }
+        scalar::distances_from(n, x1, y1, x2, y2, out);
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn distances_sq(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], out: &mut [f64]) {
+        let n = out.len() / 8 * 8;
+        for i in (0..n).step_by(8) {
+            let d = dist_sq(
+                _mm512_loadu_pd(x1.as_ptr().add(i)),
+                _mm512_loadu_pd(y1.as_ptr().add(i)),
+                _mm512_loadu_pd(x2.as_ptr().add(i)),
+                _mm512_loadu_pd(y2.as_ptr().add(i)),
+            );
+            _mm512_storeu_pd(out.as_mut_ptr().add(i), d);
+        }
+        scalar::distances_sq_from(n, x1, y1, x2, y2, out);
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn within(x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64], radius_sq: f64, out: &mut [bool]) {
+        let n = out.len() / 8 * 8;
+        let r = _mm512_set1_pd(radius_sq);
+        for i in (0..n).step_by(8) {
+            let d = dist_sq(
+                _mm512_loadu_pd(x1.as_ptr().add(i)),
+                _mm512_loadu_pd(y1.as_ptr().add(i)),
+                _mm512_loadu_pd(x2.as_ptr().add(i)),
+                _mm512_loadu_pd(y2.as_ptr().add(i)),
+            );
+            let mask = _mm512_cmp_pd_mask::<_CMP_LT_OQ>(d, r);
+            for lane in 0..8 {
+                out[i + lane] = mask & (1 << lane) != 0;
+            }
+        }
+        scalar::within_from(n, x1, y1, x2, y2, radius_sq, out);
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn filter_within(x: f64, y: f64, xs: &[f64], ys: &[f64], radius_sq: f64, out: &mut Vec<u32>) {
+        let n = xs.len() / 8 * 8;
+        let (qx, qy, r) = (_mm512_set1_pd(x), _mm512_set1_pd(y), _mm512_set1_pd(radius_sq));
+        for i in (0..n).step_by(8) {
+            let d = dist_sq(qx, qy, _mm512_loadu_pd(xs.as_ptr().add(i)), _mm512_loadu_pd(ys.as_ptr().add(i)));
+            let mut mask = _mm512_cmp_pd_mask::<_CMP_LT_OQ>(d, r) as u32;
+            while mask != 0 {
+                out.push(i as u32 + mask.trailing_zeros());
+                mask &= mask - 1;
+            }
+        }
+        scalar::filter_from(n, x, y, xs, ys, radius_sq, out);
+    }
+}
@@ -0,0 +1,477 @@
+//! System Runner with Per-System Prepared Statements
+//!
+//! Shared by bins via `mod system_runner;`. Each system declares its SQL once
+//! with named parameters (`$dt`, `$tick`, ...). The runner rewrites them to
+//! positional `?`, prepares the statement once and keeps the handle, then
+//! binds the current parameter values every tick.
+//!
+//! Systems whose SQL is generated per tick (the `format!` style used by the
+//! simulation benchmarks) are supported as "dynamic" systems: they are only
+//! re-prepared when the generated text changes, and the report shows which
+//! systems keep re-preparing and what that parsing costs per tick.
+//!
+//! `compile_tick` builds a runner from structured UPDATE systems and scalar
+//! queries: adjacent compatible UPDATEs on one table are merged into one
+//! statement and all queries into one SELECT. The tick is still several
+//! statements (one per group, the SELECT, BEGIN and COMMIT), wrapped in a
+//! single transaction.
+//!
+//! `run_tick_atomic` commits a tick only if every system succeeds;
+//! `simulate_ahead(n, observe)` runs ticks speculatively and rolls them back.
+
+#![allow(dead_code)]
+
+use duckdb::types::Value;
+use duckdb::{params_from_iter, Connection, Statement};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemKind {
+    /// UPDATE / INSERT / DELETE; result is the affected row count.
+    Execute,
+    /// SELECT; result rows are returned from `run_tick`.
+    Query,
+}
+
+/// Current values of the named parameters, shared by all systems.
+#[derive(Default)]
+pub struct Params(HashMap<String, Value>);
+
+impl Params {
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.0.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+}
+
+enum SystemSql {
+    Static(String),
+    Dynamic(Box<dyn Fn(&Params) -> String>),
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct SystemStats {
+    pub runs: u64,
+    pub prepares: u64,
+    pub prepare_time: Duration,
+    pub exec_time: Duration,
+}
+
+struct SystemEntry<'c> {
+    name: String,
+    kind: SystemKind,
+    sql: SystemSql,
+    /// Source text the current handle was prepared from.
+    prepared_from: Option<String>,
+    param_names: Vec<String>,
+    stmt: Option<Statement<'c>>,
+    stats: SystemStats,
+}
+
+#[derive(Debug, Clone)]
+pub enum SystemOutput {
+    Affected(usize),
+    Rows(Vec<Vec<Value>>),
+}
+
+pub struct SystemRunner<'c> {
+    conn: &'c Connection,
+    systems: Vec<SystemEntry<'c>>,
+    pub params: Params,
+    ticks: u64,
+}
+
+/// Rewrite `$name` parameters to `?` outside string literals; returns the
+/// rewritten SQL and the parameter names in binding order.
+pub fn compile_named_params(sql: &str) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(sql.len());
+    let mut names = Vec::new();
+    let mut chars = sql.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            in_string = !in_string;
+            out.push(c);
+        } else if c == '$' && !in_string && chars.peek().is_some_and(|n| n.is_ascii_alphabetic() || *n == '_') {
+            let mut name = String::new();
+            while let Some(&n) = chars.peek() {
+                if !(n.is_ascii_alphanumeric() || n == '_') {
+                    break;
+                }
+                name.push(n);
+                chars.next();
+            }
+            names.push(name);
+            out.push('?');
+        } else {
+            out.push(c);
+        }
+    }
+    (out, names)
+}
+
+impl<'c> SystemRunner<'c> {
+    pub fn new(conn: &'c Connection) -> Self {
+        Self { conn, systems: Vec::new(), params: Params::default(), ticks: 0 }
+    }
+
+    /// System with fixed SQL: prepared once, on its first run.
+    pub fn add_system(&mut self, name: impl Into<String>, kind: SystemKind, sql: &str) {
+        self.push(name.into(), kind, SystemSql::Static(sql.to_string()));
+    }
+
+    /// System whose SQL is generated every tick; re-prepared when the text changes.
+    pub fn add_dynamic_system(&mut self, name: impl Into<String>, kind: SystemKind, sql: impl Fn(&Params) -> String + 'static) {
+        self.push(name.into(), kind, SystemSql::Dynamic(Box::new(sql)));
+    }
+
+    fn push(&mut self, name: String, kind: SystemKind, sql: SystemSql) {
+        self.systems.push(SystemEntry {
+            name,
+            kind,
+            sql,
+            prepared_from: None,
+            param_names: Vec::new(),
+            stmt: None,
+            stats: SystemStats::default(),
+        });
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Run every system once, in declaration order. `$tick` is always bound
+    /// to the current tick number; any other parameter a system uses but
+    /// nobody set is an `InvalidParameterName` error.
+    pub fn run_tick(&mut self) -> duckdb::Result<Vec<(String, SystemOutput)>> {
+        self.params.set("tick", Value::BigInt(self.ticks as i64));
+        let mut outputs = Vec::with_capacity(self.systems.len());
+        for system in &mut self.systems {
+            let source = match &system.sql {
+                SystemSql::Static(sql) => sql.clone(),
+                SystemSql::Dynamic(f) => f(&self.params),
+            };
+
+            if system.prepared_from.as_deref() != Some(source.as_str()) {
+                let start = Instant::now();
+                let (sql, names) = compile_named_params(&source);
+                system.stmt = Some(self.conn.prepare(&sql)?);
+                system.param_names = names;
+                system.prepared_from = Some(source);
+                system.stats.prepares += 1;
+                system.stats.prepare_time += start.elapsed();
+            }
+
+            let values: Vec<Value> = system
+                .param_names
+                .iter()
+                .map(|n| {
+                    self.params.get(n).cloned().ok_or_else(|| {
+                        duckdb::Error::InvalidParameterName(format!("system '{}' uses unset parameter ${}", system.name, n))
+                    })
+                })
+                .collect::<duckdb::Result<_>>()?;
+
+            let stmt = system.stmt.as_mut().expect("prepared above");
+            let start = Instant::now();
+            let output = match system.kind {
+                SystemKind::Execute => SystemOutput::Affected(stmt.execute(params_from_iter(values))?),
+                SystemKind::Query => {
+                    let mut rows = stmt.query(params_from_iter(values))?;
+                    let columns = rows.as_ref().map_or(0, |s| s.column_count());
+                    let mut out = Vec::new();
+                    while let Some(row) = rows.next()? {
+                        out.push((0..columns).map(|i| row.get::<_, Value>(i)).collect::<duckdb::Result<Vec<_>>>()?);
+                    }
+                    SystemOutput::Rows(out)
+                }
+            };
+            system.stats.exec_time += start.elapsed();
+            system.stats.runs += 1;
+            outputs.push((system.name.clone(), output));
+        }
+        self.ticks += 1;
+        Ok(outputs)
+    }
+
+    /// Run `f` inside a transaction: commit if it succeeds, roll back if it
+    /// (or the commit) fails.
+    fn in_transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> duckdb::Result<T>) -> duckdb::Result<T> {
+        self.conn.execute_batch("BEGIN TRANSACTION")?;
+        match f(self).and_then(|v| self.conn.execute_batch("COMMIT").map(|_| v)) {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                let _ = self.conn.execute_batch("ROLLBACK");
+                Err(e)
+            }
+        }
+    }
+
+    /// `run_tick` as one transaction: either every system's writes land or
+    /// none do. On error the tick counter is not advanced.
+    pub fn run_tick_atomic(&mut self) -> duckdb::Result<Vec<(String, SystemOutput)>> {
+        let tick = self.ticks;
+        let result = self.in_transaction(|runner| runner.run_tick());
+        if result.is_err() {
+            self.ticks = tick;
+        }
+        result
+    }
+
+    /// Run `n` ticks speculatively, calling `observe(conn, tick)` after each,
+    /// then roll everything back (AI look-ahead, client-side prediction).
+    /// The world and the tick counter are left exactly as they were.
+    pub fn simulate_ahead<T>(
+        &mut self,
+        n: usize,
+        mut observe: impl FnMut(&Connection, u64) -> duckdb::Result<T>,
+    ) -> duckdb::Result<Vec<T>> {
+        let tick = self.ticks;
+        self.conn.execute_batch("BEGIN TRANSACTION")?;
+        let mut run = || -> duckdb::Result<Vec<T>> {
+            let mut observed = Vec::with_capacity(n);
+            for _ in 0..n {
+                self.run_tick()?;
+                observed.push(observe(self.conn, self.ticks)?);
+            }
+            Ok(observed)
+        };
+        let result = run();
+        self.ticks = tick;
+        // A failed tick's error comes first; a failed rollback only matters
+        // when the ticks themselves succeeded.
+        let rolled_back = self.conn.execute_batch("ROLLBACK");
+        let observed = result?;
+        rolled_back?;
+        Ok(observed)
+    }
+
+    pub fn stats(&self) -> Vec<(String, SystemStats)> {
+        self.systems.iter().map(|s| (s.name.clone(), s.stats)).collect()
+    }
+
+    /// Average time spent preparing statements per tick so far.
+    pub fn parse_cost_per_tick(&self) -> Duration {
+        let total: Duration = self.systems.iter().map(|s| s.stats.prepare_time).sum();
+        total / self.ticks.max(1) as u32
+    }
+
+    pub fn print_report(&self) {
+        println!("  {:<22} {:>6} {:>9} {:>12} {:>12}", "system", "runs", "prepares", "prep µs/run", "exec µs/run");
+        for (name, s) in self.stats() {
+            let runs = s.runs.max(1) as f64;
+            // Anything prepared more than once is re-preparing on the hot path
+            let flag = if s.prepares > 1 { "⚠ re-preparing" } else { "" };
+            println!("  {:<22} {:>6} {:>9} {:>12.2} {:>12.2}  {}",
+                     name, s.runs, s.prepares,
+                     s.prepare_time.as_secs_f64() * 1e6 / runs,
+                     s.exec_time.as_secs_f64() * 1e6 / runs,
+                     flag);
+        }
+        println!("  Parse cost per tick: {:.2} µs", self.parse_cost_per_tick().as_secs_f64() * 1e6);
+    }
+}
+
+// ============================================================================
+// Tick compiler: merge compatible UPDATEs, one transaction per tick
+// ============================================================================
+
+/// `UPDATE table SET col = expr, ... [WHERE filter]`, declared structurally
+/// so the compiler can see which columns it reads and writes.
+pub struct UpdateSystem {
+    pub name: &'static str,
+    pub table: &'static str,
+    pub set: Vec<(&'static str, &'static str)>,
+    pub filter: Option<&'static str>,
+}
+
+/// A query returning a single value (COUNT, SUM, ...).
+pub struct ScalarQuery {
+    pub name: &'static str,
+    pub sql: String,
+}
+
+/// Identifiers in `expr` outside string literals, excluding `$params`.
+fn identifiers(expr: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut chars = expr.chars().peekable();
+    let mut in_string = false;
+    let mut prev = ' ';
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            in_string = !in_string;
+        } else if !in_string && (c.is_ascii_alphabetic() || c == '_') && !(prev.is_ascii_alphanumeric() || prev == '_' || prev == '$') {
+            let mut ident = c.to_string();
+            while let Some(&n) = chars.peek() {
+                if !(n.is_ascii_alphanumeric() || n == '_') {
+                    break;
+                }
+                ident.push(n);
+                chars.next();
+            }
+            found.push(ident);
+            prev = 'a';
+            continue;
+        }
+        prev = c;
+    }
+    found
+}
+
+/// Identifiers in `expr` that are columns of the table, each once.
+fn referenced_columns(expr: &str, columns: &[String]) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    for ident in identifiers(expr) {
+        if columns.iter().any(|col| col.eq_ignore_ascii_case(&ident)) && !found.contains(&ident) {
+            found.push(ident);
+        }
+    }
+    found
+}
+
+/// A subquery can read any table (or other rows of its own), which column
+/// matching cannot see.
+fn has_subquery(expr: &str) -> bool {
+    identifiers(expr).iter().any(|i| i.eq_ignore_ascii_case("select"))
+}
+
+struct UpdateGroup {
+    table: &'static str,
+    /// Contains a subquery: nothing may merge into it.
+    sealed: bool,
+    members: Vec<usize>,
+    reads: Vec<String>,
+    writes: Vec<String>,
+}
+
+pub struct CompiledTick<'c> {
+    pub runner: SystemRunner<'c>,
+    query_names: Vec<&'static str>,
+    /// Names of the systems merged into each statement, in run order.
+    pub groups: Vec<Vec<&'static str>>,
+    /// Statements per tick if every system ran on its own.
+    pub round_trips_before: usize,
+    /// Statements per tick after merging, including BEGIN and COMMIT.
+    pub round_trips_after: usize,
+}
+
+/// Merge adjacent `updates` on the same table into one UPDATE when no member
+/// writes a column another member reads or writes (so evaluating them
+/// against the same old row is equivalent to running them in order). Only
+/// neighbours merge, so the statement order is the declaration order; a
+/// system whose expressions or filter contain a subquery always runs alone.
+/// Per-system filters become `CASE WHEN filter THEN expr ELSE col END`. All
+/// `queries` are combined into one `SELECT (q1), (q2), ...`.
+pub fn compile_tick<'c>(conn: &'c Connection, updates: &[UpdateSystem], queries: &[ScalarQuery]) -> duckdb::Result<CompiledTick<'c>> {
+    let mut table_columns: HashMap<&'static str, Vec<String>> = HashMap::new();
+    let mut groups: Vec<UpdateGroup> = Vec::new();
+
+    for (i, system) in updates.iter().enumerate() {
+        if !table_columns.contains_key(system.table) {
+            let mut stmt = conn.prepare("SELECT column_name FROM information_schema.columns WHERE table_name = ?")?;
+            let cols = stmt.query_map([system.table], |r| r.get::<_, String>(0))?.collect::<duckdb::Result<Vec<_>>>()?;
+            table_columns.insert(system.table, cols);
+        }
+        let columns = &table_columns[system.table];
+
+        let writes: Vec<String> = system.set.iter().map(|(c, _)| c.to_string()).collect();
+        let mut reads: Vec<String> = Vec::new();
+        let mut sealed = false;
+        for expr in system.set.iter().map(|(_, e)| *e).chain(system.filter) {
+            sealed |= has_subquery(expr);
+            for c in referenced_columns(expr, columns) {
+                if !reads.contains(&c) {
+                    reads.push(c);
+                }
+            }
+        }
+
+        // Only the previous statement is a merge candidate: merging past a
+        // system on another table would run it before that system.
+        let target = groups.last_mut().filter(|g| {
+            let overlaps = |a: &[String], b: &[String]| a.iter().any(|x| b.iter().any(|y| x.eq_ignore_ascii_case(y)));
+            g.table == system.table
+                && !g.sealed
+                && !sealed
+                && !overlaps(&writes, &g.writes)
+                && !overlaps(&writes, &g.reads)
+                && !overlaps(&reads, &g.writes)
+        });
+        match target {
+            Some(g) => {
+                g.members.push(i);
+                g.reads.extend(reads);
+                g.writes.extend(writes);
+            }
+            None => groups.push(UpdateGroup { table: system.table, sealed, members: vec![i], reads, writes }),
+        }
+    }
+
+    let mut runner = SystemRunner::new(conn);
+    let mut group_names = Vec::new();
+    for g in &groups {
+        let members: Vec<&UpdateSystem> = g.members.iter().map(|&i| &updates[i]).collect();
+        let merged = members.len() > 1;
+        let sets: Vec<String> = members
+            .iter()
+            .flat_map(|s| {
+                s.set.iter().map(move |(col, expr)| match s.filter {
+                    Some(f) if merged => format!("{col} = CASE WHEN {f} THEN {expr} ELSE {col} END"),
+                    _ => format!("{col} = {expr}"),
+                })
+            })
+            .collect();
+        // A WHERE is only kept when every member has one: OR them together
+        let filter = if members.iter().all(|s| s.filter.is_some()) {
+            let parts: Vec<String> = members.iter().map(|s| format!("({})", s.filter.unwrap_or("true"))).collect();
+            format!(" WHERE {}", parts.join(" OR "))
+        } else {
+            String::new()
+        };
+        let names: Vec<&'static str> = members.iter().map(|s| s.name).collect();
+        runner.add_system(names.join("+"), SystemKind::Execute, &format!("UPDATE {} SET {}{}", g.table, sets.join(", "), filter));
+        group_names.push(names);
+    }
+
+    if !queries.is_empty() {
+        let parts: Vec<String> = queries.iter().map(|q| format!("({}) AS {}", q.sql, q.name)).collect();
+        runner.add_system("queries", SystemKind::Query, &format!("SELECT {}", parts.join(", ")));
+    }
+
+    Ok(CompiledTick {
+        runner,
+        query_names: queries.iter().map(|q| q.name).collect(),
+        round_trips_before: updates.len() + queries.len(),
+        round_trips_after: groups.len() + usize::from(!queries.is_empty()) + 2,
+        groups: group_names,
+    })
+}
+
+impl<'c> CompiledTick<'c> {
+    /// Run the whole tick in one transaction; returns each query's value.
+    pub fn run(&mut self) -> duckdb::Result<Vec<(&'static str, Value)>> {
+        let outputs = self.runner.run_tick_atomic()?;
+        let row = outputs.into_iter().find_map(|(_, out)| match out {
+            SystemOutput::Rows(mut rows) if !rows.is_empty() => Some(rows.swap_remove(0)),
+            _ => None,
+        });
+        Ok(self.query_names.iter().copied().zip(row.unwrap_or_default()).collect())
+    }
+
+    pub fn print_plan(&self) {
+        for (i, names) in self.groups.iter().enumerate() {
+            println!("  statement {}: {}", i + 1, names.join(" + "));
+        }
+        if !self.query_names.is_empty() {
+            println!("  statement {}: SELECT of {} queries", self.groups.len() + 1, self.query_names.len());
+        }
+        println!("  Round-trips per tick: {} → {} (saved {})",
+                 self.round_trips_before, self.round_trips_after,
+                 self.round_trips_before.saturating_sub(self.round_trips_after));
+    }
+}
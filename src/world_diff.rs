@@ -0,0 +1,387 @@
+//! Order-Independent Table Checksums and Diffs Across Backends
+//!
+//! Shared by bins via `mod world_diff;`. A component table from any backend
+//! is first normalized into a `TableData` (an integer key column plus typed
+//! value columns):
+//!
+//!   TableData::from_duckdb(&conn, "units", "id")
+//!   TableData::from_polars(&df, "id")
+//!   TableData::from_arrow(&batches, "id")
+//!
+//! Integer columns widen to i64 and float columns to f64, so INTEGER vs Int32
+//! or FLOAT vs Float32 don't count as differences. Then:
+//!
+//! - `checksum()` hashes every row (key, column names, value bits) and sums
+//!   the hashes, so row order and column order don't matter. Floats are
+//!   canonicalized first (-0.0 hashes as 0.0, every NaN as one NaN), and a
+//!   wrapping sum of 64-bit hashes can collide, so equal checksums mean the
+//!   tables are equal up to those canonicalizations with high probability,
+//!   not that they are bit-identical. Use `diff` to confirm.
+//! - `diff(&a, &b, tolerance)` matches rows by key and reports missing ids,
+//!   columns present on one side only, and (id, column) cells whose values
+//!   differ by more than `tolerance` (relative above 1.0, absolute below).
+
+#![allow(dead_code)]
+
+use duckdb::arrow::array::{Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray};
+use duckdb::arrow::compute::cast;
+use duckdb::arrow::datatypes::DataType as ArrowType;
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::Connection;
+use polars::prelude::{DataFrame, DataType as PolarsType};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+pub type DiffResult<T> = Result<T, Box<dyn Error>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Values {
+    Int(Vec<Option<i64>>),
+    Float(Vec<Option<f64>>),
+    Bool(Vec<Option<bool>>),
+    Text(Vec<Option<String>>),
+}
+
+/// One cell, for comparisons and reports.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Null,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cell::Null => write!(f, "NULL"),
+            Cell::Int(v) => write!(f, "{}", v),
+            Cell::Float(v) => write!(f, "{}", v),
+            Cell::Bool(v) => write!(f, "{}", v),
+            Cell::Text(v) => write!(f, "{:?}", v),
+        }
+    }
+}
+
+impl Values {
+    fn len(&self) -> usize {
+        match self {
+            Values::Int(v) => v.len(),
+            Values::Float(v) => v.len(),
+            Values::Bool(v) => v.len(),
+            Values::Text(v) => v.len(),
+        }
+    }
+
+    fn append(&mut self, other: Values) -> DiffResult<()> {
+        match (self, other) {
+            (Values::Int(a), Values::Int(b)) => a.extend(b),
+            (Values::Float(a), Values::Float(b)) => a.extend(b),
+            (Values::Bool(a), Values::Bool(b)) => a.extend(b),
+            (Values::Text(a), Values::Text(b)) => a.extend(b),
+            _ => return Err("column type changed between batches".into()),
+        }
+        Ok(())
+    }
+
+    fn cell(&self, row: usize) -> Cell {
+        match self {
+            Values::Int(v) => v[row].map_or(Cell::Null, Cell::Int),
+            Values::Float(v) => v[row].map_or(Cell::Null, Cell::Float),
+            Values::Bool(v) => v[row].map_or(Cell::Null, Cell::Bool),
+            Values::Text(v) => v[row].clone().map_or(Cell::Null, Cell::Text),
+        }
+    }
+
+    /// Bits fed to the checksum. -0.0 and NaN payloads are canonicalized so
+    /// backends that produce them differently still agree.
+    fn hash_cell(&self, row: usize) -> u64 {
+        match self {
+            Values::Int(v) => v[row].map_or(NULL_HASH, |x| x as u64),
+            Values::Float(v) => v[row].map_or(NULL_HASH, |x| {
+                if x.is_nan() {
+                    f64::NAN.to_bits()
+                } else if x == 0.0 {
+                    0
+                } else {
+                    x.to_bits()
+                }
+            }),
+            Values::Bool(v) => v[row].map_or(NULL_HASH, |x| x as u64),
+            Values::Text(v) => v[row].as_deref().map_or(NULL_HASH, hash_str),
+        }
+    }
+}
+
+const NULL_HASH: u64 = 0x6E75_6C6C_6E75_6C6C;
+
+fn splitmix64(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn hash_str(s: &str) -> u64 {
+    s.bytes().fold(splitmix64(s.len() as u64), |h, b| splitmix64(h ^ b as u64))
+}
+
+// ============================================================================
+// Normalized table
+// ============================================================================
+
+#[derive(Debug, Clone)]
+pub struct TableData {
+    pub key: String,
+    pub ids: Vec<i64>,
+    /// Value columns (key excluded), sorted by name.
+    pub columns: BTreeMap<String, Values>,
+}
+
+impl TableData {
+    fn from_columns(key: &str, mut columns: BTreeMap<String, Values>) -> DiffResult<Self> {
+        let ids = match columns.remove(key) {
+            Some(Values::Int(ids)) => ids
+                .into_iter()
+                .map(|id| id.ok_or_else(|| format!("NULL in key column `{}`", key)))
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(_) => return Err(format!("key column `{}` is not an integer", key).into()),
+            None => return Err(format!("no key column `{}`", key).into()),
+        };
+        if let Some((name, _)) = columns.iter().find(|(_, v)| v.len() != ids.len()) {
+            return Err(format!("column `{}` length differs from key column", name).into());
+        }
+        Ok(TableData { key: key.to_string(), ids, columns })
+    }
+
+    pub fn from_arrow(batches: &[RecordBatch], key: &str) -> DiffResult<Self> {
+        let mut columns: BTreeMap<String, Values> = BTreeMap::new();
+        for batch in batches {
+            for (field, array) in batch.schema().fields().iter().zip(batch.columns()) {
+                let values = arrow_values(array)?;
+                match columns.get_mut(field.name()) {
+                    Some(existing) => existing.append(values)?,
+                    None => {
+                        columns.insert(field.name().clone(), values);
+                    }
+                }
+            }
+        }
+        Self::from_columns(key, columns)
+    }
+
+    pub fn from_duckdb(conn: &Connection, table: &str, key: &str) -> DiffResult<Self> {
+        let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
+        let arrow = stmt.query_arrow([])?;
+        let schema = arrow.get_schema();
+        let batches: Vec<RecordBatch> = arrow.collect();
+        if batches.is_empty() {
+            // An empty table yields no batches, so take the columns from the schema.
+            let columns = schema
+                .fields()
+                .iter()
+                .map(|field| (field.name().clone(), empty_values(field.data_type())))
+                .collect();
+            return Self::from_columns(key, columns);
+        }
+        Self::from_arrow(&batches, key)
+    }
+
+    pub fn from_polars(df: &DataFrame, key: &str) -> DiffResult<Self> {
+        let mut columns = BTreeMap::new();
+        for column in df.get_columns() {
+            let s = column.as_materialized_series();
+            let dtype = s.dtype();
+            let values = if dtype.is_integer() {
+                Values::Int(s.cast(&PolarsType::Int64)?.i64()?.into_iter().collect())
+            } else if dtype.is_float() {
+                Values::Float(s.cast(&PolarsType::Float64)?.f64()?.into_iter().collect())
+            } else if *dtype == PolarsType::Boolean {
+                Values::Bool(s.bool()?.into_iter().collect())
+            } else {
+                let text = s.cast(&PolarsType::String)?;
+                Values::Text(text.str()?.into_iter().map(|v| v.map(str::to_string)).collect())
+            };
+            columns.insert(s.name().to_string(), values);
+        }
+        Self::from_columns(key, columns)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Order-independent checksum: wrapping sum of per-row hashes.
+    pub fn checksum(&self) -> u64 {
+        let name_hashes: Vec<(u64, &Values)> =
+            self.columns.iter().map(|(name, values)| (hash_str(name), values)).collect();
+        let mut sum = 0u64;
+        for (row, id) in self.ids.iter().enumerate() {
+            let h = name_hashes
+                .iter()
+                .fold(splitmix64(*id as u64), |h, (name, values)| splitmix64(h ^ name ^ values.hash_cell(row)));
+            sum = sum.wrapping_add(h);
+        }
+        sum.wrapping_add(self.rows() as u64)
+    }
+}
+
+fn arrow_values(array: &ArrayRef) -> DiffResult<Values> {
+    use ArrowType::*;
+    Ok(match array.data_type() {
+        Int8 | Int16 | Int32 | Int64 | UInt8 | UInt16 | UInt32 | UInt64 => {
+            let a = cast(array, &Int64)?;
+            Values::Int(a.as_any().downcast_ref::<Int64Array>().ok_or("cast to Int64")?.iter().collect())
+        }
+        Float16 | Float32 | Float64 => {
+            let a = cast(array, &Float64)?;
+            Values::Float(a.as_any().downcast_ref::<Float64Array>().ok_or("cast to Float64")?.iter().collect())
+        }
+        Boolean => Values::Bool(array.as_any().downcast_ref::<BooleanArray>().ok_or("Boolean")?.iter().collect()),
+        _ => {
+            let a = cast(array, &Utf8)?;
+            let strings = a.as_any().downcast_ref::<StringArray>().ok_or("cast to Utf8")?;
+            Values::Text(strings.iter().map(|v| v.map(str::to_string)).collect())
+        }
+    })
+}
+
+fn empty_values(data_type: &ArrowType) -> Values {
+    use ArrowType::*;
+    match data_type {
+        Int8 | Int16 | Int32 | Int64 | UInt8 | UInt16 | UInt32 | UInt64 => Values::Int(vec![]),
+        Float16 | Float32 | Float64 => Values::Float(vec![]),
+        Boolean => Values::Bool(vec![]),
+        _ => Values::Text(vec![]),
+    }
+}
+
+// ============================================================================
+// Diff
+// ============================================================================
+
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub id: i64,
+    pub column: String,
+    pub left: Cell,
+    pub right: Cell,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TableDiff {
+    pub only_left: Vec<i64>,
+    pub only_right: Vec<i64>,
+    pub duplicate_ids: Vec<i64>,
+    pub columns_only_left: Vec<String>,
+    pub columns_only_right: Vec<String>,
+    /// Sorted by (id, column).
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl TableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_left.is_empty()
+            && self.only_right.is_empty()
+            && self.duplicate_ids.is_empty()
+            && self.columns_only_left.is_empty()
+            && self.columns_only_right.is_empty()
+            && self.mismatches.is_empty()
+    }
+
+    /// Mismatch count per column.
+    pub fn by_column(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for m in &self.mismatches {
+            *counts.entry(m.column.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    pub fn print(&self, limit: usize) {
+        if self.is_empty() {
+            println!("    no differences");
+            return;
+        }
+        let preview = |ids: &[i64]| ids.iter().take(limit).map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+        if !self.only_left.is_empty() {
+            println!("    {} ids only on left: {}", self.only_left.len(), preview(&self.only_left));
+        }
+        if !self.only_right.is_empty() {
+            println!("    {} ids only on right: {}", self.only_right.len(), preview(&self.only_right));
+        }
+        if !self.duplicate_ids.is_empty() {
+            println!("    {} duplicate ids: {}", self.duplicate_ids.len(), preview(&self.duplicate_ids));
+        }
+        for c in &self.columns_only_left {
+            println!("    column `{}` only on left", c);
+        }
+        for c in &self.columns_only_right {
+            println!("    column `{}` only on right", c);
+        }
+        if !self.mismatches.is_empty() {
+            let counts: Vec<String> = self.by_column().iter().map(|(c, n)| format!("{}: {}", c, n)).collect();
+            println!("    {} differing cells ({})", self.mismatches.len(), counts.join(", "));
+            for m in self.mismatches.iter().take(limit) {
+                println!("      id {:>8}  {:<8} {} vs {}", m.id, m.column, m.left, m.right);
+            }
+        }
+    }
+}
+
+fn cells_match(left: &Cell, right: &Cell, tolerance: f64) -> bool {
+    let close = |a: f64, b: f64| (a.is_nan() && b.is_nan()) || (a - b).abs() <= tolerance * a.abs().max(b.abs()).max(1.0);
+    match (left, right) {
+        (Cell::Float(a), Cell::Float(b)) => close(*a, *b),
+        (Cell::Float(a), Cell::Int(b)) | (Cell::Int(b), Cell::Float(a)) => close(*a, *b as f64),
+        _ => left == right,
+    }
+}
+
+fn index(ids: &[i64], duplicates: &mut Vec<i64>) -> HashMap<i64, usize> {
+    let mut map = HashMap::with_capacity(ids.len());
+    for (row, id) in ids.iter().enumerate() {
+        if map.insert(*id, row).is_some() {
+            duplicates.push(*id);
+        }
+    }
+    map
+}
+
+/// Matches rows by key and compares every shared column.
+pub fn diff(left: &TableData, right: &TableData, tolerance: f64) -> TableDiff {
+    let mut out = TableDiff::default();
+    let left_index = index(&left.ids, &mut out.duplicate_ids);
+    let right_index = index(&right.ids, &mut out.duplicate_ids);
+    out.duplicate_ids.sort_unstable();
+    out.duplicate_ids.dedup();
+
+    out.columns_only_left = left.columns.keys().filter(|c| !right.columns.contains_key(*c)).cloned().collect();
+    out.columns_only_right = right.columns.keys().filter(|c| !left.columns.contains_key(*c)).cloned().collect();
+    let shared: Vec<(&String, &Values, &Values)> = left
+        .columns
+        .iter()
+        .filter_map(|(name, l)| right.columns.get(name).map(|r| (name, l, r)))
+        .collect();
+
+    for (id, &lrow) in &left_index {
+        let Some(&rrow) = right_index.get(id) else {
+            out.only_left.push(*id);
+            continue;
+        };
+        for (name, l, r) in &shared {
+            let (lc, rc) = (l.cell(lrow), r.cell(rrow));
+            if !cells_match(&lc, &rc, tolerance) {
+                out.mismatches.push(Mismatch { id: *id, column: name.to_string(), left: lc, right: rc });
+            }
+        }
+    }
+    out.only_right = right_index.keys().filter(|id| !left_index.contains_key(id)).copied().collect();
+
+    out.only_left.sort_unstable();
+    out.only_right.sort_unstable();
+    out.mismatches.sort_by(|a, b| (a.id, &a.column).cmp(&(b.id, &b.column)));
+    out
+}
@@ -0,0 +1,485 @@
+//! World Snapshots to Parquet with Schema Versioning
+//!
+//! Every other benchmark uses `Connection::open_in_memory()` and throws the
+//! world away on exit. This saves and loads whole worlds:
+//! 1. `save_snapshot_duckdb` / `load_snapshot_duckdb`: one `COPY ... TO` per
+//!    component table, `read_parquet` on the way back
+//! 2. `save_snapshot_polars` / `load_snapshot_polars`: `ParquetWriter` /
+//!    `ParquetReader` per DataFrame
+//! 3. A `manifest.txt` with schema version, tick and table list, written last
+//!    so a half-written snapshot has no manifest and is rejected; tables it
+//!    lists must be known components, since they go into SQL and paths
+//! 4. Migration hooks (one per version step, DuckDB + Polars flavour) that
+//!    upgrade an old snapshot to the current schema on load
+//!
+//! Both backends write plain Parquet, so a DuckDB snapshot loads into Polars
+//! and vice versa.
+
+use duckdb::Connection;
+use polars::prelude::*;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+type Tables = BTreeMap<String, DataFrame>;
+
+/// Current component schema:
+///   position(id BIGINT, x DOUBLE, y DOUBLE)
+///   velocity(id BIGINT, vx DOUBLE, vy DOUBLE)
+///   health(id BIGINT, hp INTEGER, max_hp INTEGER)
+const SCHEMA_VERSION: u32 = 3;
+const COMPONENTS: &[&str] = &["position", "velocity", "health"];
+const MANIFEST: &str = "manifest.txt";
+
+const ENTITIES: usize = 1_000_000;
+
+// ============================================================================
+// Manifest
+// ============================================================================
+
+/// Table names end up in SQL and file paths: only known components.
+fn check_tables(tables: &[String]) -> Result<(), Box<dyn Error>> {
+    if let Some(bad) = tables.iter().find(|t| !COMPONENTS.contains(&t.as_str())) {
+        return Err(format!("manifest lists unknown table {:?}", bad).into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct Manifest {
+    schema_version: u32,
+    tick: u64,
+    backend: String,
+    tables: Vec<String>,
+}
+
+impl Manifest {
+    fn new(schema_version: u32, tick: u64, backend: &str) -> Self {
+        Manifest {
+            schema_version,
+            tick,
+            backend: backend.to_string(),
+            tables: COMPONENTS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn write(&self, dir: &Path) -> std::io::Result<()> {
+        let text = format!(
+            "schema_version = {}\ntick = {}\nbackend = {}\ntables = {}\n",
+            self.schema_version,
+            self.tick,
+            self.backend,
+            self.tables.join(","),
+        );
+        fs::write(dir.join(MANIFEST), text)
+    }
+
+    fn read(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = dir.join(MANIFEST);
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("{}: {} (incomplete snapshot?)", path.display(), e))?;
+
+        let mut fields = BTreeMap::new();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("bad manifest line: {:?}", line))?;
+            fields.insert(key.trim(), value.trim());
+        }
+        let field = |key: &str| -> Result<&str, Box<dyn Error>> {
+            fields.get(key).copied().ok_or_else(|| format!("manifest missing `{}`", key).into())
+        };
+
+        let tables: Vec<String> = field("tables")?.split(',').map(|s| s.trim().to_string()).collect();
+        check_tables(&tables)?;
+        Ok(Manifest {
+            schema_version: field("schema_version")?.parse()?,
+            tick: field("tick")?.parse()?,
+            backend: field("backend")?.to_string(),
+            tables,
+        })
+    }
+}
+
+fn table_path(dir: &Path, table: &str) -> PathBuf {
+    dir.join(format!("{}.parquet", table))
+}
+
+/// Path as a SQL string literal.
+fn sql_path(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "''"))
+}
+
+// ============================================================================
+// Migrations
+// ============================================================================
+
+/// Upgrades a snapshot from `from` to `from + 1`, once per backend.
+struct Migration {
+    from: u32,
+    description: &'static str,
+    duckdb: fn(&Connection) -> duckdb::Result<()>,
+    polars: fn(&mut Tables) -> PolarsResult<()>,
+}
+
+fn table_mut<'a>(tables: &'a mut Tables, name: &str) -> PolarsResult<&'a mut DataFrame> {
+    tables
+        .get_mut(name)
+        .ok_or_else(|| polars_err!(ComputeError: "snapshot has no `{}` table", name))
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        description: "health gains max_hp (default 100)",
+        duckdb: |conn| conn.execute_batch("ALTER TABLE health ADD COLUMN max_hp INTEGER DEFAULT 100;"),
+        polars: |tables| {
+            let health = table_mut(tables, "health")?;
+            let max_hp = Series::new("max_hp".into(), vec![100i32; health.height()]);
+            health.with_column(max_hp)?;
+            Ok(())
+        },
+    },
+    Migration {
+        from: 2,
+        description: "position px/py renamed to x/y",
+        duckdb: |conn| {
+            conn.execute_batch(
+                "ALTER TABLE position RENAME COLUMN px TO x;
+                 ALTER TABLE position RENAME COLUMN py TO y;",
+            )
+        },
+        polars: |tables| {
+            let position = table_mut(tables, "position")?;
+            position.rename("px", "x".into())?;
+            position.rename("py", "y".into())?;
+            Ok(())
+        },
+    },
+];
+
+/// Migrations needed to bring `version` up to `SCHEMA_VERSION`, in order.
+fn migration_chain(version: u32) -> Result<Vec<&'static Migration>, Box<dyn Error>> {
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "snapshot schema v{} is newer than this build (v{})",
+            version, SCHEMA_VERSION
+        )
+        .into());
+    }
+    (version..SCHEMA_VERSION)
+        .map(|v| {
+            MIGRATIONS
+                .iter()
+                .find(|m| m.from == v)
+                .ok_or_else(|| -> Box<dyn Error> { format!("no migration from schema v{}", v).into() })
+        })
+        .collect()
+}
+
+// ============================================================================
+// DuckDB backend
+// ============================================================================
+
+fn write_snapshot_duckdb(conn: &Connection, dir: &Path, manifest: &Manifest) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let _ = fs::remove_file(dir.join(MANIFEST));
+    for table in &manifest.tables {
+        conn.execute_batch(&format!(
+            "COPY {} TO {} (FORMAT PARQUET);",
+            table,
+            sql_path(&table_path(dir, table))
+        ))?;
+    }
+    manifest.write(dir)?;
+    Ok(())
+}
+
+fn save_snapshot_duckdb(conn: &Connection, dir: &Path, tick: u64) -> Result<Manifest, Box<dyn Error>> {
+    let manifest = Manifest::new(SCHEMA_VERSION, tick, "duckdb");
+    write_snapshot_duckdb(conn, dir, &manifest)?;
+    Ok(manifest)
+}
+
+/// Replaces the component tables with the snapshot's and migrates them to
+/// `SCHEMA_VERSION`. Returns the manifest as it was on disk.
+fn load_snapshot_duckdb(conn: &Connection, dir: &Path) -> Result<Manifest, Box<dyn Error>> {
+    let manifest = Manifest::read(dir)?;
+    let chain = migration_chain(manifest.schema_version)?;
+
+    conn.execute_batch("BEGIN TRANSACTION;")?;
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        for table in &manifest.tables {
+            conn.execute_batch(&format!(
+                "CREATE OR REPLACE TABLE {} AS SELECT * FROM read_parquet({});",
+                table,
+                sql_path(&table_path(dir, table))
+            ))?;
+        }
+        for migration in &chain {
+            (migration.duckdb)(conn)?;
+        }
+        Ok(())
+    })();
+    match result {
+        Ok(()) => conn.execute_batch("COMMIT;")?,
+        Err(e) => {
+            // The load error is the one worth reporting, even if ROLLBACK fails too.
+            let _ = conn.execute_batch("ROLLBACK;");
+            return Err(e);
+        }
+    }
+    Ok(manifest)
+}
+
+// ============================================================================
+// Polars backend
+// ============================================================================
+
+/// Fails before writing anything if `tables` has a non-component table,
+/// since the loader would refuse the snapshot.
+fn save_snapshot_polars(tables: &mut Tables, dir: &Path, tick: u64) -> Result<Manifest, Box<dyn Error>> {
+    let mut manifest = Manifest::new(SCHEMA_VERSION, tick, "polars");
+    manifest.tables = tables.keys().cloned().collect();
+    check_tables(&manifest.tables)?;
+    fs::create_dir_all(dir)?;
+    let _ = fs::remove_file(dir.join(MANIFEST));
+    for (name, df) in tables.iter_mut() {
+        ParquetWriter::new(File::create(table_path(dir, name))?).finish(df)?;
+    }
+    manifest.write(dir)?;
+    Ok(manifest)
+}
+
+fn load_snapshot_polars(dir: &Path) -> Result<(Manifest, Tables), Box<dyn Error>> {
+    let manifest = Manifest::read(dir)?;
+    let chain = migration_chain(manifest.schema_version)?;
+
+    let mut tables = Tables::new();
+    for table in &manifest.tables {
+        let df = ParquetReader::new(File::open(table_path(dir, table))?).finish()?;
+        tables.insert(table.clone(), df);
+    }
+    for migration in &chain {
+        (migration.polars)(&mut tables)?;
+    }
+    Ok((manifest, tables))
+}
+
+// ============================================================================
+// Helpers
+// ============================================================================
+
+/// Builds a world in the schema of `version` (1 or current).
+fn create_world_duckdb(conn: &Connection, version: u32) -> duckdb::Result<()> {
+    let (pos_x, pos_y, max_hp) = if version == 1 { ("px", "py", "") } else { ("x", "y", ", 100 AS max_hp") };
+    conn.execute_batch(&format!(
+        "CREATE OR REPLACE TABLE position AS
+         SELECT i::BIGINT AS id, (i % 1000)::DOUBLE AS {pos_x}, (i // 1000)::DOUBLE AS {pos_y}
+         FROM range({ENTITIES}) t(i);
+         CREATE OR REPLACE TABLE velocity AS
+         SELECT i::BIGINT AS id, ((i % 7) - 3)::DOUBLE AS vx, ((i % 5) - 2)::DOUBLE AS vy
+         FROM range({ENTITIES}) t(i);
+         CREATE OR REPLACE TABLE health AS
+         SELECT i::BIGINT AS id, (50 + i % 50)::INTEGER AS hp{max_hp}
+         FROM range({ENTITIES}) t(i);"
+    ))
+}
+
+fn tick_duckdb(conn: &Connection) -> duckdb::Result<()> {
+    conn.execute_batch(
+        "UPDATE position SET x = position.x + v.vx / 60.0, y = position.y + v.vy / 60.0
+         FROM velocity v WHERE position.id = v.id;
+         UPDATE health SET hp = hp - 1 WHERE hp > 0 AND id % 10 = 0;",
+    )
+}
+
+fn columns_duckdb(conn: &Connection, table: &str) -> duckdb::Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT column_name FROM information_schema.columns WHERE table_name = ? ORDER BY ordinal_position",
+    )?;
+    let rows = stmt.query_map([table], |r| r.get(0))?;
+    rows.collect()
+}
+
+fn sum_duckdb(conn: &Connection, table: &str, column: &str) -> duckdb::Result<f64> {
+    conn.query_row(&format!("SELECT sum({})::DOUBLE FROM {}", column, table), [], |r| r.get(0))
+}
+
+fn sum_polars(df: &DataFrame, column: &str) -> PolarsResult<f64> {
+    let s = df.column(column)?.as_materialized_series().cast(&DataType::Float64)?;
+    Ok(s.f64()?.sum().unwrap_or(0.0))
+}
+
+/// Per-column sums over every component table, for cross-backend comparison.
+fn fingerprint_duckdb(conn: &Connection) -> Result<Vec<(String, f64)>, Box<dyn Error>> {
+    let mut out = Vec::new();
+    for table in COMPONENTS {
+        for column in columns_duckdb(conn, table)? {
+            out.push((format!("{}.{}", table, column), sum_duckdb(conn, table, &column)?));
+        }
+    }
+    Ok(out)
+}
+
+fn fingerprint_polars(tables: &Tables) -> Result<Vec<(String, f64)>, Box<dyn Error>> {
+    let mut out = Vec::new();
+    for table in COMPONENTS {
+        let df = tables.get(*table).ok_or_else(|| format!("missing table {}", table))?;
+        for column in df.get_column_names() {
+            out.push((format!("{}.{}", table, column), sum_polars(df, column)?));
+        }
+    }
+    Ok(out)
+}
+
+fn same_fingerprint(a: &[(String, f64)], b: &[(String, f64)]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|((na, va), (nb, vb))| na == nb && (va - vb).abs() <= 1e-9 * va.abs().max(1.0))
+}
+
+fn dir_size(dir: &Path) -> std::io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        total += entry?.metadata()?.len();
+    }
+    Ok(total)
+}
+
+// ============================================================================
+// Main
+// ============================================================================
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("=== World Snapshots: Parquet + Manifest + Migrations ===\n");
+    println!("Entities: {}, current schema: v{}\n", ENTITIES, SCHEMA_VERSION);
+
+    let root = std::env::temp_dir().join("polars_ecs_snapshots");
+    let _ = fs::remove_dir_all(&root);
+    let mut checks_failed = 0;
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch("SET threads TO 8;")?;
+
+    // --------------------------------------------------------------------
+    // 1. An old (v1) snapshot, as written by a previous build
+    // --------------------------------------------------------------------
+    println!("--- 1. Migrating a v1 snapshot ---");
+    let v1_dir = root.join("v1");
+    create_world_duckdb(&conn, 1)?;
+    write_snapshot_duckdb(&conn, &v1_dir, &Manifest::new(1, 500, "duckdb"))?;
+    println!("  Wrote v1 snapshot: position(id, px, py), health(id, hp)");
+    for m in migration_chain(1)? {
+        println!("  Pending v{} → v{}: {}", m.from, m.from + 1, m.description);
+    }
+
+    let fresh = Connection::open_in_memory()?;
+    let manifest = load_snapshot_duckdb(&fresh, &v1_dir)?;
+    println!("  DuckDB load: v{} {} snapshot at tick {}", manifest.schema_version, manifest.backend, manifest.tick);
+    println!("  Migrated: position {:?}, health {:?}",
+             columns_duckdb(&fresh, "position")?, columns_duckdb(&fresh, "health")?);
+    let (_, v1_polars) = load_snapshot_polars(&v1_dir)?;
+    println!("  Polars load: position {:?}, health {:?}",
+             v1_polars["position"].get_column_names(), v1_polars["health"].get_column_names());
+    let ok = same_fingerprint(&fingerprint_duckdb(&fresh)?, &fingerprint_polars(&v1_polars)?);
+    println!("  Both backends migrate to the same world: {}\n", if ok { "✓" } else { "✗" });
+    if !ok {
+        checks_failed += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 2. DuckDB save → DuckDB load round trip
+    // --------------------------------------------------------------------
+    println!("--- 2. DuckDB round trip ---");
+    create_world_duckdb(&conn, SCHEMA_VERSION)?;
+    for _ in 0..120 {
+        tick_duckdb(&conn)?;
+    }
+    let expected = fingerprint_duckdb(&conn)?;
+
+    let duck_dir = root.join("duckdb");
+    let start = Instant::now();
+    save_snapshot_duckdb(&conn, &duck_dir, 120)?;
+    let duck_save = start.elapsed();
+
+    let restored = Connection::open_in_memory()?;
+    let start = Instant::now();
+    let manifest = load_snapshot_duckdb(&restored, &duck_dir)?;
+    let duck_load = start.elapsed();
+    let ok = manifest.tick == 120 && same_fingerprint(&expected, &fingerprint_duckdb(&restored)?);
+    println!("  save {:?}, load {:?}, {:.1} MB on disk", duck_save, duck_load, dir_size(&duck_dir)? as f64 / 1e6);
+    println!("  Restored world identical at tick {}: {}\n", manifest.tick, if ok { "✓" } else { "✗" });
+    if !ok {
+        checks_failed += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 3. Cross-backend: DuckDB snapshot → Polars → Polars snapshot → DuckDB
+    // --------------------------------------------------------------------
+    println!("--- 3. Cross-backend ---");
+    let start = Instant::now();
+    let (manifest, mut tables) = load_snapshot_polars(&duck_dir)?;
+    let polars_load = start.elapsed();
+    let ok = same_fingerprint(&expected, &fingerprint_polars(&tables)?);
+    println!("  DuckDB snapshot → Polars: load {:?}, identical: {}", polars_load, if ok { "✓" } else { "✗" });
+    if !ok {
+        checks_failed += 1;
+    }
+
+    let polars_dir = root.join("polars");
+    let start = Instant::now();
+    save_snapshot_polars(&mut tables, &polars_dir, manifest.tick)?;
+    let polars_save = start.elapsed();
+
+    let back = Connection::open_in_memory()?;
+    load_snapshot_duckdb(&back, &polars_dir)?;
+    let ok = same_fingerprint(&expected, &fingerprint_duckdb(&back)?);
+    println!("  Polars snapshot → DuckDB: save {:?}, {:.1} MB, identical: {}\n",
+             polars_save, dir_size(&polars_dir)? as f64 / 1e6, if ok { "✓" } else { "✗" });
+    if !ok {
+        checks_failed += 1;
+    }
+
+    // --------------------------------------------------------------------
+    // 4. Rejected snapshots
+    // --------------------------------------------------------------------
+    println!("--- 4. Rejected snapshots ---");
+    let future_dir = root.join("future");
+    write_snapshot_duckdb(&conn, &future_dir, &Manifest::new(SCHEMA_VERSION + 1, 0, "duckdb"))?;
+    let future = load_snapshot_duckdb(&restored, &future_dir);
+    println!("  Newer schema:     {}", future.as_ref().err().map(|e| e.to_string()).unwrap_or_default());
+
+    let mut forged = Manifest::new(SCHEMA_VERSION, 0, "duckdb");
+    forged.tables = vec!["position AS SELECT 1; DROP TABLE health; --".into()];
+    forged.write(&duck_dir)?;
+    let injected = load_snapshot_duckdb(&restored, &duck_dir);
+    println!("  Forged table:     {}", injected.as_ref().err().map(|e| e.to_string()).unwrap_or_default());
+
+    fs::remove_file(polars_dir.join(MANIFEST))?;
+    let partial = load_snapshot_polars(&polars_dir);
+    println!("  Missing manifest: {}", partial.as_ref().err().map(|e| e.to_string()).unwrap_or_default());
+    let ok = future.is_err()
+        && injected.is_err()
+        && partial.is_err()
+        && same_fingerprint(&expected, &fingerprint_duckdb(&restored)?);
+    println!("  All rejected, loaded world untouched: {}", if ok { "✓" } else { "✗" });
+    if !ok {
+        checks_failed += 1;
+    }
+
+    let _ = fs::remove_dir_all(&root);
+
+    println!("\n=== Summary ===\n");
+    println!("  • DuckDB:  save {:?}, load {:?}", duck_save, duck_load);
+    println!("  • Polars:  save {:?}, load {:?}", polars_save, polars_load);
+    println!("  • Snapshots are plain Parquet: either backend loads the other's");
+    println!("  • Old snapshots are upgraded by per-version migration hooks on load");
+    println!("  • Failed checks: {}", checks_failed);
+
+    if checks_failed > 0 {
+        return Err(format!("{} self-check(s) failed", checks_failed).into());
+    }
+    Ok(())
+}